@@ -0,0 +1,146 @@
+use std::fmt;
+use std::io::Read;
+use std::path::Path;
+use std::str::FromStr;
+
+use color_eyre::eyre::{Context, Result};
+use quick_xml::events::Event;
+use quick_xml::Reader;
+
+const DOCX_BODY: &str = "word/document.xml";
+/// Pages averaging fewer extracted characters than this are probably scanned images
+const MIN_CHARS_PER_PAGE: usize = 20;
+
+/// Inclusive, 1-based page numbers such as `3-7` or `5`
+#[derive(Clone, Copy, Debug)]
+pub struct PageRange {
+    start: usize,
+    end: usize,
+}
+
+impl FromStr for PageRange {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (start, end) = s.split_once('-').unwrap_or((s, s));
+        let parse = |n: &str| n.trim().parse::<usize>().ok().filter(|n| *n > 0);
+        match (parse(start), parse(end)) {
+            (Some(start), Some(end)) if start <= end => Ok(Self { start, end }),
+            _ => Err(format!(
+                "invalid page range `{s}`, expected e.g. `3-7` or `5`"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for PageRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.start == self.end {
+            write!(f, "{}", self.start)
+        } else {
+            write!(f, "{}-{}", self.start, self.end)
+        }
+    }
+}
+
+pub struct Extracted {
+    pub text: String,
+    /// Set when the text is likely incomplete, e.g. for a scanned PDF
+    pub warning: Option<String>,
+}
+
+/// Reads `path` as text, extracting it from PDF and DOCX files
+pub fn extract(path: &Path, pages: Option<PageRange>) -> Result<Extracted> {
+    let extension = path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .map(str::to_lowercase);
+
+    match extension.as_deref() {
+        Some("pdf") => extract_pdf(path, pages),
+        _ if pages.is_some() => color_eyre::eyre::bail!("`--pages` only applies to PDF files"),
+        Some("docx") => Ok(Extracted {
+            text: extract_docx(path)?,
+            warning: None,
+        }),
+        _ => Ok(Extracted {
+            text: std::fs::read_to_string(path)
+                .wrap_err_with(|| format!("failed to read file `{}`", path.display()))?,
+            warning: None,
+        }),
+    }
+}
+
+fn extract_pdf(path: &Path, pages: Option<PageRange>) -> Result<Extracted> {
+    // pdf-extract panics on some malformed files, which must not take the REPL down
+    let all_pages = std::panic::catch_unwind(|| pdf_extract::extract_text_by_pages(path))
+        .map_err(|_| color_eyre::eyre::eyre!("failed to parse PDF `{}`", path.display()))?
+        .wrap_err_with(|| format!("failed to extract text from `{}`", path.display()))?;
+
+    let selected = match pages {
+        Some(range) => {
+            if range.start > all_pages.len() {
+                color_eyre::eyre::bail!("`{}` has only {} pages", path.display(), all_pages.len());
+            }
+            &all_pages[range.start - 1..range.end.min(all_pages.len())]
+        },
+        None => &all_pages[..],
+    };
+
+    let chars = selected
+        .iter()
+        .map(|page| page.chars().filter(|c| !c.is_whitespace()).count())
+        .sum::<usize>();
+    let warning = (chars < MIN_CHARS_PER_PAGE * selected.len().max(1)).then(|| {
+        format!(
+            "`{}` has little extractable text, it may be scanned images that need OCR",
+            path.display()
+        )
+    });
+
+    Ok(Extracted {
+        text: selected.join("\n"),
+        warning,
+    })
+}
+
+fn extract_docx(path: &Path) -> Result<String> {
+    let file = std::fs::File::open(path)
+        .wrap_err_with(|| format!("failed to open `{}`", path.display()))?;
+    let mut archive = zip::ZipArchive::new(file)
+        .wrap_err_with(|| format!("`{}` is not a valid DOCX file", path.display()))?;
+    let mut xml = String::new();
+    archive
+        .by_name(DOCX_BODY)
+        .wrap_err_with(|| format!("`{}` has no `{DOCX_BODY}`", path.display()))?
+        .read_to_string(&mut xml)
+        .wrap_err_with(|| format!("failed to read `{DOCX_BODY}` of `{}`", path.display()))?;
+
+    let mut reader = Reader::from_str(&xml);
+    let mut text = String::new();
+    let mut in_text = false;
+    loop {
+        match reader
+            .read_event()
+            .wrap_err_with(|| format!("failed to parse `{DOCX_BODY}` of `{}`", path.display()))?
+        {
+            Event::Start(e) if e.name().as_ref() == b"w:t" => in_text = true,
+            Event::End(e) if e.name().as_ref() == b"w:t" => in_text = false,
+            Event::End(e) if e.name().as_ref() == b"w:p" => text.push('\n'),
+            Event::Empty(e) if e.name().as_ref() == b"w:tab" => text.push('\t'),
+            Event::Empty(e) if e.name().as_ref() == b"w:br" => text.push('\n'),
+            Event::Text(e) if in_text => text.push_str(&e.decode()?),
+            Event::GeneralRef(e) if in_text => {
+                let entity = e.decode()?;
+                match quick_xml::escape::resolve_predefined_entity(&entity) {
+                    Some(resolved) => text.push_str(resolved),
+                    None => text.push_str(&format!("&{entity};")),
+                }
+            },
+            Event::Eof => break,
+            _ => {},
+        }
+    }
+
+    Ok(text)
+}
@@ -0,0 +1,392 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use color_eyre::eyre::{Context, Result};
+use reqwest::{Client, Url};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+
+use crate::conversation::{self, ChatMessage};
+use crate::encryption::Cipher;
+use crate::openai::{Length, OpenAI, Role};
+
+/// IRC servers disconnect clients that send lines faster than this
+const IRC_LINE_INTERVAL: Duration = Duration::from_millis(500);
+/// Leaves room for the `PRIVMSG <target> :` prefix within IRC's 512-byte line limit
+const IRC_MAX_MESSAGE_BYTES: usize = 400;
+const MATRIX_SYNC_TIMEOUT_MS: u64 = 30_000;
+
+/// The `[bridge]` section of the config
+#[derive(Clone, Debug, Deserialize)]
+#[serde(tag = "protocol", rename_all = "lowercase")]
+pub enum BridgeConfig {
+    Irc {
+        /// `host:port` of a plain-text IRC server
+        server: String,
+        nick: String,
+        channels: Vec<String>,
+        password: Option<String>,
+        history_dir: Option<PathBuf>,
+    },
+    Matrix {
+        homeserver: String,
+        access_token: String,
+        /// Room IDs or aliases to join
+        rooms: Vec<String>,
+        history_dir: Option<PathBuf>,
+    },
+}
+
+/// Answers mentions with a separate conversation per room, saved to `history_dir` if set
+pub struct Bridge {
+    openai: Arc<OpenAI>,
+    pins: Vec<ChatMessage>,
+    length: Length,
+    history_dir: Option<PathBuf>,
+    cipher: Option<Arc<Cipher>>,
+    client: Client,
+    rooms: HashMap<String, Vec<ChatMessage>>,
+}
+
+impl Bridge {
+    /// Talks to Matrix homeservers with `client`, sharing its connections
+    pub fn new(
+        openai: Arc<OpenAI>,
+        pins: Vec<ChatMessage>,
+        length: Length,
+        client: Client,
+    ) -> Self {
+        Self {
+            openai,
+            pins,
+            length,
+            history_dir: None,
+            cipher: None,
+            client,
+            rooms: HashMap::new(),
+        }
+    }
+
+    /// Encrypts the saved room histories
+    pub fn with_cipher(mut self, cipher: Option<Arc<Cipher>>) -> Self {
+        self.cipher = cipher;
+        self
+    }
+
+    pub async fn run(mut self, config: BridgeConfig) -> Result<()> {
+        match config {
+            BridgeConfig::Irc {
+                server,
+                nick,
+                channels,
+                password,
+                history_dir,
+            } => {
+                self.history_dir = history_dir;
+                self.run_irc(&server, &nick, &channels, password.as_deref())
+                    .await
+            },
+            BridgeConfig::Matrix {
+                homeserver,
+                access_token,
+                rooms,
+                history_dir,
+            } => {
+                self.history_dir = history_dir;
+                self.run_matrix(&homeserver, &access_token, &rooms).await
+            },
+        }
+    }
+
+    async fn answer(&mut self, room: &str, question: String) -> Result<String> {
+        let path = self.history_path(room);
+        if !self.rooms.contains_key(room) {
+            let history = match &path {
+                Some(path) => conversation::load(path, self.cipher.as_deref())?,
+                None => Vec::new(),
+            };
+            self.rooms.insert(room.to_owned(), history);
+        }
+
+        let history = self
+            .rooms
+            .get_mut(room)
+            .expect("room history was just loaded");
+        let context = self
+            .pins
+            .iter()
+            .chain(history.iter())
+            .cloned()
+            .collect::<Vec<_>>();
+        let completion = self
+            .openai
+            .q_and_a(question.clone(), &context, self.length)
+            .await?;
+        let answer = completion.content.clone().into_owned();
+
+        history.push(ChatMessage::new(Role::User, question));
+        history.push(ChatMessage::from_completion(completion));
+        if let Some(path) = &path {
+            conversation::save(path, history, self.cipher.as_deref())?;
+        }
+
+        Ok(answer)
+    }
+
+    fn history_path(&self, room: &str) -> Option<PathBuf> {
+        let file_name = room
+            .chars()
+            .map(|c| {
+                if c.is_alphanumeric() || c == '-' {
+                    c
+                } else {
+                    '_'
+                }
+            })
+            .collect::<String>();
+        self.history_dir
+            .as_ref()
+            .map(|dir| dir.join(format!("{file_name}.json")))
+    }
+
+    async fn run_irc(
+        &mut self,
+        server: &str,
+        nick: &str,
+        channels: &[String],
+        password: Option<&str>,
+    ) -> Result<()> {
+        let stream = TcpStream::connect(server)
+            .await
+            .wrap_err_with(|| format!("failed to connect to IRC server `{server}`"))?;
+        let (reader, mut writer) = stream.into_split();
+        let mut lines = BufReader::new(reader).lines();
+
+        if let Some(password) = password {
+            irc_send(&mut writer, &format!("PASS {password}")).await?;
+        }
+        irc_send(&mut writer, &format!("NICK {nick}")).await?;
+        irc_send(&mut writer, &format!("USER {nick} 0 * :{nick}")).await?;
+
+        while let Some(line) = lines.next_line().await? {
+            tracing::debug!("irc <- {line}");
+
+            if let Some(token) = line.strip_prefix("PING ") {
+                irc_send(&mut writer, &format!("PONG {token}")).await?;
+                continue;
+            }
+
+            let (prefix, rest) = match line.strip_prefix(':') {
+                Some(line) => line.split_once(' ').unwrap_or((line, "")),
+                None => ("", line.as_str()),
+            };
+            let (command, params) = rest.split_once(' ').unwrap_or((rest, ""));
+
+            match command {
+                // RPL_WELCOME: registration is complete
+                "001" => {
+                    for channel in channels {
+                        irc_send(&mut writer, &format!("JOIN {channel}")).await?;
+                    }
+                },
+                "PRIVMSG" => {
+                    let Some((target, text)) = params.split_once(" :") else {
+                        continue;
+                    };
+                    let Some(question) = strip_mention(text, nick) else {
+                        continue;
+                    };
+                    let sender = prefix.split('!').next().unwrap_or(prefix);
+                    // Direct messages are answered to the sender, in a conversation of their own
+                    let room = if target.eq_ignore_ascii_case(nick) {
+                        sender
+                    } else {
+                        target
+                    };
+
+                    let reply = match self.answer(room, question).await {
+                        Ok(answer) => answer,
+                        Err(err) => format!("error: {err:#}"),
+                    };
+                    for line in irc_split(&reply) {
+                        irc_send(&mut writer, &format!("PRIVMSG {room} :{line}")).await?;
+                        tokio::time::sleep(IRC_LINE_INTERVAL).await;
+                    }
+                },
+                _ => {},
+            }
+        }
+
+        color_eyre::eyre::bail!("IRC server `{server}` closed the connection");
+    }
+
+    async fn run_matrix(
+        &mut self,
+        homeserver: &str,
+        access_token: &str,
+        rooms: &[String],
+    ) -> Result<()> {
+        let matrix = Matrix {
+            cli: self.client.clone(),
+            homeserver: Url::parse(homeserver)
+                .wrap_err_with(|| format!("invalid homeserver URL `{homeserver}`"))?,
+            access_token: access_token.to_owned(),
+        };
+
+        let whoami = matrix
+            .request(reqwest::Method::GET, &["account", "whoami"], &[], None)
+            .await?;
+        let user_id = whoami["user_id"].as_str().unwrap_or_default().to_owned();
+        let localpart = user_id
+            .trim_start_matches('@')
+            .split(':')
+            .next()
+            .unwrap_or_default()
+            .to_owned();
+
+        for room in rooms {
+            matrix
+                .request(reqwest::Method::POST, &["join", room], &[], Some(json!({})))
+                .await
+                .wrap_err_with(|| format!("failed to join Matrix room `{room}`"))?;
+        }
+
+        // Skip the backlog so only mentions made from now on are answered
+        let mut since = matrix
+            .request(reqwest::Method::GET, &["sync"], &[("timeout", "0")], None)
+            .await?["next_batch"]
+            .as_str()
+            .unwrap_or_default()
+            .to_owned();
+
+        let mut txn = 0_u64;
+        loop {
+            let timeout = MATRIX_SYNC_TIMEOUT_MS.to_string();
+            let sync = matrix
+                .request(
+                    reqwest::Method::GET,
+                    &["sync"],
+                    &[("since", &since), ("timeout", &timeout)],
+                    None,
+                )
+                .await?;
+            since = sync["next_batch"].as_str().unwrap_or(&since).to_owned();
+
+            let Some(joined) = sync["rooms"]["join"].as_object() else {
+                continue;
+            };
+            for (room_id, room) in joined {
+                let events = room["timeline"]["events"].as_array().into_iter().flatten();
+                for event in events {
+                    if event["type"] != "m.room.message" || event["sender"] == user_id.as_str() {
+                        continue;
+                    }
+                    let body = event["content"]["body"].as_str().unwrap_or_default();
+                    let Some(question) = strip_mention(body, &localpart) else {
+                        continue;
+                    };
+
+                    let reply = match self.answer(room_id, question).await {
+                        Ok(answer) => answer,
+                        Err(err) => format!("error: {err:#}"),
+                    };
+
+                    txn += 1;
+                    let txn_id = format!("{}-{txn}", conversation::now());
+                    matrix
+                        .request(
+                            reqwest::Method::PUT,
+                            &["rooms", room_id, "send", "m.room.message", &txn_id],
+                            &[],
+                            Some(json!({ "msgtype": "m.text", "body": reply })),
+                        )
+                        .await?;
+                }
+            }
+        }
+    }
+}
+
+struct Matrix {
+    cli: Client,
+    homeserver: Url,
+    access_token: String,
+}
+
+impl Matrix {
+    async fn request(
+        &self,
+        method: reqwest::Method,
+        path: &[&str],
+        query: &[(&str, &str)],
+        body: Option<Value>,
+    ) -> Result<Value> {
+        let mut url = self.homeserver.clone();
+        url.path_segments_mut()
+            .map_err(|()| color_eyre::eyre::eyre!("homeserver URL cannot be a base"))?
+            .pop_if_empty()
+            .extend(["_matrix", "client", "v3"])
+            .extend(path);
+        url.query_pairs_mut().extend_pairs(query);
+
+        let mut req = self
+            .cli
+            .request(method, url)
+            .bearer_auth(&self.access_token);
+        if let Some(body) = body {
+            req = req.json(&body);
+        }
+
+        let resp = req.send().await?;
+        let status = resp.status();
+        let value = resp.json::<Value>().await?;
+        if !status.is_success() {
+            color_eyre::eyre::bail!("Matrix request failed with {status}: {value}");
+        }
+
+        Ok(value)
+    }
+}
+
+/// The question in `text` if it starts with or contains a mention of `name`
+fn strip_mention(text: &str, name: &str) -> Option<String> {
+    let lower = text.to_lowercase();
+    let name = name.to_lowercase();
+    let start = lower.find(&name)?;
+
+    let question = if start == 0 {
+        text[name.len()..].trim_start_matches([':', ',', ' '])
+    } else {
+        text
+    };
+
+    Some(question.trim().to_owned()).filter(|question| !question.is_empty())
+}
+
+/// Splits an answer into IRC messages, which cannot contain newlines
+fn irc_split(text: &str) -> Vec<String> {
+    let mut messages = Vec::new();
+
+    for line in text.lines().filter(|line| !line.trim().is_empty()) {
+        let mut message = String::new();
+        for c in line.chars() {
+            if message.len() + c.len_utf8() > IRC_MAX_MESSAGE_BYTES {
+                messages.push(std::mem::take(&mut message));
+            }
+            message.push(c);
+        }
+        messages.push(message);
+    }
+
+    messages
+}
+
+async fn irc_send(writer: &mut tokio::net::tcp::OwnedWriteHalf, line: &str) -> Result<()> {
+    tracing::debug!("irc -> {line}");
+    writer.write_all(format!("{line}\r\n").as_bytes()).await?;
+    Ok(())
+}
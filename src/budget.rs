@@ -0,0 +1,224 @@
+use std::fmt;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+use chrono::Local;
+use color_eyre::eyre::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::openai::Usage;
+use crate::pricing;
+
+const SPEND_FILE: &str = ".sermaid_spend.json";
+
+/// Ceilings on the estimated cost of a request and the actual cost of a day
+pub struct Budget {
+    max_per_request: Option<f64>,
+    max_per_day: Option<f64>,
+    spend_file: Option<PathBuf>,
+    spent: Mutex<DailySpend>,
+    approved: AtomicBool,
+    /// Whether failing to write the spend file was warned about
+    warned: AtomicBool,
+}
+
+/// Spending of the current local day, kept in `~/.sermaid_spend.json` across sessions
+#[derive(Default, Serialize, Deserialize)]
+struct DailySpend {
+    date: String,
+    usd: f64,
+}
+
+/// A request refused by [`Budget::check`] until approved with [`Budget::approve_next`]
+#[derive(Debug)]
+pub struct OverBudget {
+    pub estimate: f64,
+    pub reason: String,
+}
+
+impl fmt::Display for OverBudget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "estimated cost ${:.4} {}", self.estimate, self.reason)
+    }
+}
+
+impl std::error::Error for OverBudget {}
+
+impl Budget {
+    pub fn new(max_per_request: Option<f64>, max_per_day: Option<f64>) -> Result<Self> {
+        let spend_file = max_per_day
+            .and(home::home_dir())
+            .map(|home| home.join(SPEND_FILE));
+        let spent = match &spend_file {
+            Some(path) if path.exists() => serde_json::from_str(
+                &std::fs::read_to_string(path)
+                    .wrap_err_with(|| format!("failed to read `{}`", path.display()))?,
+            )
+            .wrap_err_with(|| format!("failed to parse `{}`", path.display()))?,
+            _ => DailySpend::default(),
+        };
+
+        Ok(Self {
+            max_per_request,
+            max_per_day,
+            spend_file,
+            spent: Mutex::new(spent),
+            approved: AtomicBool::new(false),
+            warned: AtomicBool::new(false),
+        })
+    }
+
+    /// Refuses a request estimated to cost more than a ceiling allows, unless approved
+    ///
+    /// Models missing from the price table cannot be estimated and are always allowed.
+    pub fn check(
+        &self,
+        model: &str,
+        prompt_tokens: u64,
+        completion_tokens: u64,
+    ) -> Result<(), OverBudget> {
+        let Some(estimate) = pricing::cost(model, prompt_tokens, completion_tokens) else {
+            return Ok(());
+        };
+        if self.approved.swap(false, Ordering::SeqCst) {
+            return Ok(());
+        }
+
+        if let Some(max) = self.max_per_request.filter(|max| estimate > *max) {
+            return Err(OverBudget {
+                estimate,
+                reason: format!("exceeds max_cost_per_request ${max:.4}"),
+            });
+        }
+
+        let spent = self.spent_today();
+        if let Some(max) = self.max_per_day.filter(|max| spent + estimate > *max) {
+            return Err(OverBudget {
+                estimate,
+                reason: format!("would bring today's ${spent:.4} over max_cost_per_day ${max:.4}"),
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Lets the next request through regardless of the ceilings
+    pub fn approve_next(&self) {
+        self.approved.store(true, Ordering::SeqCst);
+    }
+
+    /// Adds the actual cost of a finished request to today's spending
+    ///
+    /// Failing to write the spend file is warned about once and otherwise ignored, so that the
+    /// answer the request paid for is not lost.
+    pub fn record(&self, model: &str, usage: &Usage) {
+        let Some(cost) = pricing::usage_cost(model, usage) else {
+            return;
+        };
+
+        let mut spent = self.spent.lock().unwrap();
+        let today = today();
+        if spent.date != today {
+            *spent = DailySpend {
+                date: today,
+                usd: 0.0,
+            };
+        }
+        spent.usd += cost;
+
+        if let Some(path) = &self.spend_file {
+            let written = serde_json::to_string(&*spent)
+                .map_err(std::io::Error::from)
+                .and_then(|contents| std::fs::write(path, contents));
+            if let Err(err) = written {
+                if !self.warned.swap(true, Ordering::SeqCst) {
+                    println!(
+                        "warning: failed to write `{}`, today's spending is only tracked in \
+                         this session: {err}",
+                        path.display()
+                    );
+                }
+            }
+        }
+    }
+
+    /// USD spent today, across sessions when `max_cost_per_day` is set
+    pub fn spent_today(&self) -> f64 {
+        let spent = self.spent.lock().unwrap();
+        if spent.date == today() {
+            spent.usd
+        } else {
+            0.0
+        }
+    }
+}
+
+/// Sizes above which a prompt is only sent once approved, as attaching a whole vendored
+/// directory by mistake is easy
+pub struct PromptLimit {
+    max_tokens: Option<u64>,
+    max_bytes: Option<u64>,
+    approved: AtomicBool,
+}
+
+/// A prompt refused by [`PromptLimit::check`] until approved with [`PromptLimit::approve_next`]
+#[derive(Debug)]
+pub struct LargePrompt {
+    pub tokens: u64,
+    pub bytes: u64,
+    pub reason: String,
+}
+
+impl fmt::Display for LargePrompt {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "the prompt of {} tokens and {:.1} KB {}",
+            self.tokens,
+            self.bytes as f64 / 1000.0,
+            self.reason
+        )
+    }
+}
+
+impl std::error::Error for LargePrompt {}
+
+impl PromptLimit {
+    pub fn new(max_tokens: Option<u64>, max_bytes: Option<u64>) -> Self {
+        Self {
+            max_tokens,
+            max_bytes,
+            approved: AtomicBool::new(false),
+        }
+    }
+
+    /// Refuses a prompt bigger than a limit allows, unless approved
+    pub fn check(&self, tokens: u64, bytes: u64) -> Result<(), LargePrompt> {
+        let reason = if let Some(max) = self.max_tokens.filter(|max| tokens > *max) {
+            format!("exceeds max_prompt_tokens {max}")
+        } else if let Some(max) = self.max_bytes.filter(|max| bytes > *max) {
+            format!("exceeds max_prompt_bytes {max}")
+        } else {
+            return Ok(());
+        };
+        if self.approved.swap(false, Ordering::SeqCst) {
+            return Ok(());
+        }
+
+        Err(LargePrompt {
+            tokens,
+            bytes,
+            reason,
+        })
+    }
+
+    /// Lets the next prompt through regardless of its size
+    pub fn approve_next(&self) {
+        self.approved.store(true, Ordering::SeqCst);
+    }
+}
+
+fn today() -> String {
+    Local::now().format("%Y-%m-%d").to_string()
+}
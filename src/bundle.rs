@@ -0,0 +1,75 @@
+use std::path::{Path, PathBuf};
+
+use color_eyre::eyre::{Context, Result};
+
+/// Sections of the config shared by bundles: custom commands with their templates and aliases,
+/// the look and the key bindings, but nothing that holds keys, tokens or paths of this machine
+const SHARED: &[&str] = &[
+    "commands",
+    "theme",
+    "highlight_theme",
+    "editor",
+    "redaction_patterns",
+    "pr_template",
+    "explain_error",
+];
+const HEADER: &str = "# sermaid configuration bundle, added to a config with `config import`\n\n";
+
+/// Writes the shared sections of the config at `config` to `bundle`, and returns their names
+pub fn export(config: &Path, bundle: &Path) -> Result<Vec<String>> {
+    let shared = read(config)?
+        .into_iter()
+        .filter(|(key, _)| SHARED.contains(&key.as_str()))
+        .collect::<toml::Table>();
+    let content = toml::to_string_pretty(&shared)
+        .wrap_err_with(|| format!("failed to serialize `{}`", bundle.display()))?;
+    std::fs::write(bundle, format!("{HEADER}{content}"))
+        .wrap_err_with(|| format!("failed to write `{}`", bundle.display()))?;
+    Ok(shared.keys().cloned().collect())
+}
+
+/// Adds the sections of `bundle` to the config at `config`, replacing its entries of the same
+/// name, keeping the config as it was in `<config>.bak`, and returns their names
+///
+/// Bundles with other sections than those [`export`] writes are refused, so that importing one
+/// cannot change the keys or endpoints in use.
+pub fn import(config: &Path, bundle: &Path) -> Result<Vec<String>> {
+    let imported = read(bundle)?;
+    if let Some(key) = imported.keys().find(|key| !SHARED.contains(&key.as_str())) {
+        color_eyre::eyre::bail!(
+            "`{}` has a `{key}` section, bundles only share {}",
+            bundle.display(),
+            SHARED.join(", ")
+        );
+    }
+
+    let mut merged = read(config)?;
+    let keys = imported.keys().cloned().collect();
+    for (key, value) in imported {
+        match (merged.get_mut(&key), value) {
+            (Some(toml::Value::Table(section)), toml::Value::Table(entries)) => {
+                section.extend(entries);
+            },
+            (_, value) => {
+                merged.insert(key, value);
+            },
+        }
+    }
+
+    let mut backup = config.as_os_str().to_owned();
+    backup.push(".bak");
+    let backup = PathBuf::from(backup);
+    std::fs::copy(config, &backup)
+        .wrap_err_with(|| format!("failed to back up `{}`", config.display()))?;
+    let content = toml::to_string_pretty(&merged)
+        .wrap_err_with(|| format!("failed to serialize `{}`", config.display()))?;
+    std::fs::write(config, content)
+        .wrap_err_with(|| format!("failed to write `{}`", config.display()))?;
+    Ok(keys)
+}
+
+fn read(path: &Path) -> Result<toml::Table> {
+    let content = std::fs::read_to_string(path)
+        .wrap_err_with(|| format!("failed to read `{}`", path.display()))?;
+    toml::from_str(&content).wrap_err_with(|| format!("failed to parse `{}`", path.display()))
+}
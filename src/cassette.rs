@@ -0,0 +1,227 @@
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use color_eyre::eyre::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+const REDACTED: &str = "<REDACTED>";
+
+/// A recorded API exchange, stored with the API token redacted
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Interaction {
+    pub request: serde_json::Value,
+    pub status: u16,
+    pub response: String,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Tape {
+    interactions: Vec<Interaction>,
+}
+
+pub enum Cassette {
+    Record {
+        path: PathBuf,
+        secrets: Vec<String>,
+        tape: Mutex<Tape>,
+    },
+    Replay {
+        path: PathBuf,
+        interactions: Mutex<VecDeque<Interaction>>,
+    },
+}
+
+impl Cassette {
+    pub fn record(path: PathBuf, secrets: Vec<String>) -> Self {
+        Self::Record {
+            path,
+            secrets,
+            tape: Mutex::new(Tape::default()),
+        }
+    }
+
+    pub fn replay(path: PathBuf) -> Result<Self> {
+        let tape: Tape = serde_json::from_str(
+            &std::fs::read_to_string(&path)
+                .wrap_err_with(|| format!("failed to read cassette `{}`", path.display()))?,
+        )
+        .wrap_err_with(|| format!("failed to parse cassette `{}`", path.display()))?;
+
+        Ok(Self::Replay {
+            path,
+            interactions: Mutex::new(tape.interactions.into()),
+        })
+    }
+
+    /// Appends an exchange and rewrites the cassette, so a crash keeps what was recorded so far
+    pub fn push(&self, request: &serde_json::Value, status: u16, response: &str) -> Result<()> {
+        let Self::Record {
+            path,
+            secrets,
+            tape,
+        } = self
+        else {
+            return Ok(());
+        };
+
+        let redact = |s: &str| {
+            secrets
+                .iter()
+                .filter(|secret| !secret.is_empty())
+                .fold(s.to_owned(), |s, secret| {
+                    s.replace(secret.as_str(), REDACTED)
+                })
+        };
+
+        let mut tape = tape.lock().unwrap();
+        tape.interactions.push(Interaction {
+            request: serde_json::from_str(&redact(&request.to_string()))?,
+            status,
+            response: redact(response),
+        });
+
+        save(path, &tape)
+    }
+
+    /// Takes the next recorded exchange, failing if it was recorded for a different request
+    pub fn next(&self, request: &serde_json::Value) -> Result<Option<Interaction>> {
+        let Self::Replay { path, interactions } = self else {
+            return Ok(None);
+        };
+
+        let interaction = interactions.lock().unwrap().pop_front().ok_or_else(|| {
+            color_eyre::eyre::eyre!("cassette `{}` has no more interactions", path.display())
+        })?;
+
+        if &interaction.request != request {
+            color_eyre::eyre::bail!(
+                "request does not match the next interaction in cassette `{}`",
+                path.display()
+            );
+        }
+
+        Ok(Some(interaction))
+    }
+}
+
+fn save(path: &Path, tape: &Tape) -> Result<()> {
+    std::fs::write(path, serde_json::to_string_pretty(tape)?)
+        .wrap_err_with(|| format!("failed to write cassette `{}`", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    use axum::extract::State;
+    use axum::http::{header, HeaderMap, StatusCode};
+    use axum::routing::post;
+    use serde_json::json;
+
+    use super::*;
+    use crate::openai::{Length, OpenAI, OpenAIError};
+
+    const KEY: &str = "sk-test-0123456789abcdef";
+
+    /// Answers 429 to the first request and an answer to the next, quoting the bearer token in
+    /// the error as some gateways do
+    async fn chat_completions(
+        State(requests): State<Arc<AtomicUsize>>,
+        headers: HeaderMap,
+    ) -> (StatusCode, String) {
+        let auth = headers
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or_default();
+        if requests.fetch_add(1, Ordering::Relaxed) == 0 {
+            let error = json!({ "error": { "message": format!("slow down, `{auth}`") } });
+            return (StatusCode::TOO_MANY_REQUESTS, error.to_string());
+        }
+        let answer = json!({
+            "choices": [{
+                "message": { "role": "assistant", "content": "hello back" },
+                "finish_reason": "stop",
+            }],
+        });
+        (StatusCode::OK, answer.to_string())
+    }
+
+    /// Records a rate-limited question and its successful retry to `path`
+    async fn record(path: &Path) {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let endpoint = format!("http://{}", listener.local_addr().unwrap());
+        let app = axum::Router::new()
+            .route("/chat/completions", post(chat_completions))
+            .with_state(Arc::new(AtomicUsize::new(0)));
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let openai = OpenAI::new(vec![KEY.to_owned()], reqwest::Client::new())
+            .with_endpoint(Some(endpoint))
+            .with_record(path.to_owned());
+        assert!(openai.q_and_a("hello", &[], Length::Terse).await.is_err());
+        let completion = openai.q_and_a("hello", &[], Length::Terse).await.unwrap();
+        assert_eq!(completion.content, "hello back");
+    }
+
+    #[test]
+    fn replays_what_was_recorded_in_order() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("cassette.json");
+        let first = json!({ "messages": ["first"] });
+        let second = json!({ "messages": ["second"] });
+
+        let cassette = Cassette::record(path.clone(), Vec::new());
+        cassette.push(&first, 200, "one").unwrap();
+        cassette.push(&second, 200, "two").unwrap();
+
+        let cassette = Cassette::replay(path.clone()).unwrap();
+        assert_eq!(cassette.next(&first).unwrap().unwrap().response, "one");
+        assert_eq!(cassette.next(&second).unwrap().unwrap().response, "two");
+        assert!(cassette.next(&first).is_err());
+
+        let cassette = Cassette::replay(path).unwrap();
+        assert!(cassette.next(&second).is_err());
+    }
+
+    #[tokio::test]
+    async fn recording_keeps_no_key() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("cassette.json");
+        record(&path).await;
+
+        let recorded = std::fs::read_to_string(&path).unwrap();
+        assert!(!recorded.contains(KEY), "{recorded}");
+        assert!(!recorded.contains("sk-"), "{recorded}");
+        assert!(
+            !recorded.to_lowercase().contains("authorization"),
+            "{recorded}"
+        );
+        assert!(recorded.contains(REDACTED), "{recorded}");
+    }
+
+    #[tokio::test]
+    async fn replays_rate_limit_then_success() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("cassette.json");
+        record(&path).await;
+
+        let openai = OpenAI::new(Vec::new(), reqwest::Client::new())
+            .with_replay(path)
+            .unwrap();
+        let err = openai
+            .q_and_a("hello", &[], Length::Terse)
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(
+                err.downcast_ref::<OpenAIError>(),
+                Some(OpenAIError::RateLimit { .. })
+            ),
+            "{err:?}"
+        );
+        let completion = openai.q_and_a("hello", &[], Length::Terse).await.unwrap();
+        assert_eq!(completion.content, "hello back");
+    }
+}
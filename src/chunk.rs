@@ -0,0 +1,84 @@
+/// Splits `text` into chunks of at most `max_chars` characters on paragraph boundaries,
+/// never inside a fenced code block unless the block alone is too long
+pub fn split_paragraphs(text: &str, max_chars: usize) -> Vec<String> {
+    let mut chunks = Vec::new();
+    let mut chunk = String::new();
+
+    for paragraph in paragraphs(text) {
+        if !chunk.is_empty() && char_len(&chunk) + char_len(&paragraph) + 2 > max_chars {
+            chunks.push(std::mem::take(&mut chunk));
+        }
+
+        if char_len(&paragraph) > max_chars {
+            chunks.extend(split_lines(&paragraph, max_chars));
+            continue;
+        }
+
+        if !chunk.is_empty() {
+            chunk.push_str("\n\n");
+        }
+        chunk.push_str(&paragraph);
+    }
+
+    if !chunk.is_empty() {
+        chunks.push(chunk);
+    }
+
+    chunks
+}
+
+/// Paragraphs separated by blank lines, keeping fenced code blocks whole
+fn paragraphs(text: &str) -> Vec<String> {
+    let mut paragraphs = Vec::new();
+    let mut paragraph = String::new();
+    let mut in_fence = false;
+
+    for line in text.lines() {
+        if line.trim_start().starts_with("```") {
+            in_fence = !in_fence;
+        }
+
+        if line.trim().is_empty() && !in_fence {
+            if !paragraph.is_empty() {
+                paragraphs.push(std::mem::take(&mut paragraph));
+            }
+            continue;
+        }
+
+        if !paragraph.is_empty() {
+            paragraph.push('\n');
+        }
+        paragraph.push_str(line);
+    }
+
+    if !paragraph.is_empty() {
+        paragraphs.push(paragraph);
+    }
+
+    paragraphs
+}
+
+fn split_lines(paragraph: &str, max_chars: usize) -> Vec<String> {
+    let mut chunks = Vec::new();
+    let mut chunk = String::new();
+
+    for line in paragraph.lines() {
+        if !chunk.is_empty() && char_len(&chunk) + char_len(line) + 1 > max_chars {
+            chunks.push(std::mem::take(&mut chunk));
+        }
+        if !chunk.is_empty() {
+            chunk.push('\n');
+        }
+        chunk.push_str(line);
+    }
+
+    if !chunk.is_empty() {
+        chunks.push(chunk);
+    }
+
+    chunks
+}
+
+fn char_len(s: &str) -> usize {
+    s.chars().count()
+}
@@ -0,0 +1,130 @@
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+use color_eyre::eyre::{Context, Result};
+
+/// Copy commands tried in order, covering macOS, Wayland, X11 and Windows
+const COPY_COMMANDS: &[&[&str]] = &[
+    &["pbcopy"],
+    &["wl-copy"],
+    &["xclip", "-selection", "clipboard"],
+    &["xsel", "--clipboard", "--input"],
+    &["clip.exe"],
+];
+
+/// Paste commands matching [`COPY_COMMANDS`]
+const PASTE_COMMANDS: &[&[&str]] = &[
+    &["pbpaste"],
+    &["wl-paste", "--no-newline"],
+    &["xclip", "-selection", "clipboard", "-out"],
+    &["xsel", "--clipboard", "--output"],
+    &["powershell.exe", "-NoProfile", "-Command", "Get-Clipboard"],
+];
+
+/// Commands printing the image in the clipboard as PNG, covering macOS, Wayland and X11
+const PASTE_IMAGE_COMMANDS: &[&[&str]] = &[
+    &["pngpaste", "-"],
+    &["wl-paste", "--no-newline", "--type", "image/png"],
+    &[
+        "xclip",
+        "-selection",
+        "clipboard",
+        "-target",
+        "image/png",
+        "-out",
+    ],
+];
+const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+pub fn copy(text: &str) -> Result<()> {
+    for command in COPY_COMMANDS {
+        let Ok(mut child) = Command::new(command[0])
+            .args(&command[1..])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+        else {
+            continue;
+        };
+
+        child
+            .stdin
+            .take()
+            .ok_or_else(|| color_eyre::eyre::eyre!("failed to open stdin of `{}`", command[0]))?
+            .write_all(text.as_bytes())
+            .wrap_err_with(|| format!("failed to write to `{}`", command[0]))?;
+
+        if child.wait()?.success() {
+            return Ok(());
+        }
+    }
+
+    color_eyre::eyre::bail!(
+        "no clipboard tool found, install one of: {}",
+        COPY_COMMANDS
+            .iter()
+            .map(|command| command[0])
+            .collect::<Vec<_>>()
+            .join(", ")
+    );
+}
+
+pub fn paste() -> Result<String> {
+    for command in PASTE_COMMANDS {
+        let Ok(output) = Command::new(command[0])
+            .args(&command[1..])
+            .stdin(Stdio::null())
+            .stderr(Stdio::null())
+            .output()
+        else {
+            continue;
+        };
+
+        if output.status.success() {
+            return String::from_utf8(output.stdout)
+                .wrap_err_with(|| format!("`{}` returned non-UTF-8 text", command[0]));
+        }
+    }
+
+    color_eyre::eyre::bail!(
+        "no clipboard tool found, install one of: {}",
+        PASTE_COMMANDS
+            .iter()
+            .map(|command| command[0])
+            .collect::<Vec<_>>()
+            .join(", ")
+    );
+}
+
+/// The image in the clipboard, as PNG
+pub fn paste_image() -> Result<Vec<u8>> {
+    let mut found = false;
+    for command in PASTE_IMAGE_COMMANDS {
+        let Ok(output) = Command::new(command[0])
+            .args(&command[1..])
+            .stdin(Stdio::null())
+            .stderr(Stdio::null())
+            .output()
+        else {
+            continue;
+        };
+
+        found = true;
+        if output.status.success() && output.stdout.starts_with(PNG_SIGNATURE) {
+            return Ok(output.stdout);
+        }
+    }
+
+    if found {
+        color_eyre::eyre::bail!("no image in the clipboard");
+    }
+    color_eyre::eyre::bail!(
+        "no clipboard tool for images found, install one of: {}",
+        PASTE_IMAGE_COMMANDS
+            .iter()
+            .map(|command| command[0])
+            .collect::<Vec<_>>()
+            .join(", ")
+    );
+}
@@ -0,0 +1,219 @@
+use std::path::Path;
+
+use tree_sitter::{Language, Node, Parser};
+
+/// Files in languages without a grammar are cut into windows of this many lines
+const WINDOW_LINES: usize = 60;
+/// Containers such as impl blocks and classes longer than this are split into their members
+const MAX_CONTAINER_LINES: usize = 80;
+
+struct Grammar {
+    extensions: &'static [&'static str],
+    language: fn() -> Language,
+    /// Node kinds that start a chunk of their own
+    items: &'static [&'static str],
+    /// Item kinds whose members become chunks of their own when the item is long
+    containers: &'static [&'static str],
+}
+
+const GRAMMARS: &[Grammar] = &[
+    Grammar {
+        extensions: &["rs"],
+        language: || tree_sitter_rust::LANGUAGE.into(),
+        items: &[
+            "function_item",
+            "function_signature_item",
+            "struct_item",
+            "enum_item",
+            "union_item",
+            "trait_item",
+            "impl_item",
+            "mod_item",
+            "macro_definition",
+            "const_item",
+            "static_item",
+            "type_item",
+        ],
+        containers: &["impl_item", "trait_item", "mod_item"],
+    },
+    Grammar {
+        extensions: &["py"],
+        language: || tree_sitter_python::LANGUAGE.into(),
+        items: &[
+            "function_definition",
+            "class_definition",
+            "decorated_definition",
+        ],
+        containers: &["class_definition"],
+    },
+    Grammar {
+        extensions: &["js", "mjs", "cjs", "jsx"],
+        language: || tree_sitter_javascript::LANGUAGE.into(),
+        items: &[
+            "function_declaration",
+            "generator_function_declaration",
+            "class_declaration",
+            "method_definition",
+            "lexical_declaration",
+            "variable_declaration",
+            "export_statement",
+        ],
+        containers: &["class_declaration"],
+    },
+    Grammar {
+        extensions: &["go"],
+        language: || tree_sitter_go::LANGUAGE.into(),
+        items: &[
+            "function_declaration",
+            "method_declaration",
+            "type_declaration",
+            "const_declaration",
+            "var_declaration",
+        ],
+        containers: &[],
+    },
+];
+
+/// A piece of a source file, along the boundaries of the definitions in it where possible
+pub struct CodeChunk {
+    /// Names of what the chunk defines, `Type::method` for members
+    pub symbols: Vec<String>,
+    /// 1-based, inclusive
+    pub start_line: usize,
+    pub end_line: usize,
+    pub text: String,
+}
+
+/// Splits `content` into chunks of whole functions, types and other items, falling back to
+/// fixed windows of lines for languages without a grammar
+pub fn split(path: &Path, content: &str) -> Vec<CodeChunk> {
+    let extension = path
+        .extension()
+        .map(|extension| extension.to_string_lossy())
+        .unwrap_or_default();
+    GRAMMARS
+        .iter()
+        .find(|grammar| grammar.extensions.contains(&extension.as_ref()))
+        .and_then(|grammar| split_items(grammar, content))
+        .unwrap_or_else(|| split_windows(content))
+}
+
+fn split_items(grammar: &Grammar, content: &str) -> Option<Vec<CodeChunk>> {
+    let mut parser = Parser::new();
+    parser.set_language(&(grammar.language)()).ok()?;
+    let tree = parser.parse(content, None)?;
+
+    // Byte offsets where chunks start, with the symbol each chunk defines
+    let mut starts = vec![(0, None)];
+    let root = tree.root_node();
+    let mut cursor = root.walk();
+    for node in root.named_children(&mut cursor) {
+        let Some(item) = item(grammar, node) else {
+            continue;
+        };
+        let name = symbol(item, content);
+        starts.push((leading_start(node), name.clone()));
+
+        let lines = node.end_position().row - node.start_position().row + 1;
+        if lines <= MAX_CONTAINER_LINES || !grammar.containers.contains(&item.kind()) {
+            continue;
+        }
+        let Some(body) = item.child_by_field_name("body") else {
+            continue;
+        };
+        let mut cursor = body.walk();
+        for member in body.named_children(&mut cursor) {
+            let Some(member_item) = self::item(grammar, member).or_else(|| {
+                matches!(member.kind(), "function_definition" | "function_item").then_some(member)
+            }) else {
+                continue;
+            };
+            let member_name = symbol(member_item, content).map(|member_name| match &name {
+                Some(name) => format!("{name}::{member_name}"),
+                None => member_name,
+            });
+            starts.push((leading_start(member), member_name));
+        }
+    }
+
+    let mut chunks = Vec::new();
+    for (i, (start, symbol)) in starts.iter().enumerate() {
+        let end = starts.get(i + 1).map_or(content.len(), |(end, _)| *end);
+        let text = &content[*start..end];
+        if text.trim().is_empty() {
+            continue;
+        }
+
+        let first_line = content[..*start].matches('\n').count() + 1;
+        chunks.push(CodeChunk {
+            symbols: symbol.iter().cloned().collect(),
+            start_line: first_line,
+            end_line: first_line + text.trim_end().matches('\n').count(),
+            text: text.trim_end().to_owned(),
+        });
+    }
+    Some(chunks)
+}
+
+/// The item `node` is or wraps, such as the function of a decorated definition
+fn item<'a>(grammar: &Grammar, node: Node<'a>) -> Option<Node<'a>> {
+    if !grammar.items.contains(&node.kind()) {
+        return None;
+    }
+    Some(
+        ["definition", "declaration"]
+            .into_iter()
+            .find_map(|field| node.child_by_field_name(field))
+            .unwrap_or(node),
+    )
+}
+
+/// Name of the item `node`, looking one level down for declarations like `const f = ...`
+fn symbol(node: Node, content: &str) -> Option<String> {
+    let text = |node: Node| node.utf8_text(content.as_bytes()).ok().map(str::to_owned);
+    if let Some(name) = ["name", "type"]
+        .into_iter()
+        .find_map(|field| node.child_by_field_name(field))
+    {
+        return text(name);
+    }
+
+    let mut cursor = node.walk();
+    let name = node
+        .named_children(&mut cursor)
+        .find_map(|child| child.child_by_field_name("name"));
+    name.and_then(text)
+}
+
+/// Start of `node` including the comments and attributes right above it
+fn leading_start(node: Node) -> usize {
+    let mut start = node;
+    while let Some(previous) = start.prev_named_sibling() {
+        let attached = previous.end_position().row + 1 >= start.start_position().row;
+        let kind = previous.kind();
+        if !attached || !(kind.contains("comment") || kind.contains("attribute")) {
+            break;
+        }
+        start = previous;
+    }
+
+    // Whole lines, so the indentation of the first line stays with its chunk
+    start.start_byte() - start.start_position().column
+}
+
+fn split_windows(content: &str) -> Vec<CodeChunk> {
+    let lines = content.lines().collect::<Vec<_>>();
+    lines
+        .chunks(WINDOW_LINES)
+        .enumerate()
+        .map(|(i, window)| {
+            let first_line = i * WINDOW_LINES + 1;
+            CodeChunk {
+                symbols: Vec::new(),
+                start_line: first_line,
+                end_line: first_line + window.len() - 1,
+                text: window.join("\n"),
+            }
+        })
+        .collect()
+}
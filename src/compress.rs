@@ -0,0 +1,160 @@
+use regex::Regex;
+use serde::Deserialize;
+
+/// The `[compression]` section of the config, compressing long prompts before they are sent
+#[derive(Clone, Deserialize)]
+#[serde(default)]
+pub struct CompressionConfig {
+    /// Compress prompts of more tokens than this, pinned context included
+    pub above_tokens: usize,
+    /// Also drop filler words like "basically" and "just" from prose, outside code
+    pub trim_fillers: bool,
+}
+
+impl Default for CompressionConfig {
+    fn default() -> Self {
+        Self {
+            above_tokens: 4000,
+            trim_fillers: true,
+        }
+    }
+}
+
+/// `text` with comments and blank lines stripped from fenced code, and runs of blank lines and
+/// spaces collapsed in prose, or with filler words dropped too if `trim_fillers`
+///
+/// Only comments taking whole lines are stripped, as finding the end of code on a line would
+/// need to parse its strings.
+pub fn compress(text: &str, trim_fillers: bool) -> String {
+    let spaces = Regex::new(r"(\S) {2,}").unwrap();
+    let fillers = Regex::new(
+        r"(?i)\b(?:please|kindly|basically|actually|really|very|just|quite|simply|literally|essentially|certainly|definitely) ",
+    )
+    .unwrap();
+
+    let mut compressed = String::with_capacity(text.len());
+    // The comment syntax of the code block the line is in, if any
+    let mut code: Option<Comments> = None;
+    let mut in_block_comment = false;
+    let mut blank = false;
+    for line in text.lines() {
+        let line = line.trim_end();
+        let trimmed = line.trim_start();
+        if let Some(fence) = trimmed.strip_prefix("```") {
+            code = match code {
+                Some(_) => None,
+                None => Some(Comments::of(fence.trim())),
+            };
+            in_block_comment = false;
+            blank = false;
+            compressed.push_str(line);
+            compressed.push('\n');
+            continue;
+        }
+
+        match code {
+            Some(comments) => {
+                if in_block_comment {
+                    in_block_comment = !trimmed.contains("*/");
+                    if in_block_comment || trimmed.ends_with("*/") {
+                        continue;
+                    }
+                } else if comments.block && trimmed.starts_with("/*") {
+                    match trimmed.find("*/") {
+                        Some(end) if end + 2 == trimmed.len() => continue,
+                        Some(_) => {},
+                        None => {
+                            in_block_comment = true;
+                            continue;
+                        },
+                    }
+                }
+                let is_comment = comments
+                    .line
+                    .is_some_and(|marker| trimmed.starts_with(marker)) &&
+                    !trimmed.starts_with("#!");
+                if trimmed.is_empty() || is_comment {
+                    continue;
+                }
+                compressed.push_str(line);
+            },
+            None => {
+                if trimmed.is_empty() {
+                    // Keeps one blank line between paragraphs
+                    if !blank && !compressed.is_empty() {
+                        compressed.push('\n');
+                    }
+                    blank = true;
+                    continue;
+                }
+                blank = false;
+                let indent = &line[..line.len() - trimmed.len()];
+                let mut prose = spaces.replace_all(trimmed, "$1 ").into_owned();
+                if trim_fillers {
+                    prose = outside_inline_code(&prose, |text| {
+                        fillers.replace_all(text, "").replace("in order to ", "to ")
+                    });
+                }
+                compressed.push_str(indent);
+                compressed.push_str(&prose);
+            },
+        }
+        compressed.push('\n');
+    }
+    if !text.ends_with('\n') {
+        compressed.pop();
+    }
+    compressed
+}
+
+/// `text` with `f` applied to the parts outside of `inline code`
+fn outside_inline_code(text: &str, f: impl Fn(&str) -> String) -> String {
+    text.split('`')
+        .enumerate()
+        .map(|(i, part)| if i % 2 == 0 { f(part) } else { part.to_owned() })
+        .collect::<Vec<_>>()
+        .join("`")
+}
+
+/// How comments start in the language of a code block
+#[derive(Clone, Copy)]
+struct Comments {
+    line: Option<&'static str>,
+    /// Whether `/* */` are comments
+    block: bool,
+}
+
+impl Comments {
+    /// Comments of the language named by the info string of a fence, like `rs` or `python`
+    fn of(info: &str) -> Self {
+        let language = info.split_whitespace().next().unwrap_or_default();
+        match language.to_lowercase().as_str() {
+            "rs" | "rust" | "c" | "h" | "cc" | "cpp" | "hpp" | "cxx" | "java" | "js" | "jsx" |
+            "mjs" | "ts" | "tsx" | "go" | "kt" | "kts" | "swift" | "cs" | "scala" | "dart" |
+            "zig" | "php" => Self {
+                line: Some("//"),
+                block: true,
+            },
+            "py" | "python" | "sh" | "bash" | "zsh" | "fish" | "rb" | "ruby" | "pl" | "perl" |
+            "toml" | "yaml" | "yml" | "r" | "nix" | "cmake" | "mk" | "makefile" | "dockerfile" => {
+                Self {
+                    line: Some("#"),
+                    block: false,
+                }
+            },
+            "sql" | "lua" | "hs" | "haskell" => Self {
+                line: Some("--"),
+                block: false,
+            },
+            "css" | "scss" => Self {
+                line: None,
+                block: true,
+            },
+            // Comments of unknown languages are kept, only blank lines go
+            _ => Self {
+                line: None,
+                block: false,
+            },
+        }
+    }
+}
@@ -0,0 +1,64 @@
+use crate::openai::TokenLogprob;
+
+/// Probability under which a token is painted as doubtful
+const DOUBTFUL: f64 = 0.5;
+/// Probability under which a token is painted as unlikely
+const UNLIKELY: f64 = 0.2;
+const YELLOW: &str = "\x1b[33m";
+const RED: &str = "\x1b[31m";
+const RESET: &str = "\x1b[0m";
+
+/// The answer made of `tokens`, painting doubtful tokens yellow and unlikely ones red
+pub fn render(tokens: &[TokenLogprob], color: bool) -> String {
+    tokens
+        .iter()
+        .map(|token| {
+            let probability = token.logprob.exp();
+            let paint = if !color || token.token.trim().is_empty() {
+                None
+            } else if probability < UNLIKELY {
+                Some(RED)
+            } else if probability < DOUBTFUL {
+                Some(YELLOW)
+            } else {
+                None
+            };
+            match paint {
+                Some(paint) => format!("{paint}{}{RESET}", token.token),
+                None => token.token.clone(),
+            }
+        })
+        .collect()
+}
+
+/// The `n` least likely tokens under [`DOUBTFUL`], each with the likeliest tokens the model
+/// weighed in its place, like `"Lyon" 18%, instead of "Paris" 64%`
+pub fn least_likely(tokens: &[TokenLogprob], n: usize) -> Vec<String> {
+    let mut doubtful = tokens
+        .iter()
+        .filter(|token| !token.token.trim().is_empty() && token.logprob.exp() < DOUBTFUL)
+        .collect::<Vec<_>>();
+    doubtful.sort_by(|a, b| a.logprob.total_cmp(&b.logprob));
+
+    doubtful
+        .into_iter()
+        .take(n)
+        .map(|token| {
+            let mut line = format!("{:?} {}", token.token.trim(), percent(token.logprob));
+            let others = token
+                .top_logprobs
+                .iter()
+                .filter(|other| other.token != token.token)
+                .map(|other| format!("{:?} {}", other.token.trim(), percent(other.logprob)))
+                .collect::<Vec<_>>();
+            if !others.is_empty() {
+                line.push_str(&format!(", instead of {}", others.join(", ")));
+            }
+            line
+        })
+        .collect()
+}
+
+fn percent(logprob: f64) -> String {
+    format!("{:.0}%", logprob.exp() * 100.0)
+}
@@ -0,0 +1,260 @@
+use std::borrow::Cow;
+use std::io::ErrorKind;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use chrono::{DateTime, Local};
+use color_eyre::eyre::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::encryption::{self, Cipher};
+use crate::openai::{Completion, Role, Usage};
+
+/// A single turn of the conversation, in the order it was sent or received
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ChatMessage {
+    pub role: Role,
+    pub content: Cow<'static, str>,
+    /// Seconds since the Unix epoch
+    pub timestamp: u64,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub usage: Option<Usage>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub finish_reason: Option<String>,
+
+    /// The REPL command, custom command or plugin that asked a question
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub command: Option<String>,
+
+    /// The user's verdict on an answer, set with `good` and `bad`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rating: Option<Rating>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "vote", rename_all = "lowercase")]
+pub enum Rating {
+    Good,
+    Bad {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        reason: Option<String>,
+    },
+}
+
+impl ChatMessage {
+    pub fn new<S>(role: Role, content: S) -> Self
+    where
+        S: Into<Cow<'static, str>>,
+    {
+        Self {
+            role,
+            content: content.into(),
+            timestamp: now(),
+            model: None,
+            usage: None,
+            finish_reason: None,
+            command: None,
+            rating: None,
+        }
+    }
+
+    pub fn from_completion(completion: Completion) -> Self {
+        Self {
+            model: Some(completion.model),
+            usage: completion.usage,
+            finish_reason: completion.finish_reason,
+            ..Self::new(Role::Assistant, completion.content)
+        }
+    }
+
+    /// Formats the timestamp in the local timezone
+    pub fn local_time(&self) -> String {
+        local_time(self.timestamp)
+    }
+}
+
+/// A session file, the bare conversation unless it was tagged
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Session {
+    Plain(Vec<ChatMessage>),
+    Tagged {
+        tags: Vec<String>,
+        messages: Vec<ChatMessage>,
+    },
+}
+
+/// Formats seconds since the Unix epoch in the local timezone
+pub fn local_time(timestamp: u64) -> String {
+    i64::try_from(timestamp)
+        .ok()
+        .and_then(|timestamp| DateTime::from_timestamp(timestamp, 0))
+        .map_or_else(
+            || timestamp.to_string(),
+            |time| {
+                time.with_timezone(&Local)
+                    .format("%Y-%m-%d %H:%M:%S")
+                    .to_string()
+            },
+        )
+}
+
+/// Writes a conversation as pretty-printed JSON, encrypted if a `cipher` is given
+pub fn save(path: &Path, history: &[ChatMessage], cipher: Option<&Cipher>) -> Result<()> {
+    let contents =
+        serde_json::to_vec_pretty(history).wrap_err_with(|| "failed to serialize conversation")?;
+    write(path, contents, cipher)
+}
+
+/// Writes a conversation saved as a session, with its `tags` if it has any, in the format
+/// [`load_session`] reads
+pub fn save_session(
+    path: &Path,
+    history: &[ChatMessage],
+    tags: &[String],
+    cipher: Option<&Cipher>,
+) -> Result<()> {
+    if tags.is_empty() {
+        return save(path, history, cipher);
+    }
+    let session = serde_json::json!({ "tags": tags, "messages": history });
+    let contents =
+        serde_json::to_vec_pretty(&session).wrap_err_with(|| "failed to serialize conversation")?;
+    write(path, contents, cipher)
+}
+
+/// Writes each exchange as a line of the JSONL chat fine-tuning format, under its system prompt
+pub fn save_fine_tuning(
+    path: &Path,
+    exchanges: &[(String, &ChatMessage, &ChatMessage)],
+    cipher: Option<&Cipher>,
+) -> Result<()> {
+    let mut contents = Vec::new();
+    for (system, question, answer) in exchanges {
+        let example = serde_json::json!({
+            "messages": [
+                { "role": Role::System.as_str(), "content": system },
+                { "role": Role::User.as_str(), "content": question.content },
+                { "role": Role::Assistant.as_str(), "content": answer.content },
+            ],
+        });
+        serde_json::to_writer(&mut contents, &example)
+            .wrap_err_with(|| "failed to serialize fine-tuning example")?;
+        contents.push(b'\n');
+    }
+    write(path, contents, cipher)
+}
+
+/// Writes a conversation as [`markdown`]
+pub fn save_markdown(
+    path: &Path,
+    history: &[ChatMessage],
+    title: Option<&str>,
+    cipher: Option<&Cipher>,
+) -> Result<()> {
+    write(path, markdown(history, title).into_bytes(), cipher)
+}
+
+/// A conversation as markdown under `title`, each turn headed by who said it and when
+pub fn markdown(history: &[ChatMessage], title: Option<&str>) -> String {
+    let mut markdown = format!("# {}\n\n", title.unwrap_or("Conversation"));
+    for message in history {
+        let who = match (message.role, &message.command, &message.model) {
+            (Role::User, Some(command), _) => format!("You (`{command}`)"),
+            (Role::Assistant, _, Some(model)) => format!("Assistant ({model})"),
+            (Role::User, ..) => "You".to_owned(),
+            (Role::Assistant, ..) => "Assistant".to_owned(),
+            (role, ..) => role.as_str().to_owned(),
+        };
+        markdown.push_str(&format!(
+            "## {who} · {}\n\n{}\n\n",
+            message.local_time(),
+            message.content.trim()
+        ));
+    }
+    markdown
+}
+
+fn write(path: &Path, mut contents: Vec<u8>, cipher: Option<&Cipher>) -> Result<()> {
+    if let Some(cipher) = cipher {
+        contents = cipher.encrypt(&contents)?;
+    }
+    std::fs::write(path, contents)
+        .wrap_err_with(|| format!("failed to save conversation to `{}`", path.display()))
+}
+
+/// Reads a conversation written by [`save`], or an empty one if `path` does not exist
+pub fn load(path: &Path, cipher: Option<&Cipher>) -> Result<Vec<ChatMessage>> {
+    load_session(path, cipher).map(|(history, _)| history)
+}
+
+/// Reads a conversation and its tags written by [`save_session`] or [`save`], or an empty one
+/// if `path` does not exist
+pub fn load_session(
+    path: &Path,
+    cipher: Option<&Cipher>,
+) -> Result<(Vec<ChatMessage>, Vec<String>)> {
+    let mut contents = match std::fs::read(path) {
+        Ok(contents) => contents,
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok(Default::default()),
+        Err(err) => {
+            return Err(err)
+                .wrap_err_with(|| format!("failed to read conversation from `{}`", path.display()))
+        },
+    };
+
+    if encryption::is_encrypted(&contents) {
+        let cipher = cipher.ok_or_else(|| {
+            color_eyre::eyre::eyre!(
+                "`{}` is encrypted but no `[encryption]` is configured",
+                path.display()
+            )
+        })?;
+        contents = cipher
+            .decrypt(&contents)
+            .wrap_err_with(|| format!("failed to decrypt `{}`", path.display()))?;
+    }
+
+    let session: Session = serde_json::from_slice(&contents)
+        .wrap_err_with(|| format!("failed to parse conversation `{}`", path.display()))?;
+    Ok(match session {
+        Session::Plain(history) => (history, Vec::new()),
+        Session::Tagged { tags, messages } => (messages, tags),
+    })
+}
+
+/// Merges two conversations, interleaving their turns by time or putting `b` after `a`
+///
+/// A turn is a question with everything up to the next question, so answers stay with the
+/// question they answer.
+pub fn merge(a: Vec<ChatMessage>, b: Vec<ChatMessage>, interleave: bool) -> Vec<ChatMessage> {
+    let mut turns = split_turns(a);
+    turns.extend(split_turns(b));
+    if interleave {
+        // Stable, so turns asked in the same second keep their order
+        turns.sort_by_key(|turn| turn[0].timestamp);
+    }
+    turns.into_iter().flatten().collect()
+}
+
+fn split_turns(history: Vec<ChatMessage>) -> Vec<Vec<ChatMessage>> {
+    let mut turns = Vec::<Vec<ChatMessage>>::new();
+    for message in history {
+        match turns.last_mut() {
+            Some(turn) if message.role != Role::User => turn.push(message),
+            _ => turns.push(vec![message]),
+        }
+    }
+    turns
+}
+
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs())
+}
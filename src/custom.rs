@@ -0,0 +1,122 @@
+use std::collections::BTreeMap;
+
+use clap::{Arg, ArgAction};
+use serde::Deserialize;
+
+const INPUT_ARG: &str = "input";
+const VAR_ARG: &str = "var";
+const INPUT_PLACEHOLDER: &str = "{input}";
+
+/// A REPL command defined in the `[commands.<name>]` section of the config
+#[derive(Clone, Debug, Deserialize)]
+pub struct CustomCommand {
+    /// System prompt sent with every request
+    pub system: String,
+    /// User message, where `{input}` is replaced by the command's arguments
+    pub template: Option<String>,
+    pub temperature: Option<f32>,
+    /// Help text shown by `help`
+    pub about: Option<String>,
+    #[serde(default)]
+    pub aliases: Vec<String>,
+    /// `{name}` placeholders of the template besides `{input}`, filled in with
+    /// `--var NAME=VALUE` or asked for
+    #[serde(default)]
+    pub vars: BTreeMap<String, TemplateVar>,
+}
+
+/// A variable of a template, in the `[commands.<name>.vars.<var>]` section of the config
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct TemplateVar {
+    /// Shown when asking for the value
+    pub description: Option<String>,
+    /// Offered when asking for the value, and used as is without a terminal
+    pub default: Option<String>,
+}
+
+impl CustomCommand {
+    pub fn render(&self, input: &str, vars: &BTreeMap<String, String>) -> String {
+        match &self.template {
+            Some(template) => vars
+                .iter()
+                .fold(template.clone(), |template, (name, value)| {
+                    template.replace(&format!("{{{name}}}"), value)
+                })
+                .replace(INPUT_PLACEHOLDER, input),
+            None => input.to_owned(),
+        }
+    }
+}
+
+/// Adds the custom commands to `cmd`, skipping those that would shadow a built-in command
+pub fn augment(
+    mut cmd: clap::Command,
+    commands: &BTreeMap<String, CustomCommand>,
+) -> clap::Command {
+    for (name, command) in commands {
+        if cmd.find_subcommand(name).is_some() {
+            continue;
+        }
+
+        let mut subcommand = clap::Command::new(name.clone())
+            .about(
+                command
+                    .about
+                    .clone()
+                    .unwrap_or_else(|| format!("Custom command `{name}`")),
+            )
+            .visible_aliases(command.aliases.clone())
+            .arg(Arg::new(INPUT_ARG).action(ArgAction::Append));
+        if !command.vars.is_empty() {
+            subcommand = subcommand.arg(
+                Arg::new(VAR_ARG)
+                    .long(VAR_ARG)
+                    .value_name("NAME=VALUE")
+                    .value_parser(parse_var)
+                    .action(ArgAction::Append)
+                    .help(format!(
+                        "Fill in a variable of the template, asked for otherwise: {}",
+                        command.vars.keys().cloned().collect::<Vec<_>>().join(", ")
+                    )),
+            );
+        }
+        cmd = cmd.subcommand(subcommand);
+    }
+
+    cmd
+}
+
+/// The arguments of a matched custom command, joined back into one string
+pub fn input(matches: &clap::ArgMatches) -> String {
+    shell_words::join(matches.get_many::<String>(INPUT_ARG).into_iter().flatten())
+}
+
+/// The variables given with `--var` to a matched custom command
+pub fn vars(matches: &clap::ArgMatches) -> BTreeMap<String, String> {
+    matches
+        .try_get_many::<(String, String)>(VAR_ARG)
+        .ok()
+        .flatten()
+        .into_iter()
+        .flatten()
+        .cloned()
+        .collect()
+}
+
+fn parse_var(var: &str) -> Result<(String, String), String> {
+    var.split_once('=')
+        .map(|(name, value)| (name.trim().to_owned(), value.to_owned()))
+        .ok_or_else(|| format!("expected NAME=VALUE, got `{var}`"))
+}
+
+/// Names of custom commands that clash with built-in ones and are therefore ignored
+pub fn shadowed<'a>(
+    cmd: &clap::Command,
+    commands: &'a BTreeMap<String, CustomCommand>,
+) -> Vec<&'a str> {
+    commands
+        .keys()
+        .filter(|name| cmd.find_subcommand(name).is_some())
+        .map(String::as_str)
+        .collect()
+}
@@ -0,0 +1,191 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use clap::{Parser, Subcommand};
+use color_eyre::eyre::{Context, Result};
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+
+use crate::offline::QueuedRequest;
+use crate::server::{self, ServerState};
+
+const SOCKET_FILE: &str = ".sermaid_daemon.sock";
+
+/// A request sent by `ctl` as a line of JSON
+#[derive(Serialize, Deserialize)]
+struct Request {
+    #[serde(flatten)]
+    request: QueuedRequest,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    session: Option<String>,
+}
+
+/// The reply to a request, as a line of JSON
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Response {
+    Answer(String),
+    Error(String),
+}
+
+/// Talk to a running `sermaid daemon` instead of starting the REPL
+#[derive(Parser)]
+#[command(bin_name = "sermaid ctl")]
+struct Ctl {
+    #[command(subcommand)]
+    command: CtlCommand,
+}
+
+#[derive(Subcommand)]
+enum CtlCommand {
+    /// Ask a question, with the daemon's pinned context
+    Ask {
+        /// Limit the answer to about this many words
+        #[arg(long, value_name = "N")]
+        max_words: Option<u32>,
+        /// Ask in this session of the daemon, following up on its earlier questions
+        #[arg(long, value_name = "ID")]
+        session: Option<String>,
+        #[arg(required = true)]
+        question: Vec<String>,
+    },
+    /// Translate to Chinese, or Chinese to English
+    Translate {
+        /// Translate into this language instead
+        #[arg(long, value_name = "LANGUAGE")]
+        to: Option<String>,
+        #[arg(required = true)]
+        raw_text: Vec<String>,
+    },
+}
+
+/// `~/.sermaid_daemon.sock`, unless `daemon_socket` is set in config
+pub fn default_socket() -> Option<PathBuf> {
+    Some(home::home_dir()?.join(SOCKET_FILE))
+}
+
+/// Answers requests from `ctl` on the Unix socket at `socket` until Ctrl-C, keeping the API
+/// client and its connections, and the sessions, between requests
+#[cfg(unix)]
+pub async fn run(socket: &Path, state: ServerState) -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    use tokio::net::{UnixListener, UnixStream};
+
+    if UnixStream::connect(socket).await.is_ok() {
+        color_eyre::eyre::bail!("a daemon is already listening on `{}`", socket.display());
+    }
+    // Left behind by a daemon that did not exit cleanly
+    let _ = std::fs::remove_file(socket);
+    let listener = UnixListener::bind(socket)
+        .wrap_err_with(|| format!("failed to listen on `{}`", socket.display()))?;
+    std::fs::set_permissions(socket, std::fs::Permissions::from_mode(0o600))
+        .wrap_err_with(|| format!("failed to restrict `{}` to its owner", socket.display()))?;
+    println!("listening on `{}`", socket.display());
+
+    let state = Arc::new(state);
+    let res = loop {
+        tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok((stream, _)) => {
+                    tokio::spawn(serve_client(state.clone(), stream));
+                },
+                Err(err) => break Err(err).wrap_err_with(|| "failed to accept a client"),
+            },
+            _ = tokio::signal::ctrl_c() => break Ok(()),
+        }
+    };
+    let _ = std::fs::remove_file(socket);
+    res
+}
+
+#[cfg(not(unix))]
+pub async fn run(_: &Path, _: ServerState) -> Result<()> {
+    color_eyre::eyre::bail!("the daemon needs Unix sockets")
+}
+
+/// Answers each line of `stream` with a line, until the client hangs up
+#[cfg(unix)]
+async fn serve_client(state: Arc<ServerState>, stream: tokio::net::UnixStream) {
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = BufReader::new(reader).lines();
+    while let Ok(Some(line)) = lines.next_line().await {
+        let response = match serde_json::from_str::<Request>(&line) {
+            Ok(req) => {
+                match server::run_request(&state, &req.request, req.session.as_deref()).await {
+                    Ok(completion) => Response::Answer(completion.content.into_owned()),
+                    Err(err) => Response::Error(format!("{err:#}")),
+                }
+            },
+            Err(err) => Response::Error(format!("invalid request: {err}")),
+        };
+        let Ok(mut response) = serde_json::to_string(&response) else {
+            break;
+        };
+        response.push('\n');
+        if writer.write_all(response.as_bytes()).await.is_err() {
+            break;
+        }
+    }
+}
+
+/// Sends the command in `args`, starting with `ctl`, to the daemon on `socket` and prints the
+/// answer
+#[cfg(unix)]
+pub async fn ctl(socket: &Path, args: &[String]) -> Result<()> {
+    let req = match Ctl::parse_from(args).command {
+        CtlCommand::Ask {
+            max_words,
+            session,
+            question,
+        } => Request {
+            request: QueuedRequest::Ask {
+                question: shell_words::join(question),
+                max_words,
+            },
+            session,
+        },
+        CtlCommand::Translate { to, raw_text } => Request {
+            request: QueuedRequest::Translate {
+                text: shell_words::join(raw_text),
+                to,
+            },
+            session: None,
+        },
+    };
+
+    let stream = tokio::net::UnixStream::connect(socket)
+        .await
+        .wrap_err_with(|| {
+            format!(
+                "no daemon listening on `{}`, start one with `sermaid daemon`",
+                socket.display()
+            )
+        })?;
+    let (reader, mut writer) = stream.into_split();
+    let mut line = serde_json::to_string(&req).wrap_err_with(|| "failed to serialize request")?;
+    line.push('\n');
+    writer
+        .write_all(line.as_bytes())
+        .await
+        .wrap_err_with(|| "failed to send request to the daemon")?;
+
+    let line = BufReader::new(reader)
+        .lines()
+        .next_line()
+        .await
+        .wrap_err_with(|| "failed to read the daemon's reply")?
+        .ok_or_else(|| color_eyre::eyre::eyre!("the daemon hung up without replying"))?;
+    match serde_json::from_str(&line).wrap_err_with(|| "invalid reply from the daemon")? {
+        Response::Answer(answer) => {
+            println!("{answer}");
+            Ok(())
+        },
+        Response::Error(err) => color_eyre::eyre::bail!("{err}"),
+    }
+}
+
+#[cfg(not(unix))]
+pub async fn ctl(_: &Path, _: &[String]) -> Result<()> {
+    color_eyre::eyre::bail!("the daemon needs Unix sockets")
+}
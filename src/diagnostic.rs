@@ -0,0 +1,214 @@
+use std::fmt::Write;
+use std::path::PathBuf;
+
+use color_eyre::eyre::{Context, Result};
+use regex::Regex;
+use serde::Deserialize;
+
+/// Lines shown around the span of an error
+const CONTEXT_LINES: usize = 8;
+/// Lines of plain compiler output kept for an error, from its first line
+const MAX_ERROR_LINES: usize = 40;
+const DEFAULT_TEMPLATE: &str = "`{command}` 报错：\n\n```\n{error}\n```\n\n{source}";
+
+/// The `[explain_error]` section of the config
+#[derive(Clone, Deserialize)]
+#[serde(default)]
+pub struct ExplainErrorConfig {
+    /// Build command whose first error is explained, printing either cargo's JSON messages or
+    /// plain compiler output
+    pub command: String,
+    /// User message, where `{command}`, `{error}` and `{source}` are replaced by the build
+    /// command, its error and the source around the error
+    pub template: Option<String>,
+}
+
+impl Default for ExplainErrorConfig {
+    fn default() -> Self {
+        Self {
+            command: "cargo check --message-format=json".to_owned(),
+            template: None,
+        }
+    }
+}
+
+impl ExplainErrorConfig {
+    pub fn render(&self, command: &str, error: &Diagnostic, source: Option<&str>) -> String {
+        self.template
+            .as_deref()
+            .unwrap_or(DEFAULT_TEMPLATE)
+            .replace("{command}", command)
+            .replace("{error}", error.message.trim_end())
+            .replace("{source}", source.unwrap_or_default())
+    }
+}
+
+/// An error reported by a compiler
+pub struct Diagnostic {
+    pub message: String,
+    /// File and lines the error points at
+    pub span: Option<Span>,
+}
+
+pub struct Span {
+    pub file: PathBuf,
+    pub line_start: usize,
+    pub line_end: usize,
+}
+
+#[derive(Deserialize)]
+struct CargoMessage {
+    reason: String,
+    message: Option<CompilerMessage>,
+}
+
+#[derive(Deserialize)]
+struct CompilerMessage {
+    level: String,
+    rendered: Option<String>,
+    message: String,
+    #[serde(default)]
+    spans: Vec<CompilerSpan>,
+}
+
+#[derive(Deserialize)]
+struct CompilerSpan {
+    file_name: PathBuf,
+    line_start: usize,
+    line_end: usize,
+    is_primary: bool,
+}
+
+/// Runs `command` in the current directory and returns its first error, or `None` if it
+/// reported none
+pub fn first_error_of(command: &str) -> Result<Option<Diagnostic>> {
+    let words = shell_words::split(command)
+        .wrap_err_with(|| format!("failed to parse build command `{command}`"))?;
+    let Some((program, args)) = words.split_first() else {
+        color_eyre::eyre::bail!("the build command is empty");
+    };
+    let output = std::process::Command::new(program)
+        .args(args)
+        .output()
+        .wrap_err_with(|| format!("failed to run `{command}`"))?;
+
+    // Cargo prints its JSON messages to stdout and compilers mostly print errors to stderr
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let error = first_error(&stdout).or_else(|| first_error(&stderr));
+    if error.is_none() && !output.status.success() {
+        color_eyre::eyre::bail!(
+            "`{command}` failed without an error that could be found: {}",
+            stderr.trim()
+        );
+    }
+    Ok(error)
+}
+
+/// The first error in `output`, made of cargo's JSON messages or plain compiler output
+pub fn first_error(output: &str) -> Option<Diagnostic> {
+    match cargo_diagnostics(output, &["error"]) {
+        Some(errors) => errors.into_iter().next(),
+        None => first_plain_error(output),
+    }
+}
+
+/// The compiler messages at one of `levels` among cargo's JSON messages in `output`, or `None`
+/// if it has no JSON messages
+pub fn cargo_diagnostics(output: &str, levels: &[&str]) -> Option<Vec<Diagnostic>> {
+    let messages = output
+        .lines()
+        .filter_map(|line| serde_json::from_str::<CargoMessage>(line).ok())
+        .collect::<Vec<_>>();
+    if messages.is_empty() {
+        return None;
+    }
+
+    let diagnostics = messages
+        .into_iter()
+        .filter(|message| message.reason == "compiler-message")
+        .filter_map(|message| message.message)
+        .filter(|message| levels.contains(&message.level.as_str()))
+        .map(|message| {
+            let span = message
+                .spans
+                .iter()
+                .find(|span| span.is_primary)
+                .map(|span| Span {
+                    file: span.file_name.clone(),
+                    line_start: span.line_start,
+                    line_end: span.line_end,
+                });
+            Diagnostic {
+                message: message.rendered.unwrap_or(message.message),
+                span,
+            }
+        })
+        .collect();
+    Some(diagnostics)
+}
+
+/// The lines from the first one mentioning an error to the next blank line, pointing at the
+/// first location in them
+fn first_plain_error(output: &str) -> Option<Diagnostic> {
+    let error = Regex::new(r"(?i)\berror\b").unwrap();
+
+    let lines = output.lines().collect::<Vec<_>>();
+    let start = lines.iter().position(|line| error.is_match(line))?;
+    let lines = lines[start..]
+        .iter()
+        .take(MAX_ERROR_LINES)
+        .take_while(|line| !line.trim().is_empty())
+        .copied()
+        .collect::<Vec<_>>();
+    let message = lines.join("\n");
+    Some(Diagnostic {
+        span: locate(&message),
+        message,
+    })
+}
+
+/// The first `path:line` or `path(line,` in `text`, as compilers and panics print them
+pub fn locate(text: &str) -> Option<Span> {
+    let location = Regex::new(r#"([^\s:()'"]+\.\w+)(?::|\()(\d+)"#).unwrap();
+    let span = location.captures_iter(text).find_map(|captures| {
+        let line = captures[2].parse().ok()?;
+        Some(Span {
+            file: PathBuf::from(&captures[1]),
+            line_start: line,
+            line_end: line,
+        })
+    });
+    span
+}
+
+impl Span {
+    /// The lines of the span and around it as a fenced block under the path, numbered and with
+    /// the lines of the span marked by `>`
+    pub fn excerpt(&self) -> Result<String> {
+        let content = std::fs::read_to_string(&self.file)
+            .wrap_err_with(|| format!("failed to read `{}`", self.file.display()))?;
+        let first = self.line_start.saturating_sub(CONTEXT_LINES).max(1);
+        let last = self.line_end + CONTEXT_LINES;
+
+        let language = self
+            .file
+            .extension()
+            .map(|extension| extension.to_string_lossy())
+            .unwrap_or_default();
+        let mut excerpt = format!("`{}`:\n```{language}\n", self.file.display());
+        for (i, line) in content.lines().enumerate() {
+            let n = i + 1;
+            if (first..=last).contains(&n) {
+                let mark = if (self.line_start..=self.line_end).contains(&n) {
+                    '>'
+                } else {
+                    ' '
+                };
+                let _ = writeln!(excerpt, "{mark}{n:>5} {line}");
+            }
+        }
+        excerpt.push_str("```\n");
+        Ok(excerpt)
+    }
+}
@@ -0,0 +1,49 @@
+use similar::{Change, ChangeTag, TextDiff};
+
+const RED: &str = "\x1b[31m";
+const GREEN: &str = "\x1b[32m";
+const RESET: &str = "\x1b[0m";
+
+/// Line diff of two answers, with removed lines in red and added lines in green if `color`
+pub fn diff(old: &str, new: &str, color: bool) -> String {
+    let mut out = String::new();
+
+    for change in TextDiff::from_lines(old, new).iter_all_changes() {
+        push_change(&mut out, &change, color);
+    }
+
+    out
+}
+
+/// Changed lines of `old` to `new` with `context` unchanged lines around them, hunks
+/// separated by `@@` lines, colored like [`diff`]
+pub fn hunks(old: &str, new: &str, context: usize, color: bool) -> String {
+    let diff = TextDiff::from_lines(old, new);
+    let mut out = String::new();
+
+    for (i, group) in diff.grouped_ops(context).iter().enumerate() {
+        if i > 0 {
+            out.push_str("@@\n");
+        }
+        for change in group.iter().flat_map(|op| diff.iter_changes(op)) {
+            push_change(&mut out, &change, color);
+        }
+    }
+
+    out
+}
+
+fn push_change(out: &mut String, change: &Change<&str>, color: bool) {
+    let (sign, start) = match change.tag() {
+        ChangeTag::Delete => ("-", RED),
+        ChangeTag::Insert => ("+", GREEN),
+        ChangeTag::Equal => (" ", ""),
+    };
+    let line = change.value().trim_end_matches('\n');
+
+    if color && !start.is_empty() {
+        out.push_str(&format!("{start}{sign}{line}{RESET}\n"));
+    } else {
+        out.push_str(&format!("{sign}{line}\n"));
+    }
+}
@@ -0,0 +1,78 @@
+use std::path::PathBuf;
+
+use age::secrecy::SecretString;
+use age::{scrypt, x25519};
+use color_eyre::eyre::{Context, Result};
+use serde::Deserialize;
+
+const DEFAULT_PASSPHRASE_ENV: &str = "SERMAID_PASSPHRASE";
+/// Start of every binary age file
+const AGE_HEADER: &[u8] = b"age-encryption.org/v1";
+const SECRET_KEY_PREFIX: &str = "AGE-SECRET-KEY-";
+
+/// The `[encryption]` section of the config
+#[derive(Deserialize)]
+pub struct EncryptionConfig {
+    /// An age identity file as written by `age-keygen`, used instead of a passphrase
+    key_file: Option<PathBuf>,
+    /// Environment variable holding the passphrase, `SERMAID_PASSPHRASE` by default
+    passphrase_env: Option<String>,
+}
+
+/// Encrypts files written by sermaid at rest with age
+pub enum Cipher {
+    Passphrase(SecretString),
+    Key(x25519::Identity),
+}
+
+impl Cipher {
+    pub fn from_config(config: &EncryptionConfig) -> Result<Self> {
+        if let Some(key_file) = &config.key_file {
+            let contents = std::fs::read_to_string(key_file)
+                .wrap_err_with(|| format!("failed to read key file `{}`", key_file.display()))?;
+            let key = contents
+                .lines()
+                .map(str::trim)
+                .find(|line| line.starts_with(SECRET_KEY_PREFIX))
+                .ok_or_else(|| {
+                    color_eyre::eyre::eyre!("no age secret key in `{}`", key_file.display())
+                })?;
+            let identity = key.parse().map_err(|err| {
+                color_eyre::eyre::eyre!("invalid age secret key in `{}`: {err}", key_file.display())
+            })?;
+            return Ok(Self::Key(identity));
+        }
+
+        let env = config
+            .passphrase_env
+            .as_deref()
+            .unwrap_or(DEFAULT_PASSPHRASE_ENV);
+        let passphrase = std::env::var(env)
+            .wrap_err_with(|| format!("encryption is configured but `{env}` is not set"))?;
+        Ok(Self::Passphrase(SecretString::from(passphrase)))
+    }
+
+    pub fn encrypt(&self, plaintext: &[u8]) -> Result<Vec<u8>> {
+        let ciphertext = match self {
+            Self::Passphrase(passphrase) => {
+                age::encrypt(&scrypt::Recipient::new(passphrase.clone()), plaintext)
+            },
+            Self::Key(identity) => age::encrypt(&identity.to_public(), plaintext),
+        };
+        ciphertext.wrap_err_with(|| "failed to encrypt")
+    }
+
+    pub fn decrypt(&self, ciphertext: &[u8]) -> Result<Vec<u8>> {
+        let plaintext = match self {
+            Self::Passphrase(passphrase) => {
+                age::decrypt(&scrypt::Identity::new(passphrase.clone()), ciphertext)
+            },
+            Self::Key(identity) => age::decrypt(identity, ciphertext),
+        };
+        plaintext.wrap_err_with(|| "failed to decrypt, is the passphrase or key file right?")
+    }
+}
+
+pub fn is_encrypted(contents: &[u8]) -> bool {
+    contents.starts_with(AGE_HEADER)
+}
@@ -0,0 +1,44 @@
+use std::process::Command;
+
+use color_eyre::eyre::{Context, Result};
+
+const DEFAULT_EDITOR: &str = "vi";
+
+/// Opens `text` in `$VISUAL`/`$EDITOR` and returns the saved result
+pub fn edit(text: &str) -> Result<String> {
+    let editor = std::env::var("VISUAL")
+        .or_else(|_| std::env::var("EDITOR"))
+        .unwrap_or_else(|_| DEFAULT_EDITOR.to_owned());
+    let mut editor_args = shell_words::split(&editor)
+        .wrap_err_with(|| format!("failed to split editor command `{editor}`"))?;
+    if editor_args.is_empty() {
+        color_eyre::eyre::bail!("editor command is empty");
+    }
+    let program = editor_args.remove(0);
+
+    // Created exclusively under a random name, so other users cannot plant or read it
+    let file = tempfile::Builder::new()
+        .prefix(&format!("{}-", crate::CARGO_PKG_NAME))
+        .suffix(".md")
+        .tempfile()
+        .wrap_err_with(|| "failed to create temporary file")?;
+    let path = file.path();
+    std::fs::write(path, text)
+        .wrap_err_with(|| format!("failed to write temporary file `{}`", path.display()))?;
+
+    let status = Command::new(&program)
+        .args(editor_args)
+        .arg(path)
+        .status()
+        .wrap_err_with(|| format!("failed to run editor `{editor}`"));
+    // Editors that save by renaming replace the file, so it is read back by path
+    let edited = std::fs::read_to_string(path)
+        .wrap_err_with(|| format!("failed to read temporary file `{}`", path.display()));
+    let _ = file.close();
+
+    if !status?.success() {
+        color_eyre::eyre::bail!("editor `{editor}` exited unsuccessfully");
+    }
+
+    edited
+}
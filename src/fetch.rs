@@ -0,0 +1,62 @@
+use color_eyre::eyre::{Context, Result};
+use scraper::{Html, Selector};
+
+use crate::html2md;
+
+/// Tried in order to find the main content of a page
+const CONTENT_SELECTORS: &[&str] = &["article", "main", "[role=main]", "body"];
+
+pub struct Page {
+    pub title: Option<String>,
+    pub text: String,
+}
+
+/// Fetches `url` with `client` and reduces its main content to markdown
+pub async fn readable(client: &reqwest::Client, url: &str) -> Result<Page> {
+    let resp = client
+        .get(url)
+        .header(reqwest::header::USER_AGENT, crate::CARGO_PKG_NAME)
+        .send()
+        .await
+        .wrap_err_with(|| format!("failed to fetch `{url}`"))?
+        .error_for_status()
+        .wrap_err_with(|| format!("failed to fetch `{url}`"))?;
+
+    let is_html = resp
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|content_type| content_type.to_str().ok())
+        .is_none_or(|content_type| content_type.contains("html"));
+    let body = resp
+        .text()
+        .await
+        .wrap_err_with(|| format!("failed to read `{url}`"))?;
+
+    if !is_html {
+        return Ok(Page {
+            title: None,
+            text: body,
+        });
+    }
+    Ok(extract(&body))
+}
+
+fn extract(html: &str) -> Page {
+    let document = Html::parse_document(html);
+
+    let title = Selector::parse("title")
+        .ok()
+        .and_then(|selector| document.select(&selector).next())
+        .map(|title| title.text().collect::<String>().trim().to_owned())
+        .filter(|title| !title.is_empty());
+
+    let content = CONTENT_SELECTORS
+        .iter()
+        .filter_map(|selector| Selector::parse(selector).ok())
+        .find_map(|selector| document.select(&selector).next());
+
+    Page {
+        title,
+        text: content.map(html2md::convert).unwrap_or_default(),
+    }
+}
@@ -0,0 +1,276 @@
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+
+use color_eyre::eyre::{Context, Result};
+use globset::GlobBuilder;
+use ignore::WalkBuilder;
+use regex::Regex;
+
+use crate::codechunk;
+use crate::tokenizer::Tokenizer;
+
+const GLOB_CHARS: &[char] = &['*', '?', '[', '{'];
+/// Question words shorter than this say little about which files are relevant
+const MIN_KEYWORD_CHARS: usize = 3;
+const CITE_INSTRUCTION: &str =
+    "Cite the numbered sources below like [1] or [2, 3] where the answer relies on them.";
+
+/// A text file read for a question, or an excerpt of one
+pub struct File {
+    pub path: PathBuf,
+    pub content: String,
+    /// First and last line of an excerpt, 1-based
+    pub lines: Option<(usize, usize)>,
+    tokens: usize,
+}
+
+impl File {
+    /// The path, followed by the lines of an excerpt like `src/main.rs:10-42`
+    pub fn label(&self) -> String {
+        match self.lines {
+            Some((start, end)) => format!("{}:{start}-{end}", self.path.display()),
+            None => self.path.display().to_string(),
+        }
+    }
+}
+
+/// Files chosen to fit the token budget and the ones left out
+pub struct Selection {
+    pub files: Vec<File>,
+    pub skipped: Vec<PathBuf>,
+}
+
+/// Expands `patterns`, paths or globs like `src/**/*.rs`, to the files they match
+///
+/// Directories and globs skip hidden files and what .gitignore excludes, while files named
+/// outright are always kept.
+pub fn expand(patterns: &[String]) -> Result<Vec<PathBuf>> {
+    let mut paths = BTreeSet::new();
+    for pattern in patterns {
+        let path = Path::new(pattern);
+        let matched = if path.is_file() {
+            vec![path.to_owned()]
+        } else if path.is_dir() {
+            walk(path, |_| true)
+        } else if pattern.contains(GLOB_CHARS) {
+            let glob = GlobBuilder::new(pattern.trim_start_matches("./"))
+                .literal_separator(true)
+                .build()
+                .wrap_err_with(|| format!("invalid glob `{pattern}`"))?
+                .compile_matcher();
+            walk(&glob_base(pattern), |path| glob.is_match(path))
+        } else {
+            color_eyre::eyre::bail!("no file `{pattern}`");
+        };
+
+        if matched.is_empty() {
+            color_eyre::eyre::bail!("no files match `{pattern}`");
+        }
+        paths.extend(matched);
+    }
+
+    Ok(paths.into_iter().collect())
+}
+
+/// Files under `dir` outside .gitignore that `filter` accepts
+fn walk(dir: &Path, filter: impl Fn(&Path) -> bool) -> Vec<PathBuf> {
+    WalkBuilder::new(dir)
+        .require_git(false)
+        .build()
+        .filter_map(Result::ok)
+        .filter(|entry| {
+            entry
+                .file_type()
+                .is_some_and(|file_type| file_type.is_file())
+        })
+        .map(|entry| {
+            let path = entry.into_path();
+            path.strip_prefix(".").map_or(path.clone(), Path::to_owned)
+        })
+        .filter(|path| filter(path))
+        .collect()
+}
+
+/// The directory before the first component with glob characters
+fn glob_base(pattern: &str) -> PathBuf {
+    let base = Path::new(pattern)
+        .components()
+        .take_while(|component| !component.as_os_str().to_string_lossy().contains(GLOB_CHARS))
+        .collect::<PathBuf>();
+    if base.as_os_str().is_empty() {
+        PathBuf::from(".")
+    } else {
+        base
+    }
+}
+
+/// Reads `paths` and keeps the files most relevant to `question` that fit in `max_tokens`,
+/// in path order
+///
+/// Relevance is how many words of the question a file mentions, in its path or content.
+/// Files too large to fit whole are split into functions, types and other items, of which
+/// the ones named after words of the question come first. Files that are not UTF-8 text are
+/// skipped.
+pub fn select(
+    paths: Vec<PathBuf>,
+    question: &str,
+    tokenizer: Tokenizer,
+    max_tokens: usize,
+) -> Result<Selection> {
+    let keywords = question
+        .split(|c: char| !c.is_alphanumeric() && c != '_')
+        .filter(|word| word.chars().count() >= MIN_KEYWORD_CHARS)
+        .map(str::to_lowercase)
+        .collect::<BTreeSet<_>>();
+
+    let mut skipped = Vec::new();
+    let mut candidates = Vec::new();
+    for path in paths {
+        let content = match std::fs::read(&path) {
+            Ok(bytes) => match String::from_utf8(bytes) {
+                Ok(content) => content,
+                Err(_) => {
+                    skipped.push(path);
+                    continue;
+                },
+            },
+            Err(err) => {
+                return Err(err).wrap_err_with(|| format!("failed to read `{}`", path.display()))
+            },
+        };
+
+        let score = score(&keywords, &format!("{}\n{content}", path.display()));
+        let tokens = tokenizer.count(&content);
+        candidates.push((
+            score,
+            File {
+                path,
+                content,
+                lines: None,
+                tokens,
+            },
+        ));
+    }
+
+    // Most relevant first, smaller files first among equals so more of them fit
+    candidates
+        .sort_by(|(a_score, a), (b_score, b)| b_score.cmp(a_score).then(a.tokens.cmp(&b.tokens)));
+
+    let mut budget = max_tokens;
+    let mut files = Vec::new();
+    let mut too_large = Vec::new();
+    for (_, file) in candidates {
+        if file.tokens <= budget {
+            budget -= file.tokens;
+            files.push(file);
+        } else {
+            too_large.push(file);
+        }
+    }
+
+    // Excerpts of the files left out, by the symbols they define, then by their content
+    let mut excerpts = too_large
+        .iter()
+        .flat_map(|file| {
+            codechunk::split(&file.path, &file.content)
+                .into_iter()
+                .map(|chunk| {
+                    let symbols = score(&keywords, &chunk.symbols.join("\n"));
+                    let score = score(&keywords, &chunk.text);
+                    let excerpt = File {
+                        path: file.path.clone(),
+                        tokens: tokenizer.count(&chunk.text),
+                        content: chunk.text,
+                        lines: Some((chunk.start_line, chunk.end_line)),
+                    };
+                    ((symbols, score), excerpt)
+                })
+        })
+        .filter(|((symbols, score), _)| symbols + score > 0)
+        .collect::<Vec<_>>();
+    excerpts
+        .sort_by(|(a_score, a), (b_score, b)| b_score.cmp(a_score).then(a.tokens.cmp(&b.tokens)));
+    for (_, excerpt) in excerpts {
+        if excerpt.tokens <= budget {
+            budget -= excerpt.tokens;
+            files.push(excerpt);
+        }
+    }
+    skipped.extend(
+        too_large
+            .into_iter()
+            .map(|file| file.path)
+            .filter(|path| !files.iter().any(|file| &file.path == path)),
+    );
+
+    files.sort_by(|a, b| a.path.cmp(&b.path).then(a.lines.cmp(&b.lines)));
+    // Excerpts that follow each other read better as one
+    files.dedup_by(|next, previous| match (previous.lines, next.lines) {
+        (Some((start, end)), Some((next_start, next_end)))
+            if previous.path == next.path && next_start <= end + 2 =>
+        {
+            let gap = "\n".repeat(next_start - end);
+            previous.content = format!("{}{gap}{}", previous.content, next.content);
+            previous.lines = Some((start, next_end));
+            previous.tokens += next.tokens;
+            true
+        },
+        _ => false,
+    });
+    skipped.sort();
+
+    Ok(Selection { files, skipped })
+}
+
+/// How many of `keywords` appear in `text`
+fn score(keywords: &BTreeSet<String>, text: &str) -> usize {
+    let text = text.to_lowercase();
+    keywords
+        .iter()
+        .filter(|keyword| text.contains(keyword.as_str()))
+        .count()
+}
+
+/// Concatenates `files` as fenced blocks under their paths and the lines of excerpts, numbered
+/// from 1 as sources for the answer to cite
+pub fn render(files: &[File]) -> String {
+    let sources = files
+        .iter()
+        .enumerate()
+        .map(|(i, file)| {
+            let language = file
+                .path
+                .extension()
+                .map(|extension| extension.to_string_lossy())
+                .unwrap_or_default();
+            let lines = file
+                .lines
+                .map(|(start, end)| format!(", lines {start}-{end}"))
+                .unwrap_or_default();
+            format!(
+                "[{}] File `{}`{lines}:\n```{language}\n{}\n```\n\n",
+                i + 1,
+                file.path.display(),
+                file.content.trim_end()
+            )
+        })
+        .collect::<String>();
+    format!("{CITE_INSTRUCTION}\n\n{sources}")
+}
+
+/// Numbers of the `count` sources that `answer` cites, in order of first citation
+pub fn citations(answer: &str, count: usize) -> Vec<usize> {
+    let citation = Regex::new(r"\[(\d+(?:\s*,\s*\d+)*)\]").unwrap();
+    let mut cited = Vec::new();
+    for captures in citation.captures_iter(answer) {
+        for n in captures[1].split(',') {
+            let Ok(n) = n.trim().parse() else {
+                continue;
+            };
+            if (1..=count).contains(&n) && !cited.contains(&n) {
+                cited.push(n);
+            }
+        }
+    }
+    cited
+}
@@ -0,0 +1,47 @@
+const WORDS_PER_MINUTE: usize = 200;
+const CHARS_PER_TOKEN: usize = 4;
+
+/// Summarizes the length of an answer, preferring the API's token count over an estimate
+pub fn footer(content: &str, completion_tokens: Option<u32>) -> String {
+    let words = count_words(content);
+    let chars = content.chars().count();
+    let tokens = completion_tokens.map_or_else(
+        || format!("~{}", estimate_tokens(content)),
+        |tokens| tokens.to_string(),
+    );
+    let minutes = words.div_ceil(WORDS_PER_MINUTE).max(1);
+
+    format!("-- {words} words, {chars} chars, {tokens} tokens, ~{minutes} min read")
+}
+
+/// Rough token count for when the API has not reported one
+pub fn estimate_tokens(content: &str) -> usize {
+    content.chars().count().div_ceil(CHARS_PER_TOKEN)
+}
+
+/// Counts whitespace-separated words, with each CJK character counted as a word of its own
+fn count_words(content: &str) -> usize {
+    let mut words = 0;
+    let mut in_word = false;
+    for c in content.chars() {
+        if is_cjk(c) {
+            words += 1;
+            in_word = false;
+        } else if c.is_whitespace() {
+            in_word = false;
+        } else if !in_word {
+            words += 1;
+            in_word = true;
+        }
+    }
+    words
+}
+
+fn is_cjk(c: char) -> bool {
+    matches!(c,
+        '\u{3040}'..='\u{30ff}'
+        | '\u{3400}'..='\u{4dbf}'
+        | '\u{4e00}'..='\u{9fff}'
+        | '\u{ac00}'..='\u{d7af}'
+        | '\u{f900}'..='\u{faff}')
+}
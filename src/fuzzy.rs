@@ -0,0 +1,68 @@
+use std::collections::HashSet;
+
+/// Bonus for a matched character right after the previous one
+const CONSECUTIVE_BONUS: i64 = 16;
+/// Bonus for a matched character at the start of a word
+const WORD_START_BONUS: i64 = 8;
+/// Penalty for every skipped character between two matched ones
+const GAP_PENALTY: i64 = 1;
+
+/// How well `candidate` matches `query`, fzf-like, or `None` if it does not
+///
+/// Every whitespace-separated term of the query must appear in order, though not necessarily
+/// contiguously, ignoring case. Runs of consecutive characters and matches at word starts
+/// score higher, gaps lower.
+pub fn score(query: &str, candidate: &str) -> Option<i64> {
+    let candidate = candidate.to_lowercase().chars().collect::<Vec<_>>();
+    query
+        .split_whitespace()
+        .map(|term| score_term(&term.to_lowercase(), &candidate))
+        .sum()
+}
+
+fn score_term(term: &str, candidate: &[char]) -> Option<i64> {
+    let mut score = 0;
+    let mut next = 0;
+    let mut previous = None;
+    for c in term.chars() {
+        let i = next +
+            candidate[next..]
+                .iter()
+                .position(|&candidate| candidate == c)?;
+        if previous.is_some_and(|previous| previous + 1 == i) {
+            score += CONSECUTIVE_BONUS;
+        } else if let Some(previous) = previous {
+            score -= GAP_PENALTY * (i - previous - 1) as i64;
+        }
+        if i == 0 || !candidate[i - 1].is_alphanumeric() {
+            score += WORD_START_BONUS;
+        }
+        previous = Some(i);
+        next = i + 1;
+    }
+    Some(score)
+}
+
+/// The `candidates` matching `query`, best first and most recent first among equals,
+/// without duplicates
+///
+/// `candidates` are ordered from oldest to newest.
+pub fn rank<'a>(query: &str, candidates: impl DoubleEndedIterator<Item = &'a str>) -> Vec<String> {
+    let mut seen = HashSet::new();
+    let mut matches = Vec::new();
+    for candidate in candidates.rev() {
+        if !seen.insert(candidate) {
+            continue;
+        }
+        if let Some(score) = score(query, candidate) {
+            matches.push((score, candidate));
+        }
+    }
+
+    // Stable, so recency breaks ties
+    matches.sort_by(|(a, _), (b, _)| b.cmp(a));
+    matches
+        .into_iter()
+        .map(|(_, candidate)| candidate.to_owned())
+        .collect()
+}
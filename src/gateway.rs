@@ -0,0 +1,199 @@
+use std::collections::BTreeMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use color_eyre::eyre::{Context, Result};
+use hmac::{Hmac, Mac};
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use serde::Deserialize;
+use sha2::Sha256;
+
+const SECRET_ENV: &str = "SERMAID_GATEWAY_SECRET";
+
+/// The `[gateway]` section of the config, for corporate gateways in front of the API that
+/// route or authorize requests by their headers
+#[derive(Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct GatewayConfig {
+    /// Headers sent with every API request, like `X-Request-Source` or internal auth headers
+    pub headers: BTreeMap<String, String>,
+    /// Sign the body of every API request with HMAC-SHA256
+    pub signing: Option<SigningConfig>,
+}
+
+#[derive(Clone, Deserialize)]
+pub struct SigningConfig {
+    /// Secret shared with the gateway, `$SERMAID_GATEWAY_SECRET` by default
+    pub secret: Option<String>,
+    /// Header carrying the signature in lowercase hex
+    #[serde(default = "default_signature_header")]
+    pub header: String,
+    /// Header carrying the Unix time of the request, which is then signed as `<time>.<body>`
+    /// so that the gateway can refuse replayed requests
+    pub timestamp_header: Option<String>,
+}
+
+fn default_signature_header() -> String {
+    "X-Signature".to_owned()
+}
+
+/// The headers and signing of [`GatewayConfig`], checked once when loading the config
+#[derive(Default)]
+pub struct Gateway {
+    headers: HeaderMap,
+    signing: Option<Signing>,
+}
+
+struct Signing {
+    secret: Vec<u8>,
+    header: HeaderName,
+    timestamp_header: Option<HeaderName>,
+}
+
+impl Gateway {
+    pub fn new(config: GatewayConfig) -> Result<Self> {
+        let mut headers = HeaderMap::new();
+        for (name, value) in config.headers {
+            let value = HeaderValue::from_str(&value)
+                .wrap_err_with(|| format!("invalid value of header `{name}` in `[gateway]`"))?;
+            headers.insert(header_name(&name)?, value);
+        }
+
+        let signing = config
+            .signing
+            .map(|signing| -> Result<_> {
+                let secret = signing
+                    .secret
+                    .or_else(|| std::env::var(SECRET_ENV).ok())
+                    .ok_or_else(|| {
+                        color_eyre::eyre::eyre!(
+                            "no `secret` in `[gateway.signing]` and no `${SECRET_ENV}`"
+                        )
+                    })?;
+                Ok(Signing {
+                    secret: secret.into_bytes(),
+                    header: header_name(&signing.header)?,
+                    timestamp_header: signing
+                        .timestamp_header
+                        .as_deref()
+                        .map(header_name)
+                        .transpose()?,
+                })
+            })
+            .transpose()?;
+
+        Ok(Self { headers, signing })
+    }
+
+    /// Adds the headers to `request` and signs its body, an empty one for requests without
+    pub fn apply(&self, request: &mut reqwest::Request) {
+        for (name, value) in &self.headers {
+            request.headers_mut().insert(name, value.clone());
+        }
+
+        let Some(signing) = &self.signing else {
+            return;
+        };
+        let body = request
+            .body()
+            .and_then(reqwest::Body::as_bytes)
+            .unwrap_or_default()
+            .to_vec();
+        // HMAC takes secrets of any length
+        let Ok(mut mac) = Hmac::<Sha256>::new_from_slice(&signing.secret) else {
+            return;
+        };
+        if let Some(timestamp_header) = &signing.timestamp_header {
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs();
+            mac.update(format!("{now}.").as_bytes());
+            request
+                .headers_mut()
+                .insert(timestamp_header, HeaderValue::from(now));
+        }
+        mac.update(&body);
+
+        let signature = mac
+            .finalize()
+            .into_bytes()
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect::<String>();
+        if let Ok(signature) = HeaderValue::from_str(&signature) {
+            request.headers_mut().insert(&signing.header, signature);
+        }
+    }
+}
+
+fn header_name(name: &str) -> Result<HeaderName> {
+    HeaderName::from_bytes(name.as_bytes())
+        .wrap_err_with(|| format!("invalid header name `{name}` in `[gateway]`"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gateway(timestamp_header: Option<&str>) -> Gateway {
+        Gateway::new(GatewayConfig {
+            headers: BTreeMap::from([("X-Request-Source".to_owned(), "sermaid".to_owned())]),
+            signing: Some(SigningConfig {
+                secret: Some("Jefe".to_owned()),
+                header: default_signature_header(),
+                timestamp_header: timestamp_header.map(str::to_owned),
+            }),
+        })
+        .unwrap()
+    }
+
+    fn request(body: &'static str) -> reqwest::Request {
+        reqwest::Client::new()
+            .post("http://localhost/v1/chat/completions")
+            .body(body)
+            .build()
+            .unwrap()
+    }
+
+    fn header<'a>(request: &'a reqwest::Request, name: &str) -> &'a str {
+        request.headers()[name].to_str().unwrap()
+    }
+
+    #[test]
+    fn signs_body_with_hmac_sha256() {
+        // RFC 4231, test case 2
+        let mut request = request("what do ya want for nothing?");
+        gateway(None).apply(&mut request);
+        assert_eq!(
+            header(&request, "X-Signature"),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+        assert_eq!(header(&request, "X-Request-Source"), "sermaid");
+    }
+
+    #[test]
+    fn signs_timestamp_before_body() {
+        let mut request = request("{}");
+        gateway(Some("X-Timestamp")).apply(&mut request);
+
+        let timestamp = header(&request, "X-Timestamp");
+        let mut mac = Hmac::<Sha256>::new_from_slice(b"Jefe").unwrap();
+        mac.update(format!("{timestamp}.{{}}").as_bytes());
+        let expected = mac
+            .finalize()
+            .into_bytes()
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect::<String>();
+        assert_eq!(header(&request, "X-Signature"), expected);
+    }
+
+    #[test]
+    fn rejects_invalid_header_names() {
+        let config = GatewayConfig {
+            headers: BTreeMap::from([("bad header".to_owned(), "x".to_owned())]),
+            signing: None,
+        };
+        assert!(Gateway::new(config).is_err());
+    }
+}
@@ -0,0 +1,89 @@
+use std::process::Command;
+
+use color_eyre::eyre::{Context, Result};
+
+/// The diff of a single file, as cut out of `git diff` output
+pub struct FileDiff {
+    pub path: String,
+    pub diff: String,
+}
+
+/// Subjects and bodies of the commits in `range`, oldest first
+pub fn log(range: &str) -> Result<String> {
+    run(&["log", "--no-color", "--reverse", "--format=- %s%n%b", range])
+}
+
+pub fn diff(args: &[&str]) -> Result<String> {
+    run(&[&["diff", "--no-color"], args].concat())
+}
+
+/// Runs git with `args` in the current directory and returns its stdout
+pub fn run(args: &[&str]) -> Result<String> {
+    let output = Command::new("git")
+        .args(args)
+        .output()
+        .wrap_err_with(|| format!("failed to run `git {}`", args.join(" ")))?;
+
+    if !output.status.success() {
+        color_eyre::eyre::bail!(
+            "`git {}` failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+
+    String::from_utf8(output.stdout)
+        .wrap_err_with(|| format!("`git {}` printed invalid UTF-8", args.join(" ")))
+}
+
+pub fn split_by_file(diff: &str) -> Vec<FileDiff> {
+    let mut files: Vec<FileDiff> = Vec::new();
+
+    for line in diff.lines() {
+        if let Some(paths) = line.strip_prefix("diff --git ") {
+            let path = paths
+                .rsplit_once(" b/")
+                .map_or(paths, |(_, path)| path)
+                .to_owned();
+            files.push(FileDiff {
+                path,
+                diff: String::new(),
+            });
+        }
+
+        if let Some(file) = files.last_mut() {
+            file.diff.push_str(line);
+            file.diff.push('\n');
+        }
+    }
+
+    files
+}
+
+/// Prefixes every line that exists in the new file with its line number, so comments can
+/// refer to lines without the model having to count through hunk headers
+pub fn number_new_lines(diff: &str) -> String {
+    let mut numbered = String::new();
+    let mut new_line: Option<usize> = None;
+
+    for line in diff.lines() {
+        if let Some(hunk) = line.strip_prefix("@@ ") {
+            new_line = hunk
+                .split_whitespace()
+                .find_map(|range| range.strip_prefix('+'))
+                .and_then(|range| range.split(',').next())
+                .and_then(|start| start.parse().ok());
+            numbered.push_str(line);
+        } else if let Some(n) = new_line.as_mut().filter(|_| !line.starts_with('-')) {
+            numbered.push_str(&format!("{n:>6} {line}"));
+            *n += 1;
+        } else if new_line.is_some() {
+            numbered.push_str(&format!("{:>6} {line}", ""));
+        } else {
+            numbered.push_str(line);
+        }
+        numbered.push('\n');
+    }
+
+    numbered
+}
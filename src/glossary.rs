@@ -0,0 +1,51 @@
+use std::fmt::Write;
+
+/// Separates the translation from the term list the model appends to it
+pub const MARKER: &str = "<<<GLOSSARY>>>";
+
+/// Term translations chosen so far, kept so later chunks of a document reuse them
+#[derive(Default)]
+pub struct Glossary {
+    entries: Vec<(String, String)>,
+}
+
+impl Glossary {
+    /// Instruction listing the known terms and asking for new ones after [`MARKER`]
+    pub fn instruction(&self) -> String {
+        let mut instruction = format!(
+            "译文之后另起一行输出 {MARKER}，再逐行列出本段中专有名词和术语的译法，格式为`原文 => 译文`"
+        );
+
+        if !self.entries.is_empty() {
+            instruction.push_str("。以下术语必须沿用已有译法：\n");
+            for (term, translation) in &self.entries {
+                let _ = writeln!(instruction, "{term} => {translation}");
+            }
+        }
+
+        instruction
+    }
+
+    /// Records new terms from `answer` and returns the translation without the term list
+    pub fn absorb<'a>(&mut self, answer: &'a str) -> &'a str {
+        let Some((translation, terms)) = answer.split_once(MARKER) else {
+            return answer.trim_end();
+        };
+
+        for line in terms.lines() {
+            let Some((term, translated)) = line.split_once("=>") else {
+                continue;
+            };
+            let (term, translated) = (term.trim(), translated.trim());
+            if term.is_empty() || translated.is_empty() {
+                continue;
+            }
+
+            if !self.entries.iter().any(|(known, _)| known == term) {
+                self.entries.push((term.to_owned(), translated.to_owned()));
+            }
+        }
+
+        translation.trim_end()
+    }
+}
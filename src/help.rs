@@ -0,0 +1,555 @@
+use std::fmt::Write;
+
+/// A command line shown by `help <command>`, with what it does
+pub struct Example {
+    pub line: &'static str,
+    pub about: &'static str,
+}
+
+const fn example(line: &'static str, about: &'static str) -> Example {
+    Example { line, about }
+}
+
+/// Examples of the built-in commands, by command name
+const EXAMPLES: &[(&str, &[Example])] = &[
+    (
+        "ask",
+        &[
+            example(
+                "ask what does `set -e` do",
+                "Ask a question, without the conversation so far",
+            ),
+            example(
+                "q --max-words 50 explain RAII",
+                "Ask for a short answer, `q` being an alias",
+            ),
+            example(
+                "ask --file 'src/**/*.rs' where is the config parsed",
+                "Attach the most relevant files",
+            ),
+            example(
+                "ask --repo where should a new command go",
+                "Attach an outline of the repository",
+            ),
+            example(
+                "ask --ocr screenshot.png translate this",
+                "Ask about the text in an image",
+            ),
+            example(
+                "ask --choices 3 name this function",
+                "Pick one of three answers to keep",
+            ),
+            example(
+                "ask --prefill '```json' list three primes",
+                "Start the answer for the model to continue",
+            ),
+            example(
+                "ask --show-confidence when was Rust 1.0 released",
+                "Paint the parts of the answer the model was unsure of",
+            ),
+            example(
+                "ask --bg summarize RFC 9110",
+                "Ask in the background, see `jobs` and `fg`",
+            ),
+            example(
+                "ask --speak explain the borrow checker",
+                "Hear the answer while it is generated",
+            ),
+        ],
+    ),
+    (
+        "continue",
+        &[
+            example(
+                "continue and in Python?",
+                "Ask with the conversation so far",
+            ),
+            example("c", "Let the last answer go on"),
+            example(
+                "continue --all what did I ask first?",
+                "Send every turn, past `history_limit`",
+            ),
+        ],
+    ),
+    (
+        "multi",
+        &[example(
+            "multi",
+            "Enter questions one per line, then a blank line to send them",
+        )],
+    ),
+    (
+        "listen",
+        &[
+            example("listen", "Ask a question by voice"),
+            example("listen translate --to en", "Translate what you say"),
+        ],
+    ),
+    (
+        "translate",
+        &[
+            example(
+                "tr 今天天气很好",
+                "Translate Chinese to English, or anything else to Chinese",
+            ),
+            example(
+                "translate --to French good morning",
+                "Translate into another language",
+            ),
+        ],
+    ),
+    (
+        "flush",
+        &[example("flush", "Send what was queued while offline")],
+    ),
+    ("jobs", &[example("jobs", "List background questions")]),
+    (
+        "fg",
+        &[example(
+            "fg 1",
+            "Wait for background question 1 and add it to the history",
+        )],
+    ),
+    ("good", &[example("good", "Tag the last answer as good")]),
+    (
+        "bad",
+        &[example(
+            "bad misses the edge case",
+            "Tag the last answer as bad, saying why",
+        )],
+    ),
+    (
+        "amend",
+        &[example("amend", "Correct the last answer in $EDITOR")],
+    ),
+    (
+        "apply",
+        &[example(
+            "apply",
+            "Apply the edits of the last answer, confirming each file",
+        )],
+    ),
+    (
+        "sources",
+        &[example(
+            "sources",
+            "Print what the last answer to `ask --file` cited",
+        )],
+    ),
+    (
+        "history",
+        &[
+            example("history", "Show the conversation"),
+            example(
+                "history -v",
+                "Also show models, token counts and timestamps",
+            ),
+        ],
+    ),
+    (
+        "timeline",
+        &[example(
+            "timeline",
+            "Show tokens and cost per answer to find the expensive turns",
+        )],
+    ),
+    (
+        "export",
+        &[
+            example(
+                "export chat.json",
+                "Save the conversation, secrets redacted",
+            ),
+            example(
+                "export --format openai-ft --rating good ft.jsonl",
+                "Keep good answers for fine-tuning",
+            ),
+            example(
+                "export --format markdown chat.md",
+                "Save it as readable markdown",
+            ),
+        ],
+    ),
+    (
+        "share",
+        &[example(
+            "share",
+            "Upload the conversation to a gist or 0x0.st, secrets redacted",
+        )],
+    ),
+    (
+        "session",
+        &[
+            example(
+                "session load chat.json",
+                "Continue an exported conversation with the model that answered it",
+            ),
+            example(
+                "session merge a.json b.json --into both.json",
+                "Merge two exported conversations by time",
+            ),
+            example(
+                "session list chats --tag work",
+                "List the exported conversations tagged `work`",
+            ),
+        ],
+    ),
+    (
+        "store",
+        &[
+            example("store list", "List the stored conversations started last"),
+            example(
+                "store stats",
+                "Count stored messages, tokens and cost by model",
+            ),
+            example("store load 12", "Continue stored conversation 12"),
+        ],
+    ),
+    (
+        "search-all",
+        &[
+            example(
+                "search-all tokio select --tag rust",
+                "Find the stored messages mentioning both words",
+            ),
+            example(
+                "search-all --raw '\"borrow checker\" OR lifetime*'",
+                "Search with FTS5 phrases, prefixes and operators",
+            ),
+            example(
+                "search-all flaky test --open",
+                "Continue the conversation of the best match",
+            ),
+        ],
+    ),
+    (
+        "diff",
+        &[example(
+            "diff 1 3",
+            "Compare two messages, numbered as in `history`",
+        )],
+    ),
+    (
+        "pin",
+        &[
+            example(
+                "pin answer in British English",
+                "Send some text with every question",
+            ),
+            example("pin --file NOTES.md", "Send a file with every question"),
+        ],
+    ),
+    (
+        "attach",
+        &[
+            example("attach --pages 3-7 paper.pdf", "Pin some pages of a PDF"),
+            example(
+                "attach --clipboard-image",
+                "Send the screenshot in the clipboard with the next question",
+            ),
+        ],
+    ),
+    ("pins", &[example("pins", "List pinned context")]),
+    ("unpin", &[example("unpin 0", "Remove the first pin")]),
+    (
+        "tokens",
+        &[example(
+            "tokens --file prompt.txt",
+            "Count the tokens of a file",
+        )],
+    ),
+    (
+        "buf",
+        &[
+            example(
+                "buf add --file error.log",
+                "Add a file to the scratch buffer",
+            ),
+            example("buf add why does this fail", "Add a line of text"),
+            example(
+                "buf send continue",
+                "Send the buffer with `continue` instead of `ask`",
+            ),
+        ],
+    ),
+    (
+        "schedule",
+        &[
+            example(
+                "schedule --into digest.md 'every day 9am' ask summarize HN",
+                "Append a daily digest to a file while `serve` runs",
+            ),
+            example(
+                "schedule --session fr 'every weekday 17:30' translate --to French bye",
+                "Add answers to an HTTP API session",
+            ),
+        ],
+    ),
+    (
+        "schedules",
+        &[example("schedules", "List scheduled prompts")],
+    ),
+    (
+        "unschedule",
+        &[example("unschedule 0", "Remove the first scheduled prompt")],
+    ),
+    (
+        "review",
+        &[
+            example("review --staged", "Review the staged changes"),
+            example(
+                "review --range main..HEAD --report review.md",
+                "Review a branch into a report",
+            ),
+        ],
+    ),
+    (
+        "pr-desc",
+        &[example(
+            "pr-desc --base develop --copy",
+            "Describe a branch and copy the text",
+        )],
+    ),
+    (
+        "explain-error",
+        &[
+            example("explain-error", "Explain the first error of `cargo check`"),
+            example(
+                "explain-error make -C build",
+                "Explain the first error of another build",
+            ),
+        ],
+    ),
+    (
+        "triage",
+        &[
+            example(
+                "triage",
+                "Plan fixes for the failures of `cargo clippy` and `cargo test`",
+            ),
+            example(
+                "triage --test --refresh",
+                "Only run the tests, asking again about every failure",
+            ),
+        ],
+    ),
+    (
+        "cheatsheet",
+        &[
+            example("cheatsheet", "Print a quick reference of the commands"),
+            example(
+                "cheatsheet --output sermaid.md",
+                "Write it to a markdown file",
+            ),
+        ],
+    ),
+    (
+        "mcp",
+        &[
+            example("mcp list", "List the tools of the MCP servers"),
+            example(
+                "ask what is in a.txt",
+                "Ask with the tools offered, except with --bg, --choices, --grammar, --prefill, \
+                 --show-confidence, --speak or an attached image",
+            ),
+            example(
+                "mcp call files read '{\"path\": \"a.txt\"}'",
+                "Call a tool directly",
+            ),
+        ],
+    ),
+    (
+        "watch-clipboard",
+        &[example(
+            "watch-clipboard --translate --notify",
+            "Translate copied text into notifications",
+        )],
+    ),
+    (
+        "watch",
+        &[
+            example(
+                "watch --file questions.fifo --out answers.fifo",
+                "Answer each line written to a FIFO into another",
+            ),
+            example(
+                "watch --file notes.txt --out answers.md",
+                "Answer lines appended to a file",
+            ),
+        ],
+    ),
+    (
+        "rpc",
+        &[example(
+            "sermaid rpc",
+            "Answer JSON-RPC from an editor plugin on stdio, streaming answers",
+        )],
+    ),
+    (
+        "serve",
+        &[example(
+            "serve --listen 127.0.0.1:9000",
+            "Serve the HTTP API on another port",
+        )],
+    ),
+    (
+        "daemon",
+        &[
+            example("daemon", "Keep answering `sermaid ctl` until Ctrl-C"),
+            example(
+                "sermaid ctl ask --session w what is a monad",
+                "Ask the daemon from a shell, in session `w`",
+            ),
+        ],
+    ),
+    (
+        "bridge",
+        &[example(
+            "bridge",
+            "Answer mentions in the configured chat rooms",
+        )],
+    ),
+    (
+        "bind",
+        &[
+            example("bind F2 edit", "Edit the line in $EDITOR with F2"),
+            example(
+                "bind C-t insert translate",
+                "Insert `translate` with Ctrl-T",
+            ),
+        ],
+    ),
+    (
+        "set",
+        &[
+            example("set footer on", "Show counts after each answer"),
+            example(
+                "set stream on",
+                "Show answers formatted as they are generated",
+            ),
+            example(
+                "set pruning 'window(4)'",
+                "Only send the last 4 turns with `continue`",
+            ),
+            example("set seed 42", "Ask for reproducible answers"),
+            example("set seed", "Sample freely again"),
+        ],
+    ),
+    (
+        "config",
+        &[
+            example(
+                "config export team.toml",
+                "Share custom commands, theme and key bindings, without keys",
+            ),
+            example(
+                "config import team.toml",
+                "Add a shared bundle to the config",
+            ),
+        ],
+    ),
+    ("keys", &[example("keys", "Show which API key is in use")]),
+    (
+        "quota",
+        &[example(
+            "quota",
+            "Compare the provider's month-to-date spend or credits with the local totals",
+        )],
+    ),
+    (
+        "name",
+        &[example("name release notes", "Name the conversation")],
+    ),
+    (
+        "tag",
+        &[example(
+            "tag work",
+            "Tag the conversation to find it by project",
+        )],
+    ),
+    (
+        "context",
+        &[example("context --full", "Show what `continue` will send")],
+    ),
+    (
+        "help",
+        &[
+            example("help", "List the commands"),
+            example("help buf send", "Show a subcommand with examples"),
+        ],
+    ),
+];
+
+/// Examples of the built-in command `name`, empty for commands without any
+pub fn examples(name: &str) -> &'static [Example] {
+    EXAMPLES
+        .iter()
+        .find(|(command, _)| *command == name)
+        .map_or(&[], |(_, examples)| examples)
+}
+
+/// `examples` as an indented section
+pub fn render(examples: &[&Example]) -> String {
+    let width = examples
+        .iter()
+        .map(|example| example.line.chars().count())
+        .max();
+    examples
+        .iter()
+        .fold(String::from("Examples:\n"), |help, example| {
+            format!(
+                "{help}  {:width$}  {}\n",
+                example.line,
+                example.about,
+                width = width.unwrap_or_default()
+            )
+        })
+}
+
+/// The commands of `cli` with their aliases, options and examples, as plain text for the model
+/// to write `cheatsheet` from
+pub fn reference(cli: &clap::Command) -> String {
+    let mut reference = String::new();
+    for cmd in cli.get_subcommands().filter(|cmd| !cmd.is_hide_set()) {
+        describe(&mut reference, cmd, &[]);
+    }
+    reference
+}
+
+fn describe(reference: &mut String, cmd: &clap::Command, parents: &[&str]) {
+    let path = [parents, &[cmd.get_name()]].concat();
+    let _ = write!(reference, "## {}", path.join(" "));
+    let aliases = cmd.get_all_aliases().collect::<Vec<_>>();
+    if !aliases.is_empty() {
+        let _ = write!(reference, " (aliases: {})", aliases.join(", "));
+    }
+    reference.push('\n');
+    if let Some(about) = cmd.get_about() {
+        let _ = writeln!(reference, "{about}");
+    }
+
+    for arg in cmd
+        .get_arguments()
+        .filter(|arg| !arg.is_hide_set() && arg.get_id() != "help")
+    {
+        let name = match (arg.get_long(), arg.get_short()) {
+            (Some(long), _) => format!("--{long}"),
+            (None, Some(short)) => format!("-{short}"),
+            (None, None) => format!("<{}>", arg.get_id()),
+        };
+        let help = arg.get_help().map(ToString::to_string).unwrap_or_default();
+        let _ = writeln!(reference, "- {name}: {help}");
+    }
+    let joined = path.join(" ");
+    for example in examples(path[0])
+        .iter()
+        .filter(|example| path.len() == 1 || example.line.starts_with(&joined))
+    {
+        let _ = writeln!(reference, "example: {} — {}", example.line, example.about);
+    }
+    reference.push('\n');
+
+    for subcommand in cmd.get_subcommands().filter(|cmd| !cmd.is_hide_set()) {
+        describe(reference, subcommand, &path);
+    }
+}
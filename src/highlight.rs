@@ -0,0 +1,84 @@
+use color_eyre::eyre::Result;
+use syntect::easy::HighlightLines;
+use syntect::highlighting::{Theme, ThemeSet};
+use syntect::parsing::SyntaxSet;
+use syntect::util::{as_24_bit_terminal_escaped, LinesWithEndings};
+
+const RESET: &str = "\x1b[0m";
+
+/// Highlights fenced code blocks in answers according to their language tag
+pub struct Highlighter {
+    syntaxes: SyntaxSet,
+    theme: Theme,
+    /// Escape code starting the color of text outside code blocks
+    text_color: String,
+}
+
+impl Highlighter {
+    pub fn new(name: &str) -> Result<Self> {
+        let mut themes = ThemeSet::load_defaults();
+        let Some(theme) = themes.themes.remove(name) else {
+            color_eyre::eyre::bail!(
+                "unknown highlight theme `{name}`, expected one of: {}",
+                themes.themes.keys().cloned().collect::<Vec<_>>().join(", ")
+            );
+        };
+
+        Ok(Self {
+            syntaxes: SyntaxSet::load_defaults_newlines(),
+            theme,
+            text_color: String::new(),
+        })
+    }
+
+    /// Colors text outside code blocks with the color `escape` starts
+    pub fn with_text_color(mut self, escape: String) -> Self {
+        self.text_color = escape;
+        self
+    }
+
+    /// Returns `text` with the code inside fences replaced by terminal-escaped highlighted code
+    ///
+    /// Blocks with an unknown or missing language tag are left as they are.
+    pub fn highlight(&self, text: &str) -> String {
+        let mut out = String::with_capacity(text.len());
+        out.push_str(&self.text_color);
+        let mut in_block = false;
+        let mut block: Option<HighlightLines> = None;
+
+        for line in LinesWithEndings::from(text) {
+            if line.trim_start().starts_with("```") {
+                if in_block {
+                    if block.take().is_some() {
+                        out.push_str(RESET);
+                        out.push_str(&self.text_color);
+                    }
+                } else {
+                    let tag = line.trim().trim_start_matches('`').trim();
+                    block = self
+                        .syntaxes
+                        .find_syntax_by_token(tag)
+                        .filter(|_| !tag.is_empty())
+                        .map(|syntax| HighlightLines::new(syntax, &self.theme));
+                }
+                in_block = !in_block;
+                out.push_str(line);
+                continue;
+            }
+
+            match block
+                .as_mut()
+                .and_then(|block| block.highlight_line(line, &self.syntaxes).ok())
+            {
+                Some(ranges) => out.push_str(&as_24_bit_terminal_escaped(&ranges, false)),
+                None => out.push_str(line),
+            }
+        }
+
+        if block.is_some() || !self.text_color.is_empty() {
+            out.push_str(RESET);
+        }
+
+        out
+    }
+}
@@ -0,0 +1,338 @@
+use scraper::{ElementRef, Node};
+
+/// Elements dropped with everything inside them
+const SKIPPED_ELEMENTS: &[&str] = &[
+    "script", "style", "noscript", "nav", "header", "footer", "aside", "form", "iframe", "svg",
+    "button", "template", "head",
+];
+const CODE_LANGUAGE_PREFIXES: &[&str] = &["language-", "lang-"];
+
+/// Converts the contents of `element` to compact markdown, keeping headings, lists, tables,
+/// code blocks, links and emphasis
+pub fn convert(element: ElementRef) -> String {
+    let mut converter = Converter::default();
+    converter.children(element);
+    converter.finish()
+}
+
+#[derive(Default)]
+struct Converter {
+    out: String,
+    /// Prefix of every line in the current block, e.g. `> ` or list indentation
+    prefix: String,
+    /// Whether whitespace is kept as is, inside `<pre>`
+    preformatted: bool,
+}
+
+impl Converter {
+    fn finish(self) -> String {
+        let mut markdown = String::new();
+        let mut previous = "";
+        let mut blank = false;
+        for line in self.out.lines().map(str::trim_end) {
+            if line.trim_start_matches(['>', ' ']).is_empty() {
+                blank = true;
+                continue;
+            }
+            if blank && !markdown.is_empty() {
+                markdown.push_str(&quote_prefix(previous).min(quote_prefix(line)));
+                markdown.push('\n');
+            }
+            blank = false;
+            markdown.push_str(line);
+            markdown.push('\n');
+            previous = line;
+        }
+        markdown
+    }
+
+    fn children(&mut self, element: ElementRef) {
+        for child in element.children() {
+            match child.value() {
+                Node::Text(text) => self.text(text),
+                Node::Element(_) => {
+                    if let Some(child) = ElementRef::wrap(child) {
+                        self.element(child);
+                    }
+                },
+                _ => {},
+            }
+        }
+    }
+
+    fn text(&mut self, text: &str) {
+        if self.preformatted {
+            self.out.push_str(text);
+            return;
+        }
+
+        let words = text.split_whitespace().collect::<Vec<_>>().join(" ");
+        if words.is_empty() {
+            if text.chars().any(char::is_whitespace) {
+                self.space();
+            }
+            return;
+        }
+        if text.starts_with(char::is_whitespace) {
+            self.space();
+        }
+        self.out.push_str(&words);
+        if text.ends_with(char::is_whitespace) {
+            self.space();
+        }
+    }
+
+    fn space(&mut self) {
+        if !self.out.is_empty() && !self.out.ends_with([' ', '\n']) {
+            self.out.push(' ');
+        }
+    }
+
+    /// Starts a new line carrying the current prefix
+    fn line(&mut self) {
+        if !self.out.is_empty() && !self.out.ends_with('\n') {
+            self.out.push('\n');
+        }
+        self.out.push_str(&self.prefix);
+    }
+
+    /// Separates blocks with a blank line
+    fn block(&mut self) {
+        self.line();
+        self.out.push('\n');
+        self.out.push_str(&self.prefix);
+    }
+
+    fn inline(&mut self, element: ElementRef, marker: &str) {
+        let inner = self.render(element);
+        let inner = inner.trim();
+        if !inner.is_empty() {
+            self.space();
+            self.out.push_str(&format!("{marker}{inner}{marker}"));
+        }
+    }
+
+    /// Converts `element` on its own, for inline content that needs wrapping
+    fn render(&self, element: ElementRef) -> String {
+        let mut converter = Converter {
+            preformatted: self.preformatted,
+            ..Converter::default()
+        };
+        converter.children(element);
+        converter
+            .out
+            .split_whitespace()
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    fn element(&mut self, element: ElementRef) {
+        let name = element.value().name();
+        match name {
+            _ if SKIPPED_ELEMENTS.contains(&name) => {},
+            "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => {
+                let level = usize::from(name.as_bytes()[1] - b'0');
+                let text = self.render(element);
+                self.block();
+                self.out.push_str(&format!("{} {text}", "#".repeat(level)));
+                self.block();
+            },
+            "p" | "div" | "section" | "article" | "main" | "figure" | "dl" => {
+                self.block();
+                self.children(element);
+                self.block();
+            },
+            "br" => self.line(),
+            "hr" => {
+                self.block();
+                self.out.push_str("---");
+                self.block();
+            },
+            "strong" | "b" => self.inline(element, "**"),
+            "em" | "i" => self.inline(element, "*"),
+            "code" if !self.preformatted => self.inline(element, "`"),
+            "a" => {
+                let text = self.render(element);
+                match element.value().attr("href") {
+                    Some(href) if !text.is_empty() && !href.starts_with("javascript:") => {
+                        self.space();
+                        self.out.push_str(&format!("[{text}]({href})"));
+                    },
+                    _ => self.children(element),
+                }
+            },
+            "img" => {
+                if let Some(alt) = element.value().attr("alt").filter(|alt| !alt.is_empty()) {
+                    self.space();
+                    self.out.push_str(&format!("[image: {alt}]"));
+                }
+            },
+            "pre" => self.code_block(element),
+            "blockquote" => {
+                self.block();
+                self.prefix.push_str("> ");
+                self.line();
+                self.children(element);
+                self.prefix.truncate(self.prefix.len() - 2);
+                self.block();
+            },
+            "ul" | "ol" => self.list(element, name == "ol"),
+            "table" => self.table(element),
+            _ => self.children(element),
+        }
+    }
+
+    fn code_block(&mut self, element: ElementRef) {
+        let code = element
+            .children()
+            .filter_map(ElementRef::wrap)
+            .find(|child| child.value().name() == "code");
+        let language = code
+            .into_iter()
+            .chain([element])
+            .flat_map(|e| e.value().classes())
+            .find_map(|class| {
+                CODE_LANGUAGE_PREFIXES
+                    .iter()
+                    .find_map(|prefix| class.strip_prefix(prefix))
+            })
+            .unwrap_or_default();
+        let text = element.text().collect::<String>();
+
+        self.block();
+        self.out.push_str(&format!("```{language}"));
+        for line in text.trim_matches('\n').lines() {
+            self.line();
+            self.out.push_str(line);
+        }
+        self.line();
+        self.out.push_str("```");
+        self.block();
+    }
+
+    fn list(&mut self, element: ElementRef, ordered: bool) {
+        let nested = !self.prefix.is_empty() && self.prefix.trim().is_empty();
+        if !nested {
+            self.block();
+        }
+
+        let items = element
+            .children()
+            .filter_map(ElementRef::wrap)
+            .filter(|child| child.value().name() == "li");
+        for (i, item) in items.enumerate() {
+            let marker = if ordered {
+                format!("{}. ", i + 1)
+            } else {
+                "- ".to_owned()
+            };
+            self.line();
+            self.out.push_str(&marker);
+
+            let indent = self.prefix.len();
+            self.prefix.push_str(&" ".repeat(marker.len()));
+            self.children(item);
+            self.prefix.truncate(indent);
+        }
+
+        if !nested {
+            self.block();
+        }
+    }
+
+    fn table(&mut self, element: ElementRef) {
+        let rows = element
+            .descendants()
+            .filter_map(ElementRef::wrap)
+            .filter(|e| e.value().name() == "tr")
+            .map(|row| {
+                row.children()
+                    .filter_map(ElementRef::wrap)
+                    .filter(|cell| matches!(cell.value().name(), "td" | "th"))
+                    .map(|cell| self.render(cell).replace('|', "\\|"))
+                    .collect::<Vec<_>>()
+            })
+            .filter(|cells| !cells.is_empty())
+            .collect::<Vec<_>>();
+        let Some(columns) = rows.iter().map(Vec::len).max() else {
+            return;
+        };
+
+        self.block();
+        for (i, row) in rows.iter().enumerate() {
+            let mut cells = row.clone();
+            cells.resize(columns, String::new());
+            self.line();
+            self.out.push_str(&format!("| {} |", cells.join(" | ")));
+            if i == 0 {
+                self.line();
+                self.out.push_str(&format!("|{}", " --- |".repeat(columns)));
+            }
+        }
+        self.block();
+    }
+}
+
+/// Markers of the blockquotes `line` is in, e.g. `>>` for a nested quote
+fn quote_prefix(line: &str) -> String {
+    line.chars()
+        .take_while(|c| matches!(c, '>' | ' '))
+        .filter(|&c| c == '>')
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use scraper::Html;
+
+    use super::*;
+
+    fn markdown(body: &str) -> String {
+        let html = Html::parse_document(&format!("<html><body>{body}</body></html>"));
+        convert(html.root_element())
+    }
+
+    #[test]
+    fn converts_headings_and_inline_markup() {
+        assert_eq!(
+            markdown(
+                "<h2>Title</h2><p>Some <b>bold</b> and <em>soft</em> text with \
+                 <a href=\"https://x.io\">a link</a> and <code>code</code>.</p>"
+            ),
+            "## Title\n\nSome **bold** and *soft* text with [a link](https://x.io) and `code`.\n"
+        );
+    }
+
+    #[test]
+    fn converts_nested_lists() {
+        assert_eq!(
+            markdown("<ul><li>one</li><li>two<ol><li>nested</li></ol></li></ul>"),
+            "- one\n- two\n  1. nested\n"
+        );
+    }
+
+    #[test]
+    fn converts_code_blocks_quotes_and_tables() {
+        assert_eq!(
+            markdown(
+                "<pre><code class=\"language-rust\">fn main() {\n    run();\n}\n</code></pre>\
+                 <blockquote><p>quoted</p></blockquote>\
+                 <table><tr><th>a</th><th>b</th></tr><tr><td>1</td><td>x|y</td></tr></table>"
+            ),
+            "```rust\nfn main() {\n    run();\n}\n```\n\n> quoted\n\n\
+             | a | b |\n| --- | --- |\n| 1 | x\\|y |\n"
+        );
+    }
+
+    #[test]
+    fn drops_page_chrome_and_scripts() {
+        assert_eq!(
+            markdown(
+                "<nav>menu</nav><p>kept <img alt=\"a cat\"> \
+                 <a href=\"javascript:void(0)\">click</a></p><script>alert(1)</script>\
+                 <footer>footer</footer>"
+            ),
+            "kept [image: a cat] click\n"
+        );
+    }
+}
@@ -0,0 +1,160 @@
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use color_eyre::eyre::{Context, Result};
+use reqwest::Client;
+use serde::Deserialize;
+
+/// The `[http]` section of the config, tuning the connections kept to the API and other hosts
+#[derive(Clone, Deserialize)]
+#[serde(default)]
+pub struct HttpConfig {
+    /// Idle connections kept open per host, unlimited if unset
+    pub pool_max_idle_per_host: Option<usize>,
+    /// Seconds an idle connection is kept open for the next request
+    pub pool_idle_secs: u64,
+    /// Seconds between TCP and HTTP/2 keep-alive pings, so that idle connections survive NATs
+    /// and proxies instead of needing a new handshake, or 0 to send none
+    pub keep_alive_secs: u64,
+    /// Speak HTTP/2 right away instead of negotiating it, only for endpoints known to support
+    /// it, such as an HTTP/2 proxy over plain TCP
+    pub http2_prior_knowledge: bool,
+    /// PEM bundle of private CAs trusted besides the system's, as self-hosted servers often use
+    pub ca_cert: Option<PathBuf>,
+    /// PEM client certificate for mutual TLS, which may also hold its private key
+    pub client_cert: Option<PathBuf>,
+    /// PEM private key of `client_cert`, if kept in a file of its own
+    pub client_key: Option<PathBuf>,
+    /// Accept any server certificate, expired, self-signed or for another host, only for lab
+    /// setups since anyone on the network can then read and change the traffic
+    pub danger_accept_invalid_certs: bool,
+}
+
+impl Default for HttpConfig {
+    fn default() -> Self {
+        Self {
+            pool_max_idle_per_host: None,
+            pool_idle_secs: 600,
+            keep_alive_secs: 30,
+            http2_prior_knowledge: false,
+            ca_cert: None,
+            client_cert: None,
+            client_key: None,
+            danger_accept_invalid_certs: false,
+        }
+    }
+}
+
+/// A client with pooled keep-alive connections, shared by everything that makes requests so
+/// that they reuse the same connections
+///
+/// HTTP/2 is negotiated over TLS when the server supports it.
+pub fn client(config: &HttpConfig) -> Result<Client> {
+    let mut builder = Client::builder()
+        .tcp_nodelay(true)
+        .pool_idle_timeout(Duration::from_secs(config.pool_idle_secs));
+    if let Some(max) = config.pool_max_idle_per_host {
+        builder = builder.pool_max_idle_per_host(max);
+    }
+    if config.keep_alive_secs > 0 {
+        let interval = Duration::from_secs(config.keep_alive_secs);
+        builder = builder
+            .tcp_keepalive(interval)
+            .http2_keep_alive_interval(interval)
+            .http2_keep_alive_while_idle(true);
+    }
+    if config.http2_prior_knowledge {
+        builder = builder.http2_prior_knowledge();
+    }
+    if let Some(path) = &config.ca_cert {
+        let certs = reqwest::Certificate::from_pem_bundle(&read(path)?)
+            .wrap_err_with(|| format!("failed to parse the CA bundle `{}`", path.display()))?;
+        for cert in certs {
+            builder = builder.add_root_certificate(cert);
+        }
+    }
+    if let Some(path) = &config.client_cert {
+        let mut pem = read(path)?;
+        if let Some(key) = &config.client_key {
+            pem.push(b'\n');
+            pem.extend(read(key)?);
+        }
+        let identity = reqwest::Identity::from_pem(&pem).wrap_err_with(|| {
+            format!(
+                "failed to parse the client certificate `{}` and its key",
+                path.display()
+            )
+        })?;
+        builder = builder.identity(identity);
+    } else if config.client_key.is_some() {
+        color_eyre::eyre::bail!("`client_key` in `[http]` needs a `client_cert`");
+    }
+    if config.danger_accept_invalid_certs {
+        tracing::warn!("accepting invalid certificates, as `danger_accept_invalid_certs` is set");
+        builder = builder.tls_danger_accept_invalid_certs(true);
+    }
+    builder
+        .build()
+        .wrap_err_with(|| "failed to build HTTP client")
+}
+
+fn read(path: &Path) -> Result<Vec<u8>> {
+    std::fs::read(path).wrap_err_with(|| format!("failed to read `{}`", path.display()))
+}
+
+/// A `multipart/form-data` body, which reqwest is built without support for
+pub struct Multipart {
+    boundary: String,
+    body: Vec<u8>,
+}
+
+impl Default for Multipart {
+    fn default() -> Self {
+        // Parts are unlikely to contain the time in nanoseconds
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos();
+        Self {
+            boundary: format!("{}-{nanos:x}", crate::CARGO_PKG_NAME),
+            body: Vec::new(),
+        }
+    }
+}
+
+impl Multipart {
+    pub fn text(mut self, name: &str, value: &str) -> Self {
+        self.body.extend_from_slice(
+            format!(
+                "--{}\r\nContent-Disposition: form-data; name=\"{name}\"\r\n\r\n{value}\r\n",
+                self.boundary
+            )
+            .as_bytes(),
+        );
+        self
+    }
+
+    pub fn file(mut self, name: &str, file_name: &str, content_type: &str, content: &[u8]) -> Self {
+        self.body.extend_from_slice(
+            format!(
+                "--{}\r\nContent-Disposition: form-data; name=\"{name}\"; \
+                 filename=\"{file_name}\"\r\nContent-Type: {content_type}\r\n\r\n",
+                self.boundary
+            )
+            .as_bytes(),
+        );
+        self.body.extend_from_slice(content);
+        self.body.extend_from_slice(b"\r\n");
+        self
+    }
+
+    /// The `Content-Type` header and the body
+    pub fn finish(mut self) -> (String, Vec<u8>) {
+        self.body
+            .extend_from_slice(format!("--{}--\r\n", self.boundary).as_bytes());
+        (
+            format!("multipart/form-data; boundary={}", self.boundary),
+            self.body,
+        )
+    }
+}
@@ -0,0 +1,89 @@
+use std::sync::Mutex;
+
+use crate::openai::Usage;
+
+const MASK_VISIBLE_CHARS: usize = 4;
+
+/// API tokens used one at a time, moving on to the next when one is rejected or out of quota
+pub struct Keys {
+    tokens: Vec<String>,
+    state: Mutex<State>,
+}
+
+struct State {
+    active: usize,
+    health: Vec<KeyHealth>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct KeyHealth {
+    pub requests: u64,
+    pub total_tokens: u64,
+    pub failures: u64,
+    /// Why the key was last rotated away from, cleared by its next success
+    pub last_error: Option<String>,
+}
+
+impl Keys {
+    pub fn new(tokens: Vec<String>) -> Self {
+        let health = vec![KeyHealth::default(); tokens.len()];
+        Self {
+            tokens,
+            state: Mutex::new(State { active: 0, health }),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.tokens.len()
+    }
+
+    pub fn tokens(&self) -> &[String] {
+        &self.tokens
+    }
+
+    /// Index and token of the key to use next, an empty token if there are none
+    pub fn active(&self) -> (usize, &str) {
+        let active = self.state.lock().unwrap().active;
+        (active, self.tokens.get(active).map_or("", String::as_str))
+    }
+
+    pub fn succeeded(&self, index: usize, usage: Option<&Usage>) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(health) = state.health.get_mut(index) {
+            health.requests += 1;
+            health.total_tokens += usage.map_or(0, |usage| u64::from(usage.total_tokens));
+            health.last_error = None;
+        }
+    }
+
+    /// Records why key `index` failed and rotates to the next key if it is still active
+    pub fn failed(&self, index: usize, error: String) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(health) = state.health.get_mut(index) {
+            health.requests += 1;
+            health.failures += 1;
+            health.last_error = Some(error);
+        }
+        if state.active == index && !self.tokens.is_empty() {
+            state.active = (index + 1) % self.tokens.len();
+        }
+    }
+
+    /// Masked token, whether it is active, and health of every key
+    pub fn status(&self) -> Vec<(String, bool, KeyHealth)> {
+        let state = self.state.lock().unwrap();
+        self.tokens
+            .iter()
+            .zip(&state.health)
+            .enumerate()
+            .map(|(i, (token, health))| (mask(token), i == state.active, health.clone()))
+            .collect()
+    }
+}
+
+/// Keeps only the last few characters of a token
+fn mask(token: &str) -> String {
+    let chars = token.chars().collect::<Vec<_>>();
+    let visible = &chars[chars.len().saturating_sub(MASK_VISIBLE_CHARS)..];
+    format!("…{}", visible.iter().collect::<String>())
+}
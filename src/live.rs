@@ -0,0 +1,109 @@
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use indicatif::{ProgressBar, ProgressStyle};
+
+use crate::highlight::Highlighter;
+use crate::terminal;
+
+/// Least time between renders, as each one highlights the whole visible answer again
+const RENDER_INTERVAL: Duration = Duration::from_millis(80);
+/// Rows kept when the size of the terminal is unknown
+const DEFAULT_ROWS: usize = 24;
+
+/// The answer being streamed, drawn formatted in place of the spinner of its request as it
+/// arrives, then cleared with the spinner before the whole answer is printed
+#[derive(Default)]
+pub struct LivePane {
+    state: Mutex<State>,
+}
+
+#[derive(Default)]
+struct State {
+    bar: Option<Arc<ProgressBar>>,
+    text: String,
+    rendered_at: Option<Instant>,
+}
+
+impl LivePane {
+    /// Draws into `bar`, starting over as a retried request streams its answer again
+    pub fn attach(&self, bar: Arc<ProgressBar>) {
+        if let Ok(mut state) = self.state.lock() {
+            *state = State {
+                bar: Some(bar),
+                ..State::default()
+            };
+        }
+    }
+
+    pub fn detach(&self) {
+        if let Ok(mut state) = self.state.lock() {
+            *state = State::default();
+        }
+    }
+
+    /// Adds `delta` to the answer and renders its end, at most every [`RENDER_INTERVAL`]
+    pub fn push(&self, delta: &str, highlighter: Option<&Highlighter>) {
+        let Ok(mut state) = self.state.lock() else {
+            return;
+        };
+        state.text.push_str(delta);
+        if state
+            .rendered_at
+            .is_some_and(|at| at.elapsed() < RENDER_INTERVAL)
+        {
+            return;
+        }
+        let Some(bar) = &state.bar else {
+            return;
+        };
+
+        if state.rendered_at.is_none() {
+            if let Ok(style) = ProgressStyle::with_template("{msg}") {
+                bar.set_style(style);
+            }
+        }
+        // Lines that scrolled off the screen could not be redrawn, so only the end fits
+        let rows = terminal::rows()
+            .unwrap_or(DEFAULT_ROWS)
+            .saturating_sub(2)
+            .max(1);
+        let visible = tail(&state.text, rows);
+        bar.set_message(match highlighter {
+            Some(highlighter) => highlighter.highlight(&visible),
+            None => visible,
+        });
+        state.rendered_at = Some(Instant::now());
+    }
+}
+
+/// The last `rows` lines of `text`, after the fence of the code block they start in if any, so
+/// that they are highlighted as code
+fn tail(text: &str, rows: usize) -> String {
+    let lines = text.lines().collect::<Vec<_>>();
+    let start = lines.len().saturating_sub(rows);
+    let mut open_fence = None;
+    for line in &lines[..start] {
+        if line.trim_start().starts_with("```") {
+            open_fence = match open_fence {
+                Some(_) => None,
+                None => Some(*line),
+            };
+        }
+    }
+
+    let mut tail = open_fence
+        .into_iter()
+        .chain(
+            lines[start..]
+                .iter()
+                .copied()
+                .skip(usize::from(open_fence.is_some())),
+        )
+        .collect::<Vec<_>>()
+        .join("\n");
+    if text.ends_with('\n') {
+        tail.push('\n');
+    }
+    tail
+}
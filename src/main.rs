@@ -1,13 +1,91 @@
+mod attachment;
+mod bridge;
+mod budget;
+mod bundle;
+mod cassette;
+mod chunk;
+mod clipboard;
+mod codechunk;
+mod compress;
+mod confidence;
+mod conversation;
+mod custom;
+mod daemon;
+mod diagnostic;
+mod diff;
+mod encryption;
+mod external_editor;
+mod fetch;
+mod files;
+mod footer;
+mod fuzzy;
+mod gateway;
+mod git;
+mod glossary;
+mod help;
+mod highlight;
+mod html2md;
+mod http;
+mod keys;
+mod live;
+mod mcp;
+mod mock;
+mod notify;
+mod ocr;
+mod offline;
 mod openai;
+mod patch;
+mod plugin;
+mod pricing;
+mod pruning;
+mod readline;
+mod recovery;
+mod redaction;
+mod repomap;
+mod review;
+mod rpc;
+mod schedule;
 mod sermaid;
+mod server;
+mod share;
+mod speech;
+mod store;
+mod terminal;
+mod theme;
+mod title;
+mod tokenizer;
+mod transform;
+mod triage;
+mod typeahead;
+mod voice;
+#[cfg(feature = "wasm-plugins")]
+mod wasm;
+mod watch;
 
+use std::collections::BTreeMap;
 use std::path::PathBuf;
 
+use bridge::BridgeConfig;
 use clap::Parser;
 use color_eyre::eyre::{Context, Result};
+use compress::CompressionConfig;
+use custom::CustomCommand;
+use diagnostic::ExplainErrorConfig;
+use encryption::EncryptionConfig;
 use food::bin::ConfigPathGetter;
+use gateway::GatewayConfig;
+use http::HttpConfig;
+use mcp::McpServerConfig;
+use ocr::OcrConfig;
+use openai::Provider;
+use pruning::Pruning;
+use readline::EditorConfig;
+use recovery::AutosaveConfig;
 use serde::Deserialize;
-use sermaid::SerMaid;
+use sermaid::{OutputFormat, SerMaid};
+use share::ShareConfig;
+use theme::ThemeConfig;
+use voice::VoiceConfig;
 
 const CARGO_PKG_NAME: &str = env!("CARGO_PKG_NAME");
 
@@ -17,6 +95,42 @@ pub struct Args {
     /// Specify configuration file
     #[arg(short, long, value_name = "FILE", default_value = "./config.toml")]
     pub config: PathBuf,
+
+    /// Record API exchanges to a cassette file
+    #[arg(long, value_name = "FILE", conflicts_with = "replay")]
+    pub record: Option<PathBuf>,
+
+    /// Replay API exchanges from a cassette file instead of calling the API
+    #[arg(long, value_name = "FILE")]
+    pub replay: Option<PathBuf>,
+
+    /// Print answers without syntax highlighting code blocks
+    #[arg(long)]
+    pub plain: bool,
+
+    /// Print only the answer on stdout and errors on stderr, without spinner, highlighting or
+    /// colors, and exit with a non-zero status if the command fails, for shell pipelines and
+    /// scripts: 3 if the key was rejected, 4 if rate limited, 5 if a content filter refused the
+    /// prompt or cut the answer, 2 for an invalid command and 1 otherwise
+    #[arg(long, requires = "command")]
+    pub raw: bool,
+
+    /// Print each answer as a JSON object with the model, token usage, finish reason and
+    /// latency, implying `--raw`
+    #[arg(long, value_name = "FORMAT", requires = "command")]
+    pub output: Option<OutputFormat>,
+
+    /// Keep the line history, autosaves and caches in memory instead of writing them
+    #[arg(long)]
+    pub no_persist: bool,
+
+    /// Run a single REPL command instead of starting the REPL, e.g. `translate --to zh`
+    #[arg(
+        trailing_var_arg = true,
+        allow_hyphen_values = true,
+        value_name = "COMMAND"
+    )]
+    pub command: Vec<String>,
 }
 
 impl ConfigPathGetter for Args {
@@ -27,16 +141,128 @@ impl ConfigPathGetter for Args {
 
 #[derive(Deserialize)]
 struct Config {
-    api_token: String,
+    #[serde(default)]
+    provider: Provider,
+    /// One of the models of the provider, its first by default
+    model: Option<String>,
+    /// Base URL of the OpenAI-compatible API, overriding the provider's
+    endpoint: Option<String>,
+    fixtures_dir: Option<PathBuf>,
+    api_token: Option<String>,
+    /// More tokens to fail over to after `api_token`
+    #[serde(default)]
+    api_tokens: Vec<String>,
+    organization: Option<String>,
+    project: Option<String>,
+    /// Admin key of the organization, read by `quota` from OpenAI's costs API
+    admin_key: Option<String>,
+    #[serde(default)]
+    gateway: GatewayConfig,
+    /// Sampling seed for reproducible answers where the provider supports it, see `set seed`
+    seed: Option<u64>,
+    /// USD, checked against an estimate before each request
+    max_cost_per_request: Option<f64>,
+    /// USD per local day, across sessions
+    max_cost_per_day: Option<f64>,
+    /// Ask before sending a prompt of more tokens than this, history and attachments included
+    max_prompt_tokens: Option<u64>,
+    /// Ask before sending a prompt of more bytes than this
+    max_prompt_bytes: Option<u64>,
     history_file: Option<PathBuf>,
+    #[serde(default)]
+    http: HttpConfig,
+    #[serde(default)]
+    editor: EditorConfig,
+    #[serde(default)]
+    footer: bool,
+    /// Show the model, conversation name and context size above the prompt
+    #[serde(default)]
+    status_line: bool,
+    #[serde(default)]
+    show_reasoning: bool,
+    /// Show answers to `ask` and `continue` formatted as they are generated, unless
+    /// `mcp_servers` are configured
+    #[serde(default)]
+    stream: bool,
+    /// Set the terminal title, or the tmux pane title, to the conversation name and whether a
+    /// command is running
+    #[serde(default)]
+    terminal_title: bool,
+    /// Suggest follow-up questions after answers in the REPL
+    #[serde(default)]
+    follow_ups: bool,
+    /// Allows `ask --url`, which fetches pages from the network
+    #[serde(default)]
+    url_fetch: bool,
+    /// Save the live conversation as it grows, to restore it after a crash
+    autosave: Option<AutosaveConfig>,
+    /// Compress long prompts, reporting the tokens saved
+    compression: Option<CompressionConfig>,
+    /// Queue `ask` and `translate` requests that fail for lack of network, sent with `flush`
+    #[serde(default)]
+    offline_queue: bool,
+    /// Keep the conversations of every session in `~/.sermaid_store.db`, for `store`
+    #[serde(default)]
+    store: bool,
+    #[serde(default)]
+    pruning: Pruning,
+    /// Send at most the last N turns with `continue`, whatever `pruning` keeps, unless
+    /// `continue --all`
+    history_limit: Option<usize>,
+    highlight_theme: Option<String>,
+    #[serde(default)]
+    theme: ThemeConfig,
+    notify_after_secs: Option<u64>,
+    pr_template: Option<String>,
+    #[serde(default)]
+    explain_error: ExplainErrorConfig,
+    /// How `ask --ocr` reads images
+    #[serde(default)]
+    ocr: OcrConfig,
+    /// How `listen` records and transcribes questions
+    #[serde(default)]
+    voice: VoiceConfig,
+    #[serde(default)]
+    commands: BTreeMap<String, CustomCommand>,
+    wasm_plugins_dir: Option<PathBuf>,
+    #[serde(default)]
+    mcp_servers: BTreeMap<String, McpServerConfig>,
+    bridge: Option<BridgeConfig>,
+    /// Unix socket of `daemon` and `ctl`, `~/.sermaid_daemon.sock` by default
+    daemon_socket: Option<PathBuf>,
+    /// Where `share` uploads conversations
+    share: Option<ShareConfig>,
+    encryption: Option<EncryptionConfig>,
+    /// Extra patterns redacted on export, by placeholder name
+    #[serde(default)]
+    redaction_patterns: BTreeMap<String, String>,
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
     food::log::init(CARGO_PKG_NAME).wrap_err_with(|| "failed to initialize food::log")?;
+    terminal::install_panic_hook();
 
-    let (_, config): (Args, Config) = food::bin::get_args_and_config()
+    let (args, config): (Args, Config) = food::bin::get_args_and_config()
         .wrap_err_with(|| "failed to initialize arguments and config")?;
 
-    SerMaid::from_config(config)?.run().await
+    // `ctl` only talks to the daemon, skipping the setup of the REPL that the daemon saves
+    if args.command.first().is_some_and(|command| command == "ctl") {
+        let socket = config
+            .daemon_socket
+            .or_else(daemon::default_socket)
+            .ok_or_else(|| color_eyre::eyre::eyre!("no home directory for the daemon socket"))?;
+        return daemon::ctl(&socket, &args.command).await;
+    }
+
+    let mut sermaid = SerMaid::from_config(&args, config)?;
+    if args.command.is_empty() {
+        return sermaid.run().await;
+    }
+    sermaid.run_once(args.command).await?;
+    let code = sermaid.exit_code();
+    if (args.raw || args.output.is_some()) && code != 0 {
+        std::process::exit(code.into());
+    }
+    Ok(())
 }
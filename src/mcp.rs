@@ -0,0 +1,284 @@
+use std::collections::BTreeMap;
+use std::process::Stdio;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use color_eyre::eyre::{Context, Result};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader, Lines};
+use tokio::process::{Child, ChildStdin, ChildStdout};
+use tokio::sync::{Mutex, OnceCell};
+
+use crate::openai::{Tool, ToolCall};
+use crate::CARGO_PKG_NAME;
+
+const PROTOCOL_VERSION: &str = "2024-11-05";
+/// Joins server and tool names into a function name the chat API accepts
+const TOOL_NAME_SEPARATOR: &str = "__";
+
+/// How to start an MCP server, from the `[mcp_servers.<name>]` section of the config
+#[derive(Clone, Debug, Deserialize)]
+pub struct McpServerConfig {
+    pub command: String,
+    #[serde(default)]
+    pub args: Vec<String>,
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct McpTool {
+    pub name: String,
+    pub description: Option<String>,
+    #[serde(rename = "inputSchema")]
+    pub input_schema: Value,
+}
+
+/// The configured MCP servers, each started on first use
+pub struct Mcp {
+    servers: BTreeMap<String, Server>,
+}
+
+struct Server {
+    config: McpServerConfig,
+    client: OnceCell<McpClient>,
+}
+
+impl Mcp {
+    pub fn new(configs: BTreeMap<String, McpServerConfig>) -> Self {
+        Self {
+            servers: configs
+                .into_iter()
+                .map(|(name, config)| {
+                    (
+                        name,
+                        Server {
+                            config,
+                            client: OnceCell::new(),
+                        },
+                    )
+                })
+                .collect(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.servers.is_empty()
+    }
+
+    /// Names of the configured servers
+    pub fn servers(&self) -> impl Iterator<Item = &str> {
+        self.servers.keys().map(String::as_str)
+    }
+
+    async fn client(&self, server: &str) -> Result<&McpClient> {
+        let Some(server_entry) = self.servers.get(server) else {
+            color_eyre::eyre::bail!("no MCP server `{server}` in config");
+        };
+
+        server_entry
+            .client
+            .get_or_try_init(|| McpClient::spawn(&server_entry.config))
+            .await
+            .wrap_err_with(|| format!("failed to start MCP server `{server}`"))
+    }
+
+    /// Tools of every server, keyed by server name
+    pub async fn tools(&self) -> Result<BTreeMap<&str, Vec<McpTool>>> {
+        let mut tools = BTreeMap::new();
+        for name in self.servers.keys() {
+            tools.insert(name.as_str(), self.client(name).await?.list_tools().await?);
+        }
+        Ok(tools)
+    }
+
+    pub async fn call(&self, server: &str, tool: &str, arguments: Value) -> Result<String> {
+        self.client(server).await?.call_tool(tool, arguments).await
+    }
+
+    /// Every tool as a function for the chat API, named `<server>__<tool>`
+    pub async fn chat_tools(&self) -> Result<Vec<Tool>> {
+        Ok(self
+            .tools()
+            .await?
+            .into_iter()
+            .flat_map(|(server, tools)| {
+                tools.into_iter().map(move |tool| Tool {
+                    name: format!("{server}{TOOL_NAME_SEPARATOR}{}", tool.name),
+                    description: tool.description,
+                    parameters: tool.input_schema,
+                })
+            })
+            .collect())
+    }
+
+    /// Runs a tool call from the model, reporting failures as the output so the model sees them
+    pub async fn call_chat_tool(&self, tool_call: ToolCall) -> String {
+        let res = async {
+            let (server, tool) = tool_call
+                .name
+                .split_once(TOOL_NAME_SEPARATOR)
+                .ok_or_else(|| color_eyre::eyre::eyre!("unknown tool `{}`", tool_call.name))?;
+            let arguments = serde_json::from_str(&tool_call.arguments)
+                .wrap_err_with(|| format!("invalid arguments for tool `{}`", tool_call.name))?;
+            self.call(server, tool, arguments).await
+        }
+        .await;
+
+        res.unwrap_or_else(|err| format!("error: {err:#}"))
+    }
+}
+
+/// A JSON-RPC connection to an MCP server over its stdio
+struct McpClient {
+    _child: Child,
+    io: Mutex<Io>,
+    next_id: AtomicU64,
+}
+
+struct Io {
+    stdin: ChildStdin,
+    stdout: Lines<BufReader<ChildStdout>>,
+}
+
+impl McpClient {
+    async fn spawn(config: &McpServerConfig) -> Result<Self> {
+        let mut child = tokio::process::Command::new(&config.command)
+            .args(&config.args)
+            .envs(&config.env)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .kill_on_drop(true)
+            .spawn()
+            .wrap_err_with(|| format!("failed to run `{}`", config.command))?;
+
+        let io = Io {
+            stdin: child.stdin.take().expect("stdin is piped"),
+            stdout: BufReader::new(child.stdout.take().expect("stdout is piped")).lines(),
+        };
+
+        let client = Self {
+            _child: child,
+            io: Mutex::new(io),
+            next_id: AtomicU64::new(1),
+        };
+
+        client
+            .request(
+                "initialize",
+                json!({
+                    "protocolVersion": PROTOCOL_VERSION,
+                    "capabilities": {},
+                    "clientInfo": {
+                        "name": CARGO_PKG_NAME,
+                        "version": env!("CARGO_PKG_VERSION"),
+                    },
+                }),
+            )
+            .await?;
+        client
+            .notify("notifications/initialized", json!({}))
+            .await?;
+
+        Ok(client)
+    }
+
+    async fn list_tools(&self) -> Result<Vec<McpTool>> {
+        let mut tools = Vec::new();
+        let mut cursor: Option<String> = None;
+
+        loop {
+            let params = match &cursor {
+                Some(cursor) => json!({ "cursor": cursor }),
+                None => json!({}),
+            };
+            let result = self.request("tools/list", params).await?;
+
+            tools.extend(serde_json::from_value::<Vec<McpTool>>(
+                result.get("tools").cloned().unwrap_or_default(),
+            )?);
+
+            cursor = result
+                .get("nextCursor")
+                .and_then(Value::as_str)
+                .map(str::to_owned);
+            if cursor.is_none() {
+                return Ok(tools);
+            }
+        }
+    }
+
+    /// Calls `name` and joins the text parts of its result
+    async fn call_tool(&self, name: &str, arguments: Value) -> Result<String> {
+        let result = self
+            .request(
+                "tools/call",
+                json!({ "name": name, "arguments": arguments }),
+            )
+            .await?;
+
+        let text = result
+            .get("content")
+            .and_then(Value::as_array)
+            .into_iter()
+            .flatten()
+            .filter_map(|content| content.get("text").and_then(Value::as_str))
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        if result.get("isError").and_then(Value::as_bool) == Some(true) {
+            color_eyre::eyre::bail!("tool `{name}` failed: {text}");
+        }
+
+        Ok(text)
+    }
+
+    async fn request(&self, method: &str, params: Value) -> Result<Value> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let mut io = self.io.lock().await;
+
+        io.send(&json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params }))
+            .await?;
+
+        loop {
+            let line = io
+                .stdout
+                .next_line()
+                .await?
+                .ok_or_else(|| color_eyre::eyre::eyre!("MCP server closed its stdout"))?;
+            tracing::debug!("mcp <- {line}");
+
+            let Ok(message) = serde_json::from_str::<Value>(&line) else {
+                continue;
+            };
+            if message.get("id").and_then(Value::as_u64) != Some(id) {
+                continue;
+            }
+
+            if let Some(error) = message.get("error") {
+                color_eyre::eyre::bail!("MCP `{method}` failed: {error}");
+            }
+            return Ok(message.get("result").cloned().unwrap_or_default());
+        }
+    }
+
+    async fn notify(&self, method: &str, params: Value) -> Result<()> {
+        self.io
+            .lock()
+            .await
+            .send(&json!({ "jsonrpc": "2.0", "method": method, "params": params }))
+            .await
+    }
+}
+
+impl Io {
+    async fn send(&mut self, message: &Value) -> Result<()> {
+        let line = format!("{message}\n");
+        tracing::debug!("mcp -> {}", line.trim_end());
+
+        self.stdin.write_all(line.as_bytes()).await?;
+        self.stdin.flush().await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,83 @@
+use std::borrow::Cow;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+
+use color_eyre::eyre::{Context, Result};
+
+const FALLBACK_FIXTURE: &str = "default.txt";
+
+/// Replays canned answers from `<fixtures_dir>/<prompt hash>.txt`, falling back to
+/// `<fixtures_dir>/default.txt`
+pub struct Mock {
+    fixtures_dir: PathBuf,
+}
+
+impl Mock {
+    pub fn new(fixtures_dir: PathBuf) -> Self {
+        Self { fixtures_dir }
+    }
+
+    pub async fn reply(&self, prompt: &str) -> Result<Cow<'static, str>> {
+        let hash = prompt_hash(prompt);
+        tracing::debug!("mock prompt hash = {hash:016x}");
+
+        for file_name in [format!("{hash:016x}.txt"), FALLBACK_FIXTURE.to_owned()] {
+            let path = self.fixtures_dir.join(file_name);
+            match tokio::fs::read_to_string(&path).await {
+                Ok(content) => return Ok(content.trim_end().to_owned().into()),
+                Err(err) if err.kind() == ErrorKind::NotFound => continue,
+                Err(err) => {
+                    return Err(err).wrap_err_with(|| {
+                        format!("failed to read mock fixture `{}`", path.display())
+                    })
+                },
+            }
+        }
+
+        color_eyre::eyre::bail!(
+            "no mock fixture for prompt hash {hash:016x} in `{}`",
+            self.fixtures_dir.display()
+        );
+    }
+}
+
+/// FNV-1a, chosen over `DefaultHasher` so fixture names stay stable across Rust releases
+pub fn prompt_hash(prompt: &str) -> u64 {
+    prompt.bytes().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prompt_hash_is_fnv1a() {
+        assert_eq!(prompt_hash(""), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(prompt_hash("a"), 0xaf63_dc4c_8601_ec8c);
+    }
+
+    #[tokio::test]
+    async fn reply_reads_fixture_of_prompt_hash() {
+        let dir = tempfile::tempdir().unwrap();
+        let prompt = "user: hello\n";
+        let fixture = dir.path().join(format!("{:016x}.txt", prompt_hash(prompt)));
+        std::fs::write(fixture, "hi there\n").unwrap();
+        std::fs::write(dir.path().join(FALLBACK_FIXTURE), "fallback").unwrap();
+
+        let mock = Mock::new(dir.path().to_owned());
+        assert_eq!(mock.reply(prompt).await.unwrap(), "hi there");
+        assert_eq!(mock.reply("user: bye\n").await.unwrap(), "fallback");
+    }
+
+    #[tokio::test]
+    async fn reply_fails_without_fixture() {
+        let dir = tempfile::tempdir().unwrap();
+        let mock = Mock::new(dir.path().to_owned());
+
+        let err = mock.reply("user: hello\n").await.unwrap_err();
+        let hash = format!("{:016x}", prompt_hash("user: hello\n"));
+        assert!(err.to_string().contains(&hash), "{err}");
+    }
+}
@@ -0,0 +1,27 @@
+use std::process::{Command, Stdio};
+
+use color_eyre::eyre::{Context, Result};
+
+pub fn send(body: &str) -> Result<()> {
+    notify_rust::Notification::new()
+        .summary(crate::CARGO_PKG_NAME)
+        .body(body)
+        .show()
+        .wrap_err_with(|| "failed to show desktop notification")?;
+    Ok(())
+}
+
+/// Whether the terminal running sermaid is the active window, assumed not when unknown
+///
+/// Only X11 terminals that export `$WINDOWID` can be checked, using `xdotool`.
+pub fn terminal_focused() -> bool {
+    let Ok(window_id) = std::env::var("WINDOWID") else {
+        return false;
+    };
+
+    Command::new("xdotool")
+        .arg("getactivewindow")
+        .stderr(Stdio::null())
+        .output()
+        .is_ok_and(|output| String::from_utf8_lossy(&output.stdout).trim() == window_id.trim())
+}
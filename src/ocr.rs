@@ -0,0 +1,68 @@
+use std::path::Path;
+use std::process::Command;
+
+use base64::Engine;
+use color_eyre::eyre::{Context, Result};
+use serde::Deserialize;
+
+/// The `[ocr]` section of the config, for reading the text of images with `ask --ocr`
+#[derive(Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct OcrConfig {
+    /// Read images with a local `tesseract` instead of asking a vision model
+    pub tesseract: bool,
+    /// Languages of tesseract, like `eng+chi_sim`, its default otherwise
+    pub languages: Option<String>,
+    /// Vision model asked to read images, the configured model by default
+    pub model: Option<String>,
+}
+
+/// The text `tesseract` reads in the image at `path`
+pub fn tesseract(path: &Path, languages: Option<&str>) -> Result<String> {
+    let mut command = Command::new("tesseract");
+    command.arg(path).arg("stdout");
+    if let Some(languages) = languages {
+        command.args(["-l", languages]);
+    }
+    let output = command
+        .output()
+        .wrap_err_with(|| "failed to run `tesseract`, is it installed?")?;
+    if !output.status.success() {
+        color_eyre::eyre::bail!(
+            "`tesseract` failed to read `{}`: {}",
+            path.display(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_owned())
+}
+
+/// The image at `path` as a `data:` URL, which vision models accept in place of a link
+pub fn data_url(path: &Path) -> Result<String> {
+    let extension = path
+        .extension()
+        .map(|extension| extension.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    let mime = match extension.as_str() {
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        _ => color_eyre::eyre::bail!("`{}` is not a PNG, JPEG, GIF or WebP image", path.display()),
+    };
+    let image =
+        std::fs::read(path).wrap_err_with(|| format!("failed to read `{}`", path.display()))?;
+    Ok(encode(mime, &image))
+}
+
+/// The PNG image `png` as a `data:` URL
+pub fn png_data_url(png: &[u8]) -> String {
+    encode("image/png", png)
+}
+
+fn encode(mime: &str, image: &[u8]) -> String {
+    format!(
+        "data:{mime};base64,{}",
+        base64::engine::general_purpose::STANDARD.encode(image)
+    )
+}
@@ -0,0 +1,120 @@
+use std::io::ErrorKind;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use color_eyre::eyre::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::encryption::{self, Cipher};
+
+const QUEUE_FILE: &str = ".sermaid_queue.json";
+
+/// Requests that failed for lack of network, kept in `~/.sermaid_queue.json` until `flush`
+pub struct OfflineQueue {
+    path: PathBuf,
+    cipher: Option<Arc<Cipher>>,
+}
+
+/// A request waiting in the queue, with the conversation it was asked in
+#[derive(Serialize, Deserialize)]
+pub struct Queued {
+    pub request: QueuedRequest,
+    /// Name of the conversation, see the `name` command
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub conversation: Option<String>,
+    /// Seconds since the Unix epoch
+    pub timestamp: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "command", rename_all = "lowercase")]
+pub enum QueuedRequest {
+    Ask {
+        question: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        max_words: Option<u32>,
+    },
+    Translate {
+        text: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        to: Option<String>,
+    },
+}
+
+impl QueuedRequest {
+    /// The question or text, for listing the queue
+    pub fn text(&self) -> &str {
+        match self {
+            Self::Ask { question, .. } => question,
+            Self::Translate { text, .. } => text,
+        }
+    }
+
+    pub fn command(&self) -> &'static str {
+        match self {
+            Self::Ask { .. } => "ask",
+            Self::Translate { .. } => "translate",
+        }
+    }
+}
+
+impl OfflineQueue {
+    /// The queue in the home directory, encrypted like conversations if a `cipher` is given
+    pub fn new(cipher: Option<Arc<Cipher>>) -> Option<Self> {
+        let path = home::home_dir()?.join(QUEUE_FILE);
+        Some(Self { path, cipher })
+    }
+
+    pub fn load(&self) -> Result<Vec<Queued>> {
+        let mut contents = match std::fs::read(&self.path) {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(err) => {
+                return Err(err)
+                    .wrap_err_with(|| format!("failed to read `{}`", self.path.display()))
+            },
+        };
+        if encryption::is_encrypted(&contents) {
+            let cipher = self.cipher.as_ref().ok_or_else(|| {
+                color_eyre::eyre::eyre!(
+                    "`{}` is encrypted but no `[encryption]` is configured",
+                    self.path.display()
+                )
+            })?;
+            contents = cipher
+                .decrypt(&contents)
+                .wrap_err_with(|| format!("failed to decrypt `{}`", self.path.display()))?;
+        }
+
+        serde_json::from_slice(&contents)
+            .wrap_err_with(|| format!("failed to parse `{}`", self.path.display()))
+    }
+
+    /// Writes `queue` in place of the queue, removing the file once it is empty
+    pub fn save(&self, queue: &[Queued]) -> Result<()> {
+        if queue.is_empty() {
+            return match std::fs::remove_file(&self.path) {
+                Err(err) if err.kind() != ErrorKind::NotFound => {
+                    Err(err).wrap_err_with(|| format!("failed to remove `{}`", self.path.display()))
+                },
+                _ => Ok(()),
+            };
+        }
+
+        let mut contents =
+            serde_json::to_vec_pretty(queue).wrap_err_with(|| "failed to serialize queue")?;
+        if let Some(cipher) = &self.cipher {
+            contents = cipher.encrypt(&contents)?;
+        }
+        std::fs::write(&self.path, contents)
+            .wrap_err_with(|| format!("failed to write `{}`", self.path.display()))
+    }
+
+    /// Adds `queued` at the end and returns how many requests are waiting
+    pub fn push(&self, queued: Queued) -> Result<usize> {
+        let mut queue = self.load()?;
+        queue.push(queued);
+        self.save(&queue)?;
+        Ok(queue.len())
+    }
+}
@@ -1,123 +1,1334 @@
 use std::borrow::Cow;
+use std::fmt;
+use std::future::Future;
+use std::path::PathBuf;
+use std::sync::RwLock;
+use std::time::Duration;
 
-use color_eyre::eyre::Result;
+use chrono::Datelike;
+use color_eyre::eyre::{Context, Result};
+use reqwest::header::RETRY_AFTER;
 use reqwest::Client;
 use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+use tokio::time::Instant;
+
+use crate::budget::{Budget, PromptLimit};
+use crate::cassette::Cassette;
+use crate::conversation::ChatMessage;
+use crate::gateway::Gateway;
+use crate::glossary::Glossary;
+use crate::http::Multipart;
+use crate::keys::Keys;
+use crate::mock::Mock;
+use crate::tokenizer;
 
 const OPENAI_ENDPOINT_PREFIX: &str = "https://api.openai.com/v1";
-const MODEL: &str = "gpt-4-1106-preview";
+const DEEPSEEK_ENDPOINT_PREFIX: &str = "https://api.deepseek.com/v1";
+const MISTRAL_ENDPOINT_PREFIX: &str = "https://api.mistral.ai/v1";
+const GROQ_ENDPOINT_PREFIX: &str = "https://api.groq.com/openai/v1";
+const LLAMA_CPP_ENDPOINT_PREFIX: &str = "http://127.0.0.1:8080/v1";
+const OPENAI_MODELS: &[&str] = &["gpt-4-1106-preview", "gpt-4-turbo", "gpt-4o", "gpt-4o-mini"];
+const DEEPSEEK_MODELS: &[&str] = &["deepseek-chat", "deepseek-reasoner"];
+/// llama-server answers with whatever model it loaded, whatever the request names
+const LLAMA_CPP_MODELS: &[&str] = &["local"];
+const MISTRAL_MODELS: &[&str] = &[
+    "mistral-large-latest",
+    "mistral-small-latest",
+    "codestral-latest",
+    "open-mistral-nemo",
+];
+const GROQ_MODELS: &[&str] = &[
+    "llama-3.3-70b-versatile",
+    "llama-3.1-8b-instant",
+    "gemma2-9b-it",
+];
+/// Short names accepted as `model`, and the models they stand for
+const MISTRAL_ALIASES: &[(&str, &str)] = &[
+    ("large", "mistral-large-latest"),
+    ("small", "mistral-small-latest"),
+    ("codestral", "codestral-latest"),
+    ("nemo", "open-mistral-nemo"),
+];
+const GROQ_ALIASES: &[(&str, &str)] = &[
+    ("llama-70b", "llama-3.3-70b-versatile"),
+    ("llama-8b", "llama-3.1-8b-instant"),
+    ("gemma", "gemma2-9b-it"),
+];
+/// Free tiers allow about one request per second on Mistral and 30 per minute on Groq
+const MISTRAL_REQUEST_INTERVAL: Duration = Duration::from_secs(1);
+const GROQ_REQUEST_INTERVAL: Duration = Duration::from_secs(2);
+
+const TERSE_PROMPT: &str = "回答问题，不需要复述，除非被要求否则不举例子、不做额外解释，禁止胡编";
+const DETAILED_PROMPT: &str = "回答问题，不需要复述，可以详细解释并举例，禁止胡编";
+const REVIEW_PROMPT: &str =
+    "审查以下 git diff，指出缺陷、风险和可改进之处。行首数字为新文件中的行号。\
+                             每条意见单独一行，格式为`<行号>: <意见>`，没有意见则只回复 LGTM";
+const PR_DESC_PROMPT: &str =
+    "根据提交记录和 diff 撰写 pull request，第一行为标题，空一行后为正文，\
+                              使用与提交记录相同的语言";
+const EXPLAIN_ERROR_PROMPT: &str =
+    "解释以下编译错误的原因，结合源码给出具体的修复建议，使用与错误信息相同的语言，禁止胡编";
+const TRIAGE_PROMPT: &str =
+    "针对以下 Rust 项目中的失败，先说明最可能的原因，再给出按优先级排序的修复步骤，每步一行，\
+                             结合源码，使用与错误信息相同的语言，禁止胡编";
+const CHEATSHEET_PROMPT: &str =
+    "根据以下命令信息为命令行工具 sermaid 编写一页 Markdown 速查表，按用途分组，每个命令一行，\
+                                 列出别名和最常用的参数，只使用给出的信息，禁止编造命令或参数";
+const OCR_PROMPT: &str =
+    "逐字转录图片中的全部文字，尽量保留换行和缩进，代码和表格保持原样，不翻译、不解释，只输出文字";
+const SUMMARY_PROMPT: &str =
+    "将对话浓缩为简明摘要，保留事实、结论、约定和未解决的问题，供后续对话参考，只输出摘要";
+const FOLLOW_UPS_PROMPT: &str =
+    "根据对话，提出 2 到 3 个用户接下来可能会问的问题，使用与对话相同的语言，每行一个，不编号，只输出问题";
+const FOLLOW_UPS_TEMPERATURE: f32 = 0.7;
+/// Choices sampled at temperature 0 would all be the same
+const CHOICES_TEMPERATURE: f32 = 1.0;
+const FUNCTION_TYPE: &str = "function";
+const INSUFFICIENT_QUOTA: &str = "insufficient_quota";
+const CONTEXT_LENGTH_EXCEEDED: &str = "context_length_exceeded";
+const MODEL_NOT_FOUND: &str = "model_not_found";
+const INVALID_API_KEY: &str = "invalid_api_key";
+const CONTENT_FILTER: &str = "content_filter";
+const CONTENT_POLICY_VIOLATION: &str = "content_policy_violation";
+/// Sample rate of the PCM returned by [`OpenAI::speech`]
+pub const SPEECH_SAMPLE_RATE: u32 = 24_000;
+const MAX_TOOL_ROUNDS: usize = 8;
+const TOKENS_PER_WORD: u32 = 3;
+const DETAILED_MAX_TOKENS: u32 = 4096;
+/// Completion length assumed for cost estimates of requests without `max_tokens`
+const EXPECTED_COMPLETION_TOKENS: u32 = 1024;
+
+const ORGANIZATION_ENV: &str = "OPENAI_ORG_ID";
+const PROJECT_ENV: &str = "OPENAI_PROJECT_ID";
+const ADMIN_KEY_ENV: &str = "OPENAI_ADMIN_KEY";
+
+#[derive(Clone, Copy, Debug, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Provider {
+    #[default]
+    OpenAI,
+    DeepSeek,
+    Mistral,
+    Groq,
+    /// llama.cpp's HTTP server, `llama-server`
+    #[serde(rename = "llama-cpp")]
+    LlamaCpp,
+    Mock,
+}
+
+impl Provider {
+    /// Whether requests may constrain answers with a GBNF grammar
+    pub fn supports_grammar(&self) -> bool {
+        matches!(self, Provider::LlamaCpp | Provider::Mock)
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Provider::OpenAI => "openai",
+            Provider::DeepSeek => "deepseek",
+            Provider::Mistral => "mistral",
+            Provider::Groq => "groq",
+            Provider::LlamaCpp => "llama-cpp",
+            Provider::Mock => "mock",
+        }
+    }
+
+    fn endpoint_prefix(&self) -> &'static str {
+        match self {
+            Provider::OpenAI | Provider::Mock => OPENAI_ENDPOINT_PREFIX,
+            Provider::DeepSeek => DEEPSEEK_ENDPOINT_PREFIX,
+            Provider::Mistral => MISTRAL_ENDPOINT_PREFIX,
+            Provider::Groq => GROQ_ENDPOINT_PREFIX,
+            Provider::LlamaCpp => LLAMA_CPP_ENDPOINT_PREFIX,
+        }
+    }
+
+    /// Chat models of the provider, the first being the default
+    pub fn models(&self) -> &'static [&'static str] {
+        match self {
+            Provider::OpenAI | Provider::Mock => OPENAI_MODELS,
+            Provider::DeepSeek => DEEPSEEK_MODELS,
+            Provider::Mistral => MISTRAL_MODELS,
+            Provider::Groq => GROQ_MODELS,
+            Provider::LlamaCpp => LLAMA_CPP_MODELS,
+        }
+    }
+
+    fn aliases(&self) -> &'static [(&'static str, &'static str)] {
+        match self {
+            Provider::Mistral => MISTRAL_ALIASES,
+            Provider::Groq => GROQ_ALIASES,
+            Provider::OpenAI | Provider::DeepSeek | Provider::LlamaCpp | Provider::Mock => &[],
+        }
+    }
+
+    /// The model `model` stands for if it is an alias
+    pub fn resolve(&self, model: String) -> String {
+        self.aliases()
+            .iter()
+            .find(|(alias, _)| *alias == model)
+            .map_or(model, |(_, resolved)| (*resolved).to_owned())
+    }
+
+    /// The model of the provider that `model` names, which may be an alias, or the dated snapshot
+    /// of a model that answers report, or `None` if the provider has no such model
+    pub fn known_model(&self, model: &str) -> Option<String> {
+        let model = self.resolve(model.to_owned());
+        if matches!(self, Provider::LlamaCpp) || self.models().contains(&model.as_str()) {
+            return Some(model);
+        }
+        self.models()
+            .iter()
+            .filter(|known| {
+                model
+                    .strip_prefix(**known)
+                    .is_some_and(|rest| rest.starts_with('-'))
+            })
+            .max_by_key(|known| known.len())
+            .map(|known| (*known).to_owned())
+    }
+
+    /// Least time between requests to stay under the rate limit of the provider
+    fn request_interval(&self) -> Option<Duration> {
+        match self {
+            Provider::Mistral => Some(MISTRAL_REQUEST_INTERVAL),
+            Provider::Groq => Some(GROQ_REQUEST_INTERVAL),
+            Provider::OpenAI | Provider::DeepSeek | Provider::LlamaCpp | Provider::Mock => None,
+        }
+    }
+}
+
+enum Backend {
+    Http(Client),
+    Mock(Mock),
+}
 
 pub struct OpenAI {
-    api_token: String,
-    cli: Client,
+    provider: Provider,
+    endpoint_prefix: String,
+    model: String,
+    keys: Keys,
+    organization: Option<String>,
+    project: Option<String>,
+    /// Key of OpenAI's administration APIs, for `quota`
+    admin_key: Option<String>,
+    gateway: Gateway,
+    backend: Backend,
+    cassette: Option<Cassette>,
+    budget: Option<Budget>,
+    prompt_limit: Option<PromptLimit>,
+    /// Sampling seed, changed with `set seed`
+    seed: RwLock<Option<u64>>,
+    last_request: Mutex<Option<Instant>>,
 }
 
 impl OpenAI {
-    pub fn new(api_token: String) -> Self {
+    /// Uses the first of `api_tokens`, failing over to the next on auth and quota errors, and
+    /// sends requests with `client`, such as one tuned by the `[http]` config
+    pub fn new(api_tokens: Vec<String>, client: Client) -> Self {
+        Self {
+            provider: Provider::OpenAI,
+            endpoint_prefix: Provider::OpenAI.endpoint_prefix().to_owned(),
+            model: Provider::OpenAI.models()[0].to_owned(),
+            keys: Keys::new(api_tokens),
+            organization: std::env::var(ORGANIZATION_ENV).ok(),
+            project: std::env::var(PROJECT_ENV).ok(),
+            admin_key: std::env::var(ADMIN_KEY_ENV).ok(),
+            gateway: Gateway::default(),
+            backend: Backend::Http(client),
+            cassette: None,
+            budget: None,
+            prompt_limit: None,
+            seed: RwLock::new(None),
+            last_request: Mutex::new(None),
+        }
+    }
+
+    /// Answers from canned fixtures instead of the API, for offline development and tests
+    pub fn mock(fixtures_dir: PathBuf) -> Self {
         Self {
-            api_token,
-            cli: Client::new(),
+            provider: Provider::Mock,
+            endpoint_prefix: Provider::Mock.endpoint_prefix().to_owned(),
+            model: Provider::Mock.models()[0].to_owned(),
+            keys: Keys::new(Vec::new()),
+            organization: None,
+            project: None,
+            admin_key: None,
+            gateway: Gateway::default(),
+            backend: Backend::Mock(Mock::new(fixtures_dir)),
+            cassette: None,
+            budget: None,
+            prompt_limit: None,
+            seed: RwLock::new(None),
+            last_request: Mutex::new(None),
+        }
+    }
+
+    /// Sends requests to the OpenAI-compatible API of `provider`, defaulting to its first model
+    pub fn with_provider(mut self, provider: Provider) -> Self {
+        self.provider = provider;
+        self.endpoint_prefix = provider.endpoint_prefix().to_owned();
+        self.model = provider.models()[0].to_owned();
+        self
+    }
+
+    /// Sends requests to `endpoint`, such as `http://host:port/v1`, instead of the provider's
+    pub fn with_endpoint(mut self, endpoint: Option<String>) -> Self {
+        if let Some(endpoint) = endpoint {
+            self.endpoint_prefix = endpoint.trim_end_matches('/').to_owned();
+        }
+        self
+    }
+
+    /// Asks `model`, or the model it is an alias of, instead of the default model of the provider
+    pub fn with_model(mut self, model: Option<String>) -> Self {
+        if let Some(model) = model {
+            self.model = self.provider.resolve(model);
+        }
+        self
+    }
+
+    pub fn model(&self) -> &str {
+        &self.model
+    }
+
+    /// Asks `model` from now on, see [`OpenAI::with_model`]
+    pub fn set_model(&mut self, model: String) {
+        self.model = self.provider.resolve(model);
+    }
+
+    pub fn provider(&self) -> Provider {
+        self.provider
+    }
+
+    /// Sends `OpenAI-Organization` with every request, overriding `OPENAI_ORG_ID`
+    pub fn with_organization(mut self, organization: Option<String>) -> Self {
+        if organization.is_some() {
+            self.organization = organization;
+        }
+        self
+    }
+
+    /// Records API exchanges to `path`, with the API tokens redacted
+    pub fn with_record(mut self, path: PathBuf) -> Self {
+        self.cassette = Some(Cassette::record(path, self.keys.tokens().to_vec()));
+        self
+    }
+
+    /// Refuses requests that would exceed the cost ceilings of `budget`
+    pub fn with_budget(mut self, budget: Budget) -> Self {
+        self.budget = Some(budget);
+        self
+    }
+
+    pub fn budget(&self) -> Option<&Budget> {
+        self.budget.as_ref()
+    }
+
+    pub fn with_prompt_limit(mut self, limit: PromptLimit) -> Self {
+        self.prompt_limit = Some(limit);
+        self
+    }
+
+    pub fn prompt_limit(&self) -> Option<&PromptLimit> {
+        self.prompt_limit.as_ref()
+    }
+
+    pub fn keys(&self) -> &Keys {
+        &self.keys
+    }
+
+    /// Replays API exchanges from `path` instead of sending requests
+    pub fn with_replay(mut self, path: PathBuf) -> Result<Self> {
+        self.cassette = Some(Cassette::replay(path)?);
+        Ok(self)
+    }
+
+    /// Sends `OpenAI-Project` with every request, overriding `OPENAI_PROJECT_ID`
+    pub fn with_project(mut self, project: Option<String>) -> Self {
+        if project.is_some() {
+            self.project = project;
+        }
+        self
+    }
+
+    /// Sends the headers of a gateway in front of the API with every request, signed if it
+    /// asks for it
+    pub fn with_gateway(mut self, gateway: Gateway) -> Self {
+        self.gateway = gateway;
+        self
+    }
+
+    /// Asks for reproducible answers, where the provider supports it
+    pub fn with_seed(self, seed: Option<u64>) -> Self {
+        self.set_seed(seed);
+        self
+    }
+
+    pub fn set_seed(&self, seed: Option<u64>) {
+        if let Ok(mut current) = self.seed.write() {
+            *current = seed;
+        }
+    }
+
+    pub fn seed(&self) -> Option<u64> {
+        self.seed.read().ok().and_then(|seed| *seed)
+    }
+
+    /// Reads OpenAI's costs with this admin key, overriding `OPENAI_ADMIN_KEY`
+    pub fn with_admin_key(mut self, admin_key: Option<String>) -> Self {
+        if admin_key.is_some() {
+            self.admin_key = admin_key;
         }
+        self
     }
 
     pub async fn q_and_a<S>(
         &self,
         question: S,
-        history_questions: &[String],
-        history_answers: &[Cow<'static, str>],
-    ) -> Result<Cow<'static, str>>
+        history: &[ChatMessage],
+        length: Length,
+    ) -> Result<Completion>
     where
         S: Into<Cow<'static, str>>,
     {
-        let mut req = Request::new().with_temperature(0).append(Message::new(
-            "回答问题，不需要复述，除非被要求否则不举例子、不做额外解释，禁止胡编",
-            Role::System,
-        ));
-
-        let mut history_questions_iter = history_questions.iter();
-        let mut history_answers_iter = history_answers.iter();
-        loop {
-            let history_question = history_questions_iter.next();
-            let history_answer = history_answers_iter.next();
-
-            if history_question.is_none() && history_answer.is_none() {
-                break;
-            }
+        self.chat_completions(&self.q_and_a_request(question, history, length))
+            .await
+    }
+
+    /// Like [`OpenAI::q_and_a`], but calls `on_delta` with each piece of the answer as it is
+    /// generated
+    pub async fn q_and_a_streaming<S>(
+        &self,
+        question: S,
+        history: &[ChatMessage],
+        length: Length,
+        mut on_delta: impl FnMut(&str) + Send,
+    ) -> Result<Completion>
+    where
+        S: Into<Cow<'static, str>>,
+    {
+        let mut req = self.q_and_a_request(question, history, length);
+        // Mock and recorded answers come whole
+        if self.cassette.is_none() && matches!(self.backend, Backend::Http(_)) {
+            let include_usage = matches!(self.provider, Provider::OpenAI | Provider::DeepSeek);
+            req = req.with_stream(include_usage);
+        }
+        self.send(&req, &mut on_delta).await
+    }
 
-            if let Some(history_question) = history_question {
-                req = req.append(Message::new(history_question.to_owned(), Role::User));
+    /// Like [`OpenAI::q_and_a`], but generates `n` answers to choose from
+    pub async fn q_and_a_with_choices<S>(
+        &self,
+        question: S,
+        history: &[ChatMessage],
+        length: Length,
+        n: u32,
+    ) -> Result<Completion>
+    where
+        S: Into<Cow<'static, str>>,
+    {
+        let req = self
+            .q_and_a_request(question, history, length)
+            .with_temperature(CHOICES_TEMPERATURE)
+            .with_choices(n);
+        self.chat_completions(&req).await
+    }
+
+    /// Like [`OpenAI::q_and_a`], but constrains the answer to a GBNF `grammar`
+    pub async fn q_and_a_with_grammar<S>(
+        &self,
+        question: S,
+        history: &[ChatMessage],
+        length: Length,
+        grammar: &str,
+    ) -> Result<Completion>
+    where
+        S: Into<Cow<'static, str>>,
+    {
+        if !self.provider.supports_grammar() {
+            color_eyre::eyre::bail!(
+                "provider `{}` does not support grammars, use `llama-cpp`",
+                self.provider.as_str()
+            );
+        }
+
+        let req = self
+            .q_and_a_request(question, history, length)
+            .with_grammar(grammar);
+        self.chat_completions(&req).await
+    }
+
+    /// Like [`OpenAI::q_and_a`], but starts the answer with `prefill` for the model to
+    /// continue, which steers its format
+    ///
+    /// Servers that support it answer with the continuation only, so `prefill` is prepended
+    /// to the answer returned.
+    pub async fn q_and_a_with_prefill<S>(
+        &self,
+        question: S,
+        history: &[ChatMessage],
+        length: Length,
+        prefill: &str,
+    ) -> Result<Completion>
+    where
+        S: Into<Cow<'static, str>>,
+    {
+        let req = self
+            .q_and_a_request(question, history, length)
+            .append(Message::prefill(prefill, &self.provider));
+        let mut completion = self.chat_completions(&req).await?;
+        completion.content = format!("{prefill}{}", completion.content).into();
+        Ok(completion)
+    }
+
+    /// Like [`OpenAI::q_and_a`], but sends the images at `images`, such as `data:` URLs, along
+    /// with the question for vision models
+    pub async fn q_and_a_with_images<S>(
+        &self,
+        question: S,
+        history: &[ChatMessage],
+        length: Length,
+        images: &[String],
+    ) -> Result<Completion>
+    where
+        S: Into<Cow<'static, str>>,
+    {
+        let mut req = self.q_and_a_request(question, history, length);
+        if let Some(question) = req.messages.last_mut() {
+            question.images.extend_from_slice(images);
+        }
+        self.chat_completions(&req).await
+    }
+
+    /// Like [`OpenAI::q_and_a`], but returns the log probability of each token of the answer
+    /// and of the `top_logprobs` likeliest tokens in its place
+    pub async fn q_and_a_with_logprobs<S>(
+        &self,
+        question: S,
+        history: &[ChatMessage],
+        length: Length,
+        top_logprobs: u8,
+    ) -> Result<Completion>
+    where
+        S: Into<Cow<'static, str>>,
+    {
+        let req = self
+            .q_and_a_request(question, history, length)
+            .with_logprobs(top_logprobs);
+        self.chat_completions(&req).await
+    }
+
+    /// Like [`OpenAI::q_and_a`], but lets the model call `tools` through `call_tool` until it
+    /// answers
+    pub async fn q_and_a_with_tools<S, F, Fut>(
+        &self,
+        question: S,
+        history: &[ChatMessage],
+        length: Length,
+        tools: &[Tool],
+        mut call_tool: F,
+    ) -> Result<Completion>
+    where
+        S: Into<Cow<'static, str>>,
+        F: FnMut(ToolCall) -> Fut,
+        Fut: Future<Output = String>,
+    {
+        let mut req = self
+            .q_and_a_request(question, history, length)
+            .with_tools(tools);
+
+        for _ in 0..MAX_TOOL_ROUNDS {
+            let completion = self.chat_completions(&req).await?;
+            if completion.tool_calls.is_empty() {
+                return Ok(completion);
             }
 
-            if let Some(history_answer) = history_answer {
-                req = req.append(Message::new(history_answer.clone(), Role::Assistant));
+            req = req.append(Message::tool_calls(&completion.tool_calls));
+            for tool_call in completion.tool_calls {
+                let id = tool_call.id.clone();
+                let output = call_tool(tool_call).await;
+                req = req.append(Message::tool_output(id, output));
             }
         }
 
-        req = req.append(Message::new(question, Role::User));
+        color_eyre::eyre::bail!("model kept calling tools after {MAX_TOOL_ROUNDS} rounds");
+    }
+
+    pub async fn translate<S>(&self, raw_text: S, to: Option<&str>) -> Result<Completion>
+    where
+        S: Into<Cow<'static, str>>,
+    {
+        let req = self
+            .request()
+            .with_temperature(0.0)
+            .append(Message::new(translate_prompt(to), Role::System))
+            .append(Message::new(raw_text, Role::User));
 
         self.chat_completions(&req).await
     }
 
-    pub async fn translate<S>(&self, raw_text: S) -> Result<Cow<'static, str>>
+    /// Translates one chunk of a longer document, keeping to the terms in `glossary`
+    pub async fn translate_chunk<S>(
+        &self,
+        chunk: S,
+        to: Option<&str>,
+        glossary: &Glossary,
+    ) -> Result<Completion>
     where
         S: Into<Cow<'static, str>>,
     {
-        let req = Request::new()
-            .with_temperature(0)
+        let system = format!(
+            "{}，这是长文档中的一段。{}",
+            translate_prompt(to),
+            glossary.instruction()
+        );
+
+        let req = self
+            .request()
+            .with_temperature(0.0)
+            .append(Message::new(system, Role::System))
+            .append(Message::new(chunk, Role::User));
+
+        self.chat_completions(&req).await
+    }
+
+    /// Suggests questions that could follow the conversation in `messages`, one per line
+    pub async fn follow_ups(&self, messages: &[ChatMessage]) -> Result<Completion> {
+        let conversation = messages
+            .iter()
+            .map(|message| format!("{}: {}\n", message.role.as_str(), message.content))
+            .collect::<String>();
+
+        let req = self
+            .request()
+            .with_temperature(FOLLOW_UPS_TEMPERATURE)
+            .append(Message::new(FOLLOW_UPS_PROMPT, Role::System))
+            .append(Message::new(conversation, Role::User));
+
+        self.chat_completions(&req).await
+    }
+
+    /// Folds `messages` into `summary`, the summary of the conversation before them
+    pub async fn summarize(
+        &self,
+        summary: Option<&str>,
+        messages: &[ChatMessage],
+    ) -> Result<Completion> {
+        let mut conversation = summary
+            .map(|summary| format!("已有摘要：\n{summary}\n\n后续对话：\n"))
+            .unwrap_or_default();
+        for message in messages {
+            conversation.push_str(&format!("{}: {}\n", message.role.as_str(), message.content));
+        }
+
+        let req = self
+            .request()
+            .with_temperature(0.0)
+            .append(Message::new(SUMMARY_PROMPT, Role::System))
+            .append(Message::new(conversation, Role::User));
+
+        self.chat_completions(&req).await
+    }
+
+    pub async fn review(&self, diff: &str) -> Result<Completion> {
+        let req = self
+            .request()
+            .with_temperature(0.0)
+            .append(Message::new(REVIEW_PROMPT, Role::System))
+            .append(Message::new(diff.to_owned(), Role::User));
+
+        self.chat_completions(&req).await
+    }
+
+    pub async fn pr_description(
+        &self,
+        commits: &str,
+        diff: &str,
+        template: Option<&str>,
+    ) -> Result<Completion> {
+        let mut system = PR_DESC_PROMPT.to_owned();
+        if let Some(template) = template {
+            system.push_str(&format!("，正文遵循以下模板：\n\n{template}"));
+        }
+
+        let req = self
+            .request()
+            .with_temperature(0.0)
+            .append(Message::new(system, Role::System))
             .append(Message::new(
-                "翻成中文，用户输入中文则翻成英语",
-                Role::System,
-            ))
-            .append(Message::new(raw_text, Role::User));
+                format!("提交记录：\n{commits}\n\ndiff：\n{diff}"),
+                Role::User,
+            ));
+
+        self.chat_completions(&req).await
+    }
+
+    pub async fn explain_error(&self, error: String) -> Result<Completion> {
+        let req = self
+            .request()
+            .with_temperature(0.0)
+            .append(Message::new(EXPLAIN_ERROR_PROMPT, Role::System))
+            .append(Message::new(error, Role::User));
 
         self.chat_completions(&req).await
     }
 
-    async fn chat_completions(&self, req: &Request) -> Result<Cow<'static, str>> {
-        let url = format!("{OPENAI_ENDPOINT_PREFIX}/chat/completions");
+    pub async fn triage(&self, failure: String) -> Result<Completion> {
+        let req = self
+            .request()
+            .with_temperature(0.0)
+            .append(Message::new(TRIAGE_PROMPT, Role::System))
+            .append(Message::new(failure, Role::User));
 
+        self.chat_completions(&req).await
+    }
+
+    pub async fn cheatsheet(&self, reference: String) -> Result<Completion> {
         let req = self
-            .cli
-            .post(url)
-            .bearer_auth(&self.api_token)
-            .json(req)
-            .build()?;
-        tracing::debug!(
-            "chat_completions req = {:?}",
-            String::from_utf8(req.body().unwrap().as_bytes().unwrap().to_vec()).unwrap()
-        );
+            .request()
+            .with_temperature(0.0)
+            .append(Message::new(CHEATSHEET_PROMPT, Role::System))
+            .append(Message::new(reference, Role::User));
+
+        self.chat_completions(&req).await
+    }
+
+    /// Asks `model`, or the configured model, for the text in the image at `image_url`
+    pub async fn ocr(&self, image_url: String, model: Option<&str>) -> Result<Completion> {
+        let mut req = self
+            .request()
+            .with_temperature(0.0)
+            .append(Message::new(OCR_PROMPT, Role::System))
+            .append(Message::new("", Role::User).with_image(image_url));
+        if let Some(model) = model {
+            req.model = model.to_owned();
+        }
+
+        self.chat_completions(&req).await
+    }
+
+    /// The text spoken in `wav`, transcribed by `model` at the audio endpoint of the provider
+    pub async fn transcribe(
+        &self,
+        wav: &[u8],
+        model: &str,
+        language: Option<&str>,
+    ) -> Result<String> {
+        let Backend::Http(cli) = &self.backend else {
+            color_eyre::eyre::bail!("the mock backend cannot transcribe audio");
+        };
+
+        let mut form =
+            Multipart::default()
+                .text("model", model)
+                .file("file", "speech.wav", "audio/wav", wav);
+        if let Some(language) = language {
+            form = form.text("language", language);
+        }
+        let (content_type, body) = form.finish();
+
+        self.pace().await;
+        let url = format!("{}/audio/transcriptions", self.endpoint_prefix);
+        let mut req = cli
+            .post(&url)
+            .header(reqwest::header::CONTENT_TYPE, content_type)
+            .body(body);
+        let (_, api_token) = self.keys.active();
+        if !api_token.is_empty() {
+            req = req.bearer_auth(api_token);
+        }
+        let transcription = self
+            .execute(cli, req)
+            .await?
+            .error_for_status()
+            .wrap_err_with(|| format!("failed to transcribe with `{model}`"))?
+            .json::<Transcription>()
+            .await
+            .wrap_err_with(|| "failed to parse the transcription")?;
+        Ok(transcription.text.trim().to_owned())
+    }
+
+    /// `text` read aloud by `voice` of `model` at the speech endpoint of the provider, as 16-bit
+    /// mono PCM at [`SPEECH_SAMPLE_RATE`]
+    pub async fn speech(&self, text: &str, model: &str, voice: &str) -> Result<Vec<u8>> {
+        let Backend::Http(cli) = &self.backend else {
+            color_eyre::eyre::bail!("the mock backend cannot synthesize speech");
+        };
+
+        let body = serde_json::json!({
+            "model": model,
+            "voice": voice,
+            "input": text,
+            "response_format": "pcm",
+        });
+        self.pace().await;
+        let url = format!("{}/audio/speech", self.endpoint_prefix);
+        let mut req = cli.post(&url).json(&body);
+        let (_, api_token) = self.keys.active();
+        if !api_token.is_empty() {
+            req = req.bearer_auth(api_token);
+        }
+        let speech = self
+            .execute(cli, req)
+            .await?
+            .error_for_status()
+            .wrap_err_with(|| format!("failed to synthesize speech with `{model}`"))?
+            .bytes()
+            .await
+            .wrap_err_with(|| "failed to read the speech")?;
+        Ok(speech.to_vec())
+    }
+
+    /// What the provider's usage or billing API reports of the account, for `quota`
+    ///
+    /// OpenAI's costs API only accepts admin keys, so the admin key is used in place of the
+    /// active key there.
+    pub async fn quota(&self) -> Result<Quota> {
+        let Backend::Http(cli) = &self.backend else {
+            color_eyre::eyre::bail!("the mock backend has no usage API");
+        };
+
+        match self.provider {
+            Provider::OpenAI => {
+                let admin_key = self.admin_key.as_deref().ok_or_else(|| {
+                    color_eyre::eyre::eyre!(
+                        "OpenAI's costs API needs an admin key, set `admin_key` or `${ADMIN_KEY_ENV}`"
+                    )
+                })?;
+                let month_start = chrono::Utc::now()
+                    .date_naive()
+                    .with_day(1)
+                    .and_then(|day| day.and_hms_opt(0, 0, 0))
+                    .map_or(0, |start| start.and_utc().timestamp());
+                let url = format!("{}/organization/costs", self.endpoint_prefix);
+
+                let mut spent = 0.0;
+                let mut page = None;
+                loop {
+                    let mut query = vec![
+                        ("start_time", month_start.to_string()),
+                        ("bucket_width", "1d".to_owned()),
+                        ("limit", "31".to_owned()),
+                    ];
+                    if let Some(page) = page.take() {
+                        query.push(("page", page));
+                    }
+                    let page_url = reqwest::Url::parse_with_params(&url, &query)
+                        .wrap_err_with(|| format!("invalid endpoint `{url}`"))?;
+                    let costs = self
+                        .execute(cli, cli.get(page_url).bearer_auth(admin_key))
+                        .await?
+                        .error_for_status()
+                        .wrap_err_with(|| "failed to get the costs of the organization")?
+                        .json::<CostPage>()
+                        .await
+                        .wrap_err_with(|| "failed to parse the costs of the organization")?;
+                    spent += costs
+                        .data
+                        .iter()
+                        .flat_map(|bucket| &bucket.results)
+                        .map(|result| result.amount.value)
+                        .sum::<f64>();
+                    match costs.next_page {
+                        Some(next_page) if costs.has_more => page = Some(next_page),
+                        _ => break,
+                    }
+                }
+                Ok(Quota::MonthSpend(spent))
+            },
+            Provider::DeepSeek => {
+                // The balance is outside of the versioned API
+                let url = format!(
+                    "{}/user/balance",
+                    self.endpoint_prefix.trim_end_matches("/v1")
+                );
+                let (_, api_token) = self.keys.active();
+                let balance = self
+                    .execute(cli, cli.get(&url).bearer_auth(api_token))
+                    .await?
+                    .error_for_status()
+                    .wrap_err_with(|| "failed to get the balance of the account")?
+                    .json::<BalanceReply>()
+                    .await
+                    .wrap_err_with(|| "failed to parse the balance of the account")?;
+                Ok(Quota::Balance(balance.balance_infos))
+            },
+            provider => color_eyre::eyre::bail!(
+                "provider `{}` has no usage or billing API sermaid can read",
+                provider.as_str()
+            ),
+        }
+    }
+
+    /// Sends `user` under a caller-provided system prompt, as custom commands do
+    pub async fn custom(
+        &self,
+        system: &str,
+        temperature: Option<f32>,
+        user: String,
+    ) -> Result<Completion> {
+        let mut req = self
+            .request()
+            .append(Message::new(system.to_owned(), Role::System))
+            .append(Message::new(user, Role::User));
+        if let Some(temperature) = temperature {
+            req = req.with_temperature(temperature);
+        }
+
+        self.chat_completions(&req).await
+    }
 
-        let resp = self.cli.execute(req).await?.json::<Response>().await?;
+    fn request(&self) -> Request {
+        Request::new(self.model.clone()).with_seed(self.seed())
+    }
 
-        let mut choices = if let Some(choices) = resp.choices {
-            choices
+    fn q_and_a_request<S>(&self, question: S, history: &[ChatMessage], length: Length) -> Request
+    where
+        S: Into<Cow<'static, str>>,
+    {
+        let mut req = self
+            .request()
+            .with_temperature(0.0)
+            .with_max_tokens(length.max_tokens())
+            .append(Message::new(length.system_prompt(), Role::System));
+
+        for message in history {
+            req = req.append(Message::new(message.content.clone(), message.role));
+        }
+
+        req.append(Message::new(question, Role::User))
+    }
+
+    /// Sends `req` with the headers and signature of the gateway, if any
+    async fn execute(
+        &self,
+        cli: &Client,
+        req: reqwest::RequestBuilder,
+    ) -> Result<reqwest::Response, OpenAIError> {
+        let mut request = req.build().map_err(OpenAIError::Network)?;
+        self.gateway.apply(&mut request);
+        cli.execute(request).await.map_err(OpenAIError::Network)
+    }
+
+    /// Waits until the request interval of the provider has passed since the last request
+    async fn pace(&self) {
+        let Some(interval) = self.provider.request_interval() else {
+            return;
+        };
+
+        let mut last_request = self.last_request.lock().await;
+        if let Some(last_request) = *last_request {
+            tokio::time::sleep_until(last_request + interval).await;
+        }
+        *last_request = Some(Instant::now());
+    }
+
+    async fn chat_completions(&self, req_body: &Request) -> Result<Completion> {
+        self.send(req_body, &mut |_| {}).await
+    }
+
+    /// Sends `req_body`, calling `on_delta` with the pieces of the answer as they arrive if it
+    /// asks to stream, or with the whole answer otherwise
+    async fn send(
+        &self,
+        req_body: &Request,
+        on_delta: &mut (dyn FnMut(&str) + Send),
+    ) -> Result<Completion> {
+        let cli = match &self.backend {
+            Backend::Http(cli) => cli,
+            Backend::Mock(mock) => {
+                let content = mock.reply(&req_body.prompt()).await?;
+                on_delta(&content);
+                return Ok(Completion {
+                    content,
+                    alternatives: Vec::new(),
+                    reasoning: None,
+                    model: self.provider.as_str().to_owned(),
+                    usage: None,
+                    finish_reason: None,
+                    tool_calls: Vec::new(),
+                    logprobs: Vec::new(),
+                });
+            },
+        };
+
+        let req_json = serde_json::to_value(req_body)?;
+        let mut key = None;
+        let (status, retry_after, resp, streamed) = if let Some(interaction) = self
+            .cassette
+            .as_ref()
+            .map(|c| c.next(&req_json))
+            .transpose()?
+            .flatten()
+        {
+            (interaction.status, None, interaction.response, None)
         } else {
-            let message = if let Some(error) = resp.error {
-                error.message
-            } else {
-                String::new()
-            };
+            let url = format!("{}/chat/completions", self.endpoint_prefix);
+            tracing::debug!("chat_completions req = {req_json}");
+
+            if self.prompt_limit.is_some() || self.budget.is_some() {
+                let prompt = req_body.prompt();
+                let prompt_tokens = tokenizer::for_model(&req_body.model).count(&prompt) as u64;
+                if let Some(limit) = &self.prompt_limit {
+                    limit.check(prompt_tokens, prompt.len() as u64)?;
+                }
+                if let Some(budget) = &self.budget {
+                    let completion_tokens =
+                        req_body.max_tokens.unwrap_or(EXPECTED_COMPLETION_TOKENS);
+                    budget.check(&req_body.model, prompt_tokens, u64::from(completion_tokens))?;
+                }
+            }
+
+            let mut attempts = self.keys.len().max(1);
+            loop {
+                let (index, api_token) = self.keys.active();
+                key = Some(index);
+                self.pace().await;
+
+                let mut req = cli.post(&url);
+                if !api_token.is_empty() {
+                    req = req.bearer_auth(api_token);
+                }
+                if let Provider::OpenAI = self.provider {
+                    if let Some(organization) = &self.organization {
+                        req = req.header("OpenAI-Organization", organization);
+                    }
+                    if let Some(project) = &self.project {
+                        req = req.header("OpenAI-Project", project);
+                    }
+                }
+
+                let resp = self.execute(cli, req.json(req_body)).await?;
+                let status = resp.status().as_u16();
+                let retry_after = resp
+                    .headers()
+                    .get(RETRY_AFTER)
+                    .and_then(|value| value.to_str().ok()?.parse().ok())
+                    .map(Duration::from_secs);
+                if req_body.stream && resp.status().is_success() {
+                    let streamed = read_stream(resp, status, on_delta).await?;
+                    break (status, retry_after, String::new(), Some(streamed));
+                }
+                let text = resp.text().await.map_err(OpenAIError::Network)?;
+
+                if let Some(cassette) = &self.cassette {
+                    cassette.push(&req_json, status, &text)?;
+                }
+
+                let Some(reason) = failover_reason(status, &text) else {
+                    break (status, retry_after, text, None);
+                };
+                self.keys.failed(index, reason);
+                attempts -= 1;
+                if attempts == 0 {
+                    break (status, retry_after, text, None);
+                }
+            }
+        };
+
+        let resp: Response = match streamed.map_or_else(|| serde_json::from_str(&resp), Ok) {
+            Ok(resp) => resp,
+            Err(err) if (200..300).contains(&status) => {
+                return Err(OpenAIError::Deserialize(err).into())
+            },
+            // Gateways in front of the API answer errors in HTML or plain text
+            Err(_) => {
+                let error = Error {
+                    message: resp.trim().to_owned(),
+                    code: None,
+                };
+                return Err(OpenAIError::new(status, Some(error), retry_after).into());
+            },
+        };
+        if let (Some(key), Some(_)) = (key, &resp.choices) {
+            self.keys.succeeded(key, resp.usage.as_ref());
+            if let (Some(budget), Some(usage)) = (&self.budget, &resp.usage) {
+                budget.record(resp.model.as_deref().unwrap_or(&req_body.model), usage);
+            }
+        }
+
+        if let Some(fingerprint) = &resp.system_fingerprint {
+            tracing::debug!("system_fingerprint = {fingerprint}");
+        }
+        let Some(mut choices) = resp.choices else {
+            return Err(OpenAIError::new(status, resp.error, retry_after).into());
+        };
+
+        if choices.is_empty() {
+            color_eyre::eyre::bail!("empty choices");
+        }
+        let alternatives = choices
+            .drain(1..)
+            .map(|choice| choice.message.content)
+            .collect();
+        let choice = choices.remove(0);
+        if !req_body.stream {
+            on_delta(&choice.message.content);
+        }
+
+        Ok(Completion {
+            content: choice.message.content,
+            alternatives,
+            reasoning: choice
+                .message
+                .reasoning_content
+                .filter(|reasoning| !reasoning.trim().is_empty()),
+            model: resp.model.unwrap_or_else(|| req_body.model.clone()),
+            usage: resp.usage,
+            finish_reason: choice.finish_reason,
+            tool_calls: choice
+                .message
+                .tool_calls
+                .into_iter()
+                .flatten()
+                .map(|tool_call| ToolCall {
+                    id: tool_call.id,
+                    name: tool_call.function.name,
+                    arguments: tool_call.function.arguments,
+                })
+                .collect(),
+            logprobs: choice
+                .logprobs
+                .and_then(|logprobs| logprobs.content)
+                .unwrap_or_default(),
+        })
+    }
+}
+
+/// An answer together with what the API reported about producing it
+#[derive(Clone, Debug)]
+pub struct Completion {
+    pub content: Cow<'static, str>,
+    /// Further answers when several choices were requested
+    pub alternatives: Vec<Cow<'static, str>>,
+    /// What the model reasoned before answering, for models that return it
+    pub reasoning: Option<String>,
+    pub model: String,
+    pub usage: Option<Usage>,
+    pub finish_reason: Option<String>,
+    pub tool_calls: Vec<ToolCall>,
+    /// Log probabilities of the tokens of the answer, when they were requested
+    pub logprobs: Vec<TokenLogprob>,
+}
+
+/// A token of an answer and how likely the model found it
+#[derive(Clone, Debug, Deserialize)]
+pub struct TokenLogprob {
+    pub token: String,
+    pub logprob: f64,
+    /// The likeliest tokens in its place, itself included
+    #[serde(default)]
+    pub top_logprobs: Vec<TopLogprob>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct TopLogprob {
+    pub token: String,
+    pub logprob: f64,
+}
+
+/// A function the model may call, described by a JSON schema of its arguments
+#[derive(Clone, Debug)]
+pub struct Tool {
+    pub name: String,
+    pub description: Option<String>,
+    pub parameters: serde_json::Value,
+}
 
-            color_eyre::eyre::bail!("failed to request chat completions{message}",);
+#[derive(Clone, Debug)]
+pub struct ToolCall {
+    pub id: String,
+    pub name: String,
+    /// JSON-encoded arguments
+    pub arguments: String,
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct Usage {
+    pub prompt_tokens: u32,
+    pub completion_tokens: u32,
+    pub total_tokens: u32,
+}
+
+fn translate_prompt(to: Option<&str>) -> Cow<'static, str> {
+    match to {
+        Some(to) => format!("翻成{to}，保留原有的 markdown 格式，只输出译文").into(),
+        None => "翻成中文，用户输入中文则翻成英语".into(),
+    }
+}
+
+/// Why a chat completions request failed, for the REPL to react to each kind differently
+#[derive(Debug)]
+pub enum OpenAIError {
+    /// The key was rejected, after trying every configured key
+    Auth { message: String },
+    /// Too many requests, with how long the API asked to wait if it said
+    RateLimit {
+        message: String,
+        retry_after: Option<Duration>,
+    },
+    /// The balance or quota of the key ran out, after trying every configured key
+    Quota { message: String },
+    /// The prompt and answer do not fit the context window of the model
+    ContextLength { message: String },
+    /// The model does not exist or the key has no access to it
+    ModelNotFound { message: String },
+    /// A content filter of the provider refused the prompt
+    ContentFilter { message: String },
+    /// Any other error answered by the API
+    Api { status: u16, message: String },
+    /// The API could not be reached or the response was cut off
+    Network(reqwest::Error),
+    /// The response was not the JSON expected
+    Deserialize(serde_json::Error),
+}
+
+impl OpenAIError {
+    /// Classifies an error response by its status and the code of its error body
+    fn new(status: u16, error: Option<Error>, retry_after: Option<Duration>) -> Self {
+        let (message, code) =
+            error.map_or_else(Default::default, |error| (error.message, error.code));
+        let context_length = code.as_deref() == Some(CONTEXT_LENGTH_EXCEEDED) ||
+            message.contains("context length") ||
+            message.contains("context window");
+        match (status, code.as_deref()) {
+            _ if context_length => Self::ContextLength { message },
+            (402, _) | (_, Some(INSUFFICIENT_QUOTA)) => Self::Quota { message },
+            (404, _) | (_, Some(MODEL_NOT_FOUND)) => Self::ModelNotFound { message },
+            (_, Some(CONTENT_FILTER | CONTENT_POLICY_VIOLATION)) => Self::ContentFilter { message },
+            (401 | 403, _) | (_, Some(INVALID_API_KEY)) => Self::Auth { message },
+            (429, _) => Self::RateLimit {
+                message,
+                retry_after,
+            },
+            _ => Self::Api { status, message },
+        }
+    }
+}
+
+impl fmt::Display for OpenAIError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (what, message) = match self {
+            Self::Auth { message } => ("the API rejected the key".to_owned(), message),
+            Self::RateLimit { message, .. } => ("rate limited by the API".to_owned(), message),
+            Self::Quota { message } => ("the key is out of quota".to_owned(), message),
+            Self::ContextLength { message } => (
+                "the conversation is too long for the context window of the model".to_owned(),
+                message,
+            ),
+            Self::ModelNotFound { message } => ("the model is not available".to_owned(), message),
+            Self::ContentFilter { message } => (
+                "the content filter of the provider refused the prompt".to_owned(),
+                message,
+            ),
+            Self::Api { status, message } => (format!("the API answered {status}"), message),
+            // The underlying error is the source, which reports print after this
+            Self::Network(_) => return write!(f, "failed to reach the API"),
+            Self::Deserialize(_) => return write!(f, "failed to parse the API response"),
         };
+        if message.is_empty() {
+            write!(f, "{what}")
+        } else {
+            write!(f, "{what}: {message}")
+        }
+    }
+}
 
-        Ok(choices
-            .pop()
-            .ok_or_else(|| color_eyre::eyre::eyre!("empty choices"))?
-            .message
-            .content)
+impl std::error::Error for OpenAIError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Network(err) => Some(err),
+            Self::Deserialize(err) => Some(err),
+            _ => None,
+        }
     }
 }
 
 #[derive(Debug, Deserialize)]
 struct Error {
     message: String,
+    #[serde(default)]
+    code: Option<String>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+/// Why a response means the key should be rotated, if it does
+fn failover_reason(status: u16, text: &str) -> Option<String> {
+    let error = serde_json::from_str::<Response>(text)
+        .ok()
+        .and_then(|resp| resp.error);
+    let quota = error
+        .as_ref()
+        .is_some_and(|error| error.code.as_deref() == Some(INSUFFICIENT_QUOTA));
+
+    // DeepSeek answers 402 when the balance of a key runs out
+    (status == 401 || status == 402 || status == 429 || quota).then(|| {
+        let message = error.map_or_else(String::new, |error| error.message);
+        format!("{status} {message}").trim().to_owned()
+    })
+}
+
+#[derive(Debug, Deserialize)]
 struct Message {
+    #[serde(default, deserialize_with = "null_as_empty")]
     content: Cow<'static, str>,
     role: Role,
+
+    #[serde(default)]
+    tool_calls: Option<Vec<ToolCallMessage>>,
+
+    #[serde(default)]
+    tool_call_id: Option<String>,
+
+    /// Reasoning trace of models that think before answering, never sent back
+    #[serde(default, alias = "reasoning")]
+    reasoning_content: Option<String>,
+
+    /// URLs of images sent along with the content to vision models
+    #[serde(skip)]
+    images: Vec<String>,
+
+    /// Marks a last assistant message as the start of the answer, as Mistral requires
+    #[serde(skip)]
+    prefix: bool,
+}
+
+impl Serialize for Message {
+    fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        use serde::ser::SerializeMap;
+
+        let mut map = serializer.serialize_map(None)?;
+        if self.images.is_empty() {
+            map.serialize_entry("content", &self.content)?;
+        } else {
+            // Vision models take the text and the images as parts of the content
+            let mut parts = Vec::new();
+            if !self.content.is_empty() {
+                parts.push(serde_json::json!({"type": "text", "text": self.content}));
+            }
+            parts.extend(
+                self.images
+                    .iter()
+                    .map(|url| serde_json::json!({"type": "image_url", "image_url": {"url": url}})),
+            );
+            map.serialize_entry("content", &parts)?;
+        }
+        map.serialize_entry("role", &self.role)?;
+        if let Some(tool_calls) = &self.tool_calls {
+            map.serialize_entry("tool_calls", tool_calls)?;
+        }
+        if let Some(tool_call_id) = &self.tool_call_id {
+            map.serialize_entry("tool_call_id", tool_call_id)?;
+        }
+        if self.prefix {
+            map.serialize_entry("prefix", &true)?;
+        }
+        map.end()
+    }
 }
 
 impl Message {
@@ -128,34 +1339,256 @@ impl Message {
         Self {
             content: content.into(),
             role,
+            tool_calls: None,
+            tool_call_id: None,
+            reasoning_content: None,
+            images: Vec::new(),
+            prefix: false,
+        }
+    }
+
+    /// The start of the answer, for the model to continue
+    fn prefill(prefill: &str, provider: &Provider) -> Self {
+        Self {
+            prefix: matches!(provider, Provider::Mistral),
+            ..Self::new(prefill.to_owned(), Role::Assistant)
+        }
+    }
+
+    fn with_image(mut self, url: String) -> Self {
+        self.images.push(url);
+        self
+    }
+
+    fn tool_calls(tool_calls: &[ToolCall]) -> Self {
+        Self {
+            tool_calls: Some(
+                tool_calls
+                    .iter()
+                    .map(|tool_call| ToolCallMessage {
+                        id: tool_call.id.clone(),
+                        kind: FUNCTION_TYPE.into(),
+                        function: FunctionCall {
+                            name: tool_call.name.clone(),
+                            arguments: tool_call.arguments.clone(),
+                        },
+                    })
+                    .collect(),
+            ),
+            ..Self::new("", Role::Assistant)
+        }
+    }
+
+    fn tool_output(tool_call_id: String, output: String) -> Self {
+        Self {
+            tool_call_id: Some(tool_call_id),
+            ..Self::new(output, Role::Tool)
         }
     }
 }
 
+/// Assistant messages that only call tools have a `null` content
+fn null_as_empty<'de, D>(deserializer: D) -> Result<Cow<'static, str>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    Ok(Option::<String>::deserialize(deserializer)?
+        .unwrap_or_default()
+        .into())
+}
+
 #[derive(Debug, Serialize, Deserialize)]
+struct ToolCallMessage {
+    id: String,
+    #[serde(rename = "type")]
+    kind: Cow<'static, str>,
+    function: FunctionCall,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct FunctionCall {
+    name: String,
+    arguments: String,
+}
+
+#[derive(Debug, Serialize)]
+struct ToolSpec {
+    #[serde(rename = "type")]
+    kind: &'static str,
+    function: FunctionSpec,
+}
+
+#[derive(Debug, Serialize)]
+struct FunctionSpec {
+    name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    description: Option<String>,
+    parameters: serde_json::Value,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "lowercase")]
-enum Role {
+pub enum Role {
     System,
     User,
     Assistant,
+    Tool,
+}
+
+impl Role {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::System => "system",
+            Role::User => "user",
+            Role::Assistant => "assistant",
+            Role::Tool => "tool",
+        }
+    }
+}
+
+/// How long answers to questions should be
+#[derive(Clone, Copy, Debug, Default)]
+pub enum Length {
+    #[default]
+    Terse,
+    Words(u32),
+    Detailed,
+}
+
+impl Length {
+    /// The system message sent first with [`OpenAI::q_and_a`]
+    pub fn system_prompt(self) -> String {
+        match self {
+            Length::Terse => TERSE_PROMPT.to_owned(),
+            Length::Words(words) => format!("{TERSE_PROMPT}，回答不超过{words}个词"),
+            Length::Detailed => DETAILED_PROMPT.to_owned(),
+        }
+    }
+
+    /// Leaves headroom over the word limit so answers end on a full sentence
+    fn max_tokens(self) -> Option<u32> {
+        match self {
+            Length::Terse => None,
+            Length::Words(words) => Some(words.saturating_mul(TOKENS_PER_WORD)),
+            Length::Detailed => Some(DETAILED_MAX_TOKENS),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct Transcription {
+    text: String,
+}
+
+/// Spending or credits of the account, as reported by the provider
+pub enum Quota {
+    /// USD spent by the organization since the start of the month in UTC
+    MonthSpend(f64),
+    /// Credits left, by currency
+    Balance(Vec<Balance>),
+}
+
+#[derive(Deserialize)]
+pub struct Balance {
+    pub currency: String,
+    pub total_balance: String,
+    pub granted_balance: String,
+    pub topped_up_balance: String,
+}
+
+#[derive(Deserialize)]
+struct BalanceReply {
+    balance_infos: Vec<Balance>,
+}
+
+/// A page of daily buckets of `/organization/costs`
+#[derive(Deserialize)]
+struct CostPage {
+    data: Vec<CostBucket>,
+    #[serde(default)]
+    has_more: bool,
+    next_page: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct CostBucket {
+    results: Vec<CostResult>,
+}
+
+#[derive(Deserialize)]
+struct CostResult {
+    amount: CostAmount,
+}
+
+#[derive(Deserialize)]
+struct CostAmount {
+    value: f64,
 }
 
 #[derive(Debug, Serialize)]
 struct Request {
     messages: Vec<Message>,
 
-    model: &'static str,
+    model: String,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    temperature: Option<f32>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_tokens: Option<u32>,
 
+    /// Makes sampling deterministic on a best-effort basis
     #[serde(skip_serializing_if = "Option::is_none")]
-    temperature: Option<u8>,
+    seed: Option<u64>,
+
+    /// Number of choices to generate
+    #[serde(skip_serializing_if = "Option::is_none")]
+    n: Option<u32>,
+
+    /// Return the log probability of each token of the answer
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    logprobs: bool,
+
+    /// Number of likeliest tokens returned in place of each, with `logprobs`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    top_logprobs: Option<u8>,
+
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    tools: Vec<ToolSpec>,
+
+    /// GBNF grammar, an extension of llama-server
+    #[serde(skip_serializing_if = "Option::is_none")]
+    grammar: Option<String>,
+
+    /// Send the answer as server-sent events while it is generated
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    stream: bool,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stream_options: Option<StreamOptions>,
+}
+
+#[derive(Debug, Serialize)]
+struct StreamOptions {
+    /// Send the usage in a last event, which only some providers support
+    include_usage: bool,
 }
 
 impl Request {
-    fn new() -> Self {
+    fn new(model: String) -> Self {
         Self {
             messages: Vec::new(),
-            model: MODEL,
+            model,
             temperature: None,
+            max_tokens: None,
+            seed: None,
+            n: None,
+            logprobs: false,
+            top_logprobs: None,
+            tools: Vec::new(),
+            grammar: None,
+            stream: false,
+            stream_options: None,
         }
     }
 
@@ -164,20 +1597,181 @@ impl Request {
         self
     }
 
-    fn with_temperature(mut self, temperature: u8) -> Self {
+    fn with_temperature(mut self, temperature: f32) -> Self {
         self.temperature = Some(temperature);
         self
     }
+
+    fn with_logprobs(mut self, top_logprobs: u8) -> Self {
+        self.logprobs = true;
+        self.top_logprobs = Some(top_logprobs);
+        self
+    }
+
+    fn with_seed(mut self, seed: Option<u64>) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    fn with_max_tokens(mut self, max_tokens: Option<u32>) -> Self {
+        self.max_tokens = max_tokens;
+        self
+    }
+
+    fn with_choices(mut self, n: u32) -> Self {
+        self.n = Some(n);
+        self
+    }
+
+    fn with_stream(mut self, include_usage: bool) -> Self {
+        self.stream = true;
+        self.stream_options = include_usage.then_some(StreamOptions { include_usage });
+        self
+    }
+
+    fn with_grammar(mut self, grammar: &str) -> Self {
+        self.grammar = Some(grammar.to_owned());
+        self
+    }
+
+    fn with_tools(mut self, tools: &[Tool]) -> Self {
+        self.tools = tools
+            .iter()
+            .map(|tool| ToolSpec {
+                kind: FUNCTION_TYPE,
+                function: FunctionSpec {
+                    name: tool.name.clone(),
+                    description: tool.description.clone(),
+                    parameters: tool.parameters.clone(),
+                },
+            })
+            .collect();
+        self
+    }
+
+    /// Flattens the messages into the text the mock backend hashes to pick a fixture
+    fn prompt(&self) -> String {
+        self.messages
+            .iter()
+            .map(|message| format!("{}: {}\n", message.role.as_str(), message.content))
+            .collect()
+    }
+}
+
+/// An event of a streamed answer
+#[derive(Debug, Deserialize)]
+struct StreamChunk {
+    #[serde(default)]
+    choices: Vec<StreamChoice>,
+
+    model: Option<String>,
+
+    system_fingerprint: Option<String>,
+
+    usage: Option<Usage>,
+
+    error: Option<Error>,
+}
+
+#[derive(Debug, Deserialize)]
+struct StreamChoice {
+    delta: Delta,
+
+    finish_reason: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Delta {
+    content: Option<String>,
+
+    #[serde(alias = "reasoning")]
+    reasoning_content: Option<String>,
+}
+
+/// Reads the server-sent events of a streamed answer into the response they add up to
+async fn read_stream(
+    mut resp: reqwest::Response,
+    status: u16,
+    on_delta: &mut (dyn FnMut(&str) + Send),
+) -> Result<Response> {
+    let mut buf = Vec::new();
+    let mut content = String::new();
+    let mut reasoning = String::new();
+    let mut finish_reason = None;
+    let mut model = None;
+    let mut system_fingerprint = None;
+    let mut usage = None;
+    while let Some(bytes) = resp.chunk().await.map_err(OpenAIError::Network)? {
+        buf.extend_from_slice(&bytes);
+        while let Some(end) = buf.iter().position(|&byte| byte == b'\n') {
+            let line = buf.drain(..=end).collect::<Vec<_>>();
+            let line = String::from_utf8_lossy(&line);
+            let Some(data) = line.trim().strip_prefix("data:").map(str::trim) else {
+                continue;
+            };
+            if data == "[DONE]" {
+                continue;
+            }
+
+            let chunk: StreamChunk =
+                serde_json::from_str(data).map_err(OpenAIError::Deserialize)?;
+            if let Some(error) = chunk.error {
+                return Err(OpenAIError::new(status, Some(error), None).into());
+            }
+            model = chunk.model.or(model);
+            system_fingerprint = chunk.system_fingerprint.or(system_fingerprint);
+            usage = chunk.usage.or(usage);
+            for choice in chunk.choices {
+                if let Some(delta) = choice.delta.content {
+                    on_delta(&delta);
+                    content.push_str(&delta);
+                }
+                reasoning.extend(choice.delta.reasoning_content);
+                finish_reason = choice.finish_reason.or(finish_reason);
+            }
+        }
+    }
+
+    Ok(Response {
+        choices: Some(vec![Choice {
+            message: Message {
+                reasoning_content: Some(reasoning),
+                ..Message::new(content, Role::Assistant)
+            },
+            finish_reason,
+            logprobs: None,
+        }]),
+        model,
+        system_fingerprint,
+        usage,
+        error: None,
+    })
 }
 
 #[derive(Debug, Deserialize)]
 struct Response {
     choices: Option<Vec<Choice>>,
 
+    model: Option<String>,
+
+    /// Backend configuration that answered, which changes the answers to the same seed
+    system_fingerprint: Option<String>,
+
+    usage: Option<Usage>,
+
     error: Option<Error>,
 }
 
 #[derive(Debug, Deserialize)]
 struct Choice {
     message: Message,
+
+    finish_reason: Option<String>,
+
+    logprobs: Option<ChoiceLogprobs>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ChoiceLogprobs {
+    content: Option<Vec<TokenLogprob>>,
 }
@@ -0,0 +1,416 @@
+use std::path::{Component, PathBuf};
+
+use color_eyre::eyre::{Context, Result};
+
+const SEARCH: &str = "<<<<<<< SEARCH";
+const DIVIDER: &str = "=======";
+const REPLACE: &str = ">>>>>>> REPLACE";
+
+/// Edits an answer suggests for one file
+pub struct FilePatch {
+    pub path: PathBuf,
+    edits: Vec<Edit>,
+    /// Whether a unified diff removes the file, with `+++ /dev/null`
+    deletes: bool,
+}
+
+/// Lines to replace, located by their content rather than by line number, which models often
+/// get wrong
+struct Edit {
+    old: Vec<String>,
+    new: Vec<String>,
+    /// 1-based line the edit was said to start at, to pick among several matches
+    line: Option<usize>,
+}
+
+impl FilePatch {
+    /// Whether the patch creates the file rather than editing it
+    pub fn creates(&self) -> bool {
+        self.edits.iter().all(|edit| edit.old.is_empty())
+    }
+
+    pub fn deletes(&self) -> bool {
+        self.deletes
+    }
+
+    /// Whether the path is absolute or climbs out of the current directory with `..`
+    pub fn escapes(&self) -> bool {
+        self.path
+            .components()
+            .any(|component| !matches!(component, Component::Normal(_) | Component::CurDir))
+    }
+
+    /// Contents of the file before and after the edits, empty before if the patch creates it
+    /// and after if it deletes it
+    pub fn read(&self) -> Result<(String, String)> {
+        let old = match std::fs::read_to_string(&self.path) {
+            Ok(old) => old,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound && self.creates() => {
+                String::new()
+            },
+            Err(err) => {
+                return Err(err)
+                    .wrap_err_with(|| format!("failed to read `{}`", self.path.display()))
+            },
+        };
+        let new = if self.deletes {
+            String::new()
+        } else {
+            self.apply(&old)?
+        };
+        Ok((old, new))
+    }
+
+    /// `content` with the edits applied, in order
+    fn apply(&self, content: &str) -> Result<String> {
+        let mut lines = content.lines().map(str::to_owned).collect::<Vec<_>>();
+        for edit in &self.edits {
+            if edit.old.is_empty() {
+                lines.extend(edit.new.iter().cloned());
+                continue;
+            }
+
+            let Some(start) = find(&lines, &edit.old, edit.line) else {
+                color_eyre::eyre::bail!(
+                    "lines to replace not found in `{}`:\n{}",
+                    self.path.display(),
+                    edit.old.join("\n")
+                );
+            };
+            lines.splice(start..start + edit.old.len(), edit.new.iter().cloned());
+        }
+
+        let mut patched = lines.join("\n");
+        if !patched.is_empty() && (content.is_empty() || content.ends_with('\n')) {
+            patched.push('\n');
+        }
+        Ok(patched)
+    }
+}
+
+/// Start of `old` in `lines`, closest to `line` if it appears more than once
+///
+/// Lines are compared exactly first, then ignoring trailing and then all surrounding
+/// whitespace, since answers often mangle indentation.
+fn find(lines: &[String], old: &[String], line: Option<usize>) -> Option<usize> {
+    let comparisons: [fn(&str, &str) -> bool; 3] = [
+        |a, b| a == b,
+        |a, b| a.trim_end() == b.trim_end(),
+        |a, b| a.trim() == b.trim(),
+    ];
+    comparisons.into_iter().find_map(|equal| {
+        let hint = line.unwrap_or(1).saturating_sub(1);
+        (0..=lines.len().checked_sub(old.len())?)
+            .filter(|&start| {
+                lines[start..start + old.len()]
+                    .iter()
+                    .zip(old)
+                    .all(|(a, b)| equal(a, b))
+            })
+            .min_by_key(|start| start.abs_diff(hint))
+    })
+}
+
+/// Unified diffs and search/replace blocks in `answer`, merged per file in order of appearance
+pub fn parse(answer: &str) -> Vec<FilePatch> {
+    let lines = answer.lines().collect::<Vec<_>>();
+    let mut patches = Vec::<FilePatch>::new();
+    let mut add = |path: PathBuf, edit: Edit, deletes: bool| match patches
+        .iter_mut()
+        .find(|patch| patch.path == path)
+    {
+        Some(patch) => {
+            patch.edits.push(edit);
+            patch.deletes |= deletes;
+        },
+        None => patches.push(FilePatch {
+            path,
+            edits: vec![edit],
+            deletes,
+        }),
+    };
+
+    let mut i = 0;
+    // The file of the diff being read, and whether the diff deletes it
+    let mut diff_path = None;
+    while i < lines.len() {
+        let line = lines[i];
+        if let (Some(old), Some(new)) = (
+            line.strip_prefix("--- "),
+            lines.get(i + 1).and_then(|next| next.strip_prefix("+++ ")),
+        ) {
+            diff_path = match (diff_file(old), diff_file(new)) {
+                (Some(old), None) => Some((old, true)),
+                (old, new) => new.or(old).map(|path| (path, false)),
+            };
+            i += 2;
+        } else if let (Some((path, deletes)), Some(range)) = (&diff_path, line.strip_prefix("@@ "))
+        {
+            let (edit, end) = parse_hunk(&lines, i + 1, range);
+            add(path.clone(), edit, *deletes);
+            i = end;
+        } else if line.trim() == SEARCH {
+            match (block_path(&lines[..i]), parse_block(&lines, i + 1)) {
+                (Some(path), Some((edit, end))) => {
+                    add(path, edit, false);
+                    i = end;
+                },
+                _ => i += 1,
+            }
+        } else {
+            if line.starts_with("```") {
+                diff_path = None;
+            }
+            i += 1;
+        }
+    }
+
+    patches
+}
+
+/// Path of a `---` or `+++` header line, without the `a/` and `b/` prefixes of git
+fn diff_file(header: &str) -> Option<PathBuf> {
+    let path = header.split('\t').next()?.trim();
+    if path == "/dev/null" {
+        return None;
+    }
+    let path = path
+        .strip_prefix("a/")
+        .or_else(|| path.strip_prefix("b/"))
+        .unwrap_or(path);
+    Some(PathBuf::from(path))
+}
+
+/// The hunk whose body starts at `start`, and where the line after it is
+fn parse_hunk(lines: &[&str], start: usize, range: &str) -> (Edit, usize) {
+    // `-12,5 +12,6 @@`, of which the old start line is the hint
+    let mut ranges = range.split(' ');
+    let old_range = ranges.next().and_then(|range| range.strip_prefix('-'));
+    let new_range = ranges.next().and_then(|range| range.strip_prefix('+'));
+    let line = old_range
+        .and_then(|range| range.split(',').next())
+        .and_then(|line| line.parse().ok());
+    let mut edit = Edit {
+        old: Vec::new(),
+        new: Vec::new(),
+        line,
+    };
+    // Lines the header says are left, within which `--- ` starts a removed line rather than
+    // the next file, as in SQL comments; answers often get these wrong, so running out of them
+    // does not end the hunk
+    let (mut old_left, mut new_left) = (line_count(old_range), line_count(new_range));
+
+    let mut end = start;
+    while let Some(line) = lines.get(end) {
+        let next_file = old_left == 0 &&
+            new_left == 0 &&
+            line.starts_with("--- ") &&
+            lines
+                .get(end + 1)
+                .is_some_and(|next| next.starts_with("+++ "));
+        if line.starts_with("@@") || line.starts_with("```") || next_file {
+            break;
+        }
+        match line.split_at_checked(1) {
+            Some(("-", old)) => {
+                edit.old.push(old.to_owned());
+                old_left = old_left.saturating_sub(1);
+            },
+            Some(("+", new)) => {
+                edit.new.push(new.to_owned());
+                new_left = new_left.saturating_sub(1);
+            },
+            Some((" ", context)) => {
+                edit.old.push(context.to_owned());
+                edit.new.push(context.to_owned());
+                old_left = old_left.saturating_sub(1);
+                new_left = new_left.saturating_sub(1);
+            },
+            Some(("\\", _)) => {},
+            // Blank context lines often lose their leading space
+            _ if line.is_empty() => {
+                edit.old.push(String::new());
+                edit.new.push(String::new());
+                old_left = old_left.saturating_sub(1);
+                new_left = new_left.saturating_sub(1);
+            },
+            _ => break,
+        }
+        end += 1;
+    }
+
+    // A blank line before prose after the hunk is not context
+    while end > start &&
+        lines[end - 1].is_empty() &&
+        edit.old.last().is_some_and(String::is_empty) &&
+        edit.new.last().is_some_and(String::is_empty)
+    {
+        edit.old.pop();
+        edit.new.pop();
+        end -= 1;
+    }
+    (edit, end)
+}
+
+/// Lines of a hunk range like `12,5`, one if the count is left out, none if it is missing
+fn line_count(range: Option<&str>) -> usize {
+    match range.map(|range| range.split_once(',')) {
+        Some(Some((_, count))) => count.parse().unwrap_or_default(),
+        Some(None) => 1,
+        None => 0,
+    }
+}
+
+/// The search/replace block whose search part starts at `start`, and where the line after it is
+fn parse_block(lines: &[&str], start: usize) -> Option<(Edit, usize)> {
+    let divider = start +
+        lines[start..]
+            .iter()
+            .position(|line| line.trim() == DIVIDER)?;
+    let end = divider +
+        lines[divider..]
+            .iter()
+            .position(|line| line.trim() == REPLACE)?;
+    let edit = Edit {
+        old: lines[start..divider]
+            .iter()
+            .map(|&line| line.to_owned())
+            .collect(),
+        new: lines[divider + 1..end]
+            .iter()
+            .map(|&line| line.to_owned())
+            .collect(),
+        line: None,
+    };
+    Some((edit, end + 1))
+}
+
+/// The file a search/replace block edits, named on the last line before it besides fences
+fn block_path(before: &[&str]) -> Option<PathBuf> {
+    let line = before
+        .iter()
+        .rev()
+        .map(|line| line.trim())
+        .find(|line| !line.is_empty() && !line.starts_with("```"))?;
+    let path = line
+        .trim_end_matches(':')
+        .trim_matches(|c| matches!(c, '`' | '*'))
+        .trim();
+    (!path.is_empty() && !path.contains(' ')).then(|| PathBuf::from(path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(text: &str) -> Vec<String> {
+        text.lines().map(str::to_owned).collect()
+    }
+
+    #[test]
+    fn parses_unified_diff() {
+        let answer = "Rename it:\n\
+                      ```diff\n\
+                      --- a/src/lib.rs\n\
+                      +++ b/src/lib.rs\n\
+                      @@ -1,3 +1,3 @@\n \
+                      fn main() {\n\
+                      -    old();\n\
+                      +    new();\n \
+                      }\n\
+                      ```\n";
+        let patches = parse(answer);
+        assert_eq!(patches.len(), 1);
+        assert_eq!(patches[0].path, PathBuf::from("src/lib.rs"));
+        assert!(!patches[0].creates() && !patches[0].deletes());
+        assert_eq!(
+            patches[0].apply("fn main() {\n    old();\n}\n").unwrap(),
+            "fn main() {\n    new();\n}\n"
+        );
+    }
+
+    #[test]
+    fn parses_search_replace_block() {
+        let answer = "`src/lib.rs`:\n\
+                      ```\n\
+                      <<<<<<< SEARCH\n\
+                      let x = 1;\n\
+                      =======\n\
+                      let x = 2;\n\
+                      >>>>>>> REPLACE\n\
+                      ```\n";
+        let patches = parse(answer);
+        assert_eq!(patches.len(), 1);
+        assert_eq!(patches[0].path, PathBuf::from("src/lib.rs"));
+        assert_eq!(
+            patches[0].apply("let x = 1;\nlet y = x;\n").unwrap(),
+            "let x = 2;\nlet y = x;\n"
+        );
+    }
+
+    #[test]
+    fn diff_to_dev_null_deletes() {
+        let answer = "--- a/old.txt\n+++ /dev/null\n@@ -1 +0,0 @@\n-gone\n";
+        let patches = parse(answer);
+        assert_eq!(patches.len(), 1);
+        assert_eq!(patches[0].path, PathBuf::from("old.txt"));
+        assert!(patches[0].deletes());
+    }
+
+    #[test]
+    fn diff_from_dev_null_creates() {
+        let answer = "--- /dev/null\n+++ b/new.txt\n@@ -0,0 +1 @@\n+hello\n";
+        let patches = parse(answer);
+        assert_eq!(patches[0].path, PathBuf::from("new.txt"));
+        assert!(patches[0].creates() && !patches[0].deletes());
+        assert_eq!(patches[0].apply("").unwrap(), "hello\n");
+    }
+
+    #[test]
+    fn removed_line_like_header_stays_in_hunk() {
+        let answer = "--- a/schema.sql\n\
+                      +++ b/schema.sql\n\
+                      @@ -1,2 +1,2 @@\n\
+                      --- legacy\n\
+                      +++ counter\n \
+                      SELECT 1;\n";
+        let patches = parse(answer);
+        assert_eq!(patches.len(), 1);
+        assert_eq!(patches[0].path, PathBuf::from("schema.sql"));
+        assert_eq!(
+            patches[0].apply("-- legacy\nSELECT 1;\n").unwrap(),
+            "++ counter\nSELECT 1;\n"
+        );
+    }
+
+    #[test]
+    fn find_ignores_whitespace_when_exact_fails() {
+        let content = lines("fn f() {\n    let a = 1;\n}");
+        assert_eq!(find(&content, &lines("    let a = 1;"), None), Some(1));
+        assert_eq!(find(&content, &lines("    let a = 1;   "), None), Some(1));
+        assert_eq!(find(&content, &lines("let a = 1;"), None), Some(1));
+        assert_eq!(find(&content, &lines("let b = 1;"), None), None);
+    }
+
+    #[test]
+    fn find_picks_match_closest_to_line_hint() {
+        let content = lines("x\ny\nx\ny\nx");
+        assert_eq!(find(&content, &lines("x"), None), Some(0));
+        assert_eq!(find(&content, &lines("x"), Some(3)), Some(2));
+        assert_eq!(find(&content, &lines("x"), Some(9)), Some(4));
+    }
+
+    #[test]
+    fn escapes_outside_current_directory() {
+        let patch = |path: &str| FilePatch {
+            path: PathBuf::from(path),
+            edits: Vec::new(),
+            deletes: false,
+        };
+        assert!(patch("/etc/passwd").escapes());
+        assert!(patch("../outside.txt").escapes());
+        assert!(patch("src/../../outside.txt").escapes());
+        assert!(!patch("src/lib.rs").escapes());
+        assert!(!patch("./README.md").escapes());
+    }
+}
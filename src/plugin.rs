@@ -0,0 +1,139 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+
+use clap::{Arg, ArgAction};
+use color_eyre::eyre::{Context, Result};
+use serde::{Deserialize, Serialize};
+use tokio::io::AsyncWriteExt;
+
+use crate::conversation::ChatMessage;
+use crate::CARGO_PKG_NAME;
+
+const ARGS_ARG: &str = "args";
+
+/// What a plugin receives as JSON on stdin
+#[derive(Serialize)]
+pub struct PluginInput<'a> {
+    pub command: &'a str,
+    pub args: Vec<String>,
+    pub pins: Vec<&'a str>,
+    pub history: &'a [ChatMessage],
+}
+
+/// What a plugin prints on stdout; anything that is not such JSON is shown as an answer
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum PluginOutput {
+    /// A question to send to the model as a continuation of the conversation
+    Prompt { content: String },
+    /// A final answer to display as is
+    Answer { content: String },
+}
+
+/// Finds `sermaid-<name>` executables on `PATH`, the first one found winning
+pub fn discover() -> BTreeMap<String, PathBuf> {
+    let prefix = format!("{CARGO_PKG_NAME}-");
+    let mut plugins = BTreeMap::new();
+
+    let Some(path) = std::env::var_os("PATH") else {
+        return plugins;
+    };
+
+    for dir in std::env::split_paths(&path) {
+        let Ok(entries) = std::fs::read_dir(&dir) else {
+            continue;
+        };
+
+        for entry in entries.flatten() {
+            let path = entry.path();
+            let Some(name) = path
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .and_then(|stem| stem.strip_prefix(&prefix))
+            else {
+                continue;
+            };
+
+            if !name.is_empty() && is_executable(&path) {
+                plugins.entry(name.to_owned()).or_insert(path);
+            }
+        }
+    }
+
+    plugins
+}
+
+#[cfg(unix)]
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+
+    path.metadata()
+        .is_ok_and(|metadata| metadata.is_file() && metadata.permissions().mode() & 0o111 != 0)
+}
+
+#[cfg(not(unix))]
+fn is_executable(path: &Path) -> bool {
+    path.is_file() && path.extension().is_some_and(|extension| extension == "exe")
+}
+
+/// Adds the plugins to `cmd`, skipping those that would shadow an existing command
+pub fn augment(mut cmd: clap::Command, plugins: &BTreeMap<String, PathBuf>) -> clap::Command {
+    for (name, path) in plugins {
+        if cmd.find_subcommand(name).is_some() {
+            continue;
+        }
+
+        cmd = cmd.subcommand(
+            clap::Command::new(name.clone())
+                .about(format!("Plugin `{}`", path.display()))
+                .disable_help_flag(true)
+                .arg(
+                    Arg::new(ARGS_ARG)
+                        .action(ArgAction::Append)
+                        .allow_hyphen_values(true)
+                        .trailing_var_arg(true),
+                ),
+        );
+    }
+
+    cmd
+}
+
+pub fn args(matches: &clap::ArgMatches) -> Vec<String> {
+    matches
+        .get_many::<String>(ARGS_ARG)
+        .into_iter()
+        .flatten()
+        .cloned()
+        .collect()
+}
+
+pub async fn run(path: &Path, input: &PluginInput<'_>) -> Result<PluginOutput> {
+    let mut child = tokio::process::Command::new(path)
+        .args(&input.args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .wrap_err_with(|| format!("failed to run plugin `{}`", path.display()))?;
+
+    let json = serde_json::to_vec(input)?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin
+            .write_all(&json)
+            .await
+            .wrap_err_with(|| format!("failed to write to plugin `{}`", path.display()))?;
+    }
+
+    let output = child.wait_with_output().await?;
+    if !output.status.success() {
+        color_eyre::eyre::bail!("plugin `{}` exited with {}", path.display(), output.status);
+    }
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    Ok(
+        serde_json::from_str(&stdout).unwrap_or_else(|_| PluginOutput::Answer {
+            content: stdout.trim_end().to_owned(),
+        }),
+    )
+}
@@ -0,0 +1,43 @@
+use crate::openai::Usage;
+
+const TOKENS_PER_UNIT: f64 = 1_000_000.0;
+
+/// USD per million prompt and completion tokens, matched by the longest model name prefix
+const PRICES: &[(&str, f64, f64)] = &[
+    ("deepseek-chat", 0.27, 1.1),
+    ("deepseek-reasoner", 0.55, 2.19),
+    ("codestral", 0.3, 0.9),
+    ("gemma2-9b-it", 0.2, 0.2),
+    ("gpt-3.5-turbo", 0.5, 1.5),
+    ("gpt-4", 30.0, 60.0),
+    ("gpt-4-turbo", 10.0, 30.0),
+    ("gpt-4-1106-preview", 10.0, 30.0),
+    ("gpt-4o", 2.5, 10.0),
+    ("gpt-4o-mini", 0.15, 0.6),
+    ("llama-3.1-8b-instant", 0.05, 0.08),
+    ("llama-3.3-70b-versatile", 0.59, 0.79),
+    ("mistral-large", 2.0, 6.0),
+    ("mistral-small", 0.2, 0.6),
+    ("open-mistral-nemo", 0.15, 0.15),
+];
+
+/// Cost in USD, if the model is in the price table
+pub fn cost(model: &str, prompt_tokens: u64, completion_tokens: u64) -> Option<f64> {
+    let (_, prompt_price, completion_price) = PRICES
+        .iter()
+        .filter(|(name, ..)| model.starts_with(name))
+        .max_by_key(|(name, ..)| name.len())?;
+
+    Some(
+        (prompt_tokens as f64 * prompt_price + completion_tokens as f64 * completion_price) /
+            TOKENS_PER_UNIT,
+    )
+}
+
+pub fn usage_cost(model: &str, usage: &Usage) -> Option<f64> {
+    cost(
+        model,
+        u64::from(usage.prompt_tokens),
+        u64::from(usage.completion_tokens),
+    )
+}
@@ -0,0 +1,86 @@
+use std::fmt;
+use std::str::FromStr;
+
+use serde::Deserialize;
+
+use crate::conversation::ChatMessage;
+use crate::openai::Role;
+
+/// Recent turns sent verbatim alongside the rolling summary
+pub const SUMMARY_RECENT_TURNS: usize = 4;
+
+/// Which part of the history is sent with `continue`
+#[derive(Clone, Copy, Debug, Default, Deserialize)]
+#[serde(try_from = "String")]
+pub enum Pruning {
+    /// Every turn
+    #[default]
+    All,
+    /// Only the last n turns
+    Window(usize),
+    /// A rolling summary of older turns plus the last [`SUMMARY_RECENT_TURNS`] turns
+    Summary,
+}
+
+impl Pruning {
+    /// Index of the first message kept verbatim, the rest being dropped or summarized
+    pub fn start(self, history: &[ChatMessage]) -> usize {
+        match self {
+            Self::All => 0,
+            Self::Window(turns) => turn_start(history, turns),
+            Self::Summary => turn_start(history, SUMMARY_RECENT_TURNS),
+        }
+    }
+}
+
+/// Index of the user message starting the last `turns` turns
+pub fn turn_start(history: &[ChatMessage], turns: usize) -> usize {
+    if turns == 0 {
+        return history.len();
+    }
+
+    history
+        .iter()
+        .enumerate()
+        .rev()
+        .filter(|(_, message)| message.role == Role::User)
+        .nth(turns - 1)
+        .map_or(0, |(i, _)| i)
+}
+
+impl FromStr for Pruning {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "all" => Ok(Self::All),
+            "summary" => Ok(Self::Summary),
+            s => s
+                .strip_prefix("window(")
+                .and_then(|s| s.strip_suffix(')'))
+                .and_then(|n| n.trim().parse().ok())
+                .map(Self::Window)
+                .ok_or_else(|| {
+                    format!("invalid pruning `{s}`, expected all, window(N) or summary")
+                }),
+        }
+    }
+}
+
+impl TryFrom<String> for Pruning {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl fmt::Display for Pruning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::All => write!(f, "all"),
+            Self::Window(turns) => write!(f, "window({turns})"),
+            Self::Summary => write!(f, "summary"),
+        }
+    }
+}
@@ -0,0 +1,359 @@
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+
+use color_eyre::eyre::{Context, Result};
+use rustyline::completion::Completer;
+use rustyline::config::Behavior;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::{Hint, Hinter};
+use rustyline::history::{DefaultHistory, SearchDirection};
+use rustyline::validate::Validator;
+use rustyline::{
+    Cmd, ConditionalEventHandler, Editor, Event, EventContext, EventHandler, Helper, KeyCode,
+    KeyEvent, Modifiers, Movement, RepeatCount,
+};
+use serde::Deserialize;
+
+use crate::{external_editor, fuzzy};
+
+/// Lines shorter than this are not searched for in the history while typing
+const MIN_HINT_QUERY_CHARS: usize = 3;
+const HINT_MAX_CHARS: usize = 60;
+const DIM: &str = "\x1b[2m";
+const RESET: &str = "\x1b[0m";
+
+/// The line editor of the REPL
+pub type LineEditor = Editor<HistoryHelper, DefaultHistory>;
+
+/// The `[editor]` section of the config
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct EditorConfig {
+    pub edit_mode: EditMode,
+    pub bell_style: BellStyle,
+    /// Whether entered commands are added to the history
+    pub auto_add_history: bool,
+    /// Key bindings applied at startup, as accepted by the `bind` command
+    pub bindings: BTreeMap<String, String>,
+    /// Whether to show the rest of the latest history entry the line starts, dimmed after the
+    /// cursor and accepted with →
+    pub history_hints: bool,
+    /// Whether to hint the best fuzzy match in the history while typing, put in the line by
+    /// `C-r`, instead of the substring-only reverse search
+    pub fuzzy_history: bool,
+}
+
+impl Default for EditorConfig {
+    fn default() -> Self {
+        Self {
+            edit_mode: EditMode::default(),
+            bell_style: BellStyle::default(),
+            auto_add_history: true,
+            bindings: BTreeMap::new(),
+            history_hints: true,
+            fuzzy_history: true,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EditMode {
+    #[default]
+    Emacs,
+    Vi,
+}
+
+#[derive(Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BellStyle {
+    #[default]
+    Audible,
+    None,
+    Visible,
+}
+
+/// Builds the line editor, reading from the terminal rather than stdin if `prefer_term`
+pub fn editor(config: &EditorConfig, prefer_term: bool) -> Result<LineEditor> {
+    let behavior = if prefer_term {
+        Behavior::PreferTerm
+    } else {
+        Behavior::Stdio
+    };
+    let rl_config = rustyline::Config::builder()
+        .behavior(behavior)
+        .edit_mode(match config.edit_mode {
+            EditMode::Emacs => rustyline::EditMode::Emacs,
+            EditMode::Vi => rustyline::EditMode::Vi,
+        })
+        .bell_style(match config.bell_style {
+            BellStyle::Audible => rustyline::config::BellStyle::Audible,
+            BellStyle::None => rustyline::config::BellStyle::None,
+            BellStyle::Visible => rustyline::config::BellStyle::Visible,
+        })
+        .build();
+
+    let mut editor = LineEditor::with_config(rl_config)
+        .wrap_err_with(|| "failed to initialize rustyline editor")?;
+    if config.history_hints || config.fuzzy_history {
+        editor.set_helper(Some(HistoryHelper {
+            search: Arc::default(),
+            complete: config.history_hints,
+            fuzzy: config.fuzzy_history,
+        }));
+    }
+    if config.fuzzy_history {
+        bind(&mut editor, "C-r", "fuzzy-history-search")?;
+    }
+    for (key, action) in &config.bindings {
+        bind(&mut editor, key, action)
+            .wrap_err_with(|| format!("invalid binding `{key} = \"{action}\"` in config"))?;
+    }
+
+    Ok(editor)
+}
+
+/// Binds a key such as `F2`, `C-x` or `M-Enter` to an action such as `edit` or `insert TEXT`
+pub fn bind(editor: &mut LineEditor, key: &str, action: &str) -> Result<()> {
+    let key = parse_key(key)?;
+    let handler = match action.split_once(' ') {
+        Some(("insert", text)) => EventHandler::Simple(Cmd::Insert(1, text.to_owned())),
+        _ => match action {
+            "edit" => EventHandler::Conditional(Box::new(EditInEditor)),
+            "accept" => EventHandler::Simple(Cmd::AcceptLine),
+            "clear-screen" => EventHandler::Simple(Cmd::ClearScreen),
+            "complete" => EventHandler::Simple(Cmd::Complete),
+            "history-search-backward" => EventHandler::Simple(Cmd::HistorySearchBackward),
+            "history-search-forward" => EventHandler::Simple(Cmd::HistorySearchForward),
+            "fuzzy-history-search" => match editor.helper().filter(|helper| helper.fuzzy) {
+                Some(helper) => EventHandler::Conditional(Box::new(FuzzyHistorySearch {
+                    search: helper.search.clone(),
+                })),
+                None => color_eyre::eyre::bail!("fuzzy history search is disabled in config"),
+            },
+            "kill-line" => EventHandler::Simple(Cmd::Kill(Movement::EndOfLine)),
+            "kill-whole-line" => EventHandler::Simple(Cmd::Kill(Movement::WholeLine)),
+            "beginning-of-line" => EventHandler::Simple(Cmd::Move(Movement::BeginningOfLine)),
+            "end-of-line" => EventHandler::Simple(Cmd::Move(Movement::EndOfLine)),
+            "undo" => EventHandler::Simple(Cmd::Undo(1)),
+            "noop" => EventHandler::Simple(Cmd::Noop),
+            _ => color_eyre::eyre::bail!(
+                "unknown action `{action}`, expected one of: edit, accept, clear-screen, complete, \
+                 history-search-backward, history-search-forward, fuzzy-history-search, kill-line, \
+                 kill-whole-line, beginning-of-line, end-of-line, undo, noop, insert TEXT"
+            ),
+        },
+    };
+
+    editor.bind_sequence(key, handler);
+    Ok(())
+}
+
+fn parse_key(key: &str) -> Result<KeyEvent> {
+    let mut mods = Modifiers::NONE;
+    let mut rest = key;
+    loop {
+        if let Some(r) = rest.strip_prefix("C-") {
+            mods |= Modifiers::CTRL;
+            rest = r;
+        } else if let Some(r) = rest.strip_prefix("M-") {
+            mods |= Modifiers::ALT;
+            rest = r;
+        } else if let Some(r) = rest.strip_prefix("S-") {
+            mods |= Modifiers::SHIFT;
+            rest = r;
+        } else {
+            break;
+        }
+    }
+
+    let mut chars = rest.chars();
+    if let (Some(c), None) = (chars.next(), chars.next()) {
+        return Ok(KeyEvent::new(c, mods));
+    }
+
+    let code = match rest.to_lowercase().as_str() {
+        "enter" => KeyCode::Enter,
+        "tab" => KeyCode::Tab,
+        "esc" => KeyCode::Esc,
+        "backspace" => KeyCode::Backspace,
+        "delete" => KeyCode::Delete,
+        "insert" => KeyCode::Insert,
+        "home" => KeyCode::Home,
+        "end" => KeyCode::End,
+        "pageup" => KeyCode::PageUp,
+        "pagedown" => KeyCode::PageDown,
+        "up" => KeyCode::Up,
+        "down" => KeyCode::Down,
+        "left" => KeyCode::Left,
+        "right" => KeyCode::Right,
+        "space" => return Ok(KeyEvent::new(' ', mods)),
+        name => match name.strip_prefix('f').and_then(|n| n.parse::<u8>().ok()) {
+            Some(n @ 1..=24) => KeyCode::F(n),
+            _ => color_eyre::eyre::bail!("unknown key `{key}`"),
+        },
+    };
+
+    Ok(KeyEvent(code, mods))
+}
+
+/// Replaces the line being edited with the result of editing it in `$VISUAL`/`$EDITOR`
+struct EditInEditor;
+
+impl ConditionalEventHandler for EditInEditor {
+    fn handle(&self, _: &Event, _: RepeatCount, _: bool, ctx: &EventContext) -> Option<Cmd> {
+        match external_editor::edit(ctx.line()) {
+            Ok(edited) => Some(Cmd::Replace(
+                Movement::WholeBuffer,
+                Some(edited.trim_end().to_owned()),
+            )),
+            Err(err) => {
+                tracing::warn!("{err:?}");
+                Some(Cmd::Noop)
+            },
+        }
+    }
+}
+
+/// History entries matching the line being edited, found while hinting and cycled through by
+/// [`FuzzyHistorySearch`]
+#[derive(Default)]
+struct Search {
+    matches: Vec<String>,
+    /// The match last put in the line
+    index: usize,
+}
+
+/// Hints how the history could complete the line being edited
+pub struct HistoryHelper {
+    search: Arc<Mutex<Search>>,
+    /// Whether to complete the line with the latest entry it starts, accepted with →
+    complete: bool,
+    /// Whether to preview the best fuzzy match, which `C-r` puts in the line
+    fuzzy: bool,
+}
+
+pub struct HistoryHint {
+    display: String,
+    completion: Option<String>,
+}
+
+impl Hint for HistoryHint {
+    fn display(&self) -> &str {
+        &self.display
+    }
+
+    fn completion(&self) -> Option<&str> {
+        self.completion.as_deref()
+    }
+}
+
+impl Hinter for HistoryHelper {
+    type Hint = HistoryHint;
+
+    fn hint(&self, line: &str, pos: usize, ctx: &rustyline::Context) -> Option<HistoryHint> {
+        let mut search = self.search.lock().unwrap();
+        // A match put in the line by `C-r`, which keeps cycling through the same matches
+        if search
+            .matches
+            .get(search.index)
+            .is_some_and(|matched| matched == line)
+        {
+            return None;
+        }
+
+        *search = Search::default();
+        if pos < line.len() || line.is_empty() {
+            return None;
+        }
+        let history = ctx.history();
+        let entries = (0..history.len())
+            .filter_map(|i| history.get(i, SearchDirection::Forward).ok().flatten())
+            .map(|result| result.entry)
+            .collect::<Vec<_>>();
+        if self.fuzzy && line.trim().chars().count() >= MIN_HINT_QUERY_CHARS {
+            search.matches = fuzzy::rank(line, entries.iter().map(AsRef::as_ref))
+                .into_iter()
+                .filter(|matched| matched != line)
+                .collect();
+        }
+
+        // Fish-style, the rest of the latest entry the line starts
+        let rest = entries
+            .iter()
+            .rev()
+            .find_map(|entry| entry.strip_prefix(line).filter(|rest| !rest.is_empty()))
+            .filter(|_| self.complete);
+        if let Some(rest) = rest {
+            return Some(HistoryHint {
+                display: preview(rest),
+                completion: Some(rest.to_owned()),
+            });
+        }
+
+        let best = search.matches.first()?;
+        Some(HistoryHint {
+            display: format!("  [C-r] {}", preview(best)),
+            completion: None,
+        })
+    }
+}
+
+/// First line of `text`, cut to [`HINT_MAX_CHARS`] with an ellipsis if anything was left out
+fn preview(text: &str) -> String {
+    let mut preview = text
+        .lines()
+        .next()
+        .unwrap_or_default()
+        .chars()
+        .take(HINT_MAX_CHARS)
+        .collect::<String>();
+    if preview.len() < text.len() {
+        preview.push('…');
+    }
+    preview
+}
+
+impl Highlighter for HistoryHelper {
+    fn highlight_hint<'h>(&self, hint: &'h str) -> Cow<'h, str> {
+        Cow::Owned(format!("{DIM}{hint}{RESET}"))
+    }
+}
+
+impl Completer for HistoryHelper {
+    type Candidate = String;
+}
+
+impl Validator for HistoryHelper {}
+
+impl Helper for HistoryHelper {}
+
+/// Replaces the line with its best fuzzy match in the history, or the next best when pressed
+/// again, falling back to the reverse search when nothing matches
+struct FuzzyHistorySearch {
+    search: Arc<Mutex<Search>>,
+}
+
+impl ConditionalEventHandler for FuzzyHistorySearch {
+    fn handle(&self, _: &Event, _: RepeatCount, _: bool, ctx: &EventContext) -> Option<Cmd> {
+        let mut search = self.search.lock().unwrap();
+        if search.matches.is_empty() {
+            return Some(Cmd::ReverseSearchHistory);
+        }
+
+        let shown = search
+            .matches
+            .get(search.index)
+            .is_some_and(|matched| matched == ctx.line());
+        search.index = if shown {
+            (search.index + 1) % search.matches.len()
+        } else {
+            0
+        };
+        Some(Cmd::Replace(
+            Movement::WholeBuffer,
+            Some(search.matches[search.index].clone()),
+        ))
+    }
+}
@@ -0,0 +1,130 @@
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use color_eyre::eyre::{Context, Result};
+use serde::Deserialize;
+
+use crate::conversation::{self, ChatMessage};
+use crate::encryption::Cipher;
+
+const RECOVERY_DIR: &str = ".sermaid_recovery";
+
+/// The `[autosave]` section of the config, saving every turn if neither is set
+#[derive(Deserialize)]
+pub struct AutosaveConfig {
+    /// Save after this many new turns
+    pub every_turns: Option<usize>,
+    /// Save changes at most this often, in seconds
+    pub every_secs: Option<u64>,
+}
+
+/// The live conversation saved in `~/.sermaid_recovery/<pid>.json` as it grows, removed on a
+/// clean exit so that files left behind by dead processes can be restored
+pub struct Recovery {
+    dir: PathBuf,
+    cipher: Option<Arc<Cipher>>,
+    every_turns: Option<usize>,
+    every: Option<Duration>,
+    /// Turns since the last save
+    unsaved_turns: usize,
+    last_saved: Instant,
+}
+
+impl Recovery {
+    pub fn new(config: &AutosaveConfig, cipher: Option<Arc<Cipher>>) -> Option<Self> {
+        let dir = home::home_dir()?.join(RECOVERY_DIR);
+        let every_turns = match (config.every_turns, config.every_secs) {
+            (None, None) => Some(1),
+            (every_turns, _) => every_turns,
+        };
+        Some(Self {
+            dir,
+            cipher,
+            every_turns,
+            every: config.every_secs.map(Duration::from_secs),
+            unsaved_turns: 0,
+            last_saved: Instant::now(),
+        })
+    }
+
+    pub fn path(&self) -> PathBuf {
+        self.dir.join(format!("{}.json", std::process::id()))
+    }
+
+    /// Files left by sessions that did not exit cleanly, most recent last
+    pub fn orphans(&self) -> Vec<PathBuf> {
+        let Ok(entries) = std::fs::read_dir(&self.dir) else {
+            return Vec::new();
+        };
+        let mut orphans = entries
+            .filter_map(Result::ok)
+            .filter(|entry| {
+                let pid = entry
+                    .path()
+                    .file_stem()
+                    .and_then(|stem| stem.to_str()?.parse().ok());
+                pid.is_some_and(|pid| !is_running(pid))
+            })
+            .filter_map(|entry| Some((entry.metadata().ok()?.modified().ok()?, entry.path())))
+            .collect::<Vec<_>>();
+        orphans.sort();
+        orphans.into_iter().map(|(_, path)| path).collect()
+    }
+
+    pub fn load(&self, path: &Path) -> Result<Vec<ChatMessage>> {
+        conversation::load(path, self.cipher.as_deref())
+    }
+
+    /// Notes a new turn and saves `history` if enough turns or time have passed since the
+    /// last save
+    pub fn turn(&mut self, history: &[ChatMessage]) -> Result<()> {
+        self.unsaved_turns += 1;
+        self.tick(history)
+    }
+
+    /// Saves `history` if it changed and enough turns or time have passed since the last save
+    pub fn tick(&mut self, history: &[ChatMessage]) -> Result<()> {
+        let due = self
+            .every_turns
+            .is_some_and(|turns| self.unsaved_turns >= turns) ||
+            self.every.is_some_and(|every| {
+                self.unsaved_turns > 0 && self.last_saved.elapsed() >= every
+            });
+        if !due {
+            return Ok(());
+        }
+
+        std::fs::create_dir_all(&self.dir)
+            .wrap_err_with(|| format!("failed to create `{}`", self.dir.display()))?;
+        conversation::save(&self.path(), history, self.cipher.as_deref())?;
+        self.unsaved_turns = 0;
+        self.last_saved = Instant::now();
+        Ok(())
+    }
+
+    /// Removes the file of this session, or of a restored one at `path`
+    pub fn discard(&self, path: Option<&Path>) -> Result<()> {
+        let path = path.map_or_else(|| self.path(), Path::to_owned);
+        match std::fs::remove_file(&path) {
+            Err(err) if err.kind() != ErrorKind::NotFound => {
+                Err(err).wrap_err_with(|| format!("failed to remove `{}`", path.display()))
+            },
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Whether the process `pid` is running
+#[cfg(unix)]
+pub fn is_running(pid: i32) -> bool {
+    // SAFETY: signal 0 only checks that the process exists and may be signaled
+    let res = unsafe { libc::kill(pid, 0) };
+    res == 0 || std::io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+#[cfg(not(unix))]
+pub fn is_running(pid: i32) -> bool {
+    pid == std::process::id() as i32
+}
@@ -0,0 +1,89 @@
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+
+use color_eyre::eyre::{Context, Result};
+use regex::Regex;
+
+/// Patterns always redacted, by the name used in their placeholder
+const BUILTIN_PATTERNS: &[(&str, &str)] = &[
+    ("api-key", r"\bsk-[A-Za-z0-9_-]{20,}"),
+    ("aws-access-key", r"\bAKIA[0-9A-Z]{16}\b"),
+    (
+        "email",
+        r"\b[A-Za-z0-9._%+-]+@[A-Za-z0-9.-]+\.[A-Za-z]{2,}\b",
+    ),
+    (
+        "private-key",
+        r"(?s)-----BEGIN [A-Z ]*PRIVATE KEY-----.*?-----END [A-Z ]*PRIVATE KEY-----",
+    ),
+];
+
+/// Replaces secrets and personal data with `[REDACTED:<name>]` placeholders
+pub struct Redactor {
+    patterns: Vec<(String, Regex)>,
+}
+
+impl Redactor {
+    /// Uses the built-in patterns plus `extra`, which maps placeholder names to regexes
+    pub fn new(extra: &BTreeMap<String, String>) -> Result<Self> {
+        let builtin = BUILTIN_PATTERNS
+            .iter()
+            .map(|(name, pattern)| (name.to_string(), pattern.to_string()));
+        let patterns = builtin
+            .chain(
+                extra
+                    .iter()
+                    .map(|(name, pattern)| (name.clone(), pattern.clone())),
+            )
+            .map(|(name, pattern)| {
+                let regex = Regex::new(&pattern)
+                    .wrap_err_with(|| format!("invalid redaction pattern `{name}`: `{pattern}`"))?;
+                Ok((name, regex))
+            })
+            .collect::<Result<_>>()?;
+
+        Ok(Self { patterns })
+    }
+
+    pub fn redact<'a>(&self, text: &'a str) -> Cow<'a, str> {
+        let mut text = Cow::Borrowed(text);
+        for (name, regex) in &self.patterns {
+            if let Cow::Owned(redacted) = regex.replace_all(&text, format!("[REDACTED:{name}]")) {
+                text = Cow::Owned(redacted);
+            }
+        }
+        text
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn redacts_api_key() {
+        let redactor = Redactor::new(&BTreeMap::new()).unwrap();
+        assert_eq!(
+            redactor.redact("key = sk-proj-AbCdEf0123456789_xyz-42, ok"),
+            "key = [REDACTED:api-key], ok"
+        );
+        assert_eq!(redactor.redact("ask-me-anything"), "ask-me-anything");
+    }
+
+    #[test]
+    fn redacts_extra_patterns_and_leaves_clean_text_borrowed() {
+        let extra = BTreeMap::from([("ticket".to_owned(), r"\bJIRA-\d+\b".to_owned())]);
+        let redactor = Redactor::new(&extra).unwrap();
+        assert_eq!(
+            redactor.redact("see JIRA-123, mail a@b.io"),
+            "see [REDACTED:ticket], mail [REDACTED:email]"
+        );
+        assert!(matches!(redactor.redact("nothing here"), Cow::Borrowed(_)));
+    }
+
+    #[test]
+    fn rejects_invalid_pattern() {
+        let extra = BTreeMap::from([("broken".to_owned(), "(".to_owned())]);
+        assert!(Redactor::new(&extra).is_err());
+    }
+}
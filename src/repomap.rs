@@ -0,0 +1,104 @@
+use std::path::Path;
+use std::str::FromStr;
+
+use color_eyre::eyre::Result;
+use syntect::easy::ScopeRangeIterator;
+use syntect::highlighting::ScopeSelectors;
+use syntect::parsing::{ParseState, ScopeStack, SyntaxSet};
+use syntect::util::LinesWithEndings;
+
+use crate::git;
+
+/// Scopes of the names that definitions introduce, leaving out markup tags
+const SYMBOL_SCOPES: &str = "entity.name - entity.name.tag";
+const MAX_SYMBOLS_PER_FILE: usize = 40;
+/// Larger files are listed without symbols, as they are mostly generated or data
+const MAX_FILE_BYTES: u64 = 256 * 1024;
+
+/// Outlines the files git tracks under the current directory as a tree, each file followed
+/// by the names of the functions, types and other symbols it defines
+pub fn build() -> Result<String> {
+    let mut paths = git::run(&["ls-files"])?
+        .lines()
+        .map(str::to_owned)
+        .collect::<Vec<_>>();
+    if paths.is_empty() {
+        color_eyre::eyre::bail!("git tracks no files under the current directory");
+    }
+    paths.sort();
+
+    let syntaxes = SyntaxSet::load_defaults_newlines();
+    let selector = ScopeSelectors::from_str(SYMBOL_SCOPES)?;
+
+    let mut map = String::new();
+    let mut dirs: Vec<&str> = Vec::new();
+    for path in &paths {
+        let mut components = path.split('/').collect::<Vec<_>>();
+        let name = components.pop().unwrap_or_default();
+
+        let common = dirs
+            .iter()
+            .zip(&components)
+            .take_while(|(a, b)| a == b)
+            .count();
+        dirs.truncate(common);
+        for dir in &components[common..] {
+            map.push_str(&format!("{}{dir}/\n", "  ".repeat(dirs.len())));
+            dirs.push(dir);
+        }
+
+        let indent = "  ".repeat(dirs.len());
+        let symbols = symbols(Path::new(path), &syntaxes, &selector);
+        if symbols.is_empty() {
+            map.push_str(&format!("{indent}{name}\n"));
+        } else {
+            map.push_str(&format!("{indent}{name}: {}\n", symbols.join(", ")));
+        }
+    }
+
+    Ok(map)
+}
+
+/// Names defined in the file at `path`, in order of appearance and without repeats
+fn symbols(path: &Path, syntaxes: &SyntaxSet, selector: &ScopeSelectors) -> Vec<String> {
+    let Some(syntax) = path
+        .extension()
+        .and_then(|extension| syntaxes.find_syntax_by_extension(&extension.to_string_lossy()))
+    else {
+        return Vec::new();
+    };
+    if std::fs::metadata(path).map_or(true, |metadata| metadata.len() > MAX_FILE_BYTES) {
+        return Vec::new();
+    }
+    let Ok(content) = std::fs::read_to_string(path) else {
+        return Vec::new();
+    };
+
+    let mut state = ParseState::new(syntax);
+    let mut stack = ScopeStack::new();
+    let mut symbols = Vec::new();
+    for line in LinesWithEndings::from(&content) {
+        let Ok(ops) = state.parse_line(line, syntaxes) else {
+            break;
+        };
+        for (range, op) in ScopeRangeIterator::new(&ops, line) {
+            if stack.apply(op).is_err() {
+                return symbols;
+            }
+
+            let symbol = line[range].trim();
+            if symbol.is_empty() || selector.does_match(stack.as_slice()).is_none() {
+                continue;
+            }
+            if !symbols.iter().any(|known| known == symbol) {
+                symbols.push(symbol.to_owned());
+            }
+            if symbols.len() == MAX_SYMBOLS_PER_FILE {
+                symbols.push("…".to_owned());
+                return symbols;
+            }
+        }
+    }
+
+    symbols
+}
@@ -0,0 +1,72 @@
+use std::fmt::Write;
+
+/// Review comments on one file, sorted by line with general comments first
+pub struct FileReview {
+    pub path: String,
+    pub comments: Vec<Comment>,
+}
+
+pub struct Comment {
+    pub line: Option<usize>,
+    pub text: String,
+}
+
+impl FileReview {
+    /// Parses answers made of `<line>: <comment>` lines, where `LGTM` means no comments
+    pub fn parse(path: String, answer: &str) -> Self {
+        let mut comments: Vec<Comment> = answer
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.eq_ignore_ascii_case("lgtm"))
+            .map(|line| {
+                let (line_no, text) = line
+                    .split_once(':')
+                    .and_then(|(line_no, text)| {
+                        let line_no = line_no.trim().trim_start_matches(['L', 'l']);
+                        Some((line_no.parse().ok()?, text.trim()))
+                    })
+                    .map_or((None, line), |(line_no, text)| (Some(line_no), text));
+
+                Comment {
+                    line: line_no,
+                    text: text.to_owned(),
+                }
+            })
+            .collect();
+        comments.sort_by_key(|comment| comment.line);
+
+        Self { path, comments }
+    }
+
+    pub fn print(&self) {
+        println!("{}", self.path);
+        if self.comments.is_empty() {
+            println!("  LGTM");
+        }
+        for comment in &self.comments {
+            match comment.line {
+                Some(line) => println!("  {line:>5}: {}", comment.text),
+                None => println!("         {}", comment.text),
+            }
+        }
+    }
+}
+
+pub fn markdown_report(reviews: &[FileReview]) -> String {
+    let mut report = String::from("# Code review\n");
+
+    for review in reviews {
+        let _ = write!(report, "\n## `{}`\n\n", review.path);
+        if review.comments.is_empty() {
+            report.push_str("LGTM\n");
+        }
+        for comment in &review.comments {
+            let _ = match comment.line {
+                Some(line) => writeln!(report, "- L{line}: {}", comment.text),
+                None => writeln!(report, "- {}", comment.text),
+            };
+        }
+    }
+
+    report
+}
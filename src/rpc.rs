@@ -0,0 +1,230 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use color_eyre::eyre::{Context, Result};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::sync::mpsc::{self, UnboundedSender};
+use tokio_util::sync::CancellationToken;
+
+use crate::conversation::ChatMessage;
+use crate::openai::{Completion, Length, OpenAI, Role};
+
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+/// Failed to get an answer
+const REQUEST_FAILED: i64 = -32000;
+/// As in the Language Server Protocol
+const REQUEST_CANCELLED: i64 = -32800;
+
+/// A request, or a notification if it has no `id`
+#[derive(Deserialize)]
+struct Incoming {
+    id: Option<Value>,
+    method: String,
+    #[serde(default)]
+    params: Value,
+}
+
+#[derive(Deserialize)]
+struct AskParams {
+    question: String,
+    max_words: Option<u32>,
+}
+
+#[derive(Deserialize)]
+struct CancelParams {
+    id: Value,
+}
+
+/// The conversation of an editor talking JSON-RPC on stdio
+pub struct Rpc {
+    openai: Arc<OpenAI>,
+    /// Pinned context sent before every question
+    pins: Vec<ChatMessage>,
+    length: Length,
+    history: tokio::sync::Mutex<Vec<ChatMessage>>,
+    /// Answers being generated, by request id
+    running: std::sync::Mutex<HashMap<String, CancellationToken>>,
+}
+
+impl Rpc {
+    pub fn new(openai: Arc<OpenAI>, pins: Vec<ChatMessage>, length: Length) -> Self {
+        Self {
+            openai,
+            pins,
+            length,
+            history: Default::default(),
+            running: Default::default(),
+        }
+    }
+
+    fn handle(self: &Arc<Self>, line: &str, tx: &UnboundedSender<Value>) {
+        let incoming = match serde_json::from_str::<Incoming>(line) {
+            Ok(incoming) => incoming,
+            Err(err) => {
+                let code = if serde_json::from_str::<Value>(line).is_ok() {
+                    INVALID_REQUEST
+                } else {
+                    PARSE_ERROR
+                };
+                let _ = tx.send(error(&Value::Null, code, &err.to_string()));
+                return;
+            },
+        };
+
+        match incoming.method.as_str() {
+            method @ ("ask" | "continue") => {
+                // Nobody would read the answer to a notification
+                let Some(id) = incoming.id else {
+                    return;
+                };
+                let params = match serde_json::from_value::<AskParams>(incoming.params) {
+                    Ok(params) => params,
+                    Err(err) => {
+                        let _ = tx.send(error(&id, INVALID_PARAMS, &err.to_string()));
+                        return;
+                    },
+                };
+
+                let token = CancellationToken::new();
+                if let Ok(mut running) = self.running.lock() {
+                    running.insert(id.to_string(), token.clone());
+                }
+                let rpc = self.clone();
+                let tx = tx.clone();
+                let continues = method == "continue";
+                tokio::spawn(async move {
+                    let res = tokio::select! {
+                        res = rpc.answer(&id, continues, params, &tx) => Some(res),
+                        _ = token.cancelled() => None,
+                    };
+                    if let Ok(mut running) = rpc.running.lock() {
+                        running.remove(&id.to_string());
+                    }
+                    let _ = tx.send(match res {
+                        Some(Ok(completion)) => json!({
+                            "jsonrpc": "2.0",
+                            "id": id,
+                            "result": {
+                                "content": completion.content,
+                                "model": completion.model,
+                                "usage": completion.usage,
+                            },
+                        }),
+                        Some(Err(err)) => error(&id, REQUEST_FAILED, &format!("{err:#}")),
+                        None => error(&id, REQUEST_CANCELLED, "cancelled"),
+                    });
+                });
+            },
+            "cancel" => {
+                let cancelled = serde_json::from_value::<CancelParams>(incoming.params)
+                    .map(|params| self.cancel(&params.id));
+                if let Some(id) = incoming.id {
+                    let _ = tx.send(match cancelled {
+                        Ok(cancelled) => json!({"jsonrpc": "2.0", "id": id, "result": cancelled}),
+                        Err(err) => error(&id, INVALID_PARAMS, &err.to_string()),
+                    });
+                }
+            },
+            method => {
+                if let Some(id) = incoming.id {
+                    let _ = tx.send(error(
+                        &id,
+                        METHOD_NOT_FOUND,
+                        &format!("no method `{method}`"),
+                    ));
+                }
+            },
+        }
+    }
+
+    /// Whether request `id` was still being answered
+    fn cancel(&self, id: &Value) -> bool {
+        let token = self
+            .running
+            .lock()
+            .ok()
+            .and_then(|running| running.get(&id.to_string()).cloned());
+        token.inspect(CancellationToken::cancel).is_some()
+    }
+
+    async fn answer(
+        &self,
+        id: &Value,
+        continues: bool,
+        params: AskParams,
+        tx: &UnboundedSender<Value>,
+    ) -> Result<Completion> {
+        let length = params.max_words.map_or(self.length, Length::Words);
+        let mut context = self.pins.clone();
+        if continues {
+            context.extend(self.history.lock().await.iter().cloned());
+        }
+
+        let completion = self
+            .openai
+            .q_and_a_streaming(params.question.clone(), &context, length, |delta| {
+                let _ = tx.send(json!({
+                    "jsonrpc": "2.0",
+                    "method": "stream",
+                    "params": {"id": id, "delta": delta},
+                }));
+            })
+            .await?;
+
+        let mut history = self.history.lock().await;
+        history.push(ChatMessage {
+            command: Some(if continues { "continue" } else { "ask" }.to_owned()),
+            ..ChatMessage::new(Role::User, params.question)
+        });
+        history.push(ChatMessage::from_completion(completion.clone()));
+        Ok(completion)
+    }
+}
+
+/// Answers newline-delimited JSON-RPC 2.0 requests on stdin until it closes, on stdout
+///
+/// - `ask` and `continue` take `{"question": ..., "max_words": ...}`, `continue` following up
+///   on the earlier answers. While answering they send `stream` notifications with
+///   `{"id": <request id>, "delta": <text>}`, then answer `{"content", "model", "usage"}`.
+/// - `cancel` takes `{"id": <request id>}` and makes that request fail with code -32800.
+pub async fn run(rpc: Rpc) -> Result<()> {
+    let rpc = Arc::new(rpc);
+    let (tx, mut rx) = mpsc::unbounded_channel::<Value>();
+    let writer = tokio::spawn(async move {
+        let mut stdout = tokio::io::stdout();
+        while let Some(message) = rx.recv().await {
+            let line = format!("{message}\n");
+            if stdout.write_all(line.as_bytes()).await.is_err() || stdout.flush().await.is_err() {
+                break;
+            }
+        }
+    });
+
+    let mut lines = BufReader::new(tokio::io::stdin()).lines();
+    while let Some(line) = lines
+        .next_line()
+        .await
+        .wrap_err_with(|| "failed to read stdin")?
+    {
+        if !line.trim().is_empty() {
+            rpc.handle(&line, &tx);
+        }
+    }
+
+    // Answers still being generated are sent before exiting
+    drop(tx);
+    writer.await.wrap_err_with(|| "failed to write to stdout")
+}
+
+fn error(id: &Value, code: i64, message: &str) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "id": id,
+        "error": {"code": code, "message": message},
+    })
+}
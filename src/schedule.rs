@@ -0,0 +1,304 @@
+use std::fmt::Write as _;
+use std::io::{ErrorKind, Write as _};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use chrono::{DateTime, Datelike, Days, Local, NaiveTime, TimeDelta, TimeZone, Weekday};
+use color_eyre::eyre::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::conversation::{self, ChatMessage};
+use crate::encryption::{self, Cipher};
+use crate::offline::QueuedRequest;
+use crate::openai::{Completion, Role};
+
+const SCHEDULES_FILE: &str = ".sermaid_schedules.json";
+
+/// Prompts run by `serve` on a schedule, kept in `~/.sermaid_schedules.json`
+#[derive(Clone)]
+pub struct Schedules {
+    path: PathBuf,
+    cipher: Option<Arc<Cipher>>,
+}
+
+/// A prompt added with `schedule`
+#[derive(Serialize, Deserialize)]
+pub struct Scheduled {
+    /// When to run, like `every day 9am`, see [`When`]
+    pub when: String,
+    pub request: QueuedRequest,
+    /// HTTP API session the answers are added to
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub session: Option<String>,
+    /// File the answers are appended to, a conversation if it ends in `.json` and markdown
+    /// otherwise
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub into: Option<PathBuf>,
+    /// Seconds since the Unix epoch of the last run, or of when it was added
+    pub last_run: u64,
+}
+
+impl Scheduled {
+    /// The next time to run after the last run
+    pub fn next_run(&self) -> Result<DateTime<Local>> {
+        let when = self
+            .when
+            .parse::<When>()
+            .map_err(|err| color_eyre::eyre::eyre!("invalid schedule `{}`: {err}", self.when))?;
+        let last_run = i64::try_from(self.last_run)
+            .ok()
+            .and_then(|last_run| Local.timestamp_opt(last_run, 0).single())
+            .unwrap_or_else(Local::now);
+        Ok(when.next_after(last_run))
+    }
+
+    /// Appends the answer to the file of `into`
+    pub fn record(&self, completion: &Completion, cipher: Option<&Cipher>) -> Result<()> {
+        let Some(path) = &self.into else {
+            return Ok(());
+        };
+        let question = self.request.text();
+        if path
+            .extension()
+            .is_some_and(|extension| extension == "json")
+        {
+            let mut history = conversation::load(path, cipher)?;
+            history.push(ChatMessage {
+                command: Some(self.request.command().to_owned()),
+                ..ChatMessage::new(Role::User, question.to_owned())
+            });
+            history.push(ChatMessage::from_completion(completion.clone()));
+            return conversation::save(path, &history, cipher);
+        }
+
+        let mut entry = String::new();
+        let _ = write!(
+            entry,
+            "## {} — {} {question}\n\n{}\n\n",
+            Local::now().format("%Y-%m-%d %H:%M"),
+            self.request.command(),
+            completion.content.trim()
+        );
+        std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .and_then(|mut file| file.write_all(entry.as_bytes()))
+            .wrap_err_with(|| format!("failed to append to `{}`", path.display()))
+    }
+}
+
+impl Schedules {
+    /// The schedules in the home directory, encrypted like conversations if a `cipher` is given
+    pub fn new(cipher: Option<Arc<Cipher>>) -> Option<Self> {
+        let path = home::home_dir()?.join(SCHEDULES_FILE);
+        Some(Self { path, cipher })
+    }
+
+    pub fn cipher(&self) -> Option<&Cipher> {
+        self.cipher.as_deref()
+    }
+
+    pub fn load(&self) -> Result<Vec<Scheduled>> {
+        let mut contents = match std::fs::read(&self.path) {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(err) => {
+                return Err(err)
+                    .wrap_err_with(|| format!("failed to read `{}`", self.path.display()))
+            },
+        };
+        if encryption::is_encrypted(&contents) {
+            let cipher = self.cipher.as_ref().ok_or_else(|| {
+                color_eyre::eyre::eyre!(
+                    "`{}` is encrypted but no `[encryption]` is configured",
+                    self.path.display()
+                )
+            })?;
+            contents = cipher
+                .decrypt(&contents)
+                .wrap_err_with(|| format!("failed to decrypt `{}`", self.path.display()))?;
+        }
+
+        serde_json::from_slice(&contents)
+            .wrap_err_with(|| format!("failed to parse `{}`", self.path.display()))
+    }
+
+    pub fn save(&self, schedules: &[Scheduled]) -> Result<()> {
+        let mut contents = serde_json::to_vec_pretty(schedules)
+            .wrap_err_with(|| "failed to serialize schedules")?;
+        if let Some(cipher) = &self.cipher {
+            contents = cipher.encrypt(&contents)?;
+        }
+        std::fs::write(&self.path, contents)
+            .wrap_err_with(|| format!("failed to write `{}`", self.path.display()))
+    }
+}
+
+/// When a scheduled prompt runs: `every 15 minutes`, `every hour`, `every day 9am`,
+/// `every weekday at 17:30` or `every monday 8:15am`
+#[derive(Clone, Copy, Debug)]
+pub enum When {
+    Every(TimeDelta),
+    At { days: OnDays, time: NaiveTime },
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum OnDays {
+    All,
+    Weekdays,
+    Only(Weekday),
+}
+
+impl OnDays {
+    fn contains(self, day: Weekday) -> bool {
+        match self {
+            Self::All => true,
+            Self::Weekdays => !matches!(day, Weekday::Sat | Weekday::Sun),
+            Self::Only(only) => day == only,
+        }
+    }
+}
+
+impl When {
+    /// The first time to run strictly after `after`
+    pub fn next_after(self, after: DateTime<Local>) -> DateTime<Local> {
+        match self {
+            Self::Every(interval) => after + interval,
+            Self::At { days, time } => (0..=7)
+                .filter_map(|offset| after.date_naive().checked_add_days(Days::new(offset)))
+                .filter(|date| days.contains(date.weekday()))
+                .filter_map(|date| Local.from_local_datetime(&date.and_time(time)).earliest())
+                .find(|next| *next > after)
+                .unwrap_or(after + TimeDelta::days(1)),
+        }
+    }
+}
+
+impl FromStr for When {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let lowercase = s.to_lowercase();
+        let mut words = lowercase
+            .split_whitespace()
+            .filter(|&word| word != "at")
+            .peekable();
+        if words.next() != Some("every") {
+            return Err("expected `every ...`".to_owned());
+        }
+
+        let count = words.peek().and_then(|word| word.parse::<i64>().ok());
+        if count.is_some() {
+            words.next();
+        }
+        let count = count.unwrap_or(1);
+        let unit = words.next().ok_or("expected a unit or day after `every`")?;
+        let interval = match unit.trim_end_matches('s') {
+            "minute" | "min" => Some(TimeDelta::minutes(count)),
+            "hour" => Some(TimeDelta::hours(count)),
+            _ => None,
+        };
+        if let Some(interval) = interval {
+            if count < 1 || words.next().is_some() {
+                return Err(format!("expected `every N {unit}`"));
+            }
+            return Ok(Self::Every(interval));
+        }
+
+        let days = match unit {
+            "day" => OnDays::All,
+            "weekday" => OnDays::Weekdays,
+            day => OnDays::Only(
+                day.parse()
+                    .map_err(|_| format!("unknown unit or day `{day}`"))?,
+            ),
+        };
+        let time = words
+            .next()
+            .ok_or("expected a time like 9am or 17:30")
+            .and_then(|time| parse_time(time).ok_or("expected a time like 9am or 17:30"))?;
+        if words.next().is_some() {
+            return Err("unexpected words after the time".to_owned());
+        }
+        Ok(Self::At { days, time })
+    }
+}
+
+/// `9am`, `9:30pm` or `17:30`
+fn parse_time(time: &str) -> Option<NaiveTime> {
+    let (time, offset) = match (time.strip_suffix("am"), time.strip_suffix("pm")) {
+        (Some(time), _) => (time, Some(0)),
+        (_, Some(time)) => (time, Some(12)),
+        _ => (time, None),
+    };
+    let (hour, minute) = time.split_once(':').unwrap_or((time, "0"));
+    let mut hour = hour.parse::<u32>().ok()?;
+    let minute = minute.parse().ok()?;
+    if let Some(offset) = offset {
+        if !(1..=12).contains(&hour) {
+            return None;
+        }
+        hour = hour % 12 + offset;
+    }
+    NaiveTime::from_hms_opt(hour, minute, 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A local time in January, away from daylight saving changes
+    fn local(day: u32, hour: u32, minute: u32) -> DateTime<Local> {
+        Local
+            .with_ymd_and_hms(2024, 1, day, hour, minute, 0)
+            .unwrap()
+    }
+
+    fn next(when: &str, after: DateTime<Local>) -> DateTime<Local> {
+        when.parse::<When>().unwrap().next_after(after)
+    }
+
+    #[test]
+    fn next_fire_times() {
+        // 2024-01-10 is a Wednesday
+        assert_eq!(
+            next("every 15 minutes", local(10, 10, 0)),
+            local(10, 10, 15)
+        );
+        assert_eq!(next("every hour", local(10, 23, 30)), local(11, 0, 30));
+        assert_eq!(next("every day 9am", local(10, 8, 0)), local(10, 9, 0));
+        assert_eq!(next("every day 9am", local(10, 10, 0)), local(11, 9, 0));
+        assert_eq!(
+            next("every weekday at 17:30", local(12, 18, 0)),
+            local(15, 17, 30)
+        );
+        assert_eq!(
+            next("Every Monday 8:15am", local(15, 8, 15)),
+            local(22, 8, 15)
+        );
+    }
+
+    #[test]
+    fn parses_twelve_hour_times() {
+        assert_eq!(parse_time("12am"), NaiveTime::from_hms_opt(0, 0, 0));
+        assert_eq!(parse_time("12pm"), NaiveTime::from_hms_opt(12, 0, 0));
+        assert_eq!(parse_time("9:30pm"), NaiveTime::from_hms_opt(21, 30, 0));
+        assert_eq!(parse_time("13pm"), None);
+    }
+
+    #[test]
+    fn rejects_invalid_schedules() {
+        for when in [
+            "daily",
+            "every 0 hours",
+            "every day",
+            "every day 25:00",
+            "every fortnight 9am",
+            "every day 9am sharp",
+        ] {
+            assert!(when.parse::<When>().is_err(), "{when}");
+        }
+    }
+}
@@ -1,155 +1,4020 @@
-use std::borrow::Cow;
+use std::collections::{BTreeMap, HashSet, VecDeque};
 use std::future::Future;
-use std::path::PathBuf;
-use std::sync::Arc;
-use std::time::Duration;
+use std::io::{IsTerminal, Write};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
-use clap::{Parser, Subcommand};
+use clap::{CommandFactory, FromArgMatches, Parser, Subcommand, ValueEnum};
 use color_eyre::eyre::{Context, Result};
-use indicatif::ProgressBar;
-use rustyline::DefaultEditor;
+use futures_util::stream::{FuturesUnordered, StreamExt};
+use indicatif::{ProgressBar, ProgressStyle};
+use rustyline::error::ReadlineError;
+use serde::Serialize;
 use tokio_util::sync::CancellationToken;
 
-use crate::openai::OpenAI;
-use crate::{Config, CARGO_PKG_NAME};
+use crate::attachment::PageRange;
+use crate::bridge::{Bridge, BridgeConfig};
+use crate::budget::{Budget, LargePrompt, OverBudget, PromptLimit};
+use crate::compress::CompressionConfig;
+use crate::conversation::{ChatMessage, Rating};
+use crate::custom::{CustomCommand, TemplateVar};
+use crate::diagnostic::ExplainErrorConfig;
+use crate::encryption::Cipher;
+use crate::gateway::Gateway;
+use crate::glossary::Glossary;
+use crate::highlight::Highlighter;
+use crate::live::LivePane;
+use crate::mcp::Mcp;
+use crate::ocr::{self, OcrConfig};
+use crate::offline::{OfflineQueue, Queued, QueuedRequest};
+use crate::openai::{Completion, Length, OpenAI, OpenAIError, Provider, Quota, Role, Usage};
+use crate::plugin::{PluginInput, PluginOutput};
+use crate::pruning::{self, Pruning};
+use crate::readline::LineEditor;
+use crate::recovery::Recovery;
+use crate::redaction::Redactor;
+use crate::review::FileReview;
+use crate::rpc::Rpc;
+use crate::schedule::{Scheduled, Schedules};
+use crate::server::ServerState;
+use crate::share::{self, ShareConfig};
+use crate::speech::{Sentences, Speaker};
+use crate::store::{self, Store};
+use crate::theme::{SpinnerStyle, Theme};
+use crate::title::TerminalTitle;
+use crate::transform::Transformers;
+use crate::typeahead::TypeAhead;
+use crate::voice::{self, VoiceConfig};
+use crate::{
+    attachment, bundle, chunk, clipboard, compress, confidence, conversation, custom, daemon,
+    diagnostic, diff, external_editor, fetch, files, footer, git, help, http, notify, patch,
+    plugin, pricing, readline, repomap, review, rpc, schedule, server, terminal, tokenizer, triage,
+    watch, Args, Config, CARGO_PKG_NAME,
+};
+
+const DEFAULT_FIXTURES_DIR: &str = "./fixtures";
+const SHORT_WORDS: u32 = 50;
+const TRANSLATE_CHUNK_CHARS: usize = 6_000;
+const URL_CONTEXT_MAX_TOKENS: usize = 6_000;
+const FILES_MAX_TOKENS: usize = 32_000;
+const REPO_MAP_MAX_TOKENS: usize = 8_000;
+const CONTEXT_PREVIEW_CHARS: usize = 72;
+/// Columns of the bar of the answer with the most tokens in `timeline`
+const TIMELINE_BAR_WIDTH: usize = 20;
+const APPLY_CONTEXT_LINES: usize = 3;
+const MAX_FOLLOW_UPS: usize = 3;
+/// Alternatives returned for each token of answers to `ask --show-confidence`
+const TOP_LOGPROBS: u8 = 3;
+/// Least likely tokens listed after answers to `ask --show-confidence`
+const LEAST_LIKELY_TOKENS: usize = 5;
+const RATE_LIMIT_RETRIES: u32 = 3;
+/// Wait before the first retry of a rate-limited request that did not say how long to wait,
+/// doubled for each further retry
+const RATE_LIMIT_BACKOFF: Duration = Duration::from_secs(2);
+/// Finish reason of answers cut by a content filter
+const CONTENT_FILTER: &str = "content_filter";
+/// Exit codes of `--raw` and `--output json`, by what failed
+const EXIT_FAILURE: u8 = 1;
+const EXIT_USAGE: u8 = 2;
+const EXIT_AUTH: u8 = 3;
+const EXIT_RATE_LIMIT: u8 = 4;
+const EXIT_CONTENT_FILTER: u8 = 5;
+const QUEUED_PROMPT: &str = "(queued)> ";
+/// Tool calls are answered between requests, so the answers of MCP tool loops come whole
+const NO_STREAM_WITH_MCP: &str = "answers are not streamed while MCP servers are configured";
+const BOLD: &str = "\x1b[1m";
+const DIM: &str = "\x1b[2m";
+const RESET: &str = "\x1b[0m";
+const CLIPBOARD_POLL_INTERVAL: Duration = Duration::from_secs(1);
+const PR_DIFF_MAX_TOKENS: usize = 15_000;
+const PR_TEMPLATE_FILE: &str = ".github/pull_request_template.md";
 
 pub(crate) struct SerMaid {
-    editor: DefaultEditor,
+    editor: LineEditor,
     history_file: Option<PathBuf>,
+    auto_add_history: bool,
+    /// Whether caches are written, unset by `--no-persist`
+    persist: bool,
+    /// Files that failed to be written, each warned about once
+    unwritable: HashSet<PathBuf>,
+
+    openai: Arc<OpenAI>,
+
+    history: Vec<ChatMessage>,
+    pins: Vec<Pin>,
+    /// Pieces of a prompt being composed with `buf`
+    buffer: Vec<String>,
+    /// The composed prompt `buf send` passes as the text of a command
+    composed: Option<String>,
+
+    settings: Settings,
+    summary: Option<Summary>,
+    /// Name of the conversation, shown in the status line
+    name: Option<String>,
+    /// Tags of the conversation, saved with it by `export` and listed by `session list`
+    tags: Vec<String>,
+    /// Follow-up questions suggested after the last answer, sent with `f1`, `f2`, ...
+    follow_ups: Vec<String>,
+    /// Files attached to the last `ask`, numbered as the sources its answers cite
+    sources: Vec<files::File>,
+    /// `data:` URLs of images attached with `attach --clipboard-image`, sent with the next
+    /// `ask` or `continue`
+    images: Vec<String>,
+    /// The last question sent with `ask` or `continue`, to catch accidental repeats
+    last_question: Option<String>,
+    /// Commands typed while a request was pending, run before reading the next one
+    queued: Mutex<VecDeque<String>>,
+    /// Autosave of the live conversation, restored after a crash
+    recovery: Option<Recovery>,
+    /// Conversations of every session, if `store` is enabled
+    store: Option<Arc<Store>>,
+    /// Id in the store of the conversation, set once its first message is stored
+    stored_conversation: Option<i64>,
+    /// Messages of the history already in the store
+    stored: usize,
+    /// Version of the conversation in the store as last written or read, to catch writes of
+    /// another sermaid
+    stored_version: u64,
+    /// The answer being streamed, while `stream` is set
+    live: LivePane,
+    /// Compression of long prompts
+    compression: Option<CompressionConfig>,
+    /// Requests that failed for lack of network, sent with `flush`
+    offline_queue: Option<OfflineQueue>,
+    /// Prompts run by `serve` on a schedule
+    schedules: Option<Schedules>,
+    daemon_socket: Option<PathBuf>,
+    /// The config file, read by `config export` and written by `config import`
+    config_path: PathBuf,
+    /// HTTP client whose connections are shared with the API, for fetching pages and the bridge
+    client: reqwest::Client,
+    /// Questions asked with `ask --bg`, by id
+    jobs: BTreeMap<usize, Job>,
+    next_job: usize,
+    pr_template: Option<String>,
+    explain_error: ExplainErrorConfig,
+    ocr: OcrConfig,
+    voice: VoiceConfig,
+
+    interactive: bool,
+    /// Set by a single command that asks to continue in the REPL
+    follow_up: bool,
+    /// Print only answers on stdout and errors on stderr, set by `--raw` and `--output json`
+    raw: bool,
+    /// Print answers as JSON objects, set by `--output json`
+    json: bool,
+    /// What the last request took, retries included, for `--output json`
+    latency: Mutex<Duration>,
+    /// Exit code of the first error printed, see [`SerMaid::exit_code`]
+    exit_code: AtomicU8,
+    url_fetch: bool,
+
+    custom_commands: BTreeMap<String, CustomCommand>,
+    plugins: BTreeMap<String, PathBuf>,
+    transformers: Transformers,
+    highlighter: Option<Highlighter>,
+    color: bool,
+    theme: Theme,
+    mcp: Mcp,
+    bridge: Option<BridgeConfig>,
+    share: Option<ShareConfig>,
+    cipher: Option<Arc<Cipher>>,
+    redactor: Redactor,
+    notify_after: Option<Duration>,
+    terminal_title: bool,
+    /// The title of the terminal while the REPL runs, if `terminal_title` is enabled
+    title: Option<TerminalTitle>,
+    /// Reads the last answer to `ask --speak` aloud
+    speaker: Mutex<Option<Speaker>>,
+}
+
+struct Pin {
+    label: String,
+    content: String,
+}
+
+struct Settings {
+    footer: bool,
+    status_line: bool,
+    show_reasoning: bool,
+    follow_ups: bool,
+    stream: bool,
+    brevity: Brevity,
+    pruning: Pruning,
+    history_limit: Option<usize>,
+}
+
+/// An answer printed by `--output json`
+#[derive(Serialize)]
+struct JsonAnswer<'a> {
+    answer: &'a str,
+    model: &'a str,
+    usage: Option<Usage>,
+    finish_reason: Option<&'a str>,
+    latency_ms: u128,
+}
+
+/// Rolling summary of the history before `covers`, used by [`Pruning::Summary`]
+struct Summary {
+    content: String,
+    covers: usize,
+}
+
+/// How [`SerMaid::q_and_a`] asks a question, beyond its context and length
+#[derive(Clone, Copy, Default)]
+struct AskOptions<'a> {
+    /// GBNF grammar the answer must follow
+    grammar: Option<&'a str>,
+    /// Start of the answer, for the model to continue
+    prefill: Option<&'a str>,
+    /// Ask for the log probabilities of the tokens of the answer
+    show_confidence: bool,
+    /// Read the answer aloud as it streams
+    speak: bool,
+    /// `data:` URLs of images sent along with the question
+    images: &'a [String],
+}
+
+impl SerMaid {
+    pub fn from_config(args: &Args, config: Config) -> Result<Self> {
+        // A single command may be followed by a REPL after stdin was used up as input
+        let mut editor = readline::editor(&config.editor, !args.command.is_empty())?;
+        if let Some(history_file) = &config.history_file {
+            let _ = editor.load_history(history_file);
+        }
+
+        let client = http::client(&config.http)?;
+        let openai = match config.provider {
+            provider @ (Provider::OpenAI |
+            Provider::DeepSeek |
+            Provider::Mistral |
+            Provider::Groq |
+            Provider::LlamaCpp) => {
+                let api_tokens = config
+                    .api_token
+                    .into_iter()
+                    .chain(config.api_tokens)
+                    .collect::<Vec<_>>();
+                // llama-server only checks keys when started with `--api-key`
+                let keyless = matches!(provider, Provider::LlamaCpp);
+                if api_tokens.is_empty() && args.replay.is_none() && !keyless {
+                    color_eyre::eyre::bail!(
+                        "`api_token` or `api_tokens` is required by provider `{}`",
+                        provider.as_str()
+                    );
+                }
+
+                let mut openai = OpenAI::new(api_tokens, client.clone())
+                    .with_provider(provider)
+                    .with_endpoint(config.endpoint)
+                    .with_organization(config.organization)
+                    .with_project(config.project)
+                    .with_admin_key(config.admin_key)
+                    .with_gateway(Gateway::new(config.gateway)?);
+                if config.max_cost_per_request.is_some() || config.max_cost_per_day.is_some() {
+                    openai = openai.with_budget(Budget::new(
+                        config.max_cost_per_request,
+                        config.max_cost_per_day,
+                    )?);
+                }
+                openai
+            },
+            Provider::Mock => OpenAI::mock(
+                config
+                    .fixtures_dir
+                    .unwrap_or_else(|| PathBuf::from(DEFAULT_FIXTURES_DIR)),
+            ),
+        };
+        if let Some(model) = &config.model {
+            let model = config.provider.resolve(model.clone());
+            let any_model = matches!(config.provider, Provider::LlamaCpp);
+            if !any_model && !config.provider.models().contains(&model.as_str()) {
+                println!(
+                    "model `{model}` is not a known model of provider `{}`, using it anyway",
+                    config.provider.as_str()
+                );
+            }
+        }
+        let mut openai = openai.with_model(config.model).with_seed(config.seed);
+        if config.max_prompt_tokens.is_some() || config.max_prompt_bytes.is_some() {
+            openai = openai.with_prompt_limit(PromptLimit::new(
+                config.max_prompt_tokens,
+                config.max_prompt_bytes,
+            ));
+        }
+        if let Some(record) = &args.record {
+            openai = openai.with_record(record.clone());
+        }
+        if let Some(replay) = &args.replay {
+            openai = openai.with_replay(replay.clone())?;
+        }
+
+        for name in custom::shadowed(&Cli::command(), &config.commands) {
+            println!("custom command `{name}` is ignored as it shadows a built-in command");
+        }
+
+        let transformers = match &config.wasm_plugins_dir {
+            Some(dir) => Transformers::load(dir)?,
+            None => Transformers::default(),
+        };
+
+        let cipher = config
+            .encryption
+            .as_ref()
+            .map(Cipher::from_config)
+            .transpose()?
+            .map(Arc::new);
+
+        let store = if config.store && !args.no_persist {
+            if cipher.is_some() {
+                color_eyre::eyre::bail!(
+                    "`store` keeps conversations unencrypted, so it cannot be used with \
+                     `[encryption]`"
+                );
+            }
+            match Store::open() {
+                Ok(store) => Some(Arc::new(store)),
+                Err(err) => {
+                    println!("warning: {err:#}, conversations are not stored");
+                    None
+                },
+            }
+        } else {
+            None
+        };
+
+        let raw = args.raw || matches!(args.output, Some(OutputFormat::Json));
+        if config.stream && !raw && !config.mcp_servers.is_empty() {
+            println!("{NO_STREAM_WITH_MCP}");
+        }
+        // Escape codes would end up in files and pipes
+        let color = !args.plain && !raw && std::io::stdout().is_terminal();
+        let theme = Theme::new(&config.theme, color);
+        let highlighter = if color {
+            let name = config.highlight_theme.as_deref().unwrap_or(theme.highlight);
+            Some(Highlighter::new(name)?.with_text_color(Theme::start(theme.assistant)))
+        } else {
+            None
+        };
+
+        Ok(Self {
+            editor,
+            history_file: config.history_file.filter(|_| !args.no_persist),
+            auto_add_history: config.editor.auto_add_history,
+            persist: !args.no_persist,
+            unwritable: HashSet::new(),
+            openai: Arc::new(openai),
+            history: Vec::new(),
+            pins: Vec::new(),
+            buffer: Vec::new(),
+            composed: None,
+            // Nothing but the answer with `--raw`
+            settings: Settings {
+                footer: config.footer && !raw,
+                status_line: config.status_line && !raw,
+                show_reasoning: config.show_reasoning && !raw,
+                stream: config.stream && !raw,
+                follow_ups: config.follow_ups && !raw,
+                brevity: Brevity::Normal,
+                pruning: config.pruning,
+                history_limit: config.history_limit,
+            },
+            summary: None,
+            name: None,
+            tags: Vec::new(),
+            follow_ups: Vec::new(),
+            sources: Vec::new(),
+            images: Vec::new(),
+            last_question: None,
+            queued: Mutex::default(),
+            recovery: config
+                .autosave
+                .as_ref()
+                .filter(|_| args.command.is_empty() && !args.no_persist)
+                .and_then(|autosave| Recovery::new(autosave, cipher.clone())),
+            store,
+            stored_conversation: None,
+            stored: 0,
+            stored_version: 0,
+            live: LivePane::default(),
+            compression: config.compression,
+            offline_queue: config
+                .offline_queue
+                .then(|| OfflineQueue::new(cipher.clone()))
+                .flatten(),
+            schedules: Schedules::new(cipher.clone()),
+            daemon_socket: config.daemon_socket.or_else(daemon::default_socket),
+            config_path: args.config.clone(),
+            client,
+            jobs: BTreeMap::new(),
+            next_job: 1,
+            pr_template: config.pr_template,
+            explain_error: config.explain_error,
+            ocr: config.ocr,
+            voice: config.voice,
+            interactive: args.command.is_empty(),
+            follow_up: false,
+            raw,
+            json: matches!(args.output, Some(OutputFormat::Json)),
+            latency: Mutex::new(Duration::ZERO),
+            exit_code: AtomicU8::new(0),
+            url_fetch: config.url_fetch,
+            custom_commands: config.commands,
+            plugins: plugin::discover(),
+            transformers,
+            highlighter,
+            theme,
+            color,
+            mcp: Mcp::new(config.mcp_servers),
+            bridge: config.bridge,
+            share: config.share,
+            cipher,
+            redactor: Redactor::new(&config.redaction_patterns)?,
+            notify_after: config.notify_after_secs.map(Duration::from_secs),
+            terminal_title: config.terminal_title,
+            title: None,
+            speaker: Mutex::new(None),
+        })
+    }
+
+    /// Runs a single command given on the command line, reading input from stdin if needed
+    pub async fn run_once(&mut self, command: Vec<String>) -> Result<()> {
+        let mut args = vec![CARGO_PKG_NAME.to_owned()];
+        args.extend(command);
+
+        self.command_and_continue(args).await;
+        if self.follow_up && !self.raw {
+            self.interactive = true;
+            return self.run().await;
+        }
+        Ok(())
+    }
+
+    /// 0 unless a command failed: [`EXIT_AUTH`] if the API rejected the key,
+    /// [`EXIT_RATE_LIMIT`] if it kept rate limiting, [`EXIT_CONTENT_FILTER`] if a content filter
+    /// refused the question or cut the answer, [`EXIT_USAGE`] for an invalid command and
+    /// [`EXIT_FAILURE`] otherwise
+    pub fn exit_code(&self) -> u8 {
+        self.exit_code.load(Ordering::Relaxed)
+    }
+
+    /// Keeps `code` as the exit code unless an earlier failure set one
+    fn fail(&self, code: u8) {
+        let _ = self
+            .exit_code
+            .compare_exchange(0, code, Ordering::Relaxed, Ordering::Relaxed);
+    }
+
+    pub async fn run(&mut self) -> Result<()> {
+        self.restore();
+        if self.terminal_title && std::io::stdout().is_terminal() {
+            self.title = Some(TerminalTitle::new());
+        }
+        let res = self.repl().await;
+        if let Some(title) = self.title.take() {
+            title.restore();
+        }
+        self.release_stored();
+        if let Some(recovery) = &self.recovery {
+            if let Err(err) = recovery.discard(None) {
+                self.print_error(&err);
+            }
+        }
+        res
+    }
+
+    /// Offers to restore conversations autosaved by sessions that did not exit cleanly
+    fn restore(&mut self) {
+        let Some(recovery) = &self.recovery else {
+            return;
+        };
+        for path in recovery.orphans() {
+            let history = match recovery.load(&path) {
+                Ok(history) => history,
+                Err(err) => {
+                    self.print_error(&err);
+                    continue;
+                },
+            };
+            let when = history
+                .last()
+                .map(ChatMessage::local_time)
+                .unwrap_or_default();
+            let question = format!(
+                "restore the conversation of {} messages from a session that ended unexpectedly \
+                 ({when})?",
+                history.len()
+            );
+            if !history.is_empty() && confirm(&question) {
+                self.history.extend(history);
+            }
+            if let Err(err) = recovery.discard(Some(&path)) {
+                self.print_error(&err);
+            }
+        }
+    }
+
+    async fn repl(&mut self) -> Result<()> {
+        if let Some(queue) = &self.offline_queue {
+            match queue.load() {
+                Ok(queued) if !queued.is_empty() => println!(
+                    "{} requests queued while offline, send them with `flush`",
+                    queued.len()
+                ),
+                Ok(_) => {},
+                Err(err) => self.print_error(&err),
+            }
+        }
+
+        loop {
+            self.announce_jobs();
+            self.update_title(false);
+            if let Some(recovery) = &mut self.recovery {
+                if let Err(err) = recovery.tick(&self.history) {
+                    let path = recovery.path();
+                    self.warn_unwritable(&path, &err);
+                }
+            }
+            if self.settings.status_line {
+                println!("{}", self.status_line());
+            }
+            let queued = self.queued.lock().unwrap().pop_front();
+            let command = match queued {
+                Some(queued) => {
+                    println!("{}{queued}", Theme::paint(self.theme.prompt, QUEUED_PROMPT));
+                    queued
+                },
+                None => self.read_command()?,
+            };
+            self.update_title(true);
+
+            if self.auto_add_history {
+                self.editor
+                    .add_history_entry(command.clone())
+                    .wrap_err_with(|| {
+                        format!("failed to add history entry `{command}` to rustyline editor")
+                    })?;
+            }
+            if let Some(history_file) = self.history_file.clone() {
+                if let Err(err) = self.editor.save_history(&history_file) {
+                    self.warn_unwritable(&history_file, &err);
+                }
+            }
+
+            let mut split = match shell_words::split(&command)
+                .wrap_err_with(|| format!("failed to split command `{command}`"))
+            {
+                Ok(split) => split,
+                Err(err) => {
+                    self.print_error(&err);
+                    continue;
+                },
+            };
+
+            if let [name] = split.as_slice() {
+                if let Some(follow_up) = self.suggested(name).map(str::to_owned) {
+                    self.continue_conversation(follow_up, None, None, false)
+                        .await;
+                    continue;
+                }
+            }
+
+            let mut args = vec![CARGO_PKG_NAME.to_owned()];
+            args.append(&mut split);
+
+            if !self.command_and_continue(args).await {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Reads a command from the line editor, joining lines that end with a backslash
+    fn read_command(&mut self) -> Result<String> {
+        let mut command = String::new();
+        let prompt = Theme::paint(self.theme.prompt, "> ");
+        for line in self.editor.iter(&prompt) {
+            let mut line = line.wrap_err_with(|| "failed to get rustyline editor line")?;
+
+            line = line.trim().to_owned();
+            let eoln = !line.ends_with('\\');
+
+            if !eoln {
+                line.pop();
+                line.push('\n');
+            }
+
+            command = format!("{command}{line}");
+
+            if eoln {
+                break;
+            }
+        }
+        Ok(command)
+    }
+
+    /// The REPL commands, built-in, custom and plugins
+    fn cli(&self) -> clap::Command {
+        let cmd = custom::augment(Cli::command(), &self.custom_commands);
+        plugin::augment(cmd, &self.plugins)
+    }
+
+    async fn command_and_continue(&mut self, args: Vec<String>) -> bool {
+        let matches = match self.cli().try_get_matches_from(args) {
+            Ok(matches) => matches,
+            Err(err) if self.raw => {
+                // Help on stdout, usage errors on stderr
+                let _ = err.print();
+                if err.use_stderr() {
+                    self.fail(EXIT_USAGE);
+                }
+                return true;
+            },
+            Err(err) => {
+                println!("{err}");
+                return true;
+            },
+        };
+
+        if let Some((name, sub_matches)) = matches.subcommand() {
+            if let Some(command) = self.custom_commands.get(name).cloned() {
+                let input = self.compose(custom::input(sub_matches));
+                let vars = custom::vars(sub_matches);
+                self.custom(name, &command, &input, vars).await;
+                return true;
+            }
+
+            if let Some(path) = self.plugins.get(name).cloned() {
+                if let Err(err) = self.plugin(name, &path, plugin::args(sub_matches)).await {
+                    self.print_error(&err);
+                }
+                return true;
+            }
+        }
+
+        let args = match Cli::from_arg_matches(&matches) {
+            Ok(args) => args,
+            Err(err) => {
+                println!("{err}");
+                return true;
+            },
+        };
+
+        match args.command {
+            Command::Ask {
+                max_words,
+                stdin_context,
+                url,
+                follow_up,
+                bg,
+                grammar,
+                prefill,
+                show_confidence,
+                choices,
+                files,
+                repo,
+                ocr,
+                force,
+                speak,
+                question,
+            } => {
+                let question = self.compose(shell_words::join(question));
+                if !self.confirm_repeat(&question, force) {
+                    return true;
+                }
+                let grammar = match grammar.map(|file| {
+                    std::fs::read_to_string(&file)
+                        .wrap_err_with(|| format!("failed to read grammar `{}`", file.display()))
+                }) {
+                    Some(Ok(grammar)) => Some(grammar),
+                    Some(Err(err)) => {
+                        self.print_error(&err);
+                        return true;
+                    },
+                    None => None,
+                };
+                if stdin_context {
+                    if let Err(err) = self.pin_stdin() {
+                        self.print_error(&err);
+                        return true;
+                    }
+                }
+                if let Some(url) = url {
+                    if let Err(err) = self.pin_url(&url).await {
+                        self.print_error(&err);
+                        return true;
+                    }
+                }
+                self.follow_up = follow_up && !self.interactive;
+
+                let Some(mut question) = self.pre_prompt(question) else {
+                    return true;
+                };
+                if let Some(image) = ocr {
+                    match self.read_image(&image).await {
+                        Ok(text) => {
+                            question = format!(
+                                "Text in `{}`:\n```\n{text}\n```\n\n{question}",
+                                image.display()
+                            );
+                        },
+                        Err(err) => {
+                            self.print_error(&err);
+                            return true;
+                        },
+                    }
+                }
+                let mut sources = Vec::new();
+                if !files.is_empty() {
+                    match self.attach_files(&files, &question) {
+                        Ok(files) => {
+                            question = format!("{}{question}", files::render(&files));
+                            sources = files;
+                        },
+                        Err(err) => {
+                            self.print_error(&err);
+                            return true;
+                        },
+                    }
+                }
+                if repo {
+                    match self.repo_map() {
+                        Ok(map) => question = format!("{map}{question}"),
+                        Err(err) => {
+                            self.print_error(&err);
+                            return true;
+                        },
+                    }
+                }
+                self.sources = sources;
+                let mut context = self.context(false);
+                self.compress(&mut question, &mut context);
+                let length = self.length(max_words);
+                if bg {
+                    let images = std::mem::take(&mut self.images);
+                    self.ask_in_background(question, context, length, images);
+                } else if let Some(n) = choices.filter(|&n| n > 1) {
+                    self.ask_with_choices("ask", question, &context, length, n)
+                        .await;
+                } else if let Some(completion) = self
+                    .ask_openai_or_queue(
+                        || {
+                            self.q_and_a(
+                                question.clone(),
+                                &context,
+                                length,
+                                AskOptions {
+                                    grammar: grammar.as_deref(),
+                                    prefill: prefill.as_deref(),
+                                    show_confidence,
+                                    speak,
+                                    images: &self.images,
+                                },
+                            )
+                        },
+                        || QueuedRequest::Ask {
+                            question: question.clone(),
+                            max_words,
+                        },
+                    )
+                    .await
+                {
+                    if show_confidence && completion.logprobs.is_empty() {
+                        println!("the provider returned no log probabilities");
+                    }
+                    self.images.clear();
+                    self.print_footer(&completion);
+                    self.print_citations(&completion);
+                    self.push_exchange("ask", question, completion);
+                    self.suggest_follow_ups().await;
+                    self.finish_speaking().await;
+                }
+            },
+            Command::Flush => {
+                if let Err(err) = self.flush().await {
+                    self.print_error(&err);
+                }
+            },
+            Command::Jobs => self.print_jobs(),
+            Command::Fg { id } => self.foreground(id).await,
+            Command::Multi => {
+                if let Err(err) = self.multi().await {
+                    self.print_error(&err);
+                }
+            },
+            Command::Continue {
+                max_words,
+                choices,
+                force,
+                all,
+                question,
+            } => {
+                let question = self.compose(shell_words::join(question));
+                if !self.confirm_repeat(&question, force) {
+                    return true;
+                }
+                self.continue_conversation(question, max_words, choices, all)
+                    .await;
+            },
+            Command::Translate { to, raw_text } => {
+                let raw_text =
+                    if raw_text.is_empty() && !self.interactive && self.composed.is_none() {
+                        match std::io::read_to_string(std::io::stdin())
+                            .wrap_err_with(|| "failed to read text to translate from stdin")
+                        {
+                            Ok(raw_text) => raw_text,
+                            Err(err) => {
+                                self.print_error(&err);
+                                return true;
+                            },
+                        }
+                    } else {
+                        self.compose(shell_words::join(raw_text))
+                    };
+
+                self.translate(raw_text, to).await;
+            },
+            Command::Good => self.rate(Rating::Good),
+            Command::Bad { reason } => {
+                let reason = Some(shell_words::join(reason)).filter(|reason| !reason.is_empty());
+                self.rate(Rating::Bad { reason });
+            },
+            Command::Amend => {
+                if let Err(err) = self.amend() {
+                    self.print_error(&err);
+                }
+            },
+            Command::Apply => {
+                if let Err(err) = self.apply() {
+                    self.print_error(&err);
+                }
+            },
+            Command::Sources => self.print_sources(),
+            Command::History { verbose } => {
+                self.print_history(verbose);
+            },
+            Command::Timeline => self.print_timeline(),
+            Command::Export {
+                file,
+                raw,
+                format,
+                rating,
+                command,
+            } => {
+                if let Err(err) = self.export(&file, raw, format, rating, command) {
+                    self.print_error(&err);
+                }
+            },
+            Command::Listen { mut command } => {
+                let transcript = match self.listen().await {
+                    Ok(Some(transcript)) => transcript,
+                    Ok(None) => return true,
+                    Err(err) => {
+                        self.print_error(&err);
+                        return true;
+                    },
+                };
+                if command.is_empty() {
+                    command.push("ask".to_owned());
+                }
+                let mut args = vec![CARGO_PKG_NAME.to_owned()];
+                args.append(&mut command);
+                self.composed = Some(transcript);
+                let keep_going = Box::pin(self.command_and_continue(args)).await;
+                self.composed = None;
+                return keep_going;
+            },
+            Command::Share => {
+                if let Err(err) = self.share().await {
+                    self.print_error(&err);
+                }
+            },
+            Command::Attach {
+                file,
+                pages,
+                clipboard_image,
+            } => {
+                let res = match file {
+                    Some(file) if !clipboard_image => self.attach(&file, pages),
+                    _ => self.attach_clipboard_image(),
+                };
+                if let Err(err) = res {
+                    self.print_error(&err);
+                }
+            },
+            Command::Pin { file, text } => {
+                if let Err(err) = self.pin(file, text) {
+                    self.print_error(&err);
+                }
+            },
+            Command::Pins => {
+                for (i, pin) in self.pins.iter().enumerate() {
+                    println!(
+                        "[{i}] {} ({} chars)",
+                        pin.label,
+                        pin.content.chars().count()
+                    );
+                }
+            },
+            Command::Tokens { file, text } => {
+                if let Err(err) = self.tokens(file, text) {
+                    self.print_error(&err);
+                }
+            },
+            Command::Session {
+                command: SessionCommand::Load { file },
+            } => {
+                if let Err(err) = self.load_session(&file) {
+                    self.print_error(&err);
+                }
+            },
+            Command::Session {
+                command: SessionCommand::Merge { a, b, into, concat },
+            } => {
+                if let Err(err) = self.merge_sessions(&a, &b, &into, concat) {
+                    self.print_error(&err);
+                }
+            },
+            Command::Session {
+                command: SessionCommand::List { dir, tag },
+            } => {
+                if let Err(err) = self.list_sessions(&dir, tag.as_deref()) {
+                    self.print_error(&err);
+                }
+            },
+            Command::Store { command } => {
+                if let Err(err) = self.store_command(command) {
+                    self.print_error(&err);
+                }
+            },
+            Command::SearchAll {
+                query,
+                raw,
+                tag,
+                limit,
+                open,
+            } => {
+                let query = if raw {
+                    query.join(" ")
+                } else {
+                    Store::all_words(&query)
+                };
+                if let Err(err) = self.search_all(&query, tag.as_deref(), limit, open) {
+                    self.print_error(&err);
+                }
+            },
+            Command::Diff { old, new } => match (self.history.get(old), self.history.get(new)) {
+                (Some(old), Some(new)) => {
+                    print!("{}", diff::diff(&old.content, &new.content, self.color));
+                },
+                (None, _) => println!("no message [{old}] in history"),
+                (_, None) => println!("no message [{new}] in history"),
+            },
+            Command::Unpin { index } => {
+                if index < self.pins.len() {
+                    self.pins.remove(index);
+                } else {
+                    println!("no pin [{index}]");
+                }
+            },
+            Command::Schedule {
+                session,
+                into,
+                when,
+                command,
+            } => {
+                if let Err(err) = self.schedule(when, command, session, into) {
+                    self.print_error(&err);
+                }
+            },
+            Command::Schedules => {
+                if let Err(err) = self.print_schedules() {
+                    self.print_error(&err);
+                }
+            },
+            Command::Unschedule { index } => {
+                if let Err(err) = self.unschedule(index) {
+                    self.print_error(&err);
+                }
+            },
+            Command::Buf { command } => match command {
+                BufCommand::Add { file, text } => {
+                    if let Err(err) = self.buffer_add(file, text) {
+                        self.print_error(&err);
+                    }
+                },
+                BufCommand::Show if self.buffer.is_empty() => println!("the buffer is empty"),
+                BufCommand::Show => println!("{}", self.buffer.join("\n\n")),
+                BufCommand::Send { .. } if self.buffer.is_empty() => {
+                    println!("the buffer is empty, add to it with `buf add`");
+                },
+                BufCommand::Send { mut command } => {
+                    if command.is_empty() {
+                        command.push("ask".to_owned());
+                    }
+                    let mut args = vec![CARGO_PKG_NAME.to_owned()];
+                    args.append(&mut command);
+                    self.composed = Some(std::mem::take(&mut self.buffer).join("\n\n"));
+                    let keep_going = Box::pin(self.command_and_continue(args)).await;
+                    self.composed = None;
+                    return keep_going;
+                },
+                BufCommand::Clear => self.buffer.clear(),
+            },
+            Command::Review {
+                staged,
+                range,
+                report,
+            } => {
+                if let Err(err) = self.review(staged, range, report).await {
+                    self.print_error(&err);
+                }
+            },
+            Command::PrDesc { base, copy, create } => {
+                if let Err(err) = self.pr_desc(&base, copy, create).await {
+                    self.print_error(&err);
+                }
+            },
+            Command::ExplainError { command } => {
+                if let Err(err) = self.explain_error(command).await {
+                    self.print_error(&err);
+                }
+            },
+            Command::Triage {
+                clippy,
+                test,
+                refresh,
+            } => {
+                if let Err(err) = self.triage(clippy, test, refresh).await {
+                    self.print_error(&err);
+                }
+            },
+            Command::Cheatsheet { output } => {
+                if let Err(err) = self.cheatsheet(output).await {
+                    self.print_error(&err);
+                }
+            },
+            Command::Mcp { command } => {
+                if let Err(err) = self.mcp(command).await {
+                    self.print_error(&err);
+                }
+            },
+            Command::WatchClipboard { to, notify, .. } => {
+                if let Err(err) = self.watch_clipboard(to, notify).await {
+                    self.print_error(&err);
+                }
+            },
+            Command::Watch { file, out } => {
+                if let Err(err) = self.watch(&file, out.as_deref()).await {
+                    self.print_error(&err);
+                }
+            },
+            Command::Rpc => {
+                let rpc = Rpc::new(self.openai.clone(), self.context(false), self.length(None));
+                if let Err(err) = rpc::run(rpc).await {
+                    self.print_error(&err);
+                }
+            },
+            Command::Serve { listen } => {
+                let state = ServerState {
+                    openai: self.openai.clone(),
+                    pins: self.context(false),
+                    length: self.length(None),
+                    sessions: Default::default(),
+                    schedules: self.schedules.clone(),
+                    store: self.store.clone(),
+                };
+                if let Err(err) = server::serve(listen, state).await {
+                    self.print_error(&err);
+                }
+            },
+            Command::Daemon => {
+                let Some(socket) = self.daemon_socket.clone() else {
+                    println!("no home directory for the daemon socket, set `daemon_socket`");
+                    return true;
+                };
+                let state = ServerState {
+                    openai: self.openai.clone(),
+                    pins: self.context(false),
+                    length: self.length(None),
+                    sessions: Default::default(),
+                    schedules: None,
+                    store: self.store.clone(),
+                };
+                if let Err(err) = daemon::run(&socket, state).await {
+                    self.print_error(&err);
+                }
+            },
+            Command::Bridge => {
+                let Some(config) = self.bridge.clone() else {
+                    println!("no `[bridge]` section in config");
+                    return true;
+                };
+                let bridge = Bridge::new(
+                    self.openai.clone(),
+                    self.context(false),
+                    self.length(None),
+                    self.client.clone(),
+                )
+                .with_cipher(self.cipher.clone());
+                if let Err(err) = bridge.run(config).await {
+                    self.print_error(&err);
+                }
+            },
+            Command::Bind { key, action } => {
+                let action = shell_words::join(action);
+                match readline::bind(&mut self.editor, &key, &action) {
+                    Ok(()) => println!("bound `{key}` to `{action}`"),
+                    Err(err) => self.print_error(&err),
+                }
+            },
+            Command::Set { setting } => match setting {
+                Setting::Footer { state } => self.settings.footer = state.into(),
+                Setting::StatusLine { state } => self.settings.status_line = state.into(),
+                Setting::ShowReasoning { state } => self.settings.show_reasoning = state.into(),
+                Setting::FollowUps { state } => self.settings.follow_ups = state.into(),
+                Setting::Stream { state } => {
+                    self.settings.stream = state.into();
+                    if self.settings.stream && !self.mcp.is_empty() {
+                        println!("{NO_STREAM_WITH_MCP}");
+                    }
+                },
+                Setting::Brevity { level } => self.settings.brevity = level,
+                Setting::Pruning { strategy } => self.settings.pruning = strategy,
+                Setting::Seed { seed } => self.openai.set_seed(seed),
+            },
+            Command::Config { command } => {
+                if let Err(err) = self.config_command(command) {
+                    self.print_error(&err);
+                }
+            },
+            Command::Keys => self.print_keys(),
+            Command::Quota => self.print_quota().await,
+            Command::Name { name: Some(name) } => {
+                if let (Some(store), Some(id)) = (&self.store, self.stored_conversation) {
+                    if let Err(err) = store.rename(id, &name) {
+                        self.print_error(&err);
+                    }
+                }
+                self.name = Some(name);
+            },
+            Command::Name { name: None } => match &self.name {
+                Some(name) => println!("{name}"),
+                None => println!("the conversation has no name"),
+            },
+            Command::Tag { tag: Some(tag) } => {
+                if let Err(err) = self.tag(tag) {
+                    self.print_error(&err);
+                }
+            },
+            Command::Tag { tag: None } if self.tags.is_empty() => {
+                println!("the conversation has no tags");
+            },
+            Command::Tag { tag: None } => println!("{}", self.tags.join(", ")),
+            Command::Context { full } => {
+                self.summarize_history().await;
+                self.print_context(full);
+            },
+            Command::Help { command } => {
+                if let Err(err) = self.help(&command) {
+                    self.print_error(&err);
+                }
+            },
+            Command::Clear => {
+                if let Err(err) = self
+                    .editor
+                    .clear_screen()
+                    .wrap_err_with(|| "failed to clear screen")
+                {
+                    self.print_error(&err);
+                };
+            },
+            Command::Exit => {
+                return false;
+            },
+        }
+
+        true
+    }
+
+    /// Pinned context as system messages, followed by the history if `with_history`
+    fn context(&self, with_history: bool) -> Vec<ChatMessage> {
+        let pins = self
+            .pins
+            .iter()
+            .map(|pin| ChatMessage::new(Role::System, pin.content.clone()));
+
+        if !with_history {
+            return pins.collect();
+        }
+
+        let start = self.settings.pruning.start(&self.history);
+        let summary = match (&self.settings.pruning, &self.summary) {
+            (Pruning::Summary, Some(summary)) => Some(summary),
+            _ => None,
+        };
+        let summary_message = summary.map(|summary| {
+            ChatMessage::new(
+                Role::System,
+                format!(
+                    "Summary of the earlier conversation:\n\n{}",
+                    summary.content
+                ),
+            )
+        });
+        // Turns the summary does not cover yet are sent verbatim
+        let start = summary.map_or(start, |summary| start.min(summary.covers));
+        let start = self.settings.history_limit.map_or(start, |turns| {
+            start.max(pruning::turn_start(&self.history, turns))
+        });
+
+        pins.chain(summary_message)
+            .chain(self.history[start..].iter().cloned())
+            .collect()
+    }
+
+    /// The pins and every turn verbatim, for `continue --all`
+    fn full_context(&self) -> Vec<ChatMessage> {
+        self.context(false)
+            .into_iter()
+            .chain(self.history.iter().cloned())
+            .collect()
+    }
+
+    /// Folds turns that fell out of the recent window into the rolling summary
+    async fn summarize_history(&mut self) {
+        if !matches!(self.settings.pruning, Pruning::Summary) {
+            return;
+        }
+
+        let start = self.settings.pruning.start(&self.history);
+        let covers = self.summary.as_ref().map_or(0, |summary| summary.covers);
+        if start <= covers {
+            return;
+        }
+
+        let previous = self
+            .summary
+            .as_ref()
+            .map(|summary| summary.content.as_str());
+        let messages = &self.history[covers..start];
+        match self
+            .request_openai(|| self.openai.summarize(previous, messages))
+            .await
+        {
+            Ok(completion) => {
+                self.summary = Some(Summary {
+                    content: completion.content.into_owned(),
+                    covers: start,
+                });
+            },
+            Err(err) => self.print_error(&err),
+        }
+    }
+
+    /// The text in the image at `path`, read as set in `[ocr]`
+    async fn read_image(&self, path: &Path) -> Result<String> {
+        if self.ocr.tesseract {
+            return ocr::tesseract(path, self.ocr.languages.as_deref());
+        }
+
+        let image_url = ocr::data_url(path)?;
+        let completion = self
+            .request_openai(|| {
+                self.openai
+                    .ocr(image_url.clone(), self.ocr.model.as_deref())
+            })
+            .await?;
+        Ok(completion.content.trim().to_owned())
+    }
+
+    async fn pin_url(&mut self, url: &str) -> Result<()> {
+        if !self.url_fetch {
+            color_eyre::eyre::bail!("fetching URLs is disabled, set `url_fetch = true` in config");
+        }
+
+        let page = fetch::readable(&self.client, url).await?;
+        let mut text = page.text;
+        if tokenizer::for_model(self.openai.model()).truncate(&mut text, URL_CONTEXT_MAX_TOKENS) {
+            text.push_str("\n[truncated]");
+        }
+
+        let header = match &page.title {
+            Some(title) => format!("Contents of {url} ({title}):"),
+            None => format!("Contents of {url}:"),
+        };
+        self.pins.push(Pin {
+            label: url.to_owned(),
+            content: format!("{header}\n\n{text}"),
+        });
+        Ok(())
+    }
+
+    fn pin_stdin(&mut self) -> Result<()> {
+        if self.interactive {
+            color_eyre::eyre::bail!("stdin is the REPL itself, use `pin --file` instead");
+        }
+
+        let content = std::io::read_to_string(std::io::stdin())
+            .wrap_err_with(|| "failed to read context from stdin")?;
+        self.pins.push(Pin {
+            label: "stdin".to_owned(),
+            content: format!("Contents of stdin:\n\n{content}"),
+        });
+        Ok(())
+    }
+
+    /// Asks in a task of its own, with the attached `images` if any, and returns to the prompt,
+    /// keeping the answer as a numbered job for `jobs` and `fg`
+    fn ask_in_background(
+        &mut self,
+        question: String,
+        context: Vec<ChatMessage>,
+        length: Length,
+        images: Vec<String>,
+    ) {
+        let id = self.next_job;
+        self.next_job += 1;
+
+        let openai = self.openai.clone();
+        let task = tokio::spawn({
+            let question = question.clone();
+            async move {
+                if images.is_empty() {
+                    openai.q_and_a(question, &context, length).await
+                } else {
+                    openai
+                        .q_and_a_with_images(question, &context, length, &images)
+                        .await
+                }
+            }
+        });
+        println!("[{id}] asking in the background, see the answer with `fg {id}`");
+        self.jobs.insert(
+            id,
+            Job {
+                question,
+                started: Instant::now(),
+                task,
+                announced: false,
+            },
+        );
+    }
+
+    fn print_jobs(&self) {
+        if self.jobs.is_empty() {
+            println!("no background questions");
+        }
+        for (id, job) in &self.jobs {
+            let state = if job.task.is_finished() {
+                "done".to_owned()
+            } else {
+                format!("running {}s", job.started.elapsed().as_secs())
+            };
+            println!(
+                "[{id}] {state:<12} {}",
+                truncate(&job.question, CONTEXT_PREVIEW_CHARS)
+            );
+        }
+    }
+
+    /// Announces background questions answered since the last prompt, like a shell does
+    fn announce_jobs(&mut self) {
+        for (id, job) in &mut self.jobs {
+            if job.task.is_finished() && !job.announced {
+                job.announced = true;
+                println!(
+                    "[{id}] done {}",
+                    truncate(&job.question, CONTEXT_PREVIEW_CHARS)
+                );
+            }
+        }
+    }
+
+    async fn foreground(&mut self, id: usize) {
+        let Some(job) = self.jobs.remove(&id) else {
+            println!("no background question [{id}], see `jobs`");
+            return;
+        };
+
+        let spinner = self.spinner();
+        spinner.start();
+        let res = job.task.await;
+        spinner.stop();
+
+        let res = res
+            .wrap_err_with(|| "background question panicked")
+            .and_then(|res| res.wrap_err_with(|| "failed to get response from openai"))
+            .and_then(|mut completion| {
+                completion.content = self.transformers.post_answer(completion.content)?;
+                Ok(completion)
+            });
+        match res {
+            Ok(completion) => {
+                println!("{}", Theme::paint(self.theme.user, &job.question));
+                self.print_reasoning(&completion);
+                self.print_answer(&completion.content);
+                self.print_footer(&completion);
+                self.push_exchange("ask", job.question, completion);
+            },
+            Err(err) => self.print_error(&err),
+        }
+    }
+
+    /// Sends the questions concurrently and prints each answer as it arrives, labeled with the
+    /// number of its question
+    async fn multi(&mut self) -> Result<()> {
+        let questions = self
+            .read_questions()?
+            .into_iter()
+            .filter_map(|question| self.pre_prompt(question))
+            .collect::<Vec<_>>();
+        if questions.is_empty() {
+            return Ok(());
+        }
+
+        let this = &*self;
+        let context = this.context(false);
+        let length = this.length(None);
+        let mut pending = questions
+            .iter()
+            .enumerate()
+            .map(|(i, question)| {
+                let context = &context;
+                async move {
+                    let res = this
+                        .openai
+                        .q_and_a(question.clone(), context, length)
+                        .await
+                        .and_then(|mut completion| {
+                            completion.content =
+                                this.transformers.post_answer(completion.content)?;
+                            Ok(completion)
+                        });
+                    (i, res)
+                }
+            })
+            .collect::<FuturesUnordered<_>>();
+
+        let spinner = self.spinner();
+        spinner.start();
+        let mut completions = vec![None; questions.len()];
+        while let Some((i, res)) = pending.next().await {
+            spinner.bar.suspend(|| {
+                let label = format!("[{}] {}", i + 1, questions[i]);
+                println!("{}", Theme::paint(this.theme.user, &label));
+                match res {
+                    Ok(completion) => {
+                        this.print_reasoning(&completion);
+                        this.print_answer(&completion.content);
+                        this.print_footer(&completion);
+                        completions[i] = Some(completion);
+                    },
+                    Err(err) => self.print_error(&err),
+                }
+                println!();
+            });
+        }
+        spinner.stop();
+        drop(pending);
+
+        for (question, completion) in questions.into_iter().zip(completions) {
+            if let Some(completion) = completion {
+                self.push_exchange("multi", question, completion);
+            }
+        }
+        Ok(())
+    }
+
+    /// Records a question and returns its transcript once confirmed, or none if it was cleared
+    /// or interrupted
+    async fn listen(&mut self) -> Result<Option<String>> {
+        let config = self.voice.clone();
+        let wav = tokio::task::spawn_blocking(move || voice::record(&config))
+            .await
+            .wrap_err_with(|| "failed to record")??;
+
+        let spinner = self.spinner();
+        spinner.start();
+        let transcript = self
+            .openai
+            .transcribe(&wav, &self.voice.model, self.voice.language.as_deref())
+            .await;
+        spinner.stop();
+
+        println!("Enter sends the transcript, edit it first or clear it to cancel");
+        let prompt = Theme::paint(self.theme.prompt, "heard> ");
+        match self
+            .editor
+            .readline_with_initial(&prompt, (&transcript?, ""))
+        {
+            Ok(line) if line.trim().is_empty() => Ok(None),
+            Ok(line) => Ok(Some(line.trim().to_owned())),
+            Err(ReadlineError::Eof | ReadlineError::Interrupted) => Ok(None),
+            Err(err) => Err(err).wrap_err_with(|| "failed to get rustyline editor line"),
+        }
+    }
+
+    /// Asks for the value of template variable `var` not given with `--var`, offering its
+    /// default, or none if interrupted
+    ///
+    /// Without a terminal to ask on, the default is used if there is one.
+    fn read_var(&mut self, var: &str, declared: &TemplateVar) -> Result<Option<String>> {
+        if !self.interactive || !std::io::stdin().is_terminal() {
+            return match &declared.default {
+                Some(default) => Ok(Some(default.clone())),
+                None => color_eyre::eyre::bail!("missing `--var {var}=VALUE`"),
+            };
+        }
+
+        if let Some(description) = &declared.description {
+            println!("{var}: {description}");
+        }
+        let prompt = Theme::paint(self.theme.prompt, &format!("{var}> "));
+        let default = declared.default.as_deref().unwrap_or_default();
+        match self.editor.readline_with_initial(&prompt, (default, "")) {
+            Ok(line) => Ok(Some(line.trim().to_owned())),
+            Err(ReadlineError::Eof | ReadlineError::Interrupted) => Ok(None),
+            Err(err) => Err(err).wrap_err_with(|| "failed to get rustyline editor line"),
+        }
+    }
+
+    /// Reads questions one per line until a blank line, or none if interrupted
+    fn read_questions(&mut self) -> Result<Vec<String>> {
+        println!("Enter one question per line, then a blank line to ask them all");
+
+        let mut questions = Vec::new();
+        loop {
+            let prompt = format!("{}> ", questions.len() + 1);
+            match self
+                .editor
+                .readline(&Theme::paint(self.theme.prompt, &prompt))
+            {
+                Ok(line) if line.trim().is_empty() => break,
+                Ok(line) => questions.push(line.trim().to_owned()),
+                Err(ReadlineError::Eof) => break,
+                Err(ReadlineError::Interrupted) => return Ok(Vec::new()),
+                Err(err) => {
+                    return Err(err).wrap_err_with(|| "failed to get rustyline editor line");
+                },
+            }
+        }
+        Ok(questions)
+    }
+
+    /// Renders the files `patterns` match for `question`, within the token budget
+    fn attach_files(&self, patterns: &[String], question: &str) -> Result<Vec<files::File>> {
+        let model = self.openai.model();
+        let max_tokens = tokenizer::context_window(model)
+            .map_or(FILES_MAX_TOKENS, |window| FILES_MAX_TOKENS.min(window / 2));
+        let selection = files::select(
+            files::expand(patterns)?,
+            question,
+            tokenizer::for_model(model),
+            max_tokens,
+        )?;
+
+        if !selection.skipped.is_empty() {
+            self.print_note(&format!(
+                "skipped {} binary or less relevant files over the budget of {max_tokens} tokens:",
+                selection.skipped.len()
+            ));
+            for path in &selection.skipped {
+                self.print_note(&format!("  {}", path.display()));
+            }
+        }
+        let mut excerpted = selection
+            .files
+            .iter()
+            .filter(|file| file.lines.is_some())
+            .map(|file| &file.path)
+            .collect::<Vec<_>>();
+        excerpted.dedup();
+        if !excerpted.is_empty() {
+            self.print_note(&format!(
+                "attached the most relevant parts of {} files:",
+                excerpted.len()
+            ));
+            for path in excerpted {
+                self.print_note(&format!("  {}", path.display()));
+            }
+        }
+        if selection.files.is_empty() {
+            color_eyre::eyre::bail!("no file fits the budget of {max_tokens} tokens");
+        }
+        Ok(selection.files)
+    }
+
+    /// Outlines the git repository, cut down to the token budget
+    fn repo_map(&self) -> Result<String> {
+        let mut map = repomap::build()?;
+        if tokenizer::for_model(self.openai.model()).truncate(&mut map, REPO_MAP_MAX_TOKENS) {
+            map.push_str("\n[truncated]");
+        }
+        Ok(format!("Repository map:\n```\n{}\n```\n\n", map.trim_end()))
+    }
+
+    /// Compresses `question` and the pins at the start of `context` if they come to more tokens
+    /// than configured, reporting the tokens saved
+    ///
+    /// Earlier turns are left as they were answered.
+    fn compress(&self, question: &mut String, context: &mut [ChatMessage]) {
+        let Some(config) = &self.compression else {
+            return;
+        };
+        let tokenizer = tokenizer::for_model(self.openai.model());
+        let pins = &mut context[..self.pins.len()];
+        let count = |question: &str, pins: &[ChatMessage]| {
+            tokenizer.count(question) +
+                pins.iter()
+                    .map(|pin| tokenizer.count(&pin.content))
+                    .sum::<usize>()
+        };
+        let before = count(question, pins);
+        if before <= config.above_tokens {
+            return;
+        }
+
+        *question = compress::compress(question, config.trim_fillers);
+        for pin in pins.iter_mut() {
+            pin.content = compress::compress(&pin.content, config.trim_fillers).into();
+        }
+        let after = count(question, pins);
+        let saved = before.saturating_sub(after);
+        let line = format!(
+            "compressed the prompt from {before} to {after} tokens, saving {saved} ({}%)",
+            saved * 100 / before
+        );
+        self.print_note(&line);
+    }
+
+    /// Prints what was done along the way, dimmed, or on stderr with `--raw` to keep stdout for
+    /// the answer
+    fn print_note(&self, line: &str) {
+        if self.raw {
+            eprintln!("{line}");
+        } else if self.color {
+            println!("{DIM}{line}{RESET}");
+        } else {
+            println!("{line}");
+        }
+    }
+
+    /// Prints the token count of `text` or of the contents of `file`
+    fn tokens(&self, file: Option<PathBuf>, text: Vec<String>) -> Result<()> {
+        let text = match file {
+            Some(file) => std::fs::read_to_string(&file)
+                .wrap_err_with(|| format!("failed to read `{}`", file.display()))?,
+            None => text.join(" "),
+        };
+
+        let tokenizer = tokenizer::for_model(self.openai.model());
+        println!(
+            "{} tokens for {} ({tokenizer})",
+            tokenizer.count(&text),
+            self.openai.model()
+        );
+        Ok(())
+    }
+
+    /// Attaches the image in the clipboard to the next `ask` or `continue`, for vision models
+    fn attach_clipboard_image(&mut self) -> Result<()> {
+        let image = clipboard::paste_image()?;
+        let size = image.len();
+        self.images.push(ocr::png_data_url(&image));
+        println!(
+            "attached a {} KiB image from the clipboard to the next question, {} in all",
+            size.div_ceil(1024),
+            self.images.len()
+        );
+        Ok(())
+    }
+
+    /// Pins the text of a file, extracted from PDF and DOCX files
+    fn attach(&mut self, file: &Path, pages: Option<PageRange>) -> Result<()> {
+        let extracted = attachment::extract(file, pages)?;
+        if let Some(warning) = &extracted.warning {
+            println!("warning: {warning}");
+        }
+
+        let (label, source) = match pages {
+            Some(pages) => (
+                format!("{} (pages {pages})", file.display()),
+                format!("`{}`, pages {pages}", file.display()),
+            ),
+            None => (file.display().to_string(), format!("`{}`", file.display())),
+        };
+        self.pins.push(Pin {
+            label,
+            content: format!("Contents of {source}:\n\n{}", extracted.text),
+        });
+        Ok(())
+    }
+
+    fn pin(&mut self, file: Option<PathBuf>, text: Vec<String>) -> Result<()> {
+        let pin = match file {
+            Some(file) => return self.attach(&file, None),
+            None => {
+                let content = shell_words::join(text);
+                Pin {
+                    label: content.lines().next().unwrap_or_default().to_owned(),
+                    content,
+                }
+            },
+        };
+
+        self.pins.push(pin);
+        Ok(())
+    }
+
+    /// The text of a command followed by the prompt `buf send` composed, if any
+    fn compose(&mut self, text: String) -> String {
+        match self.composed.take() {
+            Some(composed) if text.is_empty() => composed,
+            Some(composed) => format!("{text}\n\n{composed}"),
+            None => text,
+        }
+    }
+
+    fn buffer_add(&mut self, file: Option<PathBuf>, text: Vec<String>) -> Result<()> {
+        let piece = match file {
+            Some(file) => {
+                let content = std::fs::read_to_string(&file)
+                    .wrap_err_with(|| format!("failed to read `{}`", file.display()))?;
+                let language = file
+                    .extension()
+                    .map(|extension| extension.to_string_lossy())
+                    .unwrap_or_default();
+                format!(
+                    "File `{}`:\n```{language}\n{}\n```",
+                    file.display(),
+                    content.trim_end()
+                )
+            },
+            None => text.join(" "),
+        };
+
+        self.buffer.push(piece);
+        println!(
+            "{} tokens in the buffer",
+            tokenizer::for_model(self.openai.model()).count(&self.buffer.join("\n\n"))
+        );
+        Ok(())
+    }
+
+    async fn review(
+        &self,
+        staged: bool,
+        range: Option<String>,
+        report: Option<PathBuf>,
+    ) -> Result<()> {
+        let diff = match &range {
+            Some(range) => git::diff(&[range]),
+            None if staged => git::diff(&["--staged"]),
+            None => git::diff(&[]),
+        }?;
+
+        let files = git::split_by_file(&diff);
+        if files.is_empty() {
+            println!("nothing to review");
+            return Ok(());
+        }
+
+        let mut reviews = Vec::new();
+        for file in files {
+            let numbered = git::number_new_lines(&file.diff);
+            let completion = self
+                .request_openai(|| self.openai.review(&numbered))
+                .await
+                .wrap_err_with(|| format!("failed to review `{}`", file.path))?;
+
+            let review = FileReview::parse(file.path, &completion.content);
+            review.print();
+            reviews.push(review);
+        }
+
+        if let Some(report) = report {
+            std::fs::write(&report, review::markdown_report(&reviews))
+                .wrap_err_with(|| format!("failed to write report `{}`", report.display()))?;
+        }
+
+        Ok(())
+    }
+
+    async fn pr_desc(&self, base: &str, copy: bool, create: bool) -> Result<()> {
+        let commits = git::log(&format!("{base}..HEAD"))?;
+        if commits.trim().is_empty() {
+            color_eyre::eyre::bail!("no commits between `{base}` and HEAD");
+        }
+
+        let mut diff = git::diff(&[&format!("{base}...HEAD")])?;
+        if tokenizer::for_model(self.openai.model()).truncate(&mut diff, PR_DIFF_MAX_TOKENS) {
+            diff.push_str("\n[diff truncated]");
+        }
+
+        let template = self
+            .pr_template
+            .clone()
+            .or_else(|| std::fs::read_to_string(PR_TEMPLATE_FILE).ok());
+
+        let completion = self
+            .request_openai(|| {
+                self.openai
+                    .pr_description(&commits, &diff, template.as_deref())
+            })
+            .await?;
+        let (title, body) = completion
+            .content
+            .trim()
+            .split_once('\n')
+            .map_or((completion.content.trim(), ""), |(title, body)| {
+                (title.trim(), body.trim())
+            });
+        let title = title.trim_start_matches('#').trim();
+        println!("{title}\n\n{body}");
+
+        if copy {
+            clipboard::copy(&format!("{title}\n\n{body}"))?;
+        }
+
+        if create {
+            let status = std::process::Command::new("gh")
+                .args([
+                    "pr", "create", "--base", base, "--title", title, "--body", body,
+                ])
+                .status()
+                .wrap_err_with(|| "failed to run GitHub CLI `gh`, is it installed?")?;
+            if !status.success() {
+                color_eyre::eyre::bail!("`gh pr create` failed");
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Asks why the first error of the build command happened and how to fix it, with the
+    /// source around the error
+    async fn explain_error(&mut self, command: Vec<String>) -> Result<()> {
+        let command = if command.is_empty() {
+            self.explain_error.command.clone()
+        } else {
+            shell_words::join(command)
+        };
+        let Some(error) = diagnostic::first_error_of(&command)? else {
+            println!("`{command}` reported no errors");
+            return Ok(());
+        };
+        let source = match error.span.as_ref().map(diagnostic::Span::excerpt) {
+            Some(Ok(excerpt)) => Some(excerpt),
+            Some(Err(err)) => {
+                println!("warning: {err:#}, explaining without the source");
+                None
+            },
+            None => None,
+        };
+
+        let question = self
+            .explain_error
+            .render(&command, &error, source.as_deref());
+        let Some(question) = self.pre_prompt(question) else {
+            return Ok(());
+        };
+        if let Some(completion) = self
+            .ask_openai(|| self.openai.explain_error(question.clone()))
+            .await
+        {
+            self.print_footer(&completion);
+            self.push_exchange("explain-error", question, completion);
+        }
+        Ok(())
+    }
+
+    /// Asks for a fix plan for each failure of `cargo clippy` and `cargo test`, reusing the
+    /// plans for failures that did not change since they were asked about
+    async fn triage(&mut self, clippy: bool, test: bool, refresh: bool) -> Result<()> {
+        let both = clippy == test;
+        let failures = triage::collect(clippy || both, test || both)?;
+        if failures.is_empty() {
+            println!("no failures to triage");
+            return Ok(());
+        }
+
+        let mut cache = triage::Cache::load(self.cipher.clone())?;
+        let mut requests = Vec::new();
+        let mut cached = 0;
+        for (i, failure) in failures.iter().enumerate() {
+            let header = format!(
+                "[{}/{}] {}: {}",
+                i + 1,
+                failures.len(),
+                failure.kind.as_str(),
+                failure.title()
+            );
+            if self.color {
+                println!("{BOLD}{header}{RESET}");
+            } else {
+                println!("{header}");
+            }
+
+            let request = failure.request();
+            match cache.get(&request).filter(|_| !refresh) {
+                Some(plan) => {
+                    self.print_answer(plan);
+                    cached += 1;
+                },
+                None => {
+                    if let Some(completion) = self
+                        .ask_openai(|| self.openai.triage(request.clone()))
+                        .await
+                    {
+                        self.print_footer(&completion);
+                        cache.insert(&request, completion.content.into_owned());
+                    }
+                },
+            }
+            println!();
+            requests.push(request);
+        }
+
+        // Failures found by only one of the commands say nothing about the others' failures
+        if both {
+            cache.retain(&requests);
+        }
+        if self.persist {
+            if let Err(err) = cache.save() {
+                self.warn_unwritable(cache.path(), &err);
+            }
+        }
+        println!(
+            "failures: {}, plans reused from earlier runs: {cached}",
+            failures.len()
+        );
+        Ok(())
+    }
+
+    /// Asks for a quick reference of the commands, including the custom commands, plugins and
+    /// MCP servers in config
+    async fn cheatsheet(&self, output: Option<PathBuf>) -> Result<()> {
+        let mut reference = help::reference(&self.cli());
+        for (name, command) in &self.custom_commands {
+            reference.push_str(&format!(
+                "## {name} 的设置\n系统提示词：{}\n",
+                command.system
+            ));
+            if let Some(template) = &command.template {
+                reference.push_str(&format!("模板：{template}\n"));
+            }
+            reference.push('\n');
+        }
+        let servers = self.mcp.servers().collect::<Vec<_>>();
+        if !servers.is_empty() {
+            reference.push_str(&format!("MCP 服务器：{}\n", servers.join(", ")));
+        }
+
+        let completion = self
+            .request_openai(|| self.openai.cheatsheet(reference.clone()))
+            .await?;
+        match output {
+            Some(path) => {
+                std::fs::write(&path, completion.content.as_bytes())
+                    .wrap_err_with(|| format!("failed to write `{}`", path.display()))?;
+                println!("wrote `{}`", path.display());
+            },
+            None => self.print_completion(&completion),
+        }
+        Ok(())
+    }
+
+    /// An explicit word limit wins over the brevity setting
+    fn length(&self, max_words: Option<u32>) -> Length {
+        match (max_words, self.settings.brevity) {
+            (Some(words), _) => Length::Words(words),
+            (None, Brevity::Short) => Length::Words(SHORT_WORDS),
+            (None, Brevity::Normal) => Length::Terse,
+            (None, Brevity::Long) => Length::Detailed,
+        }
+    }
+
+    fn print_footer(&self, completion: &Completion) {
+        if self.settings.footer {
+            println!(
+                "{}",
+                footer::footer(
+                    &completion.content,
+                    completion.usage.map(|usage| usage.completion_tokens)
+                )
+            );
+        }
+    }
+
+    /// Lists the attached files that `completion` cites, by their numbers
+    fn print_citations(&self, completion: &Completion) {
+        if self.raw {
+            return;
+        }
+        for n in files::citations(&completion.content, self.sources.len()) {
+            let line = format!("[{n}] {}", self.sources[n - 1].label());
+            if self.color {
+                println!("{DIM}{line}{RESET}");
+            } else {
+                println!("{line}");
+            }
+        }
+    }
+
+    /// Prints the whole files and excerpts cited by the last answer
+    fn print_sources(&self) {
+        if self.sources.is_empty() {
+            println!("the last `ask` attached no files, see `ask --file`");
+            return;
+        }
+        let answer = self
+            .history
+            .iter()
+            .rev()
+            .find(|message| message.role == Role::Assistant)
+            .map_or("", |message| &message.content);
+        let cited = files::citations(answer, self.sources.len());
+        if cited.is_empty() {
+            println!(
+                "the last answer cites none of its {} sources",
+                self.sources.len()
+            );
+            return;
+        }
+        for n in cited {
+            let source = &self.sources[n - 1];
+            println!(
+                "{}",
+                Theme::paint(self.theme.user, &format!("[{n}] {}", source.label()))
+            );
+            println!("{}\n", source.content.trim_end());
+        }
+    }
+
+    async fn custom(
+        &mut self,
+        name: &str,
+        command: &CustomCommand,
+        input: &str,
+        mut vars: BTreeMap<String, String>,
+    ) {
+        if let Some(unknown) = vars.keys().find(|var| !command.vars.contains_key(*var)) {
+            println!(
+                "`{name}` has no variable `{unknown}`, only {}",
+                command.vars.keys().cloned().collect::<Vec<_>>().join(", ")
+            );
+            return;
+        }
+        for (var, declared) in &command.vars {
+            if vars.contains_key(var) {
+                continue;
+            }
+            match self.read_var(var, declared) {
+                Ok(Some(value)) => {
+                    vars.insert(var.clone(), value);
+                },
+                Ok(None) => return,
+                Err(err) => {
+                    self.print_error(&err);
+                    return;
+                },
+            }
+        }
+
+        let Some(question) = self.pre_prompt(command.render(input, &vars)) else {
+            return;
+        };
+        if let Some(completion) = self
+            .ask_openai(|| {
+                self.openai
+                    .custom(&command.system, command.temperature, question.clone())
+            })
+            .await
+        {
+            self.print_footer(&completion);
+            self.push_exchange(name, question, completion);
+        }
+    }
+
+    async fn plugin(&mut self, name: &str, path: &Path, args: Vec<String>) -> Result<()> {
+        let input = PluginInput {
+            command: name,
+            args,
+            pins: self.pins.iter().map(|pin| pin.content.as_str()).collect(),
+            history: &self.history,
+        };
+
+        match plugin::run(path, &input).await? {
+            PluginOutput::Answer { content } => println!("{content}"),
+            PluginOutput::Prompt { content } => {
+                let content = self.transformers.pre_prompt(content)?;
+                self.summarize_history().await;
+                let context = self.context(true);
+                let length = self.length(None);
+                if let Some(completion) = self
+                    .ask_openai(|| {
+                        self.q_and_a(content.clone(), &context, length, AskOptions::default())
+                    })
+                    .await
+                {
+                    self.print_footer(&completion);
+                    self.push_exchange(name, content, completion);
+                }
+            },
+        }
+
+        Ok(())
+    }
+
+    /// Translates chunk by chunk so long documents fit the context window, sharing a glossary
+    /// between chunks for consistent terminology
+    async fn translate(&self, raw_text: String, to: Option<String>) {
+        let chunks = chunk::split_paragraphs(&raw_text, TRANSLATE_CHUNK_CHARS);
+
+        let queued = || QueuedRequest::Translate {
+            text: raw_text.clone(),
+            to: to.clone(),
+        };
+        if let [raw_text] = chunks.as_slice() {
+            if let Some(completion) = self
+                .ask_openai_or_queue(
+                    || self.openai.translate(raw_text.clone(), to.as_deref()),
+                    queued,
+                )
+                .await
+            {
+                self.print_footer(&completion);
+            }
+            return;
+        }
+
+        let mut glossary = Glossary::default();
+        for (i, chunk) in chunks.into_iter().enumerate() {
+            let completion = match self
+                .request_openai(|| {
+                    self.openai
+                        .translate_chunk(chunk.clone(), to.as_deref(), &glossary)
+                })
+                .await
+            {
+                Ok(completion) => completion,
+                Err(err) if i == 0 => {
+                    self.queue_or_print_error(err, queued);
+                    return;
+                },
+                Err(err) => {
+                    self.print_error(&err);
+                    return;
+                },
+            };
+
+            if i > 0 && !self.json {
+                println!();
+            }
+            let content = glossary.absorb(&completion.content).to_owned();
+            self.print_completion(&Completion {
+                content: content.into(),
+                ..completion
+            });
+        }
+    }
+
+    /// Warns that `path` could not be written, once for the session, as the REPL carries on
+    /// with what it keeps in memory
+    fn warn_unwritable(&mut self, path: &Path, err: &dyn std::fmt::Display) {
+        if self.unwritable.insert(path.to_owned()) {
+            let message = format!(
+                "warning: failed to write `{}`, keeping it in memory only: {err:#}",
+                path.display()
+            );
+            if self.raw {
+                eprintln!("{message}");
+            } else {
+                println!("{}", Theme::paint(self.theme.error, &message));
+            }
+        }
+    }
+
+    /// Prints `err` with its location and backtrace, except for errors answered by the API
+    /// whose kind already says what went wrong
+    fn print_error(&self, err: &(impl std::fmt::Debug + 'static)) {
+        let api_error = (err as &dyn std::any::Any)
+            .downcast_ref::<color_eyre::Report>()
+            .filter(|report| report.chain().any(|err| err.is::<OpenAIError>()));
+        let message = match api_error {
+            Some(report) => format!("{report:#}"),
+            None => format!("{err:?}"),
+        };
+        let api_error = api_error.and_then(|report| {
+            report
+                .chain()
+                .find_map(|err| err.downcast_ref::<OpenAIError>())
+        });
+        let hint = api_error.and_then(|err| self.remediation(err));
+
+        self.fail(match api_error {
+            Some(OpenAIError::Auth { .. }) => EXIT_AUTH,
+            Some(OpenAIError::RateLimit { .. }) => EXIT_RATE_LIMIT,
+            Some(OpenAIError::ContentFilter { .. }) => EXIT_CONTENT_FILTER,
+            _ => EXIT_FAILURE,
+        });
+        if self.raw {
+            eprintln!("{message}");
+            if let Some(hint) = hint {
+                eprintln!("hint: {hint}");
+            }
+            return;
+        }
+        println!("{}", Theme::paint(self.theme.error, &message));
+        if let Some(hint) = hint {
+            println!("hint: {hint}");
+        }
+    }
+
+    /// What the user can do about an error answered by the API
+    fn remediation(&self, err: &OpenAIError) -> Option<String> {
+        let keys = if self.openai.keys().len() > 1 {
+            ", `keys` shows which keys failed"
+        } else {
+            ""
+        };
+        let hint = match err {
+            OpenAIError::Auth { .. } => format!("check `api_token` in config.toml{keys}"),
+            OpenAIError::Quota { .. } => {
+                format!("top up the account or add keys to `api_tokens` in config.toml{keys}")
+            },
+            OpenAIError::RateLimit { .. } => {
+                "wait a minute before asking again, or add keys to `api_tokens` in config.toml"
+                    .to_owned()
+            },
+            OpenAIError::ContextLength { .. } => "send less history with `set pruning summary` or \
+                                                  `set pruning window(4)`, drop context with \
+                                                  `unpin`, or start over with `clear`"
+                .to_owned(),
+            OpenAIError::ModelNotFound { .. } => {
+                let provider = self.openai.provider();
+                format!(
+                    "set `model` in config.toml to one of the {} models: {}",
+                    provider.as_str(),
+                    provider.models().join(", ")
+                )
+            },
+            OpenAIError::ContentFilter { .. } => {
+                "rephrase the question, the provider refuses to answer it as it is".to_owned()
+            },
+            OpenAIError::Network(_) => {
+                "check the connection and `endpoint` in config.toml".to_owned()
+            },
+            OpenAIError::Api { .. } | OpenAIError::Deserialize(_) => return None,
+        };
+        Some(hint)
+    }
+
+    fn print_reasoning(&self, completion: &Completion) {
+        let Some(reasoning) = completion
+            .reasoning
+            .as_deref()
+            .filter(|_| self.settings.show_reasoning)
+        else {
+            return;
+        };
+
+        if self.color {
+            println!("{DIM}{}{RESET}\n", reasoning.trim());
+        } else {
+            println!("{}\n", reasoning.trim());
+        }
+    }
+
+    /// Prints the answer of `completion`, painted by how likely the model found its tokens if
+    /// their log probabilities were requested
+    fn print_completion(&self, completion: &Completion) {
+        if completion.finish_reason.as_deref() == Some(CONTENT_FILTER) {
+            self.fail(EXIT_CONTENT_FILTER);
+        }
+        if self.json {
+            let answer = JsonAnswer {
+                answer: &completion.content,
+                model: &completion.model,
+                usage: completion.usage,
+                finish_reason: completion.finish_reason.as_deref(),
+                latency_ms: self.latency.lock().unwrap().as_millis(),
+            };
+            match serde_json::to_string(&answer) {
+                Ok(answer) => println!("{answer}"),
+                Err(err) => self.print_error(&err),
+            }
+            return;
+        }
+        if completion.logprobs.is_empty() {
+            self.print_answer(&completion.content);
+            return;
+        }
+
+        println!("{}", confidence::render(&completion.logprobs, self.color));
+        let least_likely = confidence::least_likely(&completion.logprobs, LEAST_LIKELY_TOKENS);
+        if !least_likely.is_empty() {
+            println!();
+        }
+        for line in least_likely {
+            if self.color {
+                println!("{DIM}{line}{RESET}");
+            } else {
+                println!("{line}");
+            }
+        }
+    }
+
+    fn print_answer(&self, content: &str) {
+        match &self.highlighter {
+            Some(highlighter) => println!("{}", highlighter.highlight(content)),
+            None => println!("{content}"),
+        }
+    }
+
+    /// Waits for `f` behind a spinner and prints the answer after passing it through
+    /// `transformers`, or the error if it failed
+    async fn ask_openai<F, Fut>(&self, f: F) -> Option<Completion>
+    where
+        F: Fn() -> Fut,
+        Fut: Future<Output = Result<Completion>>,
+    {
+        let res = self.request_openai(f).await.and_then(|mut completion| {
+            completion.content = self.transformers.post_answer(completion.content)?;
+            Ok(completion)
+        });
+
+        match res {
+            Ok(completion) => {
+                self.print_reasoning(&completion);
+                self.print_completion(&completion);
+                Some(completion)
+            },
+            Err(err) => {
+                self.print_error(&err);
+                None
+            },
+        }
+    }
+
+    /// Like [`Self::ask_openai`], but queues the request made by `queued` for `flush` if the
+    /// network is down and `offline_queue` is enabled
+    async fn ask_openai_or_queue<F, Fut>(
+        &self,
+        f: F,
+        queued: impl FnOnce() -> QueuedRequest,
+    ) -> Option<Completion>
+    where
+        F: Fn() -> Fut,
+        Fut: Future<Output = Result<Completion>>,
+    {
+        let res = self.request_openai(f).await.and_then(|mut completion| {
+            completion.content = self.transformers.post_answer(completion.content)?;
+            Ok(completion)
+        });
+
+        match res {
+            Ok(completion) => {
+                self.print_reasoning(&completion);
+                self.print_completion(&completion);
+                Some(completion)
+            },
+            Err(err) => {
+                self.queue_or_print_error(err, queued);
+                None
+            },
+        }
+    }
+
+    fn queue_or_print_error(
+        &self,
+        err: color_eyre::Report,
+        queued: impl FnOnce() -> QueuedRequest,
+    ) {
+        let offline = err
+            .chain()
+            .any(|err| matches!(err.downcast_ref(), Some(OpenAIError::Network(_))));
+        let Some(queue) = self.offline_queue.as_ref().filter(|_| offline) else {
+            self.print_error(&err);
+            return;
+        };
+
+        let queued = Queued {
+            request: queued(),
+            conversation: self.name.clone(),
+            timestamp: conversation::now(),
+        };
+        match queue.push(queued) {
+            Ok(len) => println!(
+                "offline, queued as request {len}, send the queue with `flush` when back online"
+            ),
+            Err(queue_err) => {
+                self.print_error(&err);
+                self.print_error(&queue_err);
+            },
+        }
+    }
+
+    /// Adds `command`, an `ask` or `translate`, to the prompts `serve` runs on a schedule
+    fn schedule(
+        &self,
+        when: String,
+        command: Vec<String>,
+        session: Option<String>,
+        into: Option<PathBuf>,
+    ) -> Result<()> {
+        let Some(schedules) = &self.schedules else {
+            color_eyre::eyre::bail!("no home directory to keep schedules in");
+        };
+        let next_run = when
+            .parse::<schedule::When>()
+            .map_err(|err| color_eyre::eyre::eyre!("invalid schedule `{when}`: {err}"))?
+            .next_after(chrono::Local::now());
+
+        let args = [CARGO_PKG_NAME.to_owned()].into_iter().chain(command);
+        let request = match Cli::try_parse_from(args)?.command {
+            Command::Ask {
+                max_words,
+                stdin_context: false,
+                url: None,
+                bg: false,
+                grammar: None,
+                prefill: None,
+                show_confidence: false,
+                choices: None,
+                files,
+                repo: false,
+                ocr: None,
+                speak: false,
+                question,
+                ..
+            } if files.is_empty() => QueuedRequest::Ask {
+                question: shell_words::join(question),
+                max_words,
+            },
+            Command::Ask { .. } => {
+                color_eyre::eyre::bail!(
+                    "only the question and `--max-words` of `ask` can be scheduled"
+                )
+            },
+            Command::Translate { to, raw_text } if !raw_text.is_empty() => {
+                QueuedRequest::Translate {
+                    text: shell_words::join(raw_text),
+                    to,
+                }
+            },
+            _ => color_eyre::eyre::bail!("only `ask` and `translate` with text can be scheduled"),
+        };
+
+        let mut scheduled = schedules.load()?;
+        scheduled.push(Scheduled {
+            when,
+            request,
+            session,
+            into,
+            last_run: conversation::now(),
+        });
+        schedules.save(&scheduled)?;
+        println!(
+            "scheduled [{}], next run at {} while `serve` is running",
+            scheduled.len() - 1,
+            next_run.format("%Y-%m-%d %H:%M")
+        );
+        Ok(())
+    }
+
+    fn print_schedules(&self) -> Result<()> {
+        let scheduled = match &self.schedules {
+            Some(schedules) => schedules.load()?,
+            None => Vec::new(),
+        };
+        if scheduled.is_empty() {
+            println!("nothing scheduled, see `schedule`");
+        }
+        for (i, scheduled) in scheduled.iter().enumerate() {
+            let label = format!(
+                "{} {}",
+                scheduled.request.command(),
+                scheduled.request.text()
+            );
+            let next_run = scheduled.next_run().map_or_else(
+                |err| err.to_string(),
+                |next| next.format("%Y-%m-%d %H:%M").to_string(),
+            );
+            let to = match (&scheduled.session, &scheduled.into) {
+                (Some(session), _) => format!(" into session `{session}`"),
+                (_, Some(into)) => format!(" into `{}`", into.display()),
+                _ => String::new(),
+            };
+            println!(
+                "[{i}] {}, next {next_run}{to}: {}",
+                scheduled.when,
+                truncate(&label, CONTEXT_PREVIEW_CHARS)
+            );
+        }
+        Ok(())
+    }
+
+    fn unschedule(&self, index: usize) -> Result<()> {
+        let Some(schedules) = &self.schedules else {
+            color_eyre::eyre::bail!("no home directory to keep schedules in");
+        };
+        let mut scheduled = schedules.load()?;
+        if index >= scheduled.len() {
+            println!("no schedule [{index}]");
+            return Ok(());
+        }
+        scheduled.remove(index);
+        schedules.save(&scheduled)
+    }
+
+    /// Sends the requests queued while offline, in order, until one fails
+    async fn flush(&mut self) -> Result<()> {
+        let Some(queue) = &self.offline_queue else {
+            color_eyre::eyre::bail!("no offline queue, enable `offline_queue` in config");
+        };
+        let mut pending = VecDeque::from(queue.load()?);
+        if pending.is_empty() {
+            println!("nothing queued");
+            return Ok(());
+        }
+
+        while let Some(queued) = pending.front() {
+            let label = format!("{} {}", queued.request.command(), queued.request.text());
+            println!(
+                "{}",
+                Theme::paint(self.theme.user, &truncate(&label, CONTEXT_PREVIEW_CHARS))
+            );
+            let completion = match &queued.request {
+                QueuedRequest::Ask {
+                    question,
+                    max_words,
+                } => {
+                    let context = self.context(false);
+                    let length = self.length(*max_words);
+                    self.ask_openai(|| {
+                        self.q_and_a(question.clone(), &context, length, AskOptions::default())
+                    })
+                    .await
+                },
+                QueuedRequest::Translate { text, to } => {
+                    self.ask_openai(|| self.openai.translate(text.clone(), to.as_deref()))
+                        .await
+                },
+            };
+            let Some(completion) = completion else {
+                break;
+            };
+            self.print_footer(&completion);
+
+            let Some(queued) = pending.pop_front() else {
+                break;
+            };
+            if let QueuedRequest::Ask { question, .. } = queued.request {
+                // Only the live conversation is kept, so answers to others are printed only
+                if queued.conversation == self.name {
+                    self.push_exchange("ask", question, completion);
+                } else if let Some(name) = &queued.conversation {
+                    println!("asked in conversation `{name}`, not added to this one");
+                }
+            }
+            if let Some(queue) = &self.offline_queue {
+                queue.save(pending.make_contiguous())?;
+            }
+        }
+
+        if !pending.is_empty() {
+            println!("{} requests still queued", pending.len());
+        }
+        Ok(())
+    }
+
+    /// A spinner to show while waiting, hidden with `--raw`
+    fn spinner(&self) -> Spinner {
+        if self.raw {
+            Spinner::hidden()
+        } else {
+            Spinner::new(self.theme.spinner)
+        }
+    }
+
+    /// Waits for `f` behind a spinner without printing the answer
+    async fn request_openai<F, Fut>(&self, f: F) -> Result<Completion>
+    where
+        F: Fn() -> Fut,
+        Fut: Future<Output = Result<Completion>>,
+    {
+        let started = Instant::now();
+        let mut rate_limit_retries = 0;
+        let res = loop {
+            let spinner = self.spinner();
+            spinner.start();
+            let type_ahead = (self.interactive && std::io::stdin().is_terminal()).then(|| {
+                TypeAhead::start(
+                    spinner.bar.clone(),
+                    Theme::paint(self.theme.prompt, QUEUED_PROMPT),
+                )
+            });
+            self.live.attach(spinner.bar.clone());
+            let res = f().await;
+            if let Some(type_ahead) = type_ahead {
+                let typed = type_ahead.stop().await;
+                self.queued.lock().unwrap().extend(typed);
+            }
+            spinner.stop();
+            self.live.detach();
+
+            let err = res.as_ref().err();
+            if let Some(over) = err.and_then(|err| err.downcast_ref::<OverBudget>()) {
+                if self.interactive && confirm(&format!("{over}, send anyway?")) {
+                    if let Some(budget) = self.openai.budget() {
+                        budget.approve_next();
+                    }
+                    continue;
+                }
+            }
+            if let Some(large) = err.and_then(|err| err.downcast_ref::<LargePrompt>()) {
+                if self.interactive && confirm(&format!("{large}, send anyway?")) {
+                    if let Some(limit) = self.openai.prompt_limit() {
+                        limit.approve_next();
+                    }
+                    continue;
+                }
+            }
+            if let Some(OpenAIError::RateLimit { retry_after, .. }) =
+                err.and_then(|err| err.downcast_ref::<OpenAIError>())
+            {
+                if rate_limit_retries < RATE_LIMIT_RETRIES {
+                    let delay =
+                        retry_after.unwrap_or(RATE_LIMIT_BACKOFF * 2u32.pow(rate_limit_retries));
+                    self.print_note(&format!("rate limited, retrying in {}s", delay.as_secs()));
+                    tokio::time::sleep(delay).await;
+                    rate_limit_retries += 1;
+                    continue;
+                }
+            }
+            break res.wrap_err_with(|| "failed to get response from openai");
+        };
+
+        let elapsed = started.elapsed();
+        *self.latency.lock().unwrap() = elapsed;
+        if self.notify_after.is_some_and(|after| elapsed >= after) && !notify::terminal_focused() {
+            let body = match &res {
+                Ok(_) => format!("Answer ready after {}s", elapsed.as_secs()),
+                Err(_) => "Request failed".to_owned(),
+            };
+            if let Err(err) = notify::send(&body) {
+                tracing::warn!("{err:?}");
+            }
+        }
+
+        res
+    }
+
+    /// Translates each new clipboard text until interrupted, skipping the translations it places
+    /// there itself
+    async fn watch_clipboard(&self, to: Option<String>, notify: bool) -> Result<()> {
+        let mut last = clipboard::paste()?;
+        let mut tick = tokio::time::interval(CLIPBOARD_POLL_INTERVAL);
+        println!("Watching the clipboard, press Ctrl-C to stop");
+
+        loop {
+            tokio::select! {
+                _ = tick.tick() => {},
+                _ = tokio::signal::ctrl_c() => return Ok(()),
+            }
+
+            // A failed poll or delivery is reported and the watch goes on
+            let text = match clipboard::paste() {
+                Ok(text) => text,
+                Err(err) => {
+                    self.print_error(&err);
+                    continue;
+                },
+            };
+            if text == last {
+                continue;
+            }
+            last = text.clone();
+
+            // Without `--to` the reader's language is Chinese, which needs no translation
+            if text.trim().is_empty() || (to.is_none() && is_mostly_cjk(&text)) {
+                continue;
+            }
+
+            let Some(completion) = self
+                .ask_openai(|| self.openai.translate(text.clone(), to.as_deref()))
+                .await
+            else {
+                continue;
+            };
+
+            let translation = completion.content.into_owned();
+            if notify {
+                if let Err(err) = notify::send(&translation) {
+                    self.print_error(&err);
+                }
+            } else {
+                match clipboard::copy(&translation) {
+                    Ok(()) => last = translation,
+                    Err(err) => self.print_error(&err),
+                }
+            }
+        }
+    }
+
+    /// Asks each line read from `file` like `ask` and writes the answers to `out`, or prints
+    /// them, until interrupted
+    async fn watch(&mut self, file: &Path, out: Option<&Path>) -> Result<()> {
+        let mut input = watch::Input::open(file)?;
+        println!(
+            "Answering lines written to `{}`, press Ctrl-C to stop",
+            file.display()
+        );
+
+        loop {
+            let line = tokio::select! {
+                line = input.next_line() => line?,
+                _ = tokio::signal::ctrl_c() => return Ok(()),
+            };
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let Some(question) = self.pre_prompt(line) else {
+                continue;
+            };
+            let context = self.context(false);
+            let length = self.length(None);
+            let res = self
+                .request_openai(|| {
+                    self.q_and_a(question.clone(), &context, length, AskOptions::default())
+                })
+                .await
+                .and_then(|mut completion| {
+                    completion.content = self.transformers.post_answer(completion.content)?;
+                    Ok(completion)
+                });
+            // Editors waiting on `out` get the error in place of the answer
+            let answer = match &res {
+                Ok(completion) => completion.content.to_string(),
+                Err(err) => format!("error: {err:#}"),
+            };
+            match out {
+                Some(out) => {
+                    println!("{}", truncate(&question, CONTEXT_PREVIEW_CHARS));
+                    watch::write_answer(out, &answer).await?;
+                },
+                None => self.print_answer(&answer),
+            }
+            if let Ok(completion) = res {
+                self.push_exchange("ask", question, completion);
+            }
+        }
+    }
+
+    /// Asks with the tools of the configured MCP servers available to the model
+    async fn q_and_a(
+        &self,
+        question: String,
+        context: &[ChatMessage],
+        length: Length,
+        options: AskOptions<'_>,
+    ) -> Result<Completion> {
+        if !options.images.is_empty() {
+            if options.grammar.is_some() ||
+                options.prefill.is_some() ||
+                options.show_confidence ||
+                options.speak
+            {
+                color_eyre::eyre::bail!(
+                    "attached images cannot be sent with `--grammar`, `--prefill`, \
+                     `--show-confidence` or `--speak`"
+                );
+            }
+            return self
+                .openai
+                .q_and_a_with_images(question, context, length, options.images)
+                .await;
+        }
+        if let Some(grammar) = options.grammar {
+            return self
+                .openai
+                .q_and_a_with_grammar(question, context, length, grammar)
+                .await;
+        }
+        if let Some(prefill) = options.prefill {
+            return self
+                .openai
+                .q_and_a_with_prefill(question, context, length, prefill)
+                .await;
+        }
+        if options.show_confidence {
+            return self
+                .openai
+                .q_and_a_with_logprobs(question, context, length, TOP_LOGPROBS)
+                .await;
+        }
+        if options.speak {
+            return self.q_and_a_spoken(question, context, length).await;
+        }
+        if self.mcp.is_empty() && self.settings.stream && std::io::stderr().is_terminal() {
+            let highlighter = self.highlighter.as_ref();
+            return self
+                .openai
+                .q_and_a_streaming(question, context, length, |delta| {
+                    self.live.push(delta, highlighter)
+                })
+                .await;
+        }
+        if self.mcp.is_empty() {
+            return self.openai.q_and_a(question, context, length).await;
+        }
+
+        let tools = self.mcp.chat_tools().await?;
+        self.openai
+            .q_and_a_with_tools(question, context, length, &tools, |tool_call| {
+                self.mcp.call_chat_tool(tool_call)
+            })
+            .await
+    }
+
+    /// Asks with the answer read aloud a sentence at a time as it streams, kept reading in
+    /// [`SerMaid::speaker`] until [`SerMaid::finish_speaking`]
+    async fn q_and_a_spoken(
+        &self,
+        question: String,
+        context: &[ChatMessage],
+        length: Length,
+    ) -> Result<Completion> {
+        if !cfg!(feature = "voice") {
+            color_eyre::eyre::bail!("`ask --speak` needs sermaid built with the `voice` feature");
+        }
+
+        let speaker = Speaker::start(self.openai.clone(), self.voice.clone());
+        let mut sentences = Sentences::default();
+        let mut streamed = false;
+        let live = self.settings.stream && std::io::stderr().is_terminal();
+        let highlighter = self.highlighter.as_ref();
+        let res = self
+            .openai
+            .q_and_a_streaming(question, context, length, |delta| {
+                streamed = true;
+                if live {
+                    self.live.push(delta, highlighter);
+                }
+                for sentence in sentences.push(delta) {
+                    speaker.say(sentence);
+                }
+            })
+            .await;
+        let completion = match res {
+            Ok(completion) => completion,
+            Err(err) => {
+                speaker.stop();
+                return Err(err);
+            },
+        };
+
+        // Recorded answers come whole
+        if !streamed {
+            for sentence in sentences.push(&completion.content) {
+                speaker.say(sentence);
+            }
+        }
+        if let Some(sentence) = sentences.finish() {
+            speaker.say(sentence);
+        }
+        if let Some(previous) = self.speaker.lock().unwrap().replace(speaker) {
+            previous.stop();
+        }
+        Ok(completion)
+    }
+
+    /// Waits for the answer being read aloud to end, or Ctrl-C
+    async fn finish_speaking(&self) {
+        let speaker = self.speaker.lock().unwrap().take();
+        if let Some(speaker) = speaker {
+            if let Err(err) = speaker.finish().await {
+                self.print_error(&err);
+            }
+        }
+    }
+
+    async fn mcp(&self, command: McpCommand) -> Result<()> {
+        match command {
+            McpCommand::List => {
+                for (server, tools) in self.mcp.tools().await? {
+                    println!("{server}");
+                    for tool in tools {
+                        println!(
+                            "  {}: {}",
+                            tool.name,
+                            tool.description.as_deref().unwrap_or_default()
+                        );
+                    }
+                }
+            },
+            McpCommand::Call {
+                server,
+                tool,
+                arguments,
+            } => {
+                let arguments = match arguments {
+                    Some(arguments) => serde_json::from_str(&arguments)
+                        .wrap_err_with(|| format!("invalid JSON arguments `{arguments}`"))?,
+                    None => serde_json::json!({}),
+                };
+                println!("{}", self.mcp.call(&server, &tool, arguments).await?);
+            },
+        }
+
+        Ok(())
+    }
+
+    /// Prints the help of the command at `path` with its examples, aliases and configuration,
+    /// or lists the commands if `path` is empty
+    fn help(&self, path: &[String]) -> Result<()> {
+        let mut cli = self.cli();
+        cli.build();
+        let Some((name, subcommands)) = path.split_first() else {
+            println!("{}", cli.render_help());
+            println!("Run `help <command>` for its options and examples");
+            return Ok(());
+        };
+
+        let mut cmd = cli
+            .find_subcommand_mut(name)
+            .ok_or_else(|| color_eyre::eyre::eyre!("no command `{name}`, see `help`"))?;
+        let name = cmd.get_name().to_owned();
+        for subcommand in subcommands {
+            cmd = cmd.find_subcommand_mut(subcommand).ok_or_else(|| {
+                color_eyre::eyre::eyre!("no subcommand `{subcommand}` of `{name}`")
+            })?;
+        }
+        println!("{}", cmd.render_long_help());
+
+        let aliases = cmd.get_all_aliases().collect::<Vec<_>>();
+        if !aliases.is_empty() {
+            println!("Aliases: {}\n", aliases.join(", "));
+        }
+        if let Some(command) = self.custom_commands.get(&name) {
+            println!("System prompt: {}", command.system);
+            if let Some(template) = &command.template {
+                println!("Template: {template}");
+            }
+            for (var, declared) in &command.vars {
+                let mut line = format!("Variable {{{var}}}");
+                if let Some(description) = &declared.description {
+                    line.push_str(&format!(": {description}"));
+                }
+                if let Some(default) = &declared.default {
+                    line.push_str(&format!(" (default `{default}`)"));
+                }
+                println!("{line}");
+            }
+            if let Some(temperature) = command.temperature {
+                println!("Temperature: {temperature}");
+            }
+            println!();
+        }
+        if name == "mcp" {
+            let servers = self.mcp.servers().collect::<Vec<_>>();
+            if servers.is_empty() {
+                println!("No MCP servers in config\n");
+            } else {
+                println!("MCP servers: {}\n", servers.join(", "));
+            }
+        }
+
+        // Examples of a subcommand are those starting with its whole path
+        let prefix = [name.as_str()]
+            .into_iter()
+            .chain(subcommands.iter().map(String::as_str))
+            .collect::<Vec<_>>()
+            .join(" ");
+        let examples = help::examples(&name)
+            .iter()
+            .filter(|example| subcommands.is_empty() || example.line.starts_with(&prefix))
+            .collect::<Vec<_>>();
+        if !examples.is_empty() {
+            print!("{}", help::render(&examples));
+        }
+        Ok(())
+    }
+
+    /// Whether to send `question`, asking first if it repeats the previous one, as happens with
+    /// a double Enter or a history entry recalled by mistake
+    fn confirm_repeat(&mut self, question: &str, force: bool) -> bool {
+        let repeated = !question.is_empty() && self.last_question.as_deref() == Some(question);
+        self.last_question = Some(question.to_owned());
+        force || !repeated || confirm("identical to previous question — resend?")
+    }
+
+    fn pre_prompt(&self, question: String) -> Option<String> {
+        match self.transformers.pre_prompt(question) {
+            Ok(question) => Some(question),
+            Err(err) => {
+                self.print_error(&err);
+                None
+            },
+        }
+    }
+
+    async fn continue_conversation(
+        &mut self,
+        question: String,
+        max_words: Option<u32>,
+        choices: Option<u32>,
+        all: bool,
+    ) {
+        let Some(mut question) = self.pre_prompt(question) else {
+            return;
+        };
+        let mut context = if all {
+            self.full_context()
+        } else {
+            self.summarize_history().await;
+            self.context(true)
+        };
+        self.compress(&mut question, &mut context);
+        let length = self.length(max_words);
+        if let Some(n) = choices.filter(|&n| n > 1) {
+            self.ask_with_choices("continue", question, &context, length, n)
+                .await;
+        } else if let Some(completion) = self
+            .ask_openai(|| {
+                let options = AskOptions {
+                    images: &self.images,
+                    ..AskOptions::default()
+                };
+                self.q_and_a(question.clone(), &context, length, options)
+            })
+            .await
+        {
+            self.images.clear();
+            self.print_footer(&completion);
+            self.print_citations(&completion);
+            self.push_exchange("continue", question, completion);
+            self.suggest_follow_ups().await;
+        }
+    }
+
+    /// Prints the `n` answers labeled A, B, C, ... and lets the user pick the one that enters
+    /// history
+    async fn ask_with_choices(
+        &mut self,
+        command: &str,
+        question: String,
+        context: &[ChatMessage],
+        length: Length,
+        n: u32,
+    ) {
+        let res = self
+            .request_openai(|| {
+                self.openai
+                    .q_and_a_with_choices(question.clone(), context, length, n)
+            })
+            .await
+            .and_then(|mut completion| {
+                completion.content = self.transformers.post_answer(completion.content)?;
+                for alternative in &mut completion.alternatives {
+                    *alternative = self.transformers.post_answer(alternative.clone())?;
+                }
+                Ok(completion)
+            });
+        let mut completion = match res {
+            Ok(completion) => completion,
+            Err(err) => {
+                self.print_error(&err);
+                return;
+            },
+        };
+
+        self.print_reasoning(&completion);
+        let mut answers = std::mem::take(&mut completion.alternatives);
+        answers.insert(0, completion.content);
+        if let [answer] = answers.as_slice() {
+            self.print_answer(answer);
+        } else {
+            for (label, answer) in ('A'..).zip(&answers) {
+                println!("{label})");
+                self.print_answer(answer);
+                println!();
+            }
+        }
+
+        let Some(pick) = self.pick_choice(answers.len()) else {
+            return;
+        };
+        completion.content = answers.swap_remove(pick);
+        self.print_footer(&completion);
+        self.push_exchange(command, question, completion);
+        self.suggest_follow_ups().await;
+    }
+
+    /// Reads the label of one of `count` choices, or none if interrupted
+    fn pick_choice(&mut self, count: usize) -> Option<usize> {
+        if count == 1 {
+            return Some(0);
+        }
+
+        let last = ('A'..).nth(count - 1).unwrap_or('A');
+        loop {
+            let prompt = format!("pick A-{last}: ");
+            match self
+                .editor
+                .readline(&Theme::paint(self.theme.prompt, &prompt))
+            {
+                Ok(line) => {
+                    let label = line.trim().to_ascii_uppercase();
+                    if let Some(pick) = ('A'..).take(count).position(|c| label == c.to_string()) {
+                        return Some(pick);
+                    }
+                    println!("no choice `{}`", line.trim());
+                },
+                Err(ReadlineError::Eof | ReadlineError::Interrupted) => return None,
+                Err(err) => {
+                    self.print_error(&err);
+                    return None;
+                },
+            }
+        }
+    }
+
+    /// Asks for questions that could follow the last exchange and prints them numbered
+    async fn suggest_follow_ups(&mut self) {
+        self.follow_ups.clear();
+        if !self.settings.follow_ups || !(self.interactive || self.follow_up) {
+            return;
+        }
+
+        let exchange = &self.history[self.history.len().saturating_sub(2)..];
+        let completion = match self
+            .request_openai(|| self.openai.follow_ups(exchange))
+            .await
+        {
+            Ok(completion) => completion,
+            Err(err) => {
+                self.print_error(&err);
+                return;
+            },
+        };
+
+        self.follow_ups = completion
+            .content
+            .lines()
+            .map(|line| {
+                line.trim()
+                    .trim_start_matches(|c: char| c.is_ascii_digit() || "-*.、) ".contains(c))
+                    .to_owned()
+            })
+            .filter(|line| !line.is_empty())
+            .take(MAX_FOLLOW_UPS)
+            .collect();
+        for (i, follow_up) in self.follow_ups.iter().enumerate() {
+            println!("f{}. {follow_up}", i + 1);
+        }
+    }
+
+    /// The suggested follow-up named like `f1`
+    fn suggested(&self, name: &str) -> Option<&str> {
+        let index = name.strip_prefix('f')?.parse::<usize>().ok()?;
+        self.follow_ups
+            .get(index.checked_sub(1)?)
+            .map(String::as_str)
+    }
+
+    fn push_exchange(&mut self, command: &str, question: String, completion: Completion) {
+        self.history.push(ChatMessage {
+            command: Some(command.to_owned()),
+            ..ChatMessage::new(Role::User, question)
+        });
+        self.history.push(ChatMessage::from_completion(completion));
+        self.store_history();
+
+        if let Some(recovery) = &mut self.recovery {
+            if let Err(err) = recovery.turn(&self.history) {
+                let path = recovery.path();
+                self.warn_unwritable(&path, &err);
+            }
+        }
+    }
+
+    fn rate(&mut self, rating: Rating) {
+        match self
+            .history
+            .iter_mut()
+            .rev()
+            .find(|message| message.role == Role::Assistant)
+        {
+            Some(answer) => answer.rating = Some(rating),
+            None => println!("no answer to rate"),
+        }
+        self.store_last_answer();
+    }
 
-    openai: OpenAI,
+    /// Adds the messages added to the history since the last call to the store, starting the
+    /// conversation there with the first
+    fn store_history(&mut self) {
+        let Some(store) = self.store.clone() else {
+            return;
+        };
+        let mut res = self.append_to_store(&store);
+        if let Err(err) = &res {
+            if self.fork_stored(err) {
+                res = self.append_to_store(&store);
+            }
+        }
+        if let Err(err) = res {
+            self.warn_unwritable(store.path(), &err);
+        }
+    }
 
-    history_questions: Vec<String>,
-    history_answers: Vec<Cow<'static, str>>,
-}
+    /// Returns the id of the conversation in the store
+    fn append_to_store(&mut self, store: &Store) -> Result<i64> {
+        let id = match self.stored_conversation {
+            Some(id) => id,
+            None => {
+                let id = store.create(self.name.as_deref())?;
+                self.stored_version = store.lock(id, store::REPL)?;
+                *self.stored_conversation.insert(id)
+            },
+        };
+        self.stored_version =
+            store.append(id, self.stored_version, &self.history[self.stored..])?;
+        self.stored = self.history.len();
+        Ok(id)
+    }
 
-impl SerMaid {
-    pub fn from_config(config: Config) -> Result<Self> {
-        let mut editor =
-            DefaultEditor::new().wrap_err_with(|| "failed to initialize rustyline editor")?;
-        if let Some(history_file) = &config.history_file {
-            let _ = editor.load_history(history_file);
+    /// Stops writing to the conversation in the store if `err` is another sermaid writing to it
+    /// too, so that the next write stores the whole history as a new conversation, and returns
+    /// whether it did
+    fn fork_stored(&mut self, err: &color_eyre::Report) -> bool {
+        let Some(id) = self.stored_conversation else {
+            return false;
+        };
+        if !err.is::<store::Locked>() && !err.is::<store::Conflict>() {
+            return false;
         }
+        let message = format!(
+            "warning: {err}, storing this conversation as a new one, see `store load {id}` for \
+             the other"
+        );
+        println!("{}", Theme::paint(self.theme.error, &message));
+        self.release_stored();
+        true
+    }
 
-        Ok(Self {
-            editor,
-            history_file: config.history_file,
-            openai: OpenAI::new(config.api_token),
-            history_questions: Vec::new(),
-            history_answers: Vec::new(),
-        })
+    /// Releases the lock of the conversation in the store, to be continued by another sermaid
+    fn release_stored(&mut self) {
+        let (Some(store), Some(id)) = (self.store.clone(), self.stored_conversation.take()) else {
+            return;
+        };
+        self.stored = 0;
+        self.stored_version = 0;
+        if let Err(err) = store.unlock(id, store::REPL) {
+            self.warn_unwritable(store.path(), &err);
+        }
     }
 
-    pub async fn run(&mut self) -> Result<()> {
-        loop {
-            let mut command = String::new();
-            for line in self.editor.iter("> ") {
-                let mut line = line.wrap_err_with(|| "failed to get rustyline editor line")?;
+    /// Updates the last answer in the store after it was rated or amended
+    fn store_last_answer(&mut self) {
+        let (Some(store), Some(id)) = (self.store.clone(), self.stored_conversation) else {
+            return;
+        };
+        let Some(answer) = self.history[..self.stored]
+            .iter()
+            .rev()
+            .find(|message| message.role == Role::Assistant)
+        else {
+            return;
+        };
+        match store.update_last_answer(id, self.stored_version, answer) {
+            Ok(version) => self.stored_version = version,
+            Err(err) => {
+                // The rating or amendment reaches the store with the rest of the history
+                if !self.fork_stored(&err) {
+                    self.warn_unwritable(store.path(), &err);
+                }
+            },
+        }
+    }
 
-                line = line.trim().to_owned();
-                let eoln = !line.ends_with('\\');
+    fn config_command(&self, command: ConfigCommand) -> Result<()> {
+        match command {
+            ConfigCommand::Export { file } => {
+                let sections = bundle::export(&self.config_path, &file)?;
+                if sections.is_empty() {
+                    println!("nothing to share in `{}`", self.config_path.display());
+                } else {
+                    println!("exported {} to `{}`", sections.join(", "), file.display());
+                }
+            },
+            ConfigCommand::Import { file } => {
+                let sections = bundle::import(&self.config_path, &file)?;
+                println!(
+                    "imported {} into `{}`, restart to apply",
+                    sections.join(", "),
+                    self.config_path.display()
+                );
+            },
+        }
+        Ok(())
+    }
 
-                if !eoln {
-                    line.pop();
-                    line.push('\n');
+    fn store_command(&mut self, command: StoreCommand) -> Result<()> {
+        let store = self.store.clone().ok_or_else(|| {
+            color_eyre::eyre::eyre!("conversations are not stored, set `store = true` in config")
+        })?;
+        match command {
+            StoreCommand::List { limit } => {
+                let listings = store.recent(limit)?;
+                if listings.is_empty() {
+                    println!("no stored conversations");
+                }
+                for listing in listings {
+                    let tags = if listing.tags.is_empty() {
+                        String::new()
+                    } else {
+                        format!(" [{}]", listing.tags.join(", "))
+                    };
+                    println!(
+                        "{:>5}  {}  {}, {} messages{tags}",
+                        listing.id,
+                        conversation::local_time(listing.started),
+                        listing.name.as_deref().unwrap_or("(unnamed)"),
+                        listing.turns
+                    );
+                }
+            },
+            StoreCommand::Stats => {
+                let stats = store.stats()?;
+                println!(
+                    "{} conversations, {} messages",
+                    stats.conversations, stats.messages
+                );
+                let mut total = 0.0;
+                for (model, (prompt, completion)) in &stats.tokens {
+                    let cost = pricing::cost(model, *prompt, *completion);
+                    total += cost.unwrap_or(0.0);
+                    println!(
+                        "  {model}: {prompt} prompt + {completion} completion tokens, {}",
+                        cost.map_or_else(
+                            || "unknown cost".to_owned(),
+                            |cost| format!("${cost:.4}")
+                        )
+                    );
                 }
+                println!("cost: ${total:.4}");
+            },
+            StoreCommand::Tag { tag } => {
+                let id = self.append_to_store(&store)?;
+                store.tag(id, &tag)?;
+            },
+            StoreCommand::Load { id } => self.load_stored(&store, id)?,
+        }
+        Ok(())
+    }
 
-                command = format!("{command}{line}");
+    /// Continues conversation `id` of the store, adding the next turns to it
+    ///
+    /// Fails with [`store::Locked`] while another sermaid writes to it.
+    fn load_stored(&mut self, store: &Store, id: i64) -> Result<()> {
+        if self.stored_conversation == Some(id) {
+            color_eyre::eyre::bail!("conversation {id} of the store is the current one");
+        }
+        let version = store.lock(id, store::REPL)?;
+        let res = store.load(id).and_then(|(name, history)| {
+            self.switch_conversation(history, name, &format!("conversation {id} of the store"))
+        });
+        if let Err(err) = res {
+            let _ = store.unlock(id, store::REPL);
+            return Err(err);
+        }
+        self.release_stored();
+        self.stored_conversation = Some(id);
+        self.stored = self.history.len();
+        self.stored_version = version;
+        Ok(())
+    }
 
-                if eoln {
-                    break;
-                }
-            }
+    fn search_all(
+        &mut self,
+        query: &str,
+        tag: Option<&str>,
+        limit: usize,
+        open: bool,
+    ) -> Result<()> {
+        let store = self.store.clone().ok_or_else(|| {
+            color_eyre::eyre::eyre!("conversations are not stored, set `store = true` in config")
+        })?;
+        let hits = store.search(query, tag, limit)?;
+        let Some(best) = hits.first().map(|hit| hit.conversation) else {
+            println!("no stored message matches");
+            return Ok(());
+        };
 
-            self.editor
-                .add_history_entry(command.clone())
-                .wrap_err_with(|| {
-                    format!("failed to add history entry `{command}` to rustyline editor")
-                })?;
-            if let Some(history_file) = &self.history_file {
-                self.editor.save_history(history_file).wrap_err_with(|| {
-                    format!(
-                        "failed to save history to file `{}`",
-                        history_file.display()
-                    )
-                })?;
+        // Grouped by conversation, in the order of their best match
+        let mut groups: Vec<(i64, Vec<&store::Hit>)> = Vec::new();
+        for hit in &hits {
+            match groups.iter_mut().find(|(id, _)| *id == hit.conversation) {
+                Some((_, group)) => group.push(hit),
+                None => groups.push((hit.conversation, vec![hit])),
+            }
+        }
+        for (id, group) in groups {
+            println!("[{id}] {}", group[0].name.as_deref().unwrap_or("(unnamed)"));
+            for hit in group {
+                println!(
+                    "    [{}] {} {}: {}",
+                    hit.position,
+                    conversation::local_time(hit.timestamp),
+                    hit.role,
+                    hit.snippet.split_whitespace().collect::<Vec<_>>().join(" ")
+                );
             }
+        }
 
-            let mut split = match shell_words::split(&command)
-                .wrap_err_with(|| format!("failed to split command `{command}`"))
-            {
-                Ok(split) => split,
+        if open {
+            self.load_stored(&store, best)?;
+        } else {
+            println!("continue one with `store load ID`, or the best match with `--open`");
+        }
+        Ok(())
+    }
+
+    fn amend(&mut self) -> Result<()> {
+        let answer = self
+            .history
+            .iter_mut()
+            .rev()
+            .find(|message| message.role == Role::Assistant)
+            .ok_or_else(|| color_eyre::eyre::eyre!("no answer to amend"))?;
+
+        let edited = external_editor::edit(&answer.content)?;
+        answer.content = edited.trim_end().to_owned().into();
+        self.store_last_answer();
+
+        Ok(())
+    }
+
+    fn apply(&self) -> Result<()> {
+        let answer = self
+            .history
+            .iter()
+            .rev()
+            .find(|message| message.role == Role::Assistant)
+            .ok_or_else(|| color_eyre::eyre::eyre!("no answer to apply"))?;
+        let patches = patch::parse(&answer.content);
+        if patches.is_empty() {
+            color_eyre::eyre::bail!("no unified diff or search/replace block in the last answer");
+        }
+
+        for patch in patches {
+            let path = patch.path.display();
+            // Answers are not trusted to write outside the current directory
+            if patch.escapes() {
+                self.print_error(&color_eyre::eyre::eyre!(
+                    "refusing to patch `{path}`, which is outside the current directory"
+                ));
+                continue;
+            }
+            let (old, new) = match patch.read() {
+                Ok(contents) => contents,
                 Err(err) => {
-                    println!("{err:?}");
+                    self.print_error(&err);
                     continue;
                 },
             };
+            if new == old && !patch.deletes() {
+                println!("`{path}` is already up to date");
+                continue;
+            }
 
-            let mut args = vec![CARGO_PKG_NAME.to_owned()];
-            args.append(&mut split);
+            println!("--- {path}");
+            print!(
+                "{}",
+                diff::hunks(&old, &new, APPLY_CONTEXT_LINES, self.color)
+            );
+            if patch.deletes() {
+                if confirm(&format!("delete `{path}`?")) {
+                    std::fs::remove_file(&patch.path)
+                        .wrap_err_with(|| format!("failed to delete `{path}`"))?;
+                    println!("deleted `{path}`");
+                }
+                continue;
+            }
+            if !confirm(&format!("apply to `{path}`?")) {
+                continue;
+            }
+            if let Some(parent) = patch
+                .path
+                .parent()
+                .filter(|parent| !parent.as_os_str().is_empty())
+            {
+                std::fs::create_dir_all(parent)
+                    .wrap_err_with(|| format!("failed to create `{}`", parent.display()))?;
+            }
+            std::fs::write(&patch.path, new)
+                .wrap_err_with(|| format!("failed to write `{path}`"))?;
+            println!("applied to `{path}`");
+        }
 
-            if !self.command_and_continue(args).await {
-                return Ok(());
+        Ok(())
+    }
+
+    fn print_history(&self, verbose: bool) {
+        for (i, message) in self.history.iter().enumerate() {
+            let color = match message.role {
+                Role::User => self.theme.user,
+                Role::Assistant => self.theme.assistant,
+                _ => None,
+            };
+            let role = Theme::paint(color, message.role.as_str());
+            println!("[{i}] {role}: {}", message.content);
+
+            if verbose {
+                let mut meta = vec![message.local_time()];
+                if let Some(model) = &message.model {
+                    meta.push(model.clone());
+                }
+                if let Some(usage) = &message.usage {
+                    meta.push(format!(
+                        "{} prompt + {} completion tokens",
+                        usage.prompt_tokens, usage.completion_tokens
+                    ));
+                }
+                if let Some(finish_reason) = &message.finish_reason {
+                    meta.push(format!("finish_reason: {finish_reason}"));
+                }
+                match &message.rating {
+                    Some(Rating::Good) => meta.push("rated good".to_owned()),
+                    Some(Rating::Bad {
+                        reason: Some(reason),
+                    }) => {
+                        meta.push(format!("rated bad: {reason}"));
+                    },
+                    Some(Rating::Bad { reason: None }) => meta.push("rated bad".to_owned()),
+                    None => {},
+                }
+                println!("    ({})", meta.join(", "));
             }
         }
     }
 
-    async fn command_and_continue(&mut self, args: Vec<String>) -> bool {
-        let args = match Cli::try_parse_from(args) {
-            Ok(args) => args,
-            Err(err) => {
-                println!("{err}");
-                return true;
-            },
-        };
+    fn print_timeline(&self) {
+        let turns = self
+            .history
+            .iter()
+            .enumerate()
+            .filter(|(_, message)| message.role == Role::Assistant)
+            .map(|(i, answer)| {
+                // The command is recorded on the question before the answer
+                let command = self.history[..i]
+                    .iter()
+                    .rev()
+                    .find(|message| message.role == Role::User)
+                    .and_then(|question| question.command.as_deref())
+                    .unwrap_or("-");
+                let model = answer.model.as_deref().unwrap_or("-");
+                let cost = answer
+                    .usage
+                    .as_ref()
+                    .and_then(|usage| pricing::usage_cost(model, usage));
+                (i, command, answer.usage.as_ref(), cost, model)
+            })
+            .collect::<Vec<_>>();
+        if turns.is_empty() {
+            println!("no answers yet");
+            return;
+        }
 
-        match args.command {
-            Command::Ask { question } => {
-                let question = shell_words::join(question);
-                if let Some(answer) =
-                    ask_openai(|| self.openai.q_and_a(question.clone(), &[], &[])).await
-                {
-                    self.history_questions.push(question);
-                    self.history_answers.push(answer);
+        let most_tokens = turns
+            .iter()
+            .filter_map(|(_, _, usage, ..)| usage.map(|usage| usage.total_tokens))
+            .max()
+            .unwrap_or(0)
+            .max(1);
+        let command_width = turns
+            .iter()
+            .map(|(_, command, ..)| command.len())
+            .chain(["command".len()])
+            .max()
+            .unwrap_or(0);
+        println!(
+            "{:>4}  {:<command_width$}  {:>8}  {:>8}  {:>9}  {:<TIMELINE_BAR_WIDTH$}  model",
+            "#", "command", "prompt", "answer", "cost", "tokens"
+        );
+
+        let (mut total_tokens, mut total_cost) = (0, 0.0);
+        for (i, command, usage, cost, model) in turns {
+            let (prompt, completion, bar) = match usage {
+                Some(usage) => {
+                    total_tokens += usage.total_tokens;
+                    let width = (usage.total_tokens as usize * TIMELINE_BAR_WIDTH)
+                        .div_ceil(most_tokens as usize);
+                    (
+                        usage.prompt_tokens.to_string(),
+                        usage.completion_tokens.to_string(),
+                        "█".repeat(width),
+                    )
+                },
+                None => ("-".to_owned(), "-".to_owned(), String::new()),
+            };
+            total_cost += cost.unwrap_or(0.0);
+            let cost = cost.map_or_else(|| "-".to_owned(), |cost| format!("${cost:.4}"));
+            println!(
+                "{i:>4}  {command:<command_width$}  {prompt:>8}  {completion:>8}  {cost:>9}  \
+                 {bar:<TIMELINE_BAR_WIDTH$}  {model}"
+            );
+        }
+        println!("total: {total_tokens} tokens, ${total_cost:.4}");
+    }
+
+    fn print_keys(&self) {
+        let status = self.openai.keys().status();
+        if status.is_empty() {
+            println!("no API keys configured");
+        }
+
+        for (i, (masked, active, health)) in status.into_iter().enumerate() {
+            let marker = if active { '*' } else { ' ' };
+            let state = match &health.last_error {
+                Some(error) => format!("failing ({error})"),
+                None => "ok".to_owned(),
+            };
+            println!(
+                "{marker} [{i}] {masked} {state}, {} requests, {} failures, {} tokens",
+                health.requests, health.failures, health.total_tokens
+            );
+        }
+    }
+
+    async fn print_quota(&self) {
+        let session_cost = self
+            .history
+            .iter()
+            .filter_map(|message| {
+                pricing::usage_cost(message.model.as_deref()?, message.usage.as_ref()?)
+            })
+            .fold(0.0, |total, cost| total + cost);
+        let mut local = vec![format!("${session_cost:.4} this session")];
+        if let Some(budget) = self.openai.budget() {
+            local.push(format!("${:.4} today", budget.spent_today()));
+        }
+        println!("tracked here: {}", local.join(", "));
+
+        match self.openai.quota().await {
+            Ok(Quota::MonthSpend(spent)) => {
+                println!(
+                    "{}: ${spent:.4} this month",
+                    self.openai.provider().as_str()
+                );
+            },
+            Ok(Quota::Balance(balances)) => {
+                for balance in balances {
+                    println!(
+                        "{}: {} {} left, {} granted and {} topped up",
+                        self.openai.provider().as_str(),
+                        balance.total_balance,
+                        balance.currency,
+                        balance.granted_balance,
+                        balance.topped_up_balance
+                    );
                 }
             },
-            Command::Continue { question } => {
-                let question = shell_words::join(question);
-                if let Some(answer) = ask_openai(|| {
-                    self.openai.q_and_a(
-                        question.clone(),
-                        &self.history_questions,
-                        &self.history_answers,
-                    )
-                })
-                .await
-                {
-                    self.history_questions.push(question);
-                    self.history_answers.push(answer);
+            Err(err) => self.print_error(&err),
+        }
+    }
+
+    /// The system prompt and context of the next continue
+    fn next_context(&self) -> Vec<ChatMessage> {
+        let system = ChatMessage::new(Role::System, self.length(None).system_prompt());
+        std::iter::once(system).chain(self.context(true)).collect()
+    }
+
+    /// Shows the conversation name, whether a command is running and how many questions are
+    /// answered in the background in the terminal title, like
+    /// `sermaid: rust-help (busy) [2 in background]`
+    fn update_title(&mut self, busy: bool) {
+        if self.title.is_none() {
+            return;
+        }
+        let mut text = format!(
+            "{CARGO_PKG_NAME}: {}",
+            self.name.as_deref().unwrap_or("unnamed")
+        );
+        if busy {
+            text.push_str(" (busy)");
+        }
+        let running = self
+            .jobs
+            .values()
+            .filter(|job| !job.task.is_finished())
+            .count();
+        if running > 0 {
+            text.push_str(&format!(" [{running} in background]"));
+        }
+        if let Some(title) = &mut self.title {
+            title.set(&text);
+        }
+    }
+
+    /// A line like `gpt-4o | thread:rust-help | 3.2k/128k tokens`
+    fn status_line(&self) -> String {
+        let model = self.openai.model();
+        let tokenizer = tokenizer::for_model(model);
+        let tokens = self
+            .next_context()
+            .iter()
+            .map(|message| tokenizer.count(&message.content))
+            .sum::<usize>();
+
+        let mut segments = vec![model.to_owned()];
+        if let Some(name) = &self.name {
+            segments.push(format!("thread:{name}"));
+        }
+        segments.push(match tokenizer::context_window(model) {
+            Some(window) => format!("{}/{} tokens", compact(tokens), compact(window)),
+            None => format!("{} tokens", compact(tokens)),
+        });
+
+        let line = segments.join(" | ");
+        if self.color {
+            format!("{DIM}{line}{RESET}")
+        } else {
+            line
+        }
+    }
+
+    fn print_context(&self, full: bool) {
+        let messages = self.next_context();
+
+        let tokenizer = tokenizer::for_model(self.openai.model());
+        let approx = if tokenizer.is_exact() { "" } else { "~" };
+
+        println!("pruning: {}", self.settings.pruning);
+        let mut total = 0;
+        for (i, message) in messages.iter().enumerate() {
+            let tokens = tokenizer.count(&message.content);
+            total += tokens;
+
+            let content = if full {
+                message.content.to_string()
+            } else {
+                truncate(&message.content, CONTEXT_PREVIEW_CHARS)
+            };
+            println!(
+                "[{i}] {:<9} {:<6} {content}",
+                message.role.as_str(),
+                format!("{approx}{tokens}")
+            );
+        }
+        println!("-- {} messages, {approx}{total} tokens", messages.len());
+    }
+
+    /// Replaces the conversation with the one saved in `file`, pinning the model of its last
+    /// answer so that it goes on as it started whatever the default model is now
+    fn load_session(&mut self, file: &Path) -> Result<()> {
+        if !file.exists() {
+            color_eyre::eyre::bail!("no conversation `{}`", file.display());
+        }
+        let (history, tags) = conversation::load_session(file, self.cipher.as_deref())?;
+        let name = file
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned());
+        self.switch_conversation(history, name, &format!("`{}`", file.display()))?;
+        self.tags = tags;
+        // Stored as a new conversation with its next turn
+        self.release_stored();
+        Ok(())
+    }
+
+    /// Replaces the conversation with `history`, loaded from `origin`, switching to the model
+    /// that answered it
+    fn switch_conversation(
+        &mut self,
+        history: Vec<ChatMessage>,
+        name: Option<String>,
+        origin: &str,
+    ) -> Result<()> {
+        let provider = self.openai.provider();
+        let pinned = history
+            .iter()
+            .rev()
+            .find_map(|message| message.model.as_deref());
+        match pinned.map(|model| (model, provider.known_model(model))) {
+            Some((_, Some(model))) if model != self.openai.model() => {
+                let Some(openai) = Arc::get_mut(&mut self.openai) else {
+                    color_eyre::eyre::bail!(
+                        "cannot switch to the conversation's model `{model}` while questions are \
+                         answered in the background"
+                    );
+                };
+                openai.set_model(model.clone());
+                println!("switched to the conversation's model `{model}`");
+            },
+            Some((model, None)) => println!(
+                "the conversation was answered by `{model}`, which provider `{}` no longer has, \
+                 continuing with `{}`",
+                provider.as_str(),
+                self.openai.model()
+            ),
+            _ => {},
+        }
+
+        println!("loaded {} messages from {origin}", history.len());
+        self.history = history;
+        self.summary = None;
+        self.follow_ups.clear();
+        self.name = name;
+        self.tags.clear();
+        Ok(())
+    }
+
+    fn merge_sessions(&self, a: &Path, b: &Path, into: &Path, concat: bool) -> Result<()> {
+        if into.exists() {
+            color_eyre::eyre::bail!("`{}` already exists", into.display());
+        }
+        for path in [a, b] {
+            if !path.exists() {
+                color_eyre::eyre::bail!("no conversation `{}`", path.display());
+            }
+        }
+
+        let cipher = self.cipher.as_deref();
+        let (a, mut tags) = conversation::load_session(a, cipher)?;
+        let (b, b_tags) = conversation::load_session(b, cipher)?;
+        for tag in b_tags {
+            if !tags.contains(&tag) {
+                tags.push(tag);
+            }
+        }
+        let merged = conversation::merge(a, b, !concat);
+        conversation::save_session(into, &merged, &tags, cipher)?;
+        println!("merged {} messages into `{}`", merged.len(), into.display());
+        Ok(())
+    }
+
+    /// Lists the conversations saved in `dir`, only those tagged `tag` if given
+    fn list_sessions(&self, dir: &Path, tag: Option<&str>) -> Result<()> {
+        let entries = std::fs::read_dir(dir)
+            .wrap_err_with(|| format!("failed to read `{}`", dir.display()))?;
+        let mut paths = entries
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+            .collect::<Vec<_>>();
+        paths.sort();
+
+        let mut listed = 0;
+        for path in paths {
+            // Other JSON files are not conversations
+            let Ok((history, tags)) = conversation::load_session(&path, self.cipher.as_deref())
+            else {
+                continue;
+            };
+            if tag.is_some_and(|tag| !tags.iter().any(|t| t == tag)) {
+                continue;
+            }
+            let started = history
+                .first()
+                .map_or_else(String::new, |message| format!("  {}", message.local_time()));
+            let tags = if tags.is_empty() {
+                String::new()
+            } else {
+                format!(" [{}]", tags.join(", "))
+            };
+            println!(
+                "{}{started}, {} messages{tags}",
+                path.display(),
+                history.len()
+            );
+            listed += 1;
+        }
+        if listed == 0 {
+            match tag {
+                Some(tag) => println!("no conversations tagged `{tag}` in `{}`", dir.display()),
+                None => println!("no conversations in `{}`", dir.display()),
+            }
+        }
+        Ok(())
+    }
+
+    /// Tags the conversation, and its stored copy if `store` is enabled
+    fn tag(&mut self, tag: String) -> Result<()> {
+        if let Some(store) = self.store.clone() {
+            let id = self.append_to_store(&store)?;
+            store.tag(id, &tag)?;
+        }
+        if !self.tags.contains(&tag) {
+            self.tags.push(tag);
+        }
+        Ok(())
+    }
+
+    async fn share(&self) -> Result<()> {
+        let Some(config) = &self.share else {
+            color_eyre::eyre::bail!("no `[share]` section in config");
+        };
+        if self.history.is_empty() {
+            color_eyre::eyre::bail!("nothing to share yet");
+        }
+
+        let history = self
+            .history
+            .iter()
+            .map(|message| ChatMessage {
+                content: self.redactor.redact(&message.content).into_owned().into(),
+                ..message.clone()
+            })
+            .collect::<Vec<_>>();
+        let markdown = conversation::markdown(&history, self.name.as_deref());
+        let url = share::upload(&self.client, config, &markdown).await?;
+        println!("{url}");
+        Ok(())
+    }
+
+    fn export(
+        &self,
+        file: &Path,
+        raw: bool,
+        format: ExportFormat,
+        rating: Option<RatingFilter>,
+        command: Option<String>,
+    ) -> Result<()> {
+        let redact = |message: &ChatMessage| {
+            if raw {
+                message.clone()
+            } else {
+                ChatMessage {
+                    content: self.redactor.redact(&message.content).into_owned().into(),
+                    ..message.clone()
                 }
+            }
+        };
+
+        let exchanges = self
+            .history
+            .windows(2)
+            .filter(|pair| pair[0].role == Role::User && pair[1].role == Role::Assistant)
+            .filter(|pair| rating.is_none_or(|rating| rating.matches(pair[1].rating.as_ref())))
+            .filter(|pair| command.is_none() || pair[0].command == command)
+            .map(|pair| (redact(&pair[0]), redact(&pair[1])))
+            .collect::<Vec<_>>();
+
+        match format {
+            ExportFormat::Json if rating.is_none() && command.is_none() => {
+                let history = self.history.iter().map(redact).collect::<Vec<_>>();
+                conversation::save_session(file, &history, &self.tags, self.cipher.as_deref())
             },
-            Command::Translate { raw_text } => {
-                ask_openai(|| self.openai.translate(shell_words::join(raw_text))).await;
+            ExportFormat::Json => {
+                let history = exchanges
+                    .into_iter()
+                    .flat_map(|(question, answer)| [question, answer])
+                    .collect::<Vec<_>>();
+                conversation::save_session(file, &history, &self.tags, self.cipher.as_deref())
             },
-            Command::Clear => {
-                if let Err(err) = self
-                    .editor
-                    .clear_screen()
-                    .wrap_err_with(|| "failed to clear screen")
-                {
-                    println!("{err:?}");
-                };
+            ExportFormat::OpenaiFt => {
+                let examples = exchanges
+                    .iter()
+                    .map(|(question, answer)| (self.system_prompt(question), question, answer))
+                    .collect::<Vec<_>>();
+                conversation::save_fine_tuning(file, &examples, self.cipher.as_deref())
             },
-            Command::Exit => {
-                return false;
+            ExportFormat::Markdown => {
+                let history = exchanges
+                    .into_iter()
+                    .flat_map(|(question, answer)| [question, answer])
+                    .collect::<Vec<_>>();
+                conversation::save_markdown(
+                    file,
+                    &history,
+                    self.name.as_deref(),
+                    self.cipher.as_deref(),
+                )
             },
         }
+    }
 
-        true
+    /// The system prompt a question was asked under, as near as the history tells
+    fn system_prompt(&self, question: &ChatMessage) -> String {
+        question
+            .command
+            .as_ref()
+            .and_then(|command| self.custom_commands.get(command))
+            .map_or_else(
+                || self.length(None).system_prompt(),
+                |command| command.system.clone(),
+            )
     }
 }
 
 #[derive(Debug, Parser)]
+#[command(disable_help_subcommand = true)]
 struct Cli {
     #[command(subcommand)]
     command: Command,
@@ -159,41 +4024,574 @@ struct Cli {
 enum Command {
     /// Ask a simple question to OpenAI API and get an answer
     #[clap(alias = "q")]
-    Ask { question: Vec<String> },
+    Ask {
+        /// Limit the answer to about this many words
+        #[arg(long, value_name = "N")]
+        max_words: Option<u32>,
+        /// Pin the text read from stdin as context, when run as a single command
+        #[arg(long)]
+        stdin_context: bool,
+        /// Pin the readable text of a web page as context, if `url_fetch` is enabled
+        #[arg(long, value_name = "URL")]
+        url: Option<String>,
+        /// Start the REPL after answering, when run as a single command
+        #[arg(long)]
+        follow_up: bool,
+        /// Ask in the background and return to the prompt, see `jobs` and `fg`, without MCP tools
+        #[arg(long, conflicts_with_all = ["grammar", "prefill", "choices", "follow_up"])]
+        bg: bool,
+        /// Constrain the answer to the GBNF grammar in FILE, with provider `llama-cpp`, without
+        /// MCP tools
+        #[arg(long, value_name = "FILE")]
+        grammar: Option<PathBuf>,
+        /// Start the answer with TEXT for the model to continue, like '```json' to get JSON,
+        /// with servers that accept a partial answer as the last message, without MCP tools
+        #[arg(long, value_name = "TEXT", conflicts_with_all = ["grammar", "choices"])]
+        prefill: Option<String>,
+        /// Paint the tokens of the answer the model was unsure of and list the least likely,
+        /// to spot what it may have made up, with providers that return log probabilities,
+        /// without MCP tools
+        #[arg(long, conflicts_with_all = ["grammar", "prefill", "choices", "bg"])]
+        show_confidence: bool,
+        /// Generate N answers and pick the one that enters history, without MCP tools
+        #[arg(long, value_name = "N", conflicts_with = "grammar")]
+        choices: Option<u32>,
+        /// Attach files to the question, by path or glob like `src/**/*.rs`, keeping the most
+        /// relevant ones that fit the token budget
+        #[arg(long = "file", value_name = "PATH")]
+        files: Vec<String>,
+        /// Attach an outline of the git repository, its files and the symbols they define
+        #[arg(long)]
+        repo: bool,
+        /// Ask about the text in an image, read by a vision model or `tesseract` as set in
+        /// `[ocr]`
+        #[arg(long, value_name = "IMAGE")]
+        ocr: Option<PathBuf>,
+        /// Send the question even if it repeats the previous one
+        #[arg(long)]
+        force: bool,
+        /// Read the answer aloud sentence by sentence as it is generated, with the speech model
+        /// and voice of `[voice]`, without MCP tools
+        #[arg(long, conflicts_with_all = ["grammar", "prefill", "show_confidence", "choices", "bg"])]
+        speak: bool,
+        question: Vec<String>,
+    },
+    /// Speak a question into the microphone, check its transcript and ask it, or pass it to
+    /// COMMAND, e.g. `listen translate`
+    Listen {
+        #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
+        command: Vec<String>,
+    },
+    /// Ask several independent questions at once, entered one per line until a blank line
+    Multi,
+    /// Send the requests queued while offline, if `offline_queue` is enabled
+    Flush,
+    /// List the questions asked with `ask --bg`, pending or answered
+    Jobs,
+    /// Wait for a background question, print its answer and add it to the history
+    Fg { id: usize },
     /// Continue asking conversation
     #[clap(alias = "c")]
-    Continue { question: Vec<String> },
+    Continue {
+        /// Limit the answer to about this many words
+        #[arg(long, value_name = "N")]
+        max_words: Option<u32>,
+        /// Generate N answers and pick the one that enters history, without MCP tools
+        #[arg(long, value_name = "N")]
+        choices: Option<u32>,
+        /// Send the question even if it repeats the previous one
+        #[arg(long)]
+        force: bool,
+        /// Send every turn verbatim, regardless of `history_limit` and `pruning`
+        #[arg(long)]
+        all: bool,
+        question: Vec<String>,
+    },
     /// Ask OpenAI API to translate to Chinese, or translate Chinese to English
     #[clap(alias = "tr")]
-    Translate { raw_text: Vec<String> },
+    Translate {
+        /// Translate into this language instead
+        #[arg(long, value_name = "LANGUAGE")]
+        to: Option<String>,
+        /// Text to translate, read from stdin when omitted on the command line
+        raw_text: Vec<String>,
+    },
+    /// Tag the last answer as good, kept in the history and exports
+    Good,
+    /// Tag the last answer as bad, optionally saying why
+    Bad { reason: Vec<String> },
+    /// Edit the last answer in $EDITOR so later turns build on the corrected version
+    Amend,
+    /// Apply the unified diffs or search/replace blocks of the last answer to the files they
+    /// edit or delete under the current directory, confirming each file after a preview
+    Apply,
+    /// Print the files and excerpts cited as [1], [2], ... by the last answer to `ask --file`
+    Sources,
+    /// Show the conversation history
+    History {
+        /// Also show timestamp, model, token counts and finish reason of each turn
+        #[arg(short, long)]
+        verbose: bool,
+    },
+    /// Show a table of the answers with the command, tokens, cost and model of each, to spot
+    /// the turns that used up the budget
+    Timeline,
+    /// Export the conversation history with per-turn metadata as JSON, secrets redacted
+    Export {
+        file: PathBuf,
+        /// Keep secrets and personal data matching the redaction patterns
+        #[arg(long)]
+        raw: bool,
+        #[arg(long, value_enum, default_value_t = ExportFormat::Json)]
+        format: ExportFormat,
+        /// Only export exchanges whose answer was rated like this
+        #[arg(long, value_enum)]
+        rating: Option<RatingFilter>,
+        /// Only export exchanges asked with this command, e.g. `ask` or a custom command
+        #[arg(long, value_name = "NAME")]
+        command: Option<String>,
+    },
+    /// Upload the conversation as markdown to the `[share]` service and print its URL, secrets
+    /// redacted
+    Share,
+    /// Work with conversations saved by `export`
+    Session {
+        #[command(subcommand)]
+        command: SessionCommand,
+    },
+    /// Search, count and continue the conversations of every session, if `store` is enabled
+    Store {
+        #[command(subcommand)]
+        command: StoreCommand,
+    },
+    /// Search the messages of every stored conversation, grouped by conversation with the
+    /// matching words in brackets
+    SearchAll {
+        /// Words that must all appear, or an FTS5 query with `--raw`
+        #[arg(required = true)]
+        query: Vec<String>,
+        /// Take QUERY as FTS5 syntax: `"exact phrase"`, `tok*`, `a OR b`, `a NOT b`,
+        /// `NEAR(a b)`
+        #[arg(long)]
+        raw: bool,
+        /// Only search conversations with this tag
+        #[arg(long)]
+        tag: Option<String>,
+        #[arg(long, default_value_t = 20)]
+        limit: usize,
+        /// Continue the conversation of the best match
+        #[arg(long)]
+        open: bool,
+    },
+    /// Show a line diff between two messages, numbered as in `history`
+    Diff { old: usize, new: usize },
+    /// Pin context that is sent near the top of every question
+    Pin {
+        /// Pin the contents of a file
+        #[arg(long, value_name = "FILE", conflicts_with = "text")]
+        file: Option<PathBuf>,
+        #[arg(required_unless_present = "file")]
+        text: Vec<String>,
+    },
+    /// Pin the text of a file as context, extracting it from PDF and DOCX files, or attach the
+    /// image in the clipboard to the next question
+    Attach {
+        #[arg(required_unless_present = "clipboard_image")]
+        file: Option<PathBuf>,
+        /// Only these pages of a PDF, e.g. `3-7`
+        #[arg(long, value_name = "RANGE")]
+        pages: Option<PageRange>,
+        /// Send the image in the clipboard with the next `ask` or `continue`, for vision models,
+        /// without MCP tools
+        #[arg(long, conflicts_with_all = ["file", "pages"])]
+        clipboard_image: bool,
+    },
+    /// List pinned context
+    Pins,
+    /// Count the tokens of some text for the configured model, before sending it
+    Tokens {
+        /// Count the tokens of a file
+        #[arg(long, value_name = "FILE", conflicts_with = "text")]
+        file: Option<PathBuf>,
+        #[arg(required_unless_present = "file")]
+        text: Vec<String>,
+    },
+    /// Remove pinned context by its number in `pins`
+    Unpin { index: usize },
+    /// Run `ask` or `translate` on a schedule while `serve` is running, e.g.
+    /// `schedule "every day 9am" ask summarize the news`
+    Schedule {
+        /// Add the answers to this session of the HTTP API
+        #[arg(long, value_name = "ID", conflicts_with = "into")]
+        session: Option<String>,
+        /// Append the answers to this file, as a conversation if it ends in `.json` and as
+        /// markdown otherwise
+        #[arg(long, value_name = "FILE")]
+        into: Option<PathBuf>,
+        /// `every N minutes`, `every hour`, `every day 9am`, `every weekday 17:30`,
+        /// `every monday 8:15am`, ...
+        when: String,
+        /// The command to run
+        #[arg(
+            required = true,
+            trailing_var_arg = true,
+            allow_hyphen_values = true,
+            value_name = "COMMAND"
+        )]
+        command: Vec<String>,
+    },
+    /// List scheduled prompts with their next run
+    Schedules,
+    /// Remove a scheduled prompt by its number in `schedules`
+    Unschedule { index: usize },
+    /// Compose a prompt from pieces added over several steps, then send it as one
+    Buf {
+        #[command(subcommand)]
+        command: BufCommand,
+    },
+    /// Ask for review comments on the git diff of the working tree, grouped by file and line
+    Review {
+        /// Review staged changes instead of unstaged ones
+        #[arg(long, conflicts_with = "range")]
+        staged: bool,
+        /// Review a revision range such as `main..HEAD`
+        #[arg(long, value_name = "RANGE")]
+        range: Option<String>,
+        /// Also write the comments to a markdown report
+        #[arg(long, value_name = "FILE")]
+        report: Option<PathBuf>,
+    },
+    /// Write a pull request title and body from the commits and diff against a base branch
+    PrDesc {
+        /// Branch the pull request would merge into
+        #[arg(long, default_value = "main")]
+        base: String,
+        /// Copy the title and body to the clipboard
+        #[arg(long)]
+        copy: bool,
+        /// Open the pull request with the GitHub CLI
+        #[arg(long)]
+        create: bool,
+    },
+    /// Explain the first error of the build command in config, `cargo check` by default, and
+    /// suggest a fix
+    ExplainError {
+        /// Run this build command instead
+        #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
+        command: Vec<String>,
+    },
+    /// Run `cargo clippy` and `cargo test` and ask for a fix plan for each failure, reusing the
+    /// plans for failures that did not change
+    Triage {
+        /// Only run `cargo clippy`
+        #[arg(long, conflicts_with = "test")]
+        clippy: bool,
+        /// Only run `cargo test`
+        #[arg(long)]
+        test: bool,
+        /// Ask again about failures that did not change
+        #[arg(long)]
+        refresh: bool,
+    },
+    /// Generate a one-page quick reference of the commands, aliases and templates in use
+    Cheatsheet {
+        /// Write the markdown to this file instead of printing it
+        #[arg(long, short, value_name = "FILE")]
+        output: Option<PathBuf>,
+    },
+    /// Use tools of the MCP servers in config
+    Mcp {
+        #[command(subcommand)]
+        command: McpCommand,
+    },
+    /// Translate new foreign-language text copied to the clipboard until interrupted
+    WatchClipboard {
+        /// Translate clipboard text, currently the only action
+        #[arg(long, required = true)]
+        translate: bool,
+        /// Translate into this language instead
+        #[arg(long, value_name = "LANGUAGE")]
+        to: Option<String>,
+        /// Show translations as desktop notifications instead of replacing the clipboard
+        #[arg(long)]
+        notify: bool,
+    },
+    /// Answer questions written line by line to a FIFO or appended to a file, so that editors
+    /// can ask through files
+    Watch {
+        /// FIFO or file to read questions from
+        #[arg(long)]
+        file: PathBuf,
+        /// FIFO to write each answer to, or file to append them to, instead of printing them
+        #[arg(long)]
+        out: Option<PathBuf>,
+    },
+    /// Speak newline-delimited JSON-RPC on stdin and stdout, streaming answers, for editor
+    /// plugins
+    Rpc,
+    /// Serve an HTTP API so editors and scripts on this machine can ask questions
+    Serve {
+        /// Address to listen on
+        #[arg(long, default_value = "127.0.0.1:8080")]
+        listen: SocketAddr,
+    },
+    /// Answer `sermaid ctl ask ...` and `sermaid ctl translate ...` from other shells over a
+    /// Unix socket, keeping the API connections and sessions between them
+    Daemon,
+    /// Answer mentions in the IRC channels or Matrix rooms of the `[bridge]` config
+    Bridge,
+    /// Bind a key such as `F2` or `C-x` to an editing action for the rest of the session
+    Bind {
+        key: String,
+        /// `edit` to edit the line in $EDITOR, `insert TEXT`, `clear-screen`, `undo`, ...
+        #[arg(required = true)]
+        action: Vec<String>,
+    },
+    /// Change a setting for the rest of the session
+    Set {
+        #[command(subcommand)]
+        setting: Setting,
+    },
+    /// Share custom commands, templates, aliases, theme and key bindings as a bundle file
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand,
+    },
+    /// Show which API key is active and the health of each key
+    Keys,
+    /// Show the spending or credits the provider reports next to the spending tracked here
+    Quota,
+    /// Name the conversation, or show its name
+    Name { name: Option<String> },
+    /// Tag the conversation, e.g. with its project, or show its tags
+    ///
+    /// Tags are saved with the conversation by `export` and filter `session list --tag`, and
+    /// `search-all --tag` if `store` is enabled.
+    Tag { tag: Option<String> },
+    /// Show the messages that will be sent with the next continue, before the question
+    Context {
+        /// Show whole messages instead of their first line
+        #[arg(long)]
+        full: bool,
+    },
+    /// Show a command with examples, or list the commands
+    Help {
+        /// Command, and subcommand if any, e.g. `buf send`
+        command: Vec<String>,
+    },
     /// Clear screen
     Clear,
     /// Exit the program
     Exit,
 }
 
-async fn ask_openai<F, Fut>(f: F) -> Option<Cow<'static, str>>
-where
-    F: FnOnce() -> Fut,
-    Fut: Future<Output = Result<Cow<'static, str>>>,
-{
-    let spinner = Spinner::new();
-    spinner.start();
-
-    let res = f()
-        .await
-        .wrap_err_with(|| "failed to get response from openai");
-    spinner.stop();
-    match res {
-        Ok(content) => {
-            println!("{content}");
-            Some(content)
-        },
-        Err(err) => {
-            println!("{err:?}");
-            None
-        },
+#[derive(Clone, Debug, Subcommand)]
+enum McpCommand {
+    /// List the tools of every MCP server
+    List,
+    /// Call a tool directly
+    Call {
+        server: String,
+        tool: String,
+        /// Tool arguments as a JSON object
+        arguments: Option<String>,
+    },
+}
+
+#[derive(Clone, Debug, Subcommand)]
+enum SessionCommand {
+    /// Continue a saved conversation, named after its file, with the model that answered it
+    Load { file: PathBuf },
+    /// Merge two saved conversations into a new one, interleaving their turns by time
+    Merge {
+        a: PathBuf,
+        b: PathBuf,
+        /// File to write the merged conversation to, which must not exist yet
+        #[arg(long, value_name = "FILE")]
+        into: PathBuf,
+        /// Put the turns of the second conversation after those of the first instead
+        #[arg(long)]
+        concat: bool,
+    },
+    /// List the conversations saved in a directory with when they started and their tags
+    List {
+        #[arg(default_value = ".")]
+        dir: PathBuf,
+        /// Only list conversations with this tag
+        #[arg(long)]
+        tag: Option<String>,
+    },
+}
+
+#[derive(Clone, Debug, Subcommand)]
+enum StoreCommand {
+    /// List the conversations started last
+    List {
+        #[arg(long, default_value_t = 20)]
+        limit: usize,
+    },
+    /// Count the stored conversations, messages, tokens and their cost by model
+    Stats,
+    /// Tag the conversation to find it with `search-all --tag`
+    Tag { tag: String },
+    /// Continue a stored conversation by its number in `store list` or `search-all`, unless
+    /// another sermaid is writing to it
+    Load { id: i64 },
+}
+
+#[derive(Clone, Debug, Subcommand)]
+enum BufCommand {
+    /// Add text, or the contents of a file, to the scratch buffer
+    Add {
+        /// Add the contents of a file
+        #[arg(long, value_name = "FILE", conflicts_with = "text")]
+        file: Option<PathBuf>,
+        #[arg(required_unless_present = "file")]
+        text: Vec<String>,
+    },
+    /// Show the scratch buffer
+    Show,
+    /// Send the scratch buffer as the text of a command, `ask` by default, and empty it
+    Send { command: Vec<String> },
+    /// Empty the scratch buffer
+    Clear,
+}
+
+#[derive(Clone, Debug, Subcommand)]
+enum ConfigCommand {
+    /// Write the shareable sections of the config to a bundle, leaving out keys and tokens
+    Export { file: PathBuf },
+    /// Add the sections of a bundle to the config, replacing entries of the same name, backed
+    /// up to `<config>.bak` first
+    Import { file: PathBuf },
+}
+
+#[derive(Clone, Debug, Subcommand)]
+enum Setting {
+    /// Show word count, character count and token count after each answer
+    Footer { state: Toggle },
+    /// Show the model, conversation name and context size above the prompt
+    StatusLine { state: Toggle },
+    /// Show the reasoning of thinking models dimmed above their answers
+    ShowReasoning { state: Toggle },
+    /// Suggest follow-up questions after each answer, asked with `f1`, `f2`, ...
+    FollowUps { state: Toggle },
+    /// Show answers to ask and continue formatted as they are generated, unless MCP servers are
+    /// configured
+    Stream { state: Toggle },
+    /// Default answer length for ask and continue
+    Brevity { level: Brevity },
+    /// History sent with continue: `all`, `window(N)` turns or a rolling `summary`
+    Pruning { strategy: Pruning },
+    /// Sampling seed for reproducible answers where the provider supports it, none without
+    Seed { seed: Option<u64> },
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum Brevity {
+    Short,
+    Normal,
+    Long,
+}
+
+/// How a single command given on the command line prints answers
+#[derive(Clone, Copy, Debug, ValueEnum)]
+pub enum OutputFormat {
+    Text,
+    /// A JSON object per answer, with the model, token usage and latency
+    Json,
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum ExportFormat {
+    /// The history with per-turn metadata
+    Json,
+    /// JSONL for OpenAI chat fine-tuning, one exchange per line
+    OpenaiFt,
+    /// Readable markdown, for sharing
+    Markdown,
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum RatingFilter {
+    Good,
+    Bad,
+    Unrated,
+}
+
+impl RatingFilter {
+    fn matches(self, rating: Option<&Rating>) -> bool {
+        matches!(
+            (self, rating),
+            (Self::Good, Some(Rating::Good)) |
+                (Self::Bad, Some(Rating::Bad { .. })) |
+                (Self::Unrated, None)
+        )
+    }
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum Toggle {
+    On,
+    Off,
+}
+
+impl From<Toggle> for bool {
+    fn from(toggle: Toggle) -> Self {
+        matches!(toggle, Toggle::On)
+    }
+}
+
+/// Asks a yes/no question on stdin, defaulting to no
+fn confirm(question: &str) -> bool {
+    print!("{question} [y/N] ");
+    let _ = std::io::stdout().flush();
+
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer).is_ok() &&
+        matches!(answer.trim().to_lowercase().as_str(), "y" | "yes")
+}
+
+/// First line of `content`, cut to `max_chars` with an ellipsis if anything was left out
+fn truncate(content: &str, max_chars: usize) -> String {
+    let line = content
+        .lines()
+        .find(|line| !line.trim().is_empty())
+        .unwrap_or_default();
+    let mut truncated = line.chars().take(max_chars).collect::<String>();
+    if truncated.len() < content.trim().len() {
+        truncated.push('…');
+    }
+    truncated
+}
+
+/// Shortens counts such as 3200 to `3.2k`
+fn compact(count: usize) -> String {
+    if count < 1000 {
+        return count.to_string();
     }
+
+    let thousands = format!("{:.1}", count as f64 / 1000.0);
+    format!("{}k", thousands.trim_end_matches(".0"))
+}
+
+/// Whether more than half of the letters of `text` are CJK ideographs
+fn is_mostly_cjk(text: &str) -> bool {
+    let letters = text.chars().filter(|c| c.is_alphabetic());
+    let (cjk, total) = letters.fold((0, 0), |(cjk, total), c| {
+        let is_cjk = matches!(c, '\u{3400}'..='\u{9fff}' | '\u{f900}'..='\u{faff}');
+        (cjk + usize::from(is_cjk), total + 1)
+    });
+    cjk * 2 > total
+}
+
+/// A question asked with `ask --bg`
+struct Job {
+    question: String,
+    started: Instant,
+    task: tokio::task::JoinHandle<Result<Completion>>,
+    /// Whether the REPL said the answer is ready
+    announced: bool,
 }
 
 struct Spinner {
@@ -202,9 +4600,21 @@ struct Spinner {
 }
 
 impl Spinner {
-    fn new() -> Self {
+    fn new(style: SpinnerStyle) -> Self {
+        let bar = ProgressBar::new_spinner()
+            .with_style(ProgressStyle::default_spinner().tick_chars(style.tick_chars()))
+            .with_message("Waiting for response...");
+        let bar = Arc::new(bar);
+        terminal::track(&bar);
+        Self {
+            bar,
+            cancellation_token: CancellationToken::new(),
+        }
+    }
+
+    fn hidden() -> Self {
         Self {
-            bar: Arc::new(ProgressBar::new_spinner().with_message("Waiting for response...")),
+            bar: Arc::new(ProgressBar::hidden()),
             cancellation_token: CancellationToken::new(),
         }
     }
@@ -228,3 +4638,66 @@ impl Spinner {
         self.bar.finish_and_clear();
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mock(fixtures_dir: &Path) -> SerMaid {
+        let args = Args::parse_from([CARGO_PKG_NAME, "--raw", "--no-persist", "ask"]);
+        let config = toml::from_str(&format!(
+            "provider = \"mock\"\nfixtures_dir = {:?}",
+            fixtures_dir.display().to_string()
+        ))
+        .unwrap();
+        SerMaid::from_config(&args, config).unwrap()
+    }
+
+    fn command(line: &str) -> Vec<String> {
+        let mut args = vec![CARGO_PKG_NAME.to_owned()];
+        args.extend(shell_words::split(line).unwrap());
+        args
+    }
+
+    #[tokio::test]
+    async fn ask_and_continue_answer_from_fixtures() {
+        let dir = tempfile::tempdir().unwrap();
+        let fallback = dir.path().join("default.txt");
+        std::fs::write(&fallback, "first answer").unwrap();
+        let mut sermaid = mock(dir.path());
+
+        assert!(sermaid.command_and_continue(command("ask hello")).await);
+        std::fs::write(&fallback, "second answer").unwrap();
+        assert!(
+            sermaid
+                .command_and_continue(command("continue go on"))
+                .await
+        );
+
+        let exchanges = sermaid
+            .history
+            .iter()
+            .map(|message| (message.role, message.content.as_ref()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            exchanges,
+            [
+                (Role::User, "hello"),
+                (Role::Assistant, "first answer"),
+                (Role::User, "go on"),
+                (Role::Assistant, "second answer"),
+            ]
+        );
+        assert_eq!(sermaid.exit_code(), 0);
+    }
+
+    #[tokio::test]
+    async fn ask_fails_without_fixture() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut sermaid = mock(dir.path());
+
+        assert!(sermaid.command_and_continue(command("ask hello")).await);
+        assert!(sermaid.history.is_empty());
+        assert_eq!(sermaid.exit_code(), EXIT_FAILURE);
+    }
+}
@@ -0,0 +1,351 @@
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use axum::extract::{Path, State};
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::response::sse::{Event, Sse};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use color_eyre::eyre::{Context, Result};
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+
+use crate::conversation::{self, ChatMessage};
+use crate::offline::QueuedRequest;
+use crate::openai::{Completion, Length, OpenAI, Role, Usage};
+use crate::schedule::Schedules;
+use crate::store::{self, Locked, Store};
+
+const SCHEDULE_POLL_INTERVAL: Duration = Duration::from_secs(30);
+
+/// What the HTTP API shares with the REPL it was started from
+pub struct ServerState {
+    pub openai: Arc<OpenAI>,
+    /// Pinned context sent before every question
+    pub pins: Vec<ChatMessage>,
+    pub length: Length,
+    pub sessions: Mutex<HashMap<String, Vec<ChatMessage>>>,
+    /// Prompts added with `schedule`, run while serving
+    pub schedules: Option<Schedules>,
+    /// Where the sessions are also kept, if `store` is enabled
+    pub store: Option<Arc<Store>>,
+}
+
+#[derive(Deserialize)]
+struct AskRequest {
+    question: String,
+    max_words: Option<u32>,
+}
+
+#[derive(Serialize)]
+struct AskResponse {
+    answer: String,
+    model: String,
+    usage: Option<Usage>,
+}
+
+impl From<Completion> for AskResponse {
+    fn from(completion: Completion) -> Self {
+        Self {
+            answer: completion.content.into_owned(),
+            model: completion.model,
+            usage: completion.usage,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct ErrorResponse {
+    error: String,
+}
+
+/// Serves `POST /ask`, `POST /sessions/{id}/continue` and `GET /sessions/{id}` until Ctrl-C,
+/// running scheduled prompts meanwhile.
+/// Answers are sent as a server-sent `answer` event when the client accepts
+/// `text/event-stream`, and as JSON otherwise.
+pub async fn serve(listen: SocketAddr, state: ServerState) -> Result<()> {
+    let state = Arc::new(state);
+    let app = Router::new()
+        .route("/ask", post(ask))
+        .route("/sessions/{id}", get(session))
+        .route("/sessions/{id}/continue", post(continue_session))
+        .with_state(state.clone());
+
+    let listener = tokio::net::TcpListener::bind(listen)
+        .await
+        .wrap_err_with(|| format!("failed to listen on `{listen}`"))?;
+    println!("listening on http://{listen}");
+
+    let scheduler = tokio::spawn(run_schedules(state));
+    let res = axum::serve(listener, app)
+        .with_graceful_shutdown(async {
+            let _ = tokio::signal::ctrl_c().await;
+        })
+        .await
+        .wrap_err_with(|| "HTTP server failed");
+    scheduler.abort();
+    res
+}
+
+/// Runs scheduled prompts as they come due, reading the schedules anew each time so that
+/// changes made with `schedule` from another REPL apply
+async fn run_schedules(state: Arc<ServerState>) {
+    let Some(schedules) = &state.schedules else {
+        return;
+    };
+    let mut tick = tokio::time::interval(SCHEDULE_POLL_INTERVAL);
+    loop {
+        tick.tick().await;
+        if let Err(err) = run_due(&state, schedules).await {
+            eprintln!("error: {err:#}");
+        }
+    }
+}
+
+async fn run_due(state: &ServerState, schedules: &Schedules) -> Result<()> {
+    let now = chrono::Local::now();
+    for i in 0..schedules.load()?.len() {
+        // Reloaded for each run, which can take a while
+        let mut scheduled = schedules.load()?;
+        let Some(entry) = scheduled.get_mut(i) else {
+            break;
+        };
+        if entry.next_run()? > now {
+            continue;
+        }
+
+        println!(
+            "running `{} {}` scheduled {}",
+            entry.request.command(),
+            entry.request.text(),
+            entry.when
+        );
+        entry.last_run = conversation::now();
+        schedules.save(&scheduled)?;
+        let entry = &scheduled[i];
+        match run_request(state, &entry.request, entry.session.as_deref()).await {
+            Ok(completion) => {
+                if entry.session.is_none() && entry.into.is_none() {
+                    println!("{}", completion.content.trim());
+                }
+                entry.record(&completion, schedules.cipher())?;
+            },
+            Err(err) => eprintln!("error: {err:#}"),
+        }
+    }
+    Ok(())
+}
+
+/// Answers `request` with the pinned context, in the session `session` if given
+pub async fn run_request(
+    state: &ServerState,
+    request: &QueuedRequest,
+    session: Option<&str>,
+) -> Result<Completion> {
+    let claim = match session {
+        Some(id) => claim_session(state, id)?,
+        None => None,
+    };
+    let history = match session {
+        Some(id) => state
+            .sessions
+            .lock()
+            .await
+            .get(id)
+            .cloned()
+            .unwrap_or_default(),
+        None => Vec::new(),
+    };
+    let res = answer_request(state, request, history).await;
+
+    if let Some(id) = session {
+        match &res {
+            Ok(completion) => {
+                let mut sessions = state.sessions.lock().await;
+                let history = sessions.entry(id.to_owned()).or_default();
+                history.push(ChatMessage::new(Role::User, request.text().to_owned()));
+                history.push(ChatMessage::new(
+                    Role::Assistant,
+                    completion.content.clone(),
+                ));
+                release_session(state, id, claim, &history[history.len() - 2..]);
+            },
+            Err(_) => release_session(state, id, claim, &[]),
+        }
+    }
+    res
+}
+
+/// The answer to `request` after `history`
+async fn answer_request(
+    state: &ServerState,
+    request: &QueuedRequest,
+    history: Vec<ChatMessage>,
+) -> Result<Completion> {
+    match request {
+        QueuedRequest::Ask {
+            question,
+            max_words,
+        } => {
+            let context = state
+                .pins
+                .iter()
+                .cloned()
+                .chain(history)
+                .collect::<Vec<_>>();
+            let length = max_words.map_or(state.length, Length::Words);
+            state
+                .openai
+                .q_and_a(question.clone(), &context, length)
+                .await
+        },
+        QueuedRequest::Translate { text, to } => {
+            state.openai.translate(text.clone(), to.as_deref()).await
+        },
+    }
+}
+
+async fn ask(
+    State(state): State<Arc<ServerState>>,
+    headers: HeaderMap,
+    Json(req): Json<AskRequest>,
+) -> Response {
+    let length = req.max_words.map_or(state.length, Length::Words);
+    let res = state
+        .openai
+        .q_and_a(req.question, &state.pins, length)
+        .await;
+
+    respond(&headers, res)
+}
+
+async fn continue_session(
+    State(state): State<Arc<ServerState>>,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+    Json(req): Json<AskRequest>,
+) -> Response {
+    let claim = match claim_session(&state, &id) {
+        Ok(claim) => claim,
+        Err(err) => return error(StatusCode::CONFLICT, format!("{err:#}")),
+    };
+    let history = state
+        .sessions
+        .lock()
+        .await
+        .get(&id)
+        .cloned()
+        .unwrap_or_default();
+    let context = state
+        .pins
+        .iter()
+        .cloned()
+        .chain(history)
+        .collect::<Vec<_>>();
+
+    let length = req.max_words.map_or(state.length, Length::Words);
+    let res = state
+        .openai
+        .q_and_a(req.question.clone(), &context, length)
+        .await;
+
+    match &res {
+        Ok(completion) => {
+            let mut sessions = state.sessions.lock().await;
+            let history = sessions.entry(id.clone()).or_default();
+            history.push(ChatMessage::new(Role::User, req.question));
+            history.push(ChatMessage::new(
+                Role::Assistant,
+                completion.content.clone(),
+            ));
+            release_session(&state, &id, claim, &history[history.len() - 2..]);
+        },
+        Err(_) => release_session(&state, &id, claim, &[]),
+    }
+
+    respond(&headers, res)
+}
+
+/// Takes the lock of session `id` in the store, if any, for the time of a request, returning
+/// its conversation and version
+///
+/// Fails with [`Locked`] while a REPL continues the session with `store load`, so that their
+/// turns do not interleave.
+fn claim_session(state: &ServerState, id: &str) -> Result<Option<(i64, u64)>> {
+    let Some(store) = &state.store else {
+        return Ok(None);
+    };
+    let claim = store.session(id).and_then(|conversation| {
+        let version = store.lock(conversation, store::SESSION)?;
+        Ok((conversation, version))
+    });
+    match claim {
+        Ok(claim) => Ok(Some(claim)),
+        Err(err) if err.is::<Locked>() => Err(err),
+        Err(err) => {
+            tracing::warn!("failed to store session `{id}`: {err:#}");
+            Ok(None)
+        },
+    }
+}
+
+/// Adds `exchange` to session `id` in the store, if any, and releases the lock taken by
+/// [`claim_session`]
+fn release_session(
+    state: &ServerState,
+    id: &str,
+    claim: Option<(i64, u64)>,
+    exchange: &[ChatMessage],
+) {
+    let (Some(store), Some((conversation, version))) = (&state.store, claim) else {
+        return;
+    };
+    if !exchange.is_empty() {
+        if let Err(err) = store.append(conversation, version, exchange) {
+            tracing::warn!("failed to store session `{id}`: {err:#}");
+        }
+    }
+    if let Err(err) = store.unlock(conversation, store::SESSION) {
+        tracing::warn!("failed to release session `{id}`: {err:#}");
+    }
+}
+
+async fn session(State(state): State<Arc<ServerState>>, Path(id): Path<String>) -> Response {
+    match state.sessions.lock().await.get(&id) {
+        Some(history) => Json(history.clone()).into_response(),
+        None => error(StatusCode::NOT_FOUND, format!("no session `{id}`")),
+    }
+}
+
+fn respond(headers: &HeaderMap, res: Result<Completion>) -> Response {
+    let completion = match res {
+        Ok(completion) => completion,
+        Err(err) => return error(StatusCode::BAD_GATEWAY, format!("{err:#}")),
+    };
+
+    let wants_sse = headers
+        .get(header::ACCEPT)
+        .and_then(|accept| accept.to_str().ok())
+        .is_some_and(|accept| accept.contains("text/event-stream"));
+    if !wants_sse {
+        return Json(AskResponse::from(completion)).into_response();
+    }
+
+    let event = Event::default()
+        .event("answer")
+        .json_data(AskResponse::from(completion));
+    match event {
+        Ok(event) => {
+            Sse::new(futures_util::stream::iter([Ok::<_, Infallible>(event)])).into_response()
+        },
+        Err(err) => error(StatusCode::INTERNAL_SERVER_ERROR, err.to_string()),
+    }
+}
+
+fn error(status: StatusCode, error: String) -> Response {
+    (status, Json(ErrorResponse { error })).into_response()
+}
@@ -0,0 +1,102 @@
+use color_eyre::eyre::{Context, Result};
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::http::Multipart;
+
+const GITHUB_API: &str = "https://api.github.com";
+const NULL_POINTER: &str = "https://0x0.st";
+const FILE_NAME: &str = "conversation.md";
+
+/// The `[share]` section of the config, where `share` uploads conversations
+#[derive(Clone, Debug, Deserialize)]
+#[serde(tag = "service", rename_all = "lowercase")]
+pub enum ShareConfig {
+    Gist {
+        /// Token allowed to create gists, `$GITHUB_TOKEN` by default
+        token: Option<String>,
+        /// Listed on the profile of the token's owner, secret otherwise
+        #[serde(default)]
+        public: bool,
+        /// API of GitHub Enterprise instead of github.com
+        api_url: Option<String>,
+    },
+    #[serde(rename = "0x0")]
+    NullPointer {
+        /// Another instance than 0x0.st
+        url: Option<String>,
+        /// Deleted after this many hours instead of the instance's retention period
+        expires_hours: Option<u64>,
+    },
+}
+
+#[derive(Deserialize)]
+struct Gist {
+    html_url: String,
+}
+
+/// Uploads `markdown` to the service of `config` and returns its URL
+pub async fn upload(
+    client: &reqwest::Client,
+    config: &ShareConfig,
+    markdown: &str,
+) -> Result<String> {
+    match config {
+        ShareConfig::Gist {
+            token,
+            public,
+            api_url,
+        } => {
+            let token = token
+                .clone()
+                .or_else(|| std::env::var("GITHUB_TOKEN").ok())
+                .ok_or_else(|| {
+                    color_eyre::eyre::eyre!("no `token` in `[share]` and no `$GITHUB_TOKEN`")
+                })?;
+            let url = format!("{}/gists", api_url.as_deref().unwrap_or(GITHUB_API));
+            let gist = client
+                .post(&url)
+                .bearer_auth(token)
+                .header(reqwest::header::USER_AGENT, crate::CARGO_PKG_NAME)
+                .header(reqwest::header::ACCEPT, "application/vnd.github+json")
+                .json(&json!({
+                    "description": format!("Conversation shared from {}", crate::CARGO_PKG_NAME),
+                    "public": public,
+                    "files": {FILE_NAME: {"content": markdown}},
+                }))
+                .send()
+                .await
+                .wrap_err_with(|| format!("failed to post to `{url}`"))?
+                .error_for_status()
+                .wrap_err_with(|| "failed to create the gist")?
+                .json::<Gist>()
+                .await
+                .wrap_err_with(|| "failed to parse the created gist")?;
+            Ok(gist.html_url)
+        },
+        ShareConfig::NullPointer { url, expires_hours } => {
+            let url = url.as_deref().unwrap_or(NULL_POINTER);
+            let mut form =
+                Multipart::default().file("file", FILE_NAME, "text/markdown", markdown.as_bytes());
+            if let Some(hours) = expires_hours {
+                form = form.text("expires", &hours.to_string());
+            }
+            let (content_type, body) = form.finish();
+
+            let link = client
+                .post(url)
+                .header(reqwest::header::USER_AGENT, crate::CARGO_PKG_NAME)
+                .header(reqwest::header::CONTENT_TYPE, content_type)
+                .body(body)
+                .send()
+                .await
+                .wrap_err_with(|| format!("failed to post to `{url}`"))?
+                .error_for_status()
+                .wrap_err_with(|| format!("failed to upload to `{url}`"))?
+                .text()
+                .await
+                .wrap_err_with(|| format!("failed to read the answer of `{url}`"))?;
+            Ok(link.trim().to_owned())
+        },
+    }
+}
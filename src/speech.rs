@@ -0,0 +1,197 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use color_eyre::eyre::{Context, Result};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+
+use crate::openai::{OpenAI, SPEECH_SAMPLE_RATE};
+use crate::voice::{self, VoiceConfig};
+
+/// Fewest characters read aloud at once within a line, so that short sentences like `Yes.`
+/// are read with the next instead of after a pause of their own
+const MIN_SENTENCE_CHARS: usize = 40;
+const CODE_FENCE: &str = "```";
+
+/// Splits an answer streamed in pieces into sentences to read aloud, leaving out code blocks
+/// and markdown markup
+#[derive(Default)]
+pub struct Sentences {
+    /// Streamed text not read or skipped yet
+    buffer: String,
+    /// What is read next, once it is long enough or its line ends
+    sentence: String,
+    /// Whether the buffer starts in the middle of a line, which then cannot open a code block
+    mid_line: bool,
+    /// Whether the buffer is in a code block
+    code: bool,
+}
+
+impl Sentences {
+    /// Adds `delta` to the answer and returns the sentences it completed
+    pub fn push(&mut self, delta: &str) -> Vec<String> {
+        self.buffer.push_str(delta);
+        let mut sentences = Vec::new();
+        loop {
+            let Some(end) = self.buffer.find('\n') else {
+                self.partial_line(&mut sentences);
+                return sentences;
+            };
+            let line = self.buffer[..end].to_owned();
+            self.buffer.drain(..=end);
+            self.line(&line, &mut sentences);
+        }
+    }
+
+    /// The rest of the answer once it ended
+    pub fn finish(mut self) -> Option<String> {
+        let mut sentences = Vec::new();
+        let line = std::mem::take(&mut self.buffer);
+        self.line(&line, &mut sentences);
+        sentences.pop()
+    }
+
+    fn line(&mut self, line: &str, sentences: &mut Vec<String>) {
+        let mid_line = std::mem::take(&mut self.mid_line);
+        if !mid_line && line.trim_start().starts_with(CODE_FENCE) {
+            self.code = !self.code;
+            return;
+        }
+        if self.code {
+            return;
+        }
+        self.add(line);
+        self.flush(sentences);
+    }
+
+    /// Reads the complete sentences of the line being streamed, unless it may open a code block
+    fn partial_line(&mut self, sentences: &mut Vec<String>) {
+        let start = self.buffer.trim_start();
+        let fence = CODE_FENCE.starts_with(start) || start.starts_with(CODE_FENCE);
+        if self.code || (!self.mid_line && fence) {
+            return;
+        }
+        let Some(end) = last_sentence_end(&self.buffer) else {
+            return;
+        };
+        let text = self.buffer[..end].to_owned();
+        self.buffer.drain(..end);
+        self.mid_line = true;
+        self.add(&text);
+        if self.sentence.chars().count() >= MIN_SENTENCE_CHARS {
+            self.flush(sentences);
+        }
+    }
+
+    fn add(&mut self, text: &str) {
+        let text = text
+            .trim_start_matches(|c: char| c == '#' || c == '>' || c.is_whitespace())
+            .trim_start_matches("- ")
+            .replace(['*', '`'], "");
+        if !self.sentence.is_empty() && !text.is_empty() {
+            self.sentence.push(' ');
+        }
+        self.sentence.push_str(text.trim());
+    }
+
+    fn flush(&mut self, sentences: &mut Vec<String>) {
+        let sentence = std::mem::take(&mut self.sentence);
+        if sentence.chars().any(char::is_alphanumeric) {
+            sentences.push(sentence);
+        }
+    }
+}
+
+/// Byte offset after the last sentence in `text`: after a `.`, `!` or `?` followed by a space,
+/// which a decimal point is not, or after a full-width stop
+fn last_sentence_end(text: &str) -> Option<usize> {
+    let mut end = None;
+    let mut chars = text.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        let after = i + c.len_utf8();
+        match c {
+            '。' | '！' | '？' => end = Some(after),
+            '.' | '!' | '?' if chars.peek().is_some_and(|(_, next)| next.is_whitespace()) => {
+                end = Some(after);
+            },
+            _ => {},
+        }
+    }
+    end
+}
+
+/// Reads sentences aloud in order as they come, synthesizing each while the one before plays
+pub struct Speaker {
+    sentences: mpsc::UnboundedSender<String>,
+    stop: Arc<AtomicBool>,
+    task: JoinHandle<Result<()>>,
+}
+
+impl Speaker {
+    pub fn start(openai: Arc<OpenAI>, config: VoiceConfig) -> Self {
+        let (sentences, mut received) = mpsc::unbounded_channel::<String>();
+        let stop = Arc::new(AtomicBool::new(false));
+        let playing = stop.clone();
+        let task = tokio::spawn(async move {
+            let (clips, to_play) = std::sync::mpsc::channel::<Vec<u8>>();
+            let player = tokio::task::spawn_blocking(move || {
+                for clip in to_play {
+                    if playing.load(Ordering::Relaxed) {
+                        break;
+                    }
+                    voice::play(&clip, SPEECH_SAMPLE_RATE, &playing)?;
+                }
+                Ok::<_, color_eyre::Report>(())
+            });
+
+            while let Some(sentence) = received.recv().await {
+                // The player stopped early if it failed
+                if player.is_finished() {
+                    break;
+                }
+                let clip = openai
+                    .speech(&sentence, &config.speech_model, &config.speech_voice)
+                    .await?;
+                if clips.send(clip).is_err() {
+                    break;
+                }
+            }
+            drop(clips);
+            player.await.wrap_err_with(|| "speech playback panicked")?
+        });
+
+        Self {
+            sentences,
+            stop,
+            task,
+        }
+    }
+
+    pub fn say(&self, sentence: String) {
+        let _ = self.sentences.send(sentence);
+    }
+
+    /// Waits until everything said was read aloud, or stops reading at Ctrl-C
+    pub async fn finish(self) -> Result<()> {
+        let Self {
+            sentences,
+            stop,
+            mut task,
+        } = self;
+        drop(sentences);
+        tokio::select! {
+            res = &mut task => res.wrap_err_with(|| "speech panicked")?,
+            _ = tokio::signal::ctrl_c() => {
+                stop.store(true, Ordering::Relaxed);
+                task.abort();
+                Ok(())
+            },
+        }
+    }
+
+    /// Stops reading right away, leaving the rest unsaid
+    pub fn stop(self) {
+        self.stop.store(true, Ordering::Relaxed);
+        self.task.abort();
+    }
+}
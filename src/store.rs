@@ -0,0 +1,543 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use color_eyre::eyre::{Context, Result};
+use rusqlite::{params, Connection, OptionalExtension, Transaction};
+
+use crate::conversation::{self, ChatMessage, Rating};
+use crate::openai::{Role, Usage};
+use crate::recovery;
+
+const STORE_FILE: &str = ".sermaid_store.db";
+/// Holder of the locks of the REPL, see [`Store::lock`]
+pub const REPL: &str = "repl";
+/// Holder of the locks of `serve` and `daemon`
+pub const SESSION: &str = "session";
+/// How long to wait for another process writing to the store, like `serve` and the REPL at once
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS conversations (
+    id INTEGER PRIMARY KEY,
+    name TEXT,
+    -- `repl`, or `session` for the sessions of `serve` and `daemon`
+    source TEXT NOT NULL,
+    session TEXT,
+    started INTEGER NOT NULL,
+    -- Bumped by every write to its turns, to catch writes of another process
+    version INTEGER NOT NULL DEFAULT 0,
+    UNIQUE (source, session)
+);
+CREATE TABLE IF NOT EXISTS turns (
+    id INTEGER PRIMARY KEY,
+    conversation INTEGER NOT NULL REFERENCES conversations (id) ON DELETE CASCADE,
+    position INTEGER NOT NULL,
+    role TEXT NOT NULL,
+    content TEXT NOT NULL,
+    timestamp INTEGER NOT NULL,
+    model TEXT,
+    prompt_tokens INTEGER,
+    completion_tokens INTEGER,
+    finish_reason TEXT,
+    command TEXT,
+    -- JSON, as in exports
+    rating TEXT,
+    UNIQUE (conversation, position)
+);
+CREATE TABLE IF NOT EXISTS tags (
+    conversation INTEGER NOT NULL REFERENCES conversations (id) ON DELETE CASCADE,
+    tag TEXT NOT NULL,
+    PRIMARY KEY (conversation, tag)
+);
+-- Advisory locks of the processes writing to conversations
+CREATE TABLE IF NOT EXISTS locks (
+    conversation INTEGER PRIMARY KEY REFERENCES conversations (id) ON DELETE CASCADE,
+    -- `repl` or `session`
+    holder TEXT NOT NULL,
+    pid INTEGER NOT NULL,
+    acquired INTEGER NOT NULL
+);
+CREATE VIRTUAL TABLE IF NOT EXISTS turns_fts USING fts5 (
+    content, content = 'turns', content_rowid = 'id'
+);
+CREATE TRIGGER IF NOT EXISTS turns_insert AFTER INSERT ON turns BEGIN
+    INSERT INTO turns_fts (rowid, content) VALUES (new.id, new.content);
+END;
+CREATE TRIGGER IF NOT EXISTS turns_update AFTER UPDATE OF content ON turns BEGIN
+    INSERT INTO turns_fts (turns_fts, rowid, content) VALUES ('delete', old.id, old.content);
+    INSERT INTO turns_fts (rowid, content) VALUES (new.id, new.content);
+END;
+CREATE TRIGGER IF NOT EXISTS turns_delete AFTER DELETE ON turns BEGIN
+    INSERT INTO turns_fts (turns_fts, rowid, content) VALUES ('delete', old.id, old.content);
+END;
+";
+
+/// Conversations of the REPL and of the sessions of `serve` and `daemon`, kept in
+/// `~/.sermaid_store.db` to be searched and counted across sessions
+///
+/// The store is opened in WAL mode so that several sermaid processes can write to it at once.
+/// Writers take an advisory lock on a conversation with [`Store::lock`] and write to it at the
+/// version they last read, so that they neither interleave their turns nor write over newer ones.
+pub struct Store {
+    path: PathBuf,
+    conn: Mutex<Connection>,
+}
+
+/// A conversation as listed by [`Store::recent`]
+pub struct Listing {
+    pub id: i64,
+    pub name: Option<String>,
+    pub started: u64,
+    pub turns: u64,
+    pub tags: Vec<String>,
+}
+
+/// A message matching [`Store::search`]
+pub struct Hit {
+    pub conversation: i64,
+    pub name: Option<String>,
+    pub position: u64,
+    pub role: String,
+    pub timestamp: u64,
+    /// The matching words surrounded by `[` and `]`
+    pub snippet: String,
+}
+
+/// A write refused because another process holds the lock of the conversation
+#[derive(Debug)]
+pub struct Locked {
+    pub conversation: i64,
+    pub holder: String,
+    pub pid: i64,
+}
+
+impl fmt::Display for Locked {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "conversation {} is open in another sermaid ({}, pid {})",
+            self.conversation, self.holder, self.pid
+        )
+    }
+}
+
+impl std::error::Error for Locked {}
+
+/// A write refused because the conversation was written to since the writer read it
+#[derive(Debug)]
+pub struct Conflict {
+    pub conversation: i64,
+}
+
+impl fmt::Display for Conflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "conversation {} was changed by another sermaid since it was read",
+            self.conversation
+        )
+    }
+}
+
+impl std::error::Error for Conflict {}
+
+#[derive(Default)]
+pub struct Stats {
+    pub conversations: u64,
+    pub messages: u64,
+    /// Prompt and completion tokens by model
+    pub tokens: BTreeMap<String, (u64, u64)>,
+}
+
+impl Store {
+    pub fn open() -> Result<Self> {
+        let path = home::home_dir()
+            .ok_or_else(|| color_eyre::eyre::eyre!("no home directory for the store"))?
+            .join(STORE_FILE);
+        let conn = Connection::open(&path)
+            .wrap_err_with(|| format!("failed to open `{}`", path.display()))?;
+        conn.busy_timeout(BUSY_TIMEOUT)?;
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+        conn.pragma_update(None, "foreign_keys", true)?;
+        conn.execute_batch(SCHEMA)
+            .wrap_err_with(|| format!("failed to set up `{}`", path.display()))?;
+        // Stores created before conversations had versions
+        let versioned: bool = conn.query_row(
+            "SELECT COUNT(*) > 0 FROM pragma_table_info('conversations') WHERE name = 'version'",
+            [],
+            |row| row.get(0),
+        )?;
+        if !versioned {
+            conn.execute_batch(
+                "ALTER TABLE conversations ADD COLUMN version INTEGER NOT NULL DEFAULT 0",
+            )
+            .wrap_err_with(|| format!("failed to set up `{}`", path.display()))?;
+        }
+        Ok(Self {
+            path,
+            conn: Mutex::new(conn),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Starts a conversation of the REPL and returns its id
+    pub fn create(&self, name: Option<&str>) -> Result<i64> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT INTO conversations (name, source, started) VALUES (?1, 'repl', ?2)",
+            params![name, conversation::now()],
+        )
+        .wrap_err_with(|| "failed to start a conversation in the store")?;
+        Ok(conn.last_insert_rowid())
+    }
+
+    /// The conversation of session `session` of `serve` or `daemon`, started on first use
+    pub fn session(&self, session: &str) -> Result<i64> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT INTO conversations (name, source, session, started)
+             VALUES (?1, 'session', ?1, ?2)
+             ON CONFLICT (source, session) DO NOTHING",
+            params![session, conversation::now()],
+        )
+        .wrap_err_with(|| format!("failed to start session `{session}` in the store"))?;
+        conn.query_row(
+            "SELECT id FROM conversations WHERE source = 'session' AND session = ?1",
+            [session],
+            |row| row.get(0),
+        )
+        .wrap_err_with(|| format!("failed to find session `{session}` in the store"))
+    }
+
+    /// Takes the lock of `conversation` for `holder`, unless this process holds it already, and
+    /// returns its version
+    ///
+    /// Locks of processes that are no longer running are taken over.
+    pub fn lock(&self, conversation: i64, holder: &str) -> Result<u64> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        if !Self::check_lock(&tx, conversation)? {
+            tx.execute(
+                "INSERT OR REPLACE INTO locks (conversation, holder, pid, acquired)
+                 VALUES (?1, ?2, ?3, ?4)",
+                params![
+                    conversation,
+                    holder,
+                    std::process::id(),
+                    conversation::now()
+                ],
+            )?;
+        }
+        let version = Self::version(&tx, conversation)?;
+        tx.commit()
+            .wrap_err_with(|| format!("failed to write to `{}`", self.path.display()))?;
+        Ok(version)
+    }
+
+    /// Releases the lock of `conversation` if `holder` in this process took it
+    pub fn unlock(&self, conversation: i64, holder: &str) -> Result<()> {
+        self.conn
+            .lock()
+            .unwrap()
+            .execute(
+                "DELETE FROM locks WHERE conversation = ?1 AND holder = ?2 AND pid = ?3",
+                params![conversation, holder, std::process::id()],
+            )
+            .wrap_err_with(|| format!("failed to write to `{}`", self.path.display()))?;
+        Ok(())
+    }
+
+    /// Fails with [`Locked`] if another running process holds the lock of `conversation`, and
+    /// returns whether this process does
+    fn check_lock(tx: &Transaction, conversation: i64) -> Result<bool> {
+        let lock = tx
+            .query_row(
+                "SELECT holder, pid FROM locks WHERE conversation = ?1",
+                [conversation],
+                |row| Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)?)),
+            )
+            .optional()?;
+        match lock {
+            Some((_, pid)) if pid == i64::from(std::process::id()) => Ok(true),
+            Some((holder, pid)) if recovery::is_running(pid as i32) => Err(Locked {
+                conversation,
+                holder,
+                pid,
+            }
+            .into()),
+            _ => Ok(false),
+        }
+    }
+
+    fn version(tx: &Transaction, conversation: i64) -> Result<u64> {
+        tx.query_row(
+            "SELECT version FROM conversations WHERE id = ?1",
+            [conversation],
+            |row| row.get(0),
+        )
+        .optional()?
+        .ok_or_else(|| color_eyre::eyre::eyre!("no conversation {conversation} in the store"))
+    }
+
+    /// Fails with [`Locked`] or [`Conflict`] unless `conversation` is still at `version` and
+    /// free to write to, and bumps its version
+    fn bump(tx: &Transaction, conversation: i64, version: u64) -> Result<u64> {
+        Self::check_lock(tx, conversation)?;
+        if Self::version(tx, conversation)? != version {
+            return Err(Conflict { conversation }.into());
+        }
+        tx.execute(
+            "UPDATE conversations SET version = version + 1 WHERE id = ?1",
+            [conversation],
+        )?;
+        Ok(version + 1)
+    }
+
+    /// Adds `messages` after the last message of `conversation`, read at `version`, and returns
+    /// its new version
+    pub fn append(&self, conversation: i64, version: u64, messages: &[ChatMessage]) -> Result<u64> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        let version = Self::bump(&tx, conversation, version)?;
+        let next: u64 = tx.query_row(
+            "SELECT COALESCE(MAX(position) + 1, 0) FROM turns WHERE conversation = ?1",
+            [conversation],
+            |row| row.get(0),
+        )?;
+        for (position, message) in (next..).zip(messages) {
+            let rating = message
+                .rating
+                .as_ref()
+                .map(serde_json::to_string)
+                .transpose()?;
+            tx.execute(
+                "INSERT INTO turns (conversation, position, role, content, timestamp, model,
+                    prompt_tokens, completion_tokens, finish_reason, command, rating)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
+                params![
+                    conversation,
+                    position,
+                    message.role.as_str(),
+                    message.content,
+                    message.timestamp,
+                    message.model,
+                    message.usage.map(|usage| usage.prompt_tokens),
+                    message.usage.map(|usage| usage.completion_tokens),
+                    message.finish_reason,
+                    message.command,
+                    rating,
+                ],
+            )?;
+        }
+        tx.commit()
+            .wrap_err_with(|| format!("failed to write to `{}`", self.path.display()))?;
+        Ok(version)
+    }
+
+    pub fn rename(&self, conversation: i64, name: &str) -> Result<()> {
+        self.conn
+            .lock()
+            .unwrap()
+            .execute(
+                "UPDATE conversations SET name = ?2 WHERE id = ?1",
+                params![conversation, name],
+            )
+            .wrap_err_with(|| format!("failed to write to `{}`", self.path.display()))?;
+        Ok(())
+    }
+
+    /// Replaces the last answer of `conversation`, read at `version`, with `answer`, after
+    /// `good`, `bad` or `amend`, and returns its new version
+    pub fn update_last_answer(
+        &self,
+        conversation: i64,
+        version: u64,
+        answer: &ChatMessage,
+    ) -> Result<u64> {
+        let rating = answer
+            .rating
+            .as_ref()
+            .map(serde_json::to_string)
+            .transpose()?;
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        let version = Self::bump(&tx, conversation, version)?;
+        tx.execute(
+            "UPDATE turns SET content = ?2, rating = ?3
+             WHERE id = (SELECT id FROM turns WHERE conversation = ?1 AND role = 'assistant'
+                         ORDER BY position DESC LIMIT 1)",
+            params![conversation, answer.content, rating],
+        )?;
+        tx.commit()
+            .wrap_err_with(|| format!("failed to write to `{}`", self.path.display()))?;
+        Ok(version)
+    }
+
+    pub fn tag(&self, conversation: i64, tag: &str) -> Result<()> {
+        self.conn
+            .lock()
+            .unwrap()
+            .execute(
+                "INSERT OR IGNORE INTO tags (conversation, tag) VALUES (?1, ?2)",
+                params![conversation, tag],
+            )
+            .wrap_err_with(|| format!("failed to write to `{}`", self.path.display()))?;
+        Ok(())
+    }
+
+    /// The `limit` conversations started last, most recent first
+    pub fn recent(&self, limit: usize) -> Result<Vec<Listing>> {
+        let conn = self.conn.lock().unwrap();
+        let mut statement = conn.prepare(
+            "SELECT c.id, c.name, c.started, COUNT(t.id),
+                 (SELECT group_concat(tag, ',') FROM tags WHERE conversation = c.id)
+             FROM conversations c LEFT JOIN turns t ON t.conversation = c.id
+             GROUP BY c.id ORDER BY c.started DESC, c.id DESC LIMIT ?1",
+        )?;
+        let summaries = statement
+            .query_map([limit], |row| {
+                let tags: Option<String> = row.get(4)?;
+                Ok(Listing {
+                    id: row.get(0)?,
+                    name: row.get(1)?,
+                    started: row.get(2)?,
+                    turns: row.get(3)?,
+                    tags: tags
+                        .map(|tags| tags.split(',').map(str::to_owned).collect())
+                        .unwrap_or_default(),
+                })
+            })?
+            .collect::<rusqlite::Result<_>>()
+            .wrap_err_with(|| format!("failed to read `{}`", self.path.display()))?;
+        Ok(summaries)
+    }
+
+    /// Messages matching the FTS5 `query`, best matches first
+    pub fn search(&self, query: &str, tag: Option<&str>, limit: usize) -> Result<Vec<Hit>> {
+        let conn = self.conn.lock().unwrap();
+        let mut statement = conn.prepare(
+            "SELECT t.conversation, c.name, t.position, t.role, t.timestamp,
+                 snippet(turns_fts, 0, '[', ']', '…', 12)
+             FROM turns_fts
+             JOIN turns t ON t.id = turns_fts.rowid
+             JOIN conversations c ON c.id = t.conversation
+             WHERE turns_fts MATCH ?1
+                 AND (?2 IS NULL OR EXISTS
+                     (SELECT 1 FROM tags WHERE conversation = c.id AND tag = ?2))
+             ORDER BY rank LIMIT ?3",
+        )?;
+        let hits = statement
+            .query_map(params![query, tag, limit], |row| {
+                Ok(Hit {
+                    conversation: row.get(0)?,
+                    name: row.get(1)?,
+                    position: row.get(2)?,
+                    role: row.get(3)?,
+                    timestamp: row.get(4)?,
+                    snippet: row.get(5)?,
+                })
+            })?
+            .collect::<rusqlite::Result<_>>()
+            .wrap_err_with(|| format!("failed to search for `{query}`"))?;
+        Ok(hits)
+    }
+
+    /// An FTS5 query matching messages that contain all of `words`, quoted so that none is
+    /// taken for an operator of the query syntax
+    pub fn all_words(words: &[String]) -> String {
+        words
+            .iter()
+            .map(|word| format!("\"{}\"", word.replace('"', "\"\"")))
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    pub fn stats(&self) -> Result<Stats> {
+        let conn = self.conn.lock().unwrap();
+        let (conversations, messages) = conn.query_row(
+            "SELECT (SELECT COUNT(*) FROM conversations), (SELECT COUNT(*) FROM turns)",
+            [],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )?;
+        let mut statement = conn.prepare(
+            "SELECT model, SUM(prompt_tokens), SUM(completion_tokens) FROM turns
+             WHERE model IS NOT NULL AND prompt_tokens IS NOT NULL GROUP BY model",
+        )?;
+        let tokens = statement
+            .query_map([], |row| Ok((row.get(0)?, (row.get(1)?, row.get(2)?))))?
+            .collect::<rusqlite::Result<_>>()
+            .wrap_err_with(|| format!("failed to read `{}`", self.path.display()))?;
+        Ok(Stats {
+            conversations,
+            messages,
+            tokens,
+        })
+    }
+
+    /// The name and messages of `conversation`
+    pub fn load(&self, conversation: i64) -> Result<(Option<String>, Vec<ChatMessage>)> {
+        let conn = self.conn.lock().unwrap();
+        let name = conn
+            .query_row(
+                "SELECT name FROM conversations WHERE id = ?1",
+                [conversation],
+                |row| row.get(0),
+            )
+            .optional()?
+            .ok_or_else(|| {
+                color_eyre::eyre::eyre!("no conversation {conversation} in the store")
+            })?;
+
+        let mut statement = conn.prepare(
+            "SELECT role, content, timestamp, model, prompt_tokens, completion_tokens,
+                 finish_reason, command, rating
+             FROM turns WHERE conversation = ?1 ORDER BY position",
+        )?;
+        let rows = statement
+            .query_map([conversation], |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get(2)?,
+                    row.get(3)?,
+                    row.get::<_, Option<u32>>(4)?,
+                    row.get::<_, Option<u32>>(5)?,
+                    row.get(6)?,
+                    row.get(7)?,
+                    row.get::<_, Option<String>>(8)?,
+                ))
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()
+            .wrap_err_with(|| format!("failed to read `{}`", self.path.display()))?;
+
+        let mut messages = Vec::with_capacity(rows.len());
+        for (role, content, timestamp, model, prompt, completion, finish_reason, command, rating) in
+            rows
+        {
+            let role = serde_json::from_value::<Role>(serde_json::Value::String(role))?;
+            let rating = rating
+                .map(|rating| serde_json::from_str::<Rating>(&rating))
+                .transpose()?;
+            messages.push(ChatMessage {
+                timestamp,
+                model,
+                usage: prompt.zip(completion).map(|(prompt, completion)| Usage {
+                    prompt_tokens: prompt,
+                    completion_tokens: completion,
+                    total_tokens: prompt + completion,
+                }),
+                finish_reason,
+                command,
+                rating,
+                ..ChatMessage::new(role, content)
+            });
+        }
+        Ok((name, messages))
+    }
+}
@@ -0,0 +1,94 @@
+use std::io::{IsTerminal, Write};
+use std::sync::{Arc, Mutex, OnceLock, Weak};
+
+use indicatif::ProgressBar;
+
+/// Spinners that may be on screen, cleared before a panic message is printed
+static BARS: Mutex<Vec<Weak<ProgressBar>>> = Mutex::new(Vec::new());
+
+#[cfg(unix)]
+static MODES: OnceLock<libc::termios> = OnceLock::new();
+
+/// Makes panics that take the process down leave the terminal as it was at startup, with no
+/// spinner, raw mode or colors left over
+pub fn install_panic_hook() {
+    save_modes();
+    let previous = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        // Panics elsewhere only end their task unless they abort, so the REPL goes on
+        if cfg!(panic = "abort") || std::thread::current().name() == Some("main") {
+            restore();
+        }
+        previous(info);
+    }));
+}
+
+/// Tracks `bar` so that a panic clears it
+pub fn track(bar: &Arc<ProgressBar>) {
+    if let Ok(mut bars) = BARS.lock() {
+        bars.retain(|bar| bar.strong_count() > 0);
+        bars.push(Arc::downgrade(bar));
+    }
+}
+
+fn restore() {
+    let mut spinning = false;
+    // The panicking thread may hold the lock, so only try it
+    if let Ok(bars) = BARS.try_lock() {
+        for bar in bars.iter().filter_map(Weak::upgrade) {
+            spinning |= !bar.is_finished();
+            // Stops the ticks, though indicatif draws nothing on a panicking thread
+            bar.finish_and_clear();
+        }
+    }
+    restore_modes();
+
+    let mut stderr = std::io::stderr();
+    if stderr.is_terminal() {
+        if spinning {
+            let _ = write!(stderr, "\r\x1b[2K");
+        }
+        // Reset colors and show the cursor
+        let _ = write!(stderr, "\x1b[0m\x1b[?25h");
+        let _ = stderr.flush();
+    }
+}
+
+#[cfg(unix)]
+fn save_modes() {
+    // SAFETY: an all-zero termios is valid, and tcgetattr fills it or fails
+    let mut modes = unsafe { std::mem::zeroed::<libc::termios>() };
+    // SAFETY: `modes` is valid for writes
+    if unsafe { libc::tcgetattr(libc::STDIN_FILENO, &mut modes) } == 0 {
+        let _ = MODES.set(modes);
+    }
+}
+
+#[cfg(unix)]
+fn restore_modes() {
+    if let Some(modes) = MODES.get() {
+        // SAFETY: `modes` was filled by tcgetattr
+        unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, modes) };
+    }
+}
+
+#[cfg(not(unix))]
+fn save_modes() {}
+
+#[cfg(not(unix))]
+fn restore_modes() {}
+
+/// Rows of the terminal showing stderr, where spinners are drawn
+#[cfg(unix)]
+pub fn rows() -> Option<usize> {
+    // SAFETY: an all-zero winsize is valid, and the ioctl fills it or fails
+    let mut size = unsafe { std::mem::zeroed::<libc::winsize>() };
+    // SAFETY: `size` is valid for writes
+    let ok = unsafe { libc::ioctl(libc::STDERR_FILENO, libc::TIOCGWINSZ, &mut size) } == 0;
+    (ok && size.ws_row > 0).then_some(usize::from(size.ws_row))
+}
+
+#[cfg(not(unix))]
+pub fn rows() -> Option<usize> {
+    None
+}
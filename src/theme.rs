@@ -0,0 +1,159 @@
+use std::str::FromStr;
+
+use serde::Deserialize;
+
+const RESET: &str = "\x1b[0m";
+const RED: Color = Color(1);
+const GREEN: Color = Color(2);
+const BLUE: Color = Color(4);
+const BRIGHT_RED: Color = Color(9);
+const BRIGHT_GREEN: Color = Color(10);
+const BRIGHT_CYAN: Color = Color(14);
+const COLOR_NAMES: &[&str] = &[
+    "black", "red", "green", "yellow", "blue", "magenta", "cyan", "white",
+];
+
+/// The `[theme]` section of the config, overriding parts of a built-in theme
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct ThemeConfig {
+    base: Base,
+    prompt: Option<Color>,
+    user: Option<Color>,
+    assistant: Option<Color>,
+    error: Option<Color>,
+    spinner: Option<SpinnerStyle>,
+}
+
+/// Built-in themes, for dark and light terminal backgrounds
+#[derive(Clone, Copy, Debug, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Base {
+    #[default]
+    Dark,
+    Light,
+}
+
+/// A terminal color: `red`, `bright-red`, ... or a 256-color index
+#[derive(Clone, Copy, Debug, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Color(u8);
+
+impl Color {
+    fn escape(self) -> String {
+        format!("\x1b[38;5;{}m", self.0)
+    }
+}
+
+impl FromStr for Color {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if let Ok(index) = s.parse() {
+            return Ok(Self(index));
+        }
+
+        let (name, offset) = match s.strip_prefix("bright-") {
+            Some(name) => (name, 8),
+            None => (s, 0),
+        };
+        COLOR_NAMES
+            .iter()
+            .position(|&color| color == name)
+            .map(|index| Self(index as u8 + offset))
+            .ok_or_else(|| {
+                format!(
+                    "invalid color `{s}`, expected one of {}, bright-<color> or 0-255",
+                    COLOR_NAMES.join(", ")
+                )
+            })
+    }
+}
+
+impl TryFrom<String> for Color {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+/// Animation of the spinner shown while waiting for answers
+#[derive(Clone, Copy, Debug, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SpinnerStyle {
+    Braille,
+    Dots,
+    Line,
+    Arc,
+}
+
+impl SpinnerStyle {
+    /// Frames of the animation, the last one shown when done
+    pub fn tick_chars(self) -> &'static str {
+        match self {
+            SpinnerStyle::Braille => "⠁⠂⠄⡀⢀⠠⠐⠈ ",
+            SpinnerStyle::Dots => "⠋⠙⠹⠸⠼⠴⠦⠧⠇⠏ ",
+            SpinnerStyle::Line => "-\\|/ ",
+            SpinnerStyle::Arc => "◜◠◝◞◡◟ ",
+        }
+    }
+}
+
+/// Colors of the REPL, resolved from the config and painting nothing when color is off
+pub struct Theme {
+    pub prompt: Option<Color>,
+    pub user: Option<Color>,
+    pub assistant: Option<Color>,
+    pub error: Option<Color>,
+    pub spinner: SpinnerStyle,
+    /// Syntax highlighting theme of code blocks, unless `highlight_theme` is set
+    pub highlight: &'static str,
+}
+
+impl Theme {
+    pub fn new(config: &ThemeConfig, color: bool) -> Self {
+        let base = match config.base {
+            Base::Dark => Self {
+                prompt: Some(BRIGHT_GREEN),
+                user: Some(BRIGHT_CYAN),
+                assistant: None,
+                error: Some(BRIGHT_RED),
+                spinner: SpinnerStyle::Braille,
+                highlight: "base16-ocean.dark",
+            },
+            Base::Light => Self {
+                prompt: Some(GREEN),
+                user: Some(BLUE),
+                assistant: None,
+                error: Some(RED),
+                spinner: SpinnerStyle::Braille,
+                highlight: "InspiredGitHub",
+            },
+        };
+        let color = |config: Option<Color>, base: Option<Color>| config.or(base).filter(|_| color);
+
+        Self {
+            prompt: color(config.prompt, base.prompt),
+            user: color(config.user, base.user),
+            assistant: color(config.assistant, base.assistant),
+            error: color(config.error, base.error),
+            spinner: config.spinner.unwrap_or(base.spinner),
+            highlight: base.highlight,
+        }
+    }
+
+    /// Wraps `text` in the escape codes of `color`, if any
+    pub fn paint(color: Option<Color>, text: &str) -> String {
+        match color {
+            Some(color) => format!("{}{text}{RESET}", color.escape()),
+            None => text.to_owned(),
+        }
+    }
+
+    /// Escape code that starts text in `color`, to resume it after other escape codes
+    pub fn start(color: Option<Color>) -> String {
+        color.map(Color::escape).unwrap_or_default()
+    }
+}
@@ -0,0 +1,89 @@
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+/// Saves the window title on the title stack of xterm-compatible terminals
+const PUSH_TITLE: &str = "\x1b[22;2t";
+/// Restores the window title saved by [`PUSH_TITLE`]
+const POP_TITLE: &str = "\x1b[23;2t";
+
+/// The title of the terminal window, or of the tmux pane when running in tmux
+///
+/// Windows get their title with OSC 2. tmux pane titles are set with `select-pane -T`, which
+/// unlike OSC 2 does not depend on the `allow-set-title` option.
+pub struct TerminalTitle {
+    /// The tmux pane sermaid runs in, from `$TMUX_PANE`
+    pane: Option<String>,
+    /// Title of the pane before sermaid set it, put back by [`TerminalTitle::restore`]
+    previous: Option<String>,
+    /// The title set last, not set again until it changes
+    current: String,
+}
+
+impl TerminalTitle {
+    pub fn new() -> Self {
+        let pane = std::env::var("TMUX_PANE")
+            .ok()
+            .filter(|_| std::env::var_os("TMUX").is_some());
+        let previous = pane
+            .as_deref()
+            .and_then(|pane| tmux(&["display-message", "-p", "-t", pane, "#{pane_title}"]));
+        if pane.is_none() {
+            print_escape(PUSH_TITLE);
+        }
+        Self {
+            pane,
+            previous,
+            current: String::new(),
+        }
+    }
+
+    pub fn set(&mut self, title: &str) {
+        // Control characters in conversation names would end the escape sequence early
+        let title = title
+            .chars()
+            .filter(|c| !c.is_control())
+            .collect::<String>();
+        if title == self.current {
+            return;
+        }
+        match &self.pane {
+            Some(pane) => {
+                tmux(&["select-pane", "-t", pane, "-T", &title]);
+            },
+            None => print_escape(&format!("\x1b]2;{title}\x07")),
+        }
+        self.current = title;
+    }
+
+    /// Puts back the title the terminal or pane had before sermaid started
+    pub fn restore(&self) {
+        match (&self.pane, &self.previous) {
+            (Some(pane), Some(previous)) => {
+                tmux(&["select-pane", "-t", pane, "-T", previous]);
+            },
+            (Some(_), None) => {},
+            (None, _) => print_escape(POP_TITLE),
+        }
+    }
+}
+
+fn print_escape(sequence: &str) {
+    let mut stdout = std::io::stdout();
+    let _ = write!(stdout, "{sequence}");
+    let _ = stdout.flush();
+}
+
+/// Runs tmux with `args`, returning what it printed if it succeeded
+fn tmux(args: &[&str]) -> Option<String> {
+    let output = Command::new("tmux")
+        .args(args)
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()
+        .ok()?;
+    output.status.success().then(|| {
+        String::from_utf8_lossy(&output.stdout)
+            .trim_end()
+            .to_owned()
+    })
+}
@@ -0,0 +1,130 @@
+use std::fmt;
+
+use tiktoken_rs::CoreBPE;
+
+use crate::footer;
+
+/// Model name prefixes and the tokenizer family of the models they name, tried in order
+const FAMILIES: &[(&str, Family)] = &[
+    ("gpt-4o", Family::O200k),
+    ("gpt-4.1", Family::O200k),
+    ("gpt-4.5", Family::O200k),
+    ("gpt-5", Family::O200k),
+    ("o1", Family::O200k),
+    ("o3", Family::O200k),
+    ("o4", Family::O200k),
+    ("gpt-4", Family::Cl100k),
+    ("gpt-3.5", Family::Cl100k),
+    ("text-embedding-", Family::Cl100k),
+    ("llama", Family::Llama),
+    ("meta-llama/", Family::Llama),
+    ("codellama", Family::Llama),
+];
+
+/// Tokens a model reads at once, matched by the longest model name prefix
+const CONTEXT_WINDOWS: &[(&str, usize)] = &[
+    ("codestral", 256_000),
+    ("deepseek-", 64_000),
+    ("gemma2-", 8_192),
+    ("gpt-3.5-turbo", 16_385),
+    ("gpt-4", 8_192),
+    ("gpt-4-1106-preview", 128_000),
+    ("gpt-4-turbo", 128_000),
+    ("gpt-4o", 128_000),
+    ("llama-3.", 128_000),
+    ("mistral-", 128_000),
+    ("open-mistral-nemo", 128_000),
+];
+
+/// Context window of `model`, if it is in the table
+pub fn context_window(model: &str) -> Option<usize> {
+    CONTEXT_WINDOWS
+        .iter()
+        .filter(|(prefix, _)| model.starts_with(prefix))
+        .max_by_key(|(prefix, _)| prefix.len())
+        .map(|&(_, tokens)| tokens)
+}
+
+/// Tokenizers shared by families of models
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Family {
+    O200k,
+    Cl100k,
+    /// Llama 3, whose vocabulary extends cl100k's and is not bundled, so counted with cl100k
+    Llama,
+}
+
+impl Family {
+    fn bpe(self) -> &'static CoreBPE {
+        match self {
+            Family::O200k => tiktoken_rs::o200k_base_singleton(),
+            Family::Cl100k | Family::Llama => tiktoken_rs::cl100k_base_singleton(),
+        }
+    }
+}
+
+impl fmt::Display for Family {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Family::O200k => "o200k",
+            Family::Cl100k => "cl100k",
+            Family::Llama => "llama",
+        })
+    }
+}
+
+/// Counts tokens the way a model does, or approximates them for models of no known family
+#[derive(Clone, Copy, Debug)]
+pub struct Tokenizer {
+    family: Option<Family>,
+}
+
+/// Looks up the tokenizer of `model` by its name
+pub fn for_model(model: &str) -> Tokenizer {
+    let model = model.to_lowercase();
+    let family = FAMILIES
+        .iter()
+        .find(|(prefix, _)| model.starts_with(prefix))
+        .map(|&(_, family)| family);
+    Tokenizer { family }
+}
+
+impl Tokenizer {
+    pub fn count(&self, text: &str) -> usize {
+        match self.family {
+            Some(family) => family.bpe().encode_with_special_tokens(text).len(),
+            None => footer::estimate_tokens(text),
+        }
+    }
+
+    /// Whether counts are what the model sees rather than an approximation
+    pub fn is_exact(&self) -> bool {
+        matches!(self.family, Some(Family::O200k | Family::Cl100k))
+    }
+
+    /// Cuts `text` down to at most `max_tokens`, returning whether anything was cut
+    pub fn truncate(&self, text: &mut String, max_tokens: usize) -> bool {
+        if self.count(text) <= max_tokens {
+            return false;
+        }
+
+        let boundaries = text
+            .char_indices()
+            .map(|(i, _)| i)
+            .chain([text.len()])
+            .collect::<Vec<_>>();
+        let fits = boundaries.partition_point(|&end| self.count(&text[..end]) <= max_tokens);
+        text.truncate(boundaries[fits.saturating_sub(1)]);
+        true
+    }
+}
+
+impl fmt::Display for Tokenizer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.family {
+            Some(family) if self.is_exact() => write!(f, "{family}"),
+            Some(family) => write!(f, "{family}, approximated"),
+            None => f.write_str("unknown tokenizer, approximated"),
+        }
+    }
+}
@@ -0,0 +1,51 @@
+use std::borrow::Cow;
+use std::path::Path;
+
+use color_eyre::eyre::Result;
+
+/// The middleware chain questions pass through before being sent, and answers pass through
+/// before being shown
+#[derive(Default)]
+pub struct Transformers {
+    #[cfg(feature = "wasm-plugins")]
+    wasm: Vec<crate::wasm::Transformer>,
+}
+
+impl Transformers {
+    /// Loads every `*.wasm` transformer in `dir`
+    #[cfg(feature = "wasm-plugins")]
+    pub fn load(dir: &Path) -> Result<Self> {
+        Ok(Self {
+            wasm: crate::wasm::Transformer::load_dir(dir)?,
+        })
+    }
+
+    #[cfg(not(feature = "wasm-plugins"))]
+    pub fn load(dir: &Path) -> Result<Self> {
+        color_eyre::eyre::bail!(
+            "WASM plugins in `{}` need sermaid built with the `wasm-plugins` feature",
+            dir.display()
+        );
+    }
+
+    pub fn pre_prompt(&self, question: String) -> Result<String> {
+        #[cfg(feature = "wasm-plugins")]
+        let question = self
+            .wasm
+            .iter()
+            .try_fold(question, |question, transformer| {
+                transformer.pre_prompt(question)
+            })?;
+
+        Ok(question)
+    }
+
+    pub fn post_answer(&self, answer: Cow<'static, str>) -> Result<Cow<'static, str>> {
+        #[cfg(feature = "wasm-plugins")]
+        let answer = self.wasm.iter().try_fold(answer, |answer, transformer| {
+            transformer.post_answer(answer.into_owned()).map(Cow::from)
+        })?;
+
+        Ok(answer)
+    }
+}
@@ -0,0 +1,246 @@
+use std::collections::BTreeMap;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::Arc;
+
+use color_eyre::eyre::{Context, Result};
+use regex::Regex;
+
+use crate::diagnostic::{self, Diagnostic};
+use crate::encryption::{self, Cipher};
+use crate::mock;
+
+const CACHE_FILE: &str = ".sermaid_triage.json";
+const CLIPPY_ARGS: &[&str] = &["clippy", "--all-targets", "--message-format=json"];
+const TEST_ARGS: &[&str] = &["test", "--no-fail-fast", "--message-format=json"];
+
+/// What failed, in the order failures are triaged
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Kind {
+    Build,
+    Test,
+    Lint,
+}
+
+impl Kind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Build => "build",
+            Self::Test => "test",
+            Self::Lint => "lint",
+        }
+    }
+}
+
+pub struct Failure {
+    pub kind: Kind,
+    pub diagnostic: Diagnostic,
+}
+
+impl Failure {
+    /// The first line of the message
+    pub fn title(&self) -> &str {
+        self.diagnostic.message.lines().next().unwrap_or_default()
+    }
+
+    /// The message asking for a fix plan, with the source around the failure if it could be
+    /// read
+    pub fn request(&self) -> String {
+        let source = self
+            .diagnostic
+            .span
+            .as_ref()
+            .and_then(|span| span.excerpt().ok())
+            .unwrap_or_default();
+        format!(
+            "{} 失败：\n\n```\n{}\n```\n\n{source}",
+            self.kind.as_str(),
+            self.diagnostic.message.trim_end()
+        )
+    }
+}
+
+/// Runs `cargo clippy` and `cargo test` in the current directory, or only one of them, and
+/// returns their failures, build errors first, then failed tests, then lints
+pub fn collect(clippy: bool, test: bool) -> Result<Vec<Failure>> {
+    let mut failures = Vec::new();
+    if clippy {
+        let output = cargo(CLIPPY_ARGS)?;
+        let diagnostics = diagnostic::cargo_diagnostics(&output, &["error", "warning"]);
+        for diagnostic in diagnostics.into_iter().flatten() {
+            // Summaries like `N warnings emitted` point nowhere
+            if diagnostic.span.is_none() {
+                continue;
+            }
+            let kind = if diagnostic.message.starts_with("error") {
+                Kind::Build
+            } else {
+                Kind::Lint
+            };
+            failures.push(Failure { kind, diagnostic });
+        }
+    }
+    if test {
+        let output = cargo(TEST_ARGS)?;
+        let errors = diagnostic::cargo_diagnostics(&output, &["error"]);
+        for diagnostic in errors.into_iter().flatten() {
+            if diagnostic.span.is_some() {
+                failures.push(Failure {
+                    kind: Kind::Build,
+                    diagnostic,
+                });
+            }
+        }
+        for message in failed_tests(&output) {
+            failures.push(Failure {
+                kind: Kind::Test,
+                diagnostic: Diagnostic {
+                    span: diagnostic::locate(&message),
+                    message,
+                },
+            });
+        }
+    }
+
+    // Both commands build the crate and report the same errors
+    let mut seen = Vec::new();
+    failures.retain(|failure| {
+        let new = !seen.contains(&failure.diagnostic.message);
+        seen.push(failure.diagnostic.message.clone());
+        new
+    });
+    failures.sort_by_key(|failure| failure.kind);
+    Ok(failures)
+}
+
+/// The stdout of `cargo` with `args`, which fails in the cases triaged but should print its
+/// JSON messages anyway
+fn cargo(args: &[&str]) -> Result<String> {
+    let output = Command::new("cargo")
+        .args(args)
+        // Backtraces would make failures look new on every run
+        .env("RUST_BACKTRACE", "0")
+        .output()
+        .wrap_err_with(|| format!("failed to run `cargo {}`", args.join(" ")))?;
+    let stdout = String::from_utf8_lossy(&output.stdout).into_owned();
+    if !output.status.success() && diagnostic::cargo_diagnostics(&stdout, &[]).is_none() {
+        color_eyre::eyre::bail!(
+            "`cargo {}` failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(stdout)
+}
+
+/// The sections libtest prints for each failed test, starting with `---- <name> stdout ----`,
+/// without the ids of the threads that panicked, which change on every run
+fn failed_tests(output: &str) -> Vec<String> {
+    let thread_id = Regex::new(r"^(thread '[^']*') \(\d+\)").unwrap();
+    let mut failed = Vec::new();
+    let mut current: Option<String> = None;
+    for line in output.lines() {
+        let header = line.starts_with("---- ") && line.ends_with(" ----");
+        if header || line == "failures:" {
+            failed.extend(current.take());
+        }
+        if header {
+            current = Some(format!("{line}\n"));
+        } else if let Some(current) = &mut current {
+            if !line.starts_with("note: run with `RUST_BACKTRACE=1`") {
+                current.push_str(&thread_id.replace(line, "$1"));
+                current.push('\n');
+            }
+        }
+    }
+    failed.extend(current);
+    failed
+        .into_iter()
+        .map(|failure| failure.trim_end().to_owned())
+        .collect()
+}
+
+/// Fix plans by the hash of the request that asked for them, kept in `~/.sermaid_triage.json`
+/// so that failures that did not change are not asked about again
+pub struct Cache {
+    path: PathBuf,
+    cipher: Option<Arc<Cipher>>,
+    plans: BTreeMap<String, String>,
+}
+
+impl Cache {
+    /// The cache in the home directory, encrypted like conversations if a `cipher` is given
+    pub fn load(cipher: Option<Arc<Cipher>>) -> Result<Self> {
+        let path = home::home_dir()
+            .ok_or_else(|| color_eyre::eyre::eyre!("no home directory for the triage cache"))?
+            .join(CACHE_FILE);
+        let mut contents = match std::fs::read(&path) {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == ErrorKind::NotFound => {
+                return Ok(Self {
+                    path,
+                    cipher,
+                    plans: BTreeMap::new(),
+                })
+            },
+            Err(err) => {
+                return Err(err).wrap_err_with(|| format!("failed to read `{}`", path.display()))
+            },
+        };
+        if encryption::is_encrypted(&contents) {
+            let cipher = cipher.as_ref().ok_or_else(|| {
+                color_eyre::eyre::eyre!(
+                    "`{}` is encrypted but no `[encryption]` is configured",
+                    path.display()
+                )
+            })?;
+            contents = cipher
+                .decrypt(&contents)
+                .wrap_err_with(|| format!("failed to decrypt `{}`", path.display()))?;
+        }
+
+        let plans = serde_json::from_slice(&contents)
+            .wrap_err_with(|| format!("failed to parse `{}`", path.display()))?;
+        Ok(Self {
+            path,
+            cipher,
+            plans,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn get(&self, request: &str) -> Option<&str> {
+        self.plans.get(&key(request)).map(String::as_str)
+    }
+
+    pub fn insert(&mut self, request: &str, plan: String) {
+        self.plans.insert(key(request), plan);
+    }
+
+    /// Forgets the plans for anything but `requests`
+    pub fn retain(&mut self, requests: &[String]) {
+        let keys = requests
+            .iter()
+            .map(|request| key(request))
+            .collect::<Vec<_>>();
+        self.plans.retain(|key, _| keys.contains(key));
+    }
+
+    pub fn save(&self) -> Result<()> {
+        let mut contents = serde_json::to_vec_pretty(&self.plans)
+            .wrap_err_with(|| "failed to serialize the triage cache")?;
+        if let Some(cipher) = &self.cipher {
+            contents = cipher.encrypt(&contents)?;
+        }
+        std::fs::write(&self.path, contents)
+            .wrap_err_with(|| format!("failed to write `{}`", self.path.display()))
+    }
+}
+
+fn key(request: &str) -> String {
+    format!("{:016x}", mock::prompt_hash(request))
+}
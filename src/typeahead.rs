@@ -0,0 +1,76 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use indicatif::ProgressBar;
+use tokio::task::JoinHandle;
+
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Commands typed while a request is pending, queued to run after it
+pub struct TypeAhead {
+    stop: Arc<AtomicBool>,
+    reader: JoinHandle<Vec<String>>,
+}
+
+impl TypeAhead {
+    /// Starts reading lines from the terminal, echoing each one above `bar` after `prompt`
+    pub fn start(bar: Arc<ProgressBar>, prompt: String) -> Self {
+        let stop = Arc::new(AtomicBool::new(false));
+        let reader = tokio::task::spawn_blocking({
+            let stop = stop.clone();
+            move || read_lines(&stop, |line| bar.println(format!("{prompt}{line}")))
+        });
+        Self { stop, reader }
+    }
+
+    /// Stops reading and returns the lines typed so far
+    pub async fn stop(self) -> Vec<String> {
+        self.stop.store(true, Ordering::Relaxed);
+        self.reader.await.unwrap_or_default()
+    }
+}
+
+/// Reads whole lines from stdin until `stop` is set, without blocking on a line that is never
+/// typed, so nothing is left to steal input from the line editor afterwards
+#[cfg(unix)]
+fn read_lines(stop: &AtomicBool, echo: impl Fn(&str)) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut pending = Vec::new();
+    while !stop.load(Ordering::Relaxed) {
+        let mut fd = libc::pollfd {
+            fd: libc::STDIN_FILENO,
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        // SAFETY: `fd` is a single valid pollfd that outlives the call
+        let ready = unsafe { libc::poll(&mut fd, 1, POLL_INTERVAL.as_millis() as libc::c_int) };
+        if ready <= 0 || fd.revents & libc::POLLIN == 0 {
+            continue;
+        }
+
+        let mut buf = [0u8; 4096];
+        // SAFETY: `buf` is valid for writes of its whole length
+        let read = unsafe { libc::read(libc::STDIN_FILENO, buf.as_mut_ptr().cast(), buf.len()) };
+        if read <= 0 {
+            break;
+        }
+        pending.extend_from_slice(&buf[..read as usize]);
+
+        // The terminal is in line mode while a request is pending, so reads end at newlines
+        while let Some(end) = pending.iter().position(|&b| b == b'\n') {
+            let line = String::from_utf8_lossy(&pending[..end]).trim().to_owned();
+            pending.drain(..=end);
+            if !line.is_empty() {
+                echo(&line);
+                lines.push(line);
+            }
+        }
+    }
+    lines
+}
+
+#[cfg(not(unix))]
+fn read_lines(_: &AtomicBool, _: impl Fn(&str)) -> Vec<String> {
+    Vec::new()
+}
@@ -0,0 +1,347 @@
+use std::sync::atomic::AtomicBool;
+
+use color_eyre::eyre::Result;
+use serde::Deserialize;
+
+/// The `[voice]` section of the config, for `listen` and `ask --speak`
+#[derive(Clone, Deserialize)]
+#[serde(default)]
+pub struct VoiceConfig {
+    /// Model of the provider's `/audio/transcriptions` endpoint
+    pub model: String,
+    /// Language spoken, as an ISO-639-1 code like `zh`, detected by the model otherwise
+    pub language: Option<String>,
+    /// Seconds of silence after speaking that end the recording
+    pub silence_secs: f32,
+    /// Longest recording in seconds
+    pub max_secs: u64,
+    /// Model of the provider's `/audio/speech` endpoint
+    pub speech_model: String,
+    /// Voice that reads answers aloud, like `alloy` or `nova`
+    pub speech_voice: String,
+}
+
+impl Default for VoiceConfig {
+    fn default() -> Self {
+        Self {
+            model: "whisper-1".to_owned(),
+            language: None,
+            silence_secs: 1.5,
+            max_secs: 60,
+            speech_model: "tts-1".to_owned(),
+            speech_voice: "alloy".to_owned(),
+        }
+    }
+}
+
+/// Records from the default microphone until Enter is pressed, `silence_secs` pass in silence
+/// after speaking, or `max_secs` pass, and returns the recording as WAV
+#[cfg(feature = "voice")]
+pub fn record(config: &VoiceConfig) -> Result<Vec<u8>> {
+    microphone::record(config)
+}
+
+#[cfg(not(feature = "voice"))]
+pub fn record(_config: &VoiceConfig) -> Result<Vec<u8>> {
+    color_eyre::eyre::bail!("`listen` needs sermaid built with the `voice` feature");
+}
+
+/// Plays 16-bit mono `pcm` at `sample_rate` on the default speakers until it ends or `stop` is
+/// set
+#[cfg(feature = "voice")]
+pub fn play(pcm: &[u8], sample_rate: u32, stop: &AtomicBool) -> Result<()> {
+    speakers::play(pcm, sample_rate, stop)
+}
+
+#[cfg(not(feature = "voice"))]
+pub fn play(_pcm: &[u8], _sample_rate: u32, _stop: &AtomicBool) -> Result<()> {
+    color_eyre::eyre::bail!("`ask --speak` needs sermaid built with the `voice` feature");
+}
+
+#[cfg(feature = "voice")]
+mod microphone {
+    use std::sync::{Arc, Mutex};
+    use std::time::{Duration, Instant};
+
+    use color_eyre::eyre::{Context, Result};
+    use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+    use cpal::Sample;
+
+    use super::VoiceConfig;
+
+    /// Root mean square above which a chunk of samples counts as speech
+    const SPEECH_LEVEL: f32 = 0.02;
+    const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+    #[derive(Default)]
+    struct Recording {
+        /// Mono samples
+        samples: Vec<f32>,
+        /// When speech was last heard, if it was
+        last_speech: Option<Instant>,
+    }
+
+    pub fn record(config: &VoiceConfig) -> Result<Vec<u8>> {
+        let device = cpal::default_host()
+            .default_input_device()
+            .ok_or_else(|| color_eyre::eyre::eyre!("no microphone found"))?;
+        let supported = device
+            .default_input_config()
+            .wrap_err_with(|| "failed to get the microphone's config")?;
+        let sample_rate = supported.sample_rate().0;
+        let format = supported.sample_format();
+        let stream_config: cpal::StreamConfig = supported.into();
+
+        let recording = Arc::new(Mutex::new(Recording::default()));
+        let stream = match format {
+            cpal::SampleFormat::F32 => input_stream::<f32>(&device, &stream_config, &recording),
+            cpal::SampleFormat::I16 => input_stream::<i16>(&device, &stream_config, &recording),
+            cpal::SampleFormat::U16 => input_stream::<u16>(&device, &stream_config, &recording),
+            format => color_eyre::eyre::bail!("unsupported microphone sample format {format}"),
+        }?;
+        stream
+            .play()
+            .wrap_err_with(|| "failed to start recording")?;
+        println!("Listening, press Enter or pause to stop");
+
+        let silence = Duration::from_secs_f32(config.silence_secs);
+        let started = Instant::now();
+        loop {
+            if enter_pressed(POLL_INTERVAL) ||
+                started.elapsed() >= Duration::from_secs(config.max_secs)
+            {
+                break;
+            }
+            let last_speech = recording
+                .lock()
+                .ok()
+                .and_then(|recording| recording.last_speech);
+            if last_speech.is_some_and(|last_speech| last_speech.elapsed() >= silence) {
+                break;
+            }
+        }
+        drop(stream);
+
+        let recording = recording
+            .lock()
+            .map_err(|_| color_eyre::eyre::eyre!("the recording was poisoned"))?;
+        if recording.last_speech.is_none() {
+            color_eyre::eyre::bail!("heard nothing, is the microphone muted?");
+        }
+        Ok(wav(&recording.samples, sample_rate))
+    }
+
+    fn input_stream<T>(
+        device: &cpal::Device,
+        config: &cpal::StreamConfig,
+        recording: &Arc<Mutex<Recording>>,
+    ) -> Result<cpal::Stream>
+    where
+        T: cpal::SizedSample,
+        f32: cpal::FromSample<T>,
+    {
+        let channels = usize::from(config.channels.max(1));
+        let recording = recording.clone();
+        device
+            .build_input_stream(
+                config,
+                move |data: &[T], _: &cpal::InputCallbackInfo| {
+                    let samples = data
+                        .chunks(channels)
+                        .map(|frame| {
+                            frame
+                                .iter()
+                                .map(|&sample| sample.to_sample::<f32>())
+                                .sum::<f32>() /
+                                frame.len() as f32
+                        })
+                        .collect::<Vec<_>>();
+                    if samples.is_empty() {
+                        return;
+                    }
+                    let level = (samples.iter().map(|sample| sample * sample).sum::<f32>() /
+                        samples.len() as f32)
+                        .sqrt();
+                    if let Ok(mut recording) = recording.lock() {
+                        if level >= SPEECH_LEVEL {
+                            recording.last_speech = Some(Instant::now());
+                        }
+                        recording.samples.extend(samples);
+                    }
+                },
+                |err| tracing::warn!("microphone error: {err}"),
+                None,
+            )
+            .wrap_err_with(|| "failed to open the microphone")
+    }
+
+    /// Whether a line was entered on stdin within `timeout`, consuming it if so
+    ///
+    /// Polling instead of reading in a thread leaves stdin alone once recording stops.
+    #[cfg(unix)]
+    fn enter_pressed(timeout: Duration) -> bool {
+        let mut fd = libc::pollfd {
+            fd: libc::STDIN_FILENO,
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        let timeout = i32::try_from(timeout.as_millis()).unwrap_or(i32::MAX);
+        // SAFETY: `fd` is a valid `pollfd` for the duration of the call
+        let ready = unsafe { libc::poll(&mut fd, 1, timeout) };
+        if ready <= 0 {
+            return false;
+        }
+        let mut line = String::new();
+        let _ = std::io::stdin().read_line(&mut line);
+        true
+    }
+
+    #[cfg(not(unix))]
+    fn enter_pressed(timeout: Duration) -> bool {
+        std::thread::sleep(timeout);
+        false
+    }
+
+    /// Mono `samples` as a 16-bit PCM WAV file
+    fn wav(samples: &[f32], sample_rate: u32) -> Vec<u8> {
+        let data_len = u32::try_from(samples.len() * 2).unwrap_or(u32::MAX);
+        let mut wav = Vec::with_capacity(44 + samples.len() * 2);
+        wav.extend_from_slice(b"RIFF");
+        wav.extend_from_slice(&data_len.saturating_add(36).to_le_bytes());
+        wav.extend_from_slice(b"WAVEfmt ");
+        wav.extend_from_slice(&16u32.to_le_bytes());
+        // PCM, one channel
+        wav.extend_from_slice(&1u16.to_le_bytes());
+        wav.extend_from_slice(&1u16.to_le_bytes());
+        wav.extend_from_slice(&sample_rate.to_le_bytes());
+        wav.extend_from_slice(&(sample_rate * 2).to_le_bytes());
+        // Bytes per frame and bits per sample
+        wav.extend_from_slice(&2u16.to_le_bytes());
+        wav.extend_from_slice(&16u16.to_le_bytes());
+        wav.extend_from_slice(b"data");
+        wav.extend_from_slice(&data_len.to_le_bytes());
+        for sample in samples {
+            let sample = (sample.clamp(-1.0, 1.0) * f32::from(i16::MAX)) as i16;
+            wav.extend_from_slice(&sample.to_le_bytes());
+        }
+        wav
+    }
+}
+
+#[cfg(feature = "voice")]
+mod speakers {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    use color_eyre::eyre::{Context, Result};
+    use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+    use cpal::Sample;
+
+    const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+    struct Playback {
+        /// Mono samples at the rate of the speakers
+        samples: Vec<f32>,
+        /// Samples handed to the speakers so far
+        position: usize,
+    }
+
+    pub fn play(pcm: &[u8], sample_rate: u32, stop: &AtomicBool) -> Result<()> {
+        let device = cpal::default_host()
+            .default_output_device()
+            .ok_or_else(|| color_eyre::eyre::eyre!("no speakers found"))?;
+        let supported = device
+            .default_output_config()
+            .wrap_err_with(|| "failed to get the speakers' config")?;
+        let format = supported.sample_format();
+        let stream_config: cpal::StreamConfig = supported.into();
+
+        let samples = pcm
+            .chunks_exact(2)
+            .map(|bytes| f32::from(i16::from_le_bytes([bytes[0], bytes[1]])) / f32::from(i16::MAX))
+            .collect::<Vec<_>>();
+        let playback = Arc::new(Mutex::new(Playback {
+            samples: resample(&samples, sample_rate, stream_config.sample_rate.0),
+            position: 0,
+        }));
+        let stream = match format {
+            cpal::SampleFormat::F32 => output_stream::<f32>(&device, &stream_config, &playback),
+            cpal::SampleFormat::I16 => output_stream::<i16>(&device, &stream_config, &playback),
+            cpal::SampleFormat::U16 => output_stream::<u16>(&device, &stream_config, &playback),
+            format => color_eyre::eyre::bail!("unsupported speaker sample format {format}"),
+        }?;
+        stream.play().wrap_err_with(|| "failed to start playing")?;
+
+        loop {
+            std::thread::sleep(POLL_INTERVAL);
+            if stop.load(Ordering::Relaxed) {
+                break;
+            }
+            let played = playback
+                .lock()
+                .map_or(true, |playback| playback.position >= playback.samples.len());
+            if played {
+                // Lets the speakers play out what they buffered
+                std::thread::sleep(POLL_INTERVAL);
+                break;
+            }
+        }
+        drop(stream);
+        Ok(())
+    }
+
+    fn output_stream<T>(
+        device: &cpal::Device,
+        config: &cpal::StreamConfig,
+        playback: &Arc<Mutex<Playback>>,
+    ) -> Result<cpal::Stream>
+    where
+        T: cpal::SizedSample + cpal::FromSample<f32>,
+    {
+        let channels = usize::from(config.channels.max(1));
+        let playback = playback.clone();
+        device
+            .build_output_stream(
+                config,
+                move |data: &mut [T], _: &cpal::OutputCallbackInfo| {
+                    let Ok(mut playback) = playback.lock() else {
+                        return;
+                    };
+                    for frame in data.chunks_mut(channels) {
+                        let sample = playback
+                            .samples
+                            .get(playback.position)
+                            .copied()
+                            .unwrap_or(0.0);
+                        playback.position += 1;
+                        for out in frame {
+                            *out = T::from_sample(sample);
+                        }
+                    }
+                },
+                |err| tracing::warn!("speaker error: {err}"),
+                None,
+            )
+            .wrap_err_with(|| "failed to open the speakers")
+    }
+
+    /// `samples` at `from` Hz linearly interpolated to `to` Hz
+    fn resample(samples: &[f32], from: u32, to: u32) -> Vec<f32> {
+        if from == to || samples.is_empty() {
+            return samples.to_vec();
+        }
+        let step = f64::from(from) / f64::from(to);
+        let len = (samples.len() as f64 / step) as usize;
+        (0..len)
+            .map(|i| {
+                let position = i as f64 * step;
+                let index = position as usize;
+                let fraction = (position - index as f64) as f32;
+                let sample = samples[index];
+                let next = samples.get(index + 1).copied().unwrap_or(sample);
+                sample + (next - sample) * fraction
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,94 @@
+use std::path::{Path, PathBuf};
+
+use color_eyre::eyre::{Context, Result};
+use wasmtime::{Config, Engine, Instance, Module, Store};
+
+const PRE_PROMPT_EXPORT: &str = "pre_prompt";
+const POST_ANSWER_EXPORT: &str = "post_answer";
+/// Upper bound on the work a single call may do, so a looping plugin cannot hang the REPL
+const FUEL: u64 = 1_000_000_000;
+
+/// A sandboxed transformer module without any imports.
+///
+/// Modules export `memory`, `alloc(len: i32) -> i32`, and `pre_prompt` and/or `post_answer`
+/// as `(ptr: i32, len: i32) -> i64`, returning the UTF-8 result packed as `ptr << 32 | len`.
+pub struct Transformer {
+    path: PathBuf,
+    engine: Engine,
+    module: Module,
+}
+
+impl Transformer {
+    pub fn load_dir(dir: &Path) -> Result<Vec<Self>> {
+        let engine = Engine::new(Config::new().consume_fuel(true))
+            .map_err(|err| color_eyre::eyre::eyre!("{err}"))
+            .wrap_err_with(|| "failed to initialize wasmtime engine")?;
+
+        let mut paths = std::fs::read_dir(dir)
+            .wrap_err_with(|| format!("failed to read WASM plugins dir `{}`", dir.display()))?
+            .flatten()
+            .map(|entry| entry.path())
+            .filter(|path| {
+                path.extension()
+                    .is_some_and(|extension| extension == "wasm")
+            })
+            .collect::<Vec<_>>();
+        paths.sort();
+
+        paths
+            .into_iter()
+            .map(|path| {
+                let module = Module::from_file(&engine, &path)
+                    .map_err(|err| color_eyre::eyre::eyre!("{err}"))
+                    .wrap_err_with(|| format!("failed to load WASM plugin `{}`", path.display()))?;
+                Ok(Self {
+                    path,
+                    engine: engine.clone(),
+                    module,
+                })
+            })
+            .collect()
+    }
+
+    pub fn pre_prompt(&self, question: String) -> Result<String> {
+        self.call(PRE_PROMPT_EXPORT, question)
+    }
+
+    pub fn post_answer(&self, answer: String) -> Result<String> {
+        self.call(POST_ANSWER_EXPORT, answer)
+    }
+
+    /// Passes `input` through `export`, or returns it unchanged if the module lacks it
+    fn call(&self, export: &str, input: String) -> Result<String> {
+        if self.module.get_export(export).is_none() {
+            return Ok(input);
+        }
+
+        self.try_call(export, &input)
+            .map_err(|err| color_eyre::eyre::eyre!("{err}"))
+            .wrap_err_with(|| format!("WASM plugin `{}` failed in `{export}`", self.path.display()))
+    }
+
+    fn try_call(&self, export: &str, input: &str) -> wasmtime::Result<String> {
+        let mut store = Store::new(&self.engine, ());
+        store.set_fuel(FUEL)?;
+
+        let instance = Instance::new(&mut store, &self.module, &[])?;
+        let memory = instance
+            .get_memory(&mut store, "memory")
+            .ok_or_else(|| wasmtime::Error::msg("missing `memory` export"))?;
+        let alloc = instance.get_typed_func::<i32, i32>(&mut store, "alloc")?;
+        let transform = instance.get_typed_func::<(i32, i32), i64>(&mut store, export)?;
+
+        let len = i32::try_from(input.len())?;
+        let ptr = alloc.call(&mut store, len)?;
+        memory.write(&mut store, usize::try_from(ptr)?, input.as_bytes())?;
+
+        let packed = transform.call(&mut store, (ptr, len))? as u64;
+        let (out_ptr, out_len) = ((packed >> 32) as usize, (packed & 0xffff_ffff) as usize);
+
+        let mut output = vec![0; out_len];
+        memory.read(&store, out_ptr, &mut output)?;
+        Ok(String::from_utf8(output)?)
+    }
+}
@@ -0,0 +1,159 @@
+use std::io::SeekFrom;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use color_eyre::eyre::{Context, Result};
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+
+/// How often a watched file is checked for appended lines, and a FIFO for a reader or writer
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Lines written to a FIFO, or appended to a file after it started being watched
+pub struct Input {
+    path: PathBuf,
+    source: Source,
+    /// Read past the last complete line
+    partial: Vec<u8>,
+}
+
+enum Source {
+    #[cfg(unix)]
+    Fifo(Option<tokio::net::unix::pipe::Receiver>),
+    File {
+        offset: u64,
+    },
+}
+
+impl Input {
+    pub fn open(path: &Path) -> Result<Self> {
+        let metadata = std::fs::metadata(path)
+            .wrap_err_with(|| format!("failed to watch `{}`", path.display()))?;
+        #[cfg(unix)]
+        if is_fifo(&metadata) {
+            return Ok(Self {
+                path: path.to_owned(),
+                source: Source::Fifo(None),
+                partial: Vec::new(),
+            });
+        }
+        Ok(Self {
+            path: path.to_owned(),
+            source: Source::File {
+                offset: metadata.len(),
+            },
+            partial: Vec::new(),
+        })
+    }
+
+    /// Waits for the next line, without its line ending
+    pub async fn next_line(&mut self) -> Result<String> {
+        loop {
+            if let Some(end) = self.partial.iter().position(|&byte| byte == b'\n') {
+                let line = self.partial.drain(..=end).collect::<Vec<_>>();
+                return Ok(String::from_utf8_lossy(&line).trim_end().to_owned());
+            }
+
+            let mut read = Vec::new();
+            match &mut self.source {
+                #[cfg(unix)]
+                Source::Fifo(receiver) => {
+                    let pipe = match receiver {
+                        Some(pipe) => pipe,
+                        None => receiver.insert(open_receiver(&self.path)?),
+                    };
+                    let mut buf = [0; 4096];
+                    let n = pipe
+                        .read(&mut buf)
+                        .await
+                        .wrap_err_with(|| format!("failed to read `{}`", self.path.display()))?;
+                    if n == 0 {
+                        // Every writer went away, so open it again for the next one
+                        *receiver = None;
+                        tokio::time::sleep(POLL_INTERVAL).await;
+                    }
+                    read.extend_from_slice(&buf[..n]);
+                },
+                Source::File { offset } => {
+                    let mut file = tokio::fs::File::open(&self.path)
+                        .await
+                        .wrap_err_with(|| format!("failed to open `{}`", self.path.display()))?;
+                    let len = file
+                        .metadata()
+                        .await
+                        .wrap_err_with(|| format!("failed to stat `{}`", self.path.display()))?
+                        .len();
+                    // Truncated, so lines are appended from the start again
+                    if len < *offset {
+                        *offset = 0;
+                    }
+                    if len == *offset {
+                        tokio::time::sleep(POLL_INTERVAL).await;
+                        continue;
+                    }
+                    file.seek(SeekFrom::Start(*offset))
+                        .await
+                        .wrap_err_with(|| format!("failed to seek `{}`", self.path.display()))?;
+                    file.read_to_end(&mut read)
+                        .await
+                        .wrap_err_with(|| format!("failed to read `{}`", self.path.display()))?;
+                    *offset += read.len() as u64;
+                },
+            }
+            self.partial.extend(read);
+        }
+    }
+}
+
+/// Writes `answer` to the FIFO at `path`, waiting for a reader and closing it after so that the
+/// reader sees where the answer ends, or appends it to the file at `path` followed by a blank
+/// line
+pub async fn write_answer(path: &Path, answer: &str) -> Result<()> {
+    #[cfg(unix)]
+    if std::fs::metadata(path).is_ok_and(|metadata| is_fifo(&metadata)) {
+        let mut sender = loop {
+            match tokio::net::unix::pipe::OpenOptions::new().open_sender(path) {
+                Ok(sender) => break sender,
+                // No reader yet
+                Err(err) if err.raw_os_error() == Some(libc::ENXIO) => {
+                    tokio::time::sleep(POLL_INTERVAL).await;
+                },
+                Err(err) => {
+                    return Err(err)
+                        .wrap_err_with(|| format!("failed to open `{}`", path.display()))
+                },
+            }
+        };
+        return sender
+            .write_all(format!("{}\n", answer.trim_end()).as_bytes())
+            .await
+            .wrap_err_with(|| format!("failed to write `{}`", path.display()));
+    }
+
+    let mut file = tokio::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .await
+        .wrap_err_with(|| format!("failed to open `{}`", path.display()))?;
+    file.write_all(format!("{}\n\n", answer.trim_end()).as_bytes())
+        .await
+        .wrap_err_with(|| format!("failed to write `{}`", path.display()))
+}
+
+#[cfg(unix)]
+fn open_receiver(path: &Path) -> Result<tokio::net::unix::pipe::Receiver> {
+    let mut options = tokio::net::unix::pipe::OpenOptions::new();
+    // Also opening it for writing keeps reads from ending each time a writer closes it
+    #[cfg(target_os = "linux")]
+    options.read_write(true);
+    options
+        .open_receiver(path)
+        .wrap_err_with(|| format!("failed to open `{}`", path.display()))
+}
+
+#[cfg(unix)]
+fn is_fifo(metadata: &std::fs::Metadata) -> bool {
+    use std::os::unix::fs::FileTypeExt;
+
+    metadata.file_type().is_fifo()
+}